@@ -1,7 +1,9 @@
 extern crate winregnt;
 
+#[cfg(windows)]
 use winregnt::RegKey;
 
+#[cfg(windows)]
 fn main() {
     // Open the registry key
     let mut key = RegKey::open_write(r"\Registry\Machine\Software\DestroyMe").unwrap();
@@ -15,3 +17,8 @@ fn main() {
     key.write_string_value("StringValue", "Hello, world!")
         .expect("could not create string value!");
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("this example requires windows");
+}
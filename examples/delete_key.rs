@@ -1,8 +1,15 @@
 extern crate winregnt;
 
+#[cfg(windows)]
 use winregnt::RegKey;
 
+#[cfg(windows)]
 fn main() {
     let key = RegKey::open_write(r"\Registry\Machine\Software\DestroyMe").unwrap();
     key.delete().expect("Couldn't delete the key");
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("this example requires windows");
+}
@@ -1,7 +1,9 @@
 extern crate winregnt;
 
+#[cfg(windows)]
 use winregnt::RegKey;
 
+#[cfg(windows)]
 fn main() {
     // Open the registry key
     let key = RegKey::open_write(r"\Registry\Machine\Software\DestroyMe").unwrap();
@@ -9,3 +11,8 @@ fn main() {
     key.delete_value("DeleteThis")
         .expect("Couldn't delete the value");
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("this example requires windows");
+}
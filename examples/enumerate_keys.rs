@@ -1,17 +1,25 @@
 extern crate winregnt;
 
+#[cfg(windows)]
 use winregnt::RegKey;
 
+#[cfg(windows)]
 fn main() {
     let reg =
         RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion".to_owned())
             .unwrap();
     println!("Keys:");
-    reg.enum_keys().for_each(|k| {
+    reg.enum_keys().flatten().for_each(|k| {
         println!("- {}", k);
         let _ = k.open().map(|key| {
             key.enum_values()
+                .flatten()
                 .for_each(|v| println!("-- {}: {}", v, v.value()));
         });
     });
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("this example requires windows");
+}
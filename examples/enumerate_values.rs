@@ -1,13 +1,20 @@
 extern crate winregnt;
 
+#[cfg(windows)]
 use winregnt::RegKey;
 
+#[cfg(windows)]
 fn main() {
     let reg =
         RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run".to_owned())
             .unwrap();
     println!("Values:");
-    reg.enum_values().for_each(|k| {
+    reg.enum_values().flatten().for_each(|k| {
         println!("- {}: {}", k, k.value());
     });
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("this example requires windows");
+}
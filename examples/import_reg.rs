@@ -0,0 +1,25 @@
+extern crate winregnt;
+
+#[cfg(windows)]
+use winregnt::{apply, parse_reg, RegKey};
+
+#[cfg(windows)]
+fn main() {
+    let file = std::env::args()
+        .nth(1)
+        .expect("usage: import_reg <file.reg> [--apply]");
+    let commit = std::env::args().any(|arg| arg == "--apply");
+
+    let operations = parse_reg(&std::fs::read(file).unwrap()).unwrap();
+    let root = if commit {
+        RegKey::open_write(r"\Registry").unwrap()
+    } else {
+        RegKey::open(r"\Registry").unwrap()
+    };
+    print!("{}", apply(&operations, &root, !commit));
+}
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("this example requires windows");
+}
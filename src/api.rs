@@ -1,141 +1,13 @@
-use crate::{error::RegValueError, Result};
-use std::ptr::null_mut;
+use crate::{error::ErrorKind, status::NtStatus};
 use winapi::{
     shared::{
-        minwindef::{DWORD, PULONG, ULONG},
-        ntdef::{HANDLE, OBJECT_ATTRIBUTES, UNICODE_STRING},
+        guiddef::GUID,
+        minwindef::{PULONG, ULONG},
+        ntdef::{BOOLEAN, HANDLE, OBJECT_ATTRIBUTES, UNICODE_STRING},
     },
     um::winnt::{ACCESS_MASK, LARGE_INTEGER, PVOID},
 };
 
-/// Values read from registry keys
-#[derive(Clone, Debug)]
-pub enum RegValue {
-    /// No value
-    None,
-    /// Value that can be represented as a string
-    String(String),
-    /// DWORD
-    Dword(DWORD),
-    /// QWORD
-    Qword(u64),
-    /// Binary data
-    Binary(Vec<u8>),
-    /// Unknown or unsupported registry value type
-    Unknown,
-}
-
-impl ::std::fmt::Display for RegValue {
-    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        match self {
-            RegValue::String(ref v) => write!(fmt, "{}", v),
-            RegValue::Dword(ref v) => write!(fmt, "{}", v),
-            RegValue::Qword(ref v) => write!(fmt, "{}", v),
-            RegValue::Binary(ref v) => write!(fmt, "{:?}", v),
-            v => write!(fmt, "? {:?}", v),
-        }
-    }
-}
-
-impl RegValue {
-    pub(crate) fn new(info: &KeyValueFullInformation, data: &[u8]) -> Result<RegValue> {
-        match info.value_type.into() {
-            ValueType::REG_NONE => Ok(RegValue::None),
-            ValueType::REG_SZ | ValueType::REG_EXPAND_SZ => {
-                let tmp_data = data
-                    .iter()
-                    .copied()
-                    .skip(info.data_offset as usize)
-                    .take(info.data_length as usize)
-                    .collect::<Vec<u8>>();
-                if info.data_length > 0 && tmp_data.len() >= info.data_length as usize {
-                    let wide_data = tmp_data
-                        .chunks_exact(2)
-                        .map(|chunk| u16::from_ne_bytes([chunk[0], chunk[1]]))
-                        .filter(|c| *c != 0x0000)
-                        .collect::<Vec<_>>();
-                    widestring::U16String::from_vec(wide_data)
-                        .to_ustring()
-                        .to_string()
-                        .map(RegValue::String)
-                        .map_err(|e| e.into())
-                } else {
-                    Ok(RegValue::String(String::new()))
-                }
-            }
-            ValueType::REG_DWORD => {
-                if data.len() >= std::mem::size_of::<u32>() {
-                    let tmp_data = data
-                        .iter()
-                        .copied()
-                        .skip(info.data_offset as usize)
-                        .collect::<Vec<u8>>();
-
-                    tmp_data
-                        .chunks_exact(4)
-                        .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-                        .next()
-                        .map(RegValue::Dword)
-                        .ok_or_else(|| RegValueError::DwordConversion.into())
-                } else {
-                    Err(RegValueError::UnknownType.into())
-                }
-            }
-            ValueType::REG_DWORD_BIG_ENDIAN => {
-                if data.len() >= std::mem::size_of::<u32>() {
-                    let tmp_data = data
-                        .iter()
-                        .copied()
-                        .skip(info.data_offset as usize)
-                        .collect::<Vec<u8>>();
-
-                    tmp_data
-                        .chunks_exact(4)
-                        .map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-                        .next()
-                        .map(RegValue::Dword)
-                        .ok_or_else(|| RegValueError::DwordConversion.into())
-                } else {
-                    Err(RegValueError::UnknownType.into())
-                }
-            }
-            ValueType::REG_QWORD => {
-                if data.len() >= std::mem::size_of::<u64>() {
-                    let tmp_data = data
-                        .iter()
-                        .copied()
-                        .skip(info.data_offset as usize)
-                        .collect::<Vec<u8>>();
-
-                    tmp_data
-                        .chunks_exact(8)
-                        .map(|chunk| {
-                            u64::from_ne_bytes([
-                                chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5],
-                                chunk[6], chunk[7],
-                            ])
-                        })
-                        .next()
-                        .map(RegValue::Qword)
-                        .ok_or_else(|| RegValueError::DwordConversion.into())
-                } else {
-                    Err(RegValueError::UnknownType.into())
-                }
-            }
-            ValueType::REG_BINARY => {
-                let tmp_data = data
-                    .iter()
-                    .copied()
-                    .skip(info.data_offset as usize)
-                    .collect::<Vec<u8>>();
-
-                Ok(RegValue::Binary(tmp_data))
-            }
-            _ => Ok(RegValue::Unknown),
-        }
-    }
-}
-
 /// The KEY_INFORMATION_CLASS enumeration type represents the type of information to supply about a registry key.
 ///
 /// This library only implementes a subset of these features.
@@ -200,7 +72,7 @@ pub struct KeyBasicInformation {
 }
 
 impl KeyBasicInformation {
-    pub(crate) fn new(data: &[u8]) -> Result<Self> {
+    pub(crate) fn new(data: &[u8]) -> Result<Self, ErrorKind> {
         use byteorder::{NativeEndian, ReadBytesExt};
 
         let mut cursor = std::io::Cursor::new(&data[std::mem::size_of::<LARGE_INTEGER>()..]);
@@ -209,10 +81,10 @@ impl KeyBasicInformation {
             last_write_time: unsafe { std::mem::zeroed() },
             title_index: cursor
                 .read_u32::<NativeEndian>()
-                .map_err(RegValueError::ReadKeyBasicInformation)?,
+                .map_err(ErrorKind::ReadInformation)?,
             name_length: cursor
                 .read_u32::<NativeEndian>()
-                .map_err(RegValueError::ReadKeyBasicInformation)?,
+                .map_err(ErrorKind::ReadInformation)?,
         };
         Ok(this)
     }
@@ -241,71 +113,36 @@ pub struct KeyValueFullInformation {
 }
 
 impl KeyValueFullInformation {
-    pub(crate) fn new(data: &[u8]) -> Result<Self> {
+    pub(crate) fn new(data: &[u8]) -> Result<Self, ErrorKind> {
         use byteorder::{NativeEndian, ReadBytesExt};
         let mut cursor = std::io::Cursor::new(data);
 
         let this = Self {
             _title_index: cursor
                 .read_u32::<NativeEndian>()
-                .map_err(RegValueError::ReadKeyValueFullInformation)?,
+                .map_err(ErrorKind::ReadInformation)?,
             value_type: cursor
                 .read_u32::<NativeEndian>()
-                .map_err(RegValueError::ReadKeyValueFullInformation)?,
+                .map_err(ErrorKind::ReadInformation)?,
             data_offset: cursor
                 .read_u32::<NativeEndian>()
-                .map_err(RegValueError::ReadKeyValueFullInformation)?,
+                .map_err(ErrorKind::ReadInformation)?,
             data_length: cursor
                 .read_u32::<NativeEndian>()
-                .map_err(RegValueError::ReadKeyValueFullInformation)?,
+                .map_err(ErrorKind::ReadInformation)?,
             name_length: cursor
                 .read_u32::<NativeEndian>()
-                .map_err(RegValueError::ReadKeyValueFullInformation)?,
+                .map_err(ErrorKind::ReadInformation)?,
         };
         Ok(this)
     }
 }
 
-#[allow(non_camel_case_types)]
-#[repr(C)]
-#[derive(Debug, PartialEq, PartialOrd)]
-pub(crate) enum ValueType {
-    REG_NONE = 0,
-    REG_SZ = 1,
-    REG_EXPAND_SZ = 2,
-    REG_BINARY = 3,
-    REG_DWORD = 4,
-    REG_DWORD_BIG_ENDIAN = 5,
-    REG_LINK = 6,
-    REG_MULTI_SZ = 7,
-    REG_RESOURCE_LIST = 8,
-    REG_FULL_RESOURCE_DESCRIPTOR = 9,
-    REG_RESOURCE_REQUIREMENTS_LIST = 10,
-    REG_QWORD = 11,
-}
-
-impl Into<ValueType> for DWORD {
-    fn into(self) -> ValueType {
-        match self {
-            1 => ValueType::REG_SZ,
-            2 => ValueType::REG_EXPAND_SZ,
-            3 => ValueType::REG_BINARY,
-            4 => ValueType::REG_DWORD,
-            5 => ValueType::REG_DWORD_BIG_ENDIAN,
-            6 => ValueType::REG_LINK,
-            7 => ValueType::REG_MULTI_SZ,
-            8 => ValueType::REG_RESOURCE_LIST,
-            9 => ValueType::REG_FULL_RESOURCE_DESCRIPTOR,
-            10 => ValueType::REG_RESOURCE_REQUIREMENTS_LIST,
-            11 => ValueType::REG_QWORD,
-            _ => ValueType::REG_NONE,
-        }
-    }
-}
-
 #[link(name = "ntdll")]
 extern "system" {
+    /// Initializes a counted `UNICODE_STRING` from a null-terminated wide string.
     pub fn RtlInitUnicodeString(dest: *mut UNICODE_STRING, source: *const u16);
+    /// Returns information about the subkey at `index` of an open key.
     pub fn NtEnumerateKey(
         handle: HANDLE,
         index: ULONG,
@@ -314,6 +151,7 @@ extern "system" {
         length: ULONG,
         result_length: PULONG,
     ) -> u32;
+    /// Returns information about the value at `index` of an open key.
     pub fn NtEnumerateValueKey(
         handle: HANDLE,
         index: ULONG,
@@ -322,14 +160,109 @@ extern "system" {
         length: ULONG,
         result_length: PULONG,
     ) -> u32;
+    /// Closes a handle.
     pub fn NtClose(handle: HANDLE) -> u32;
+    /// Opens an existing key.
     pub fn NtOpenKey(
         handle: *mut HANDLE,
         access: ACCESS_MASK,
         attr: *const OBJECT_ATTRIBUTES,
     ) -> u32;
+    /// Creates a key, or opens it if it already exists.
+    pub fn NtCreateKey(
+        handle: *mut HANDLE,
+        access: ACCESS_MASK,
+        attr: *const OBJECT_ATTRIBUTES,
+        title_index: ULONG,
+        class: *mut UNICODE_STRING,
+        create_options: ULONG,
+        disposition: PULONG,
+    ) -> u32;
+    /// Deletes a value of an open key.
     pub fn NtDeleteValueKey(handle: HANDLE, value_name: *mut UNICODE_STRING) -> u32;
+    /// Deletes an open key, which must not have any subkeys.
     pub fn NtDeleteKey(handle: HANDLE) -> u32;
+    /// Opens an existing key, honouring `REG_OPTION_*` open options.
+    pub fn NtOpenKeyEx(
+        handle: *mut HANDLE,
+        access: ACCESS_MASK,
+        attr: *const OBJECT_ATTRIBUTES,
+        open_options: ULONG,
+    ) -> u32;
+    /// Returns information about an open key, such as its class or last write time.
+    pub fn NtQueryKey(
+        handle: HANDLE,
+        info_class: KeyInformationClass,
+        key_info: PVOID,
+        length: ULONG,
+        result_length: PULONG,
+    ) -> u32;
+    /// Opens an existing key as part of a transaction.
+    pub fn NtOpenKeyTransacted(
+        handle: *mut HANDLE,
+        access: ACCESS_MASK,
+        attr: *const OBJECT_ATTRIBUTES,
+        transaction: HANDLE,
+    ) -> u32;
+    /// Opens an existing key as part of a transaction, honouring `REG_OPTION_*` open options.
+    pub fn NtOpenKeyTransactedEx(
+        handle: *mut HANDLE,
+        access: ACCESS_MASK,
+        attr: *const OBJECT_ATTRIBUTES,
+        open_options: ULONG,
+        transaction: HANDLE,
+    ) -> u32;
+    /// Creates or opens a key as part of a transaction.
+    pub fn NtCreateKeyTransacted(
+        handle: *mut HANDLE,
+        access: ACCESS_MASK,
+        attr: *const OBJECT_ATTRIBUTES,
+        title_index: ULONG,
+        class: *mut UNICODE_STRING,
+        create_options: ULONG,
+        transaction: HANDLE,
+        disposition: PULONG,
+    ) -> u32;
+    /// Creates a kernel transaction.
+    pub fn NtCreateTransaction(
+        handle: *mut HANDLE,
+        access: ACCESS_MASK,
+        attr: *const OBJECT_ATTRIBUTES,
+        uow: *const GUID,
+        transaction_manager: HANDLE,
+        create_options: ULONG,
+        isolation_level: ULONG,
+        isolation_flags: ULONG,
+        timeout: *const LARGE_INTEGER,
+        description: *const UNICODE_STRING,
+    ) -> u32;
+    /// Commits a kernel transaction.
+    pub fn NtCommitTransaction(handle: HANDLE, wait: BOOLEAN) -> u32;
+    /// Mounts a hive file at a registry path.
+    pub fn NtLoadKeyEx(
+        target: *const OBJECT_ATTRIBUTES,
+        source: *const OBJECT_ATTRIBUTES,
+        flags: ULONG,
+        trust_class_key: HANDLE,
+        event: HANDLE,
+        access: ACCESS_MASK,
+        root_handle: *mut HANDLE,
+        reserved: PVOID,
+    ) -> u32;
+    /// Replaces the contents of an open key with a saved hive file.
+    pub fn NtRestoreKey(handle: HANDLE, file: HANDLE, flags: ULONG) -> u32;
+    /// Enables or disables a privilege for the current process or thread.
+    pub fn RtlAdjustPrivilege(
+        privilege: ULONG,
+        enable: BOOLEAN,
+        client: BOOLEAN,
+        was_enabled: *mut BOOLEAN,
+    ) -> u32;
+    /// Rolls back a kernel transaction.
+    pub fn NtRollbackTransaction(handle: HANDLE, wait: BOOLEAN) -> u32;
+    /// Saves an open key and its subkeys into a hive file.
+    pub fn NtSaveKeyEx(handle: HANDLE, file: HANDLE, format: ULONG) -> u32;
+    /// Creates or replaces a value of an open key.
     pub fn NtSetValueKey(
         KeyHandle: HANDLE,
         ValueName: *mut UNICODE_STRING,
@@ -338,62 +271,75 @@ extern "system" {
         Data: PVOID,
         DataSize: ULONG,
     ) -> u32;
+    /// Unmounts a hive previously loaded with [`NtLoadKeyEx`].
+    pub fn NtUnloadKey2(target: *const OBJECT_ATTRIBUTES, flags: ULONG) -> u32;
 }
 
-pub(crate) fn enumerate_value_key(handle: HANDLE, index: ULONG) -> Option<Vec<u8>> {
+/// Calls an `NtEnumerate*` function, growing the buffer until the entry fits.
+///
+/// `Ok(None)` is returned once `index` is past the last entry.
+fn enumerate<F>(mut call: F) -> Result<Option<Vec<u8>>, NtStatus>
+where
+    F: FnMut(PVOID, ULONG, PULONG) -> u32,
+{
     let mut result_length: ULONG = 0;
-    unsafe {
-        NtEnumerateValueKey(
-            handle,
-            index,
-            KeyValueInformationClass::KeyValueFullInformation,
-            null_mut() as _,
-            0,
+    let mut data: Vec<u8> = Vec::new();
+    loop {
+        match NtStatus::from(call(
+            data.as_mut_ptr() as _,
+            data.len() as _,
             &mut result_length,
-        )
-    };
+        )) {
+            NtStatus::NO_MORE_ENTRIES => return Ok(None),
+            NtStatus::BUFFER_OVERFLOW | NtStatus::BUFFER_TOO_SMALL
+                if result_length as usize > data.len() =>
+            {
+                data.resize(result_length as _, 0)
+            }
+            status if status.is_success() => return Ok(Some(data)),
+            status => return Err(status),
+        }
+    }
+}
 
-    let mut data: Vec<u8> = vec![0; result_length as _];
-    match unsafe {
+pub(crate) fn enumerate_value_key(
+    handle: HANDLE,
+    index: ULONG,
+) -> Result<Option<Vec<u8>>, NtStatus> {
+    enumerate(|buffer, length, result_length| unsafe {
         NtEnumerateValueKey(
             handle,
             index,
             KeyValueInformationClass::KeyValueFullInformation,
-            data.as_mut_ptr() as *mut _,
-            data.len() as _,
-            &mut result_length,
+            buffer,
+            length,
+            result_length,
         )
-    } {
-        0 => Some(data),
-        _ => None,
-    }
+    })
 }
 
-pub(crate) fn enumerate_key(handle: HANDLE, index: ULONG) -> Option<Vec<u8>> {
-    let mut result_length: ULONG = 0;
-    unsafe {
+pub(crate) fn enumerate_key(handle: HANDLE, index: ULONG) -> Result<Option<Vec<u8>>, NtStatus> {
+    enumerate(|buffer, length, result_length| unsafe {
         NtEnumerateKey(
             handle,
             index,
             KeyInformationClass::KeyBasicInformation,
-            null_mut() as _,
-            0,
-            &mut result_length,
+            buffer,
+            length,
+            result_length,
         )
-    };
+    })
+}
 
-    let mut data: Vec<u8> = vec![0; result_length as _];
-    match unsafe {
-        NtEnumerateKey(
+pub(crate) fn query_key(handle: HANDLE) -> Result<Vec<u8>, NtStatus> {
+    enumerate(|buffer, length, result_length| unsafe {
+        NtQueryKey(
             handle,
-            index,
             KeyInformationClass::KeyBasicInformation,
-            data.as_mut_ptr() as *mut _,
-            data.len() as _,
-            &mut result_length,
+            buffer,
+            length,
+            result_length,
         )
-    } {
-        0 => Some(data),
-        _ => None,
-    }
+    })
+    .map(Option::unwrap_or_default)
 }
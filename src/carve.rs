@@ -0,0 +1,748 @@
+use crate::{
+    hive::{
+        checksum, u32_at, BaseBlock, Hive, BASE_BLOCK_SIZE, HBIN_ALIGNMENT, HBIN_HEADER_SIZE,
+        KEY_HIVE_ENTRY, NO_CELL,
+    },
+    hive_node::HiveNode,
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom,
+};
+
+/// Alignment at which base blocks and hive bins are searched for: the sector size of the smallest disks
+const SCAN_ALIGNMENT: usize = 512;
+
+/// Minor version given to hives carved without a base block, which reads every list and data format
+const CARVED_MINOR_VERSION: u32 = 5;
+
+/// Largest hive bin put in place of a missing region, keeping its free cell within the range of a cell size
+const MAX_MISSING_BIN: usize = 0x4000_0000;
+
+/// How many bytes of missing regions a carved hive may have for every byte of hive bins actually found.
+/// Offsets and sizes come from the image, so without a limit a few stray bins could ask for gigabytes.
+const MAX_MISSING_RATIO: u64 = 16;
+
+/// Where the data of a region of a carved hive came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionOrigin {
+    /// Found where the base block says it should be, at this offset in the image
+    InPlace(u64),
+    /// Found elsewhere in the image, at this offset, and placed by the offset in its hive bin header
+    Relocated(u64),
+    /// Not found; the region holds empty hive bins so the rest of the hive can still be read
+    Missing,
+}
+
+/// A run of hive bins in a carved hive and where they came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CarvedRegion {
+    /// Offset of the region, relative to the first hive bin
+    pub offset: u32,
+    /// Size of the region in bytes
+    pub size: u32,
+    /// Where the data of the region came from
+    pub origin: RegionOrigin,
+}
+
+impl CarvedRegion {
+    /// `true` unless the region was found where its base block says it should be
+    pub fn is_reconstructed(&self) -> bool {
+        !matches!(self.origin, RegionOrigin::InPlace(_))
+    }
+
+    fn contains(&self, offset: u32) -> bool {
+        offset >= self.offset && offset - self.offset < self.size
+    }
+}
+
+/// A hive reconstructed from a raw image.
+///
+/// The hive is read with the usual offline API through [`CarvedHive::hive`]; keys and values in regions
+/// that had to be reconstructed can be told apart with [`CarvedHive::is_reconstructed`], given the offset
+/// of their cell.
+pub struct CarvedHive {
+    offset: u64,
+    base_block: Option<BaseBlock>,
+    regions: Vec<CarvedRegion>,
+    hive: Hive,
+}
+
+impl CarvedHive {
+    /// Offset in the image of the base block or, for hives carved without one, of the first hive bin found
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The base block as found in the image, or `None` if the hive was carved from hive bins alone
+    pub fn base_block(&self) -> Option<&BaseBlock> {
+        self.base_block.as_ref()
+    }
+
+    /// The reconstructed hive.
+    ///
+    /// Its base block is rewritten to describe the hive bins that were carved, with a root cell found by
+    /// searching for the hive entry key if the original one is missing.
+    pub fn hive(&self) -> &Hive {
+        &self.hive
+    }
+
+    /// Returns the reconstructed hive
+    pub fn into_hive(self) -> Hive {
+        self.hive
+    }
+
+    /// The regions of the hive bins, in order of offset
+    pub fn regions(&self) -> &[CarvedRegion] {
+        &self.regions
+    }
+
+    /// The region holding the cell at `offset`, relative to the first hive bin
+    pub fn region(&self, offset: u32) -> Option<&CarvedRegion> {
+        self.regions.iter().find(|region| region.contains(offset))
+    }
+
+    /// `true` if the cell at `offset` lies in a region that was relocated or is missing
+    pub fn is_reconstructed(&self, offset: u32) -> bool {
+        self.region(offset)
+            .is_none_or(CarvedRegion::is_reconstructed)
+    }
+
+    /// `true` if the base block was found and every hive bin it describes was found in place
+    pub fn is_complete(&self) -> bool {
+        self.base_block.as_ref().is_some_and(|base_block| {
+            base_block.hive_bins_size == self.hive.base_block().hive_bins_size
+                && self.regions.iter().all(|region| !region.is_reconstructed())
+        })
+    }
+
+    /// Keys that cannot be reached from the root because their parent is missing or damaged, each the top
+    /// of an orphaned subtree
+    pub fn orphans(&self) -> Vec<HiveNode<'_>> {
+        let cells = self.hive.cells();
+        let root = self.hive.base_block().root_cell;
+        allocated_cells(cells.bins)
+            .filter(|offset| *offset != root)
+            .filter_map(|offset| {
+                let node = cells.key(offset)?;
+                match cells.key(node.parent) {
+                    Some(_) => None,
+                    None => Some(offset),
+                }
+            })
+            .filter_map(|offset| self.hive.node_at(offset))
+            .collect()
+    }
+}
+
+/// Searches a raw image, such as a disk image, a dump of unallocated space or a page file, for hives.
+///
+/// Every base block is followed by the hive bins it describes. Bins that are not where the base block puts
+/// them are taken from elsewhere in the image by the offset in their header, and bins that cannot be found
+/// are replaced by empty ones. Hive bins left over are grouped into runs that are contiguous both in the
+/// image and in their offsets, and each run becomes a hive of its own. Candidates are searched for at every
+/// 512 byte boundary and hive bins are only accepted if their cells exactly fill them.
+///
+/// Missing regions are limited to 16 bytes for every byte of hive bins found: a hive ends before the gap
+/// that would go past that, and a run whose offset is further out than that is moved to the start of its
+/// hive, with its bin headers rewritten to match.
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::carve;
+/// let image = std::fs::read("unallocated.bin").unwrap();
+/// for carved in carve(&image) {
+///     println!("hive at {:#x}, complete: {}", carved.offset(), carved.is_complete());
+///     if let Ok(root) = carved.hive().root_node() {
+///         for key in root.subkeys().unwrap().flatten() {
+///             let flag = if carved.is_reconstructed(key.offset()) { " (reconstructed)" } else { "" };
+///             println!("  {}{}", key.name(), flag);
+///         }
+///     }
+/// }
+/// ```
+pub fn carve(image: &[u8]) -> Vec<CarvedHive> {
+    let mut base_blocks = Vec::new();
+    let mut bins = Bins::default();
+    for position in (0..image.len()).step_by(SCAN_ALIGNMENT) {
+        match image.get(position..position + 4) {
+            Some(b"regf") => base_blocks.extend(base_block_at(image, position)),
+            Some(b"hbin") => {
+                if let Some(bin) = bin_at(image, position) {
+                    bins.insert(position as u64, bin);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut hives = Vec::new();
+    for (position, base_block) in base_blocks {
+        let mut builder = Builder::default();
+        let first = position as u64 + BASE_BLOCK_SIZE as u64;
+        let size = base_block.hive_bins_size as u64;
+        let mut offset = 0u64;
+        while offset < size {
+            if let Some(bin) = bins.take_at(first + offset, offset) {
+                builder.push(bin, RegionOrigin::InPlace(first + offset));
+                offset += bin.size as u64;
+                continue;
+            }
+            if let Some((source, bin)) = bins.take_offset(offset, position as u64) {
+                builder.push(bin, RegionOrigin::Relocated(source));
+                offset += bin.size as u64;
+                continue;
+            }
+            match bins.next_offset(first, offset, size) {
+                Some(next) => {
+                    builder.missing(offset as u32, (next - offset) as u32);
+                    offset = next;
+                }
+                None => break,
+            }
+        }
+        builder.limit_missing(&mut bins);
+        hives.push(builder.finish(image, position as u64, Some(base_block)));
+    }
+
+    for run in bins.runs() {
+        let mut builder = Builder::default();
+        let (first, bin) = run[0];
+        let found = run.iter().map(|(_, bin)| bin.size as u64).sum::<u64>();
+        // A run far beyond what its bins could have been preceded by is moved to the start of the hive
+        let base = if bin.offset as u64 > found * MAX_MISSING_RATIO {
+            bin.offset
+        } else {
+            0
+        };
+        builder.missing(0, bin.offset - base);
+        for (source, bin) in run {
+            builder.region(bin.offset - base, bin.size, RegionOrigin::Relocated(source));
+        }
+        hives.push(builder.finish(image, first, None));
+    }
+    hives.sort_by_key(CarvedHive::offset);
+    hives
+}
+
+/// A plausible primary hive base block
+fn base_block_at(image: &[u8], position: usize) -> Option<(usize, BaseBlock)> {
+    let base_block = BaseBlock::parse(image.get(position..position + BASE_BLOCK_SIZE)?)?;
+    if base_block.major_version != 1
+        || base_block.file_type != 0
+        || !(base_block.hive_bins_size as usize).is_multiple_of(HBIN_ALIGNMENT)
+    {
+        return None;
+    }
+    Some((position, base_block))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bin {
+    offset: u32,
+    size: u32,
+}
+
+/// A hive bin whose header is consistent and whose cells exactly fill it
+fn bin_at(image: &[u8], position: usize) -> Option<Bin> {
+    let offset = u32_at(image, position + 4)?;
+    let size = u32_at(image, position + 8)?;
+    if !(offset as usize).is_multiple_of(HBIN_ALIGNMENT)
+        || size == 0
+        || !(size as usize).is_multiple_of(HBIN_ALIGNMENT)
+    {
+        return None;
+    }
+
+    offset.checked_add(size)?;
+    let data = image.get(position..position.checked_add(size as usize)?)?;
+    let mut cell = HBIN_HEADER_SIZE;
+    while cell < data.len() {
+        let length = (u32_at(data, cell)? as i32).unsigned_abs() as usize;
+        if length < 8 || !length.is_multiple_of(8) {
+            return None;
+        }
+        cell += length;
+    }
+    if cell == data.len() {
+        Some(Bin { offset, size })
+    } else {
+        None
+    }
+}
+
+/// The hive bins found in the image that have not been placed in a hive yet
+#[derive(Default)]
+struct Bins {
+    by_position: BTreeMap<u64, Bin>,
+    by_offset: BTreeMap<u32, Vec<u64>>,
+    taken: HashSet<u64>,
+}
+
+impl Bins {
+    fn insert(&mut self, position: u64, bin: Bin) {
+        self.by_position.insert(position, bin);
+        self.by_offset.entry(bin.offset).or_default().push(position);
+    }
+
+    fn available(&self, position: u64) -> Option<Bin> {
+        self.by_position
+            .get(&position)
+            .filter(|_| !self.taken.contains(&position))
+            .copied()
+    }
+
+    /// The bin at `position`, if it is the bin at `offset` of its hive
+    fn take_at(&mut self, position: u64, offset: u64) -> Option<Bin> {
+        let bin = self.available(position)?;
+        if bin.offset as u64 != offset {
+            return None;
+        }
+        self.taken.insert(position);
+        Some(bin)
+    }
+
+    /// A bin anywhere in the image that claims to be at `offset` of its hive, preferring the nearest one
+    /// following `near`
+    fn take_offset(&mut self, offset: u64, near: u64) -> Option<(u64, Bin)> {
+        let offset = u32::try_from(offset).ok()?;
+        let positions = self.by_offset.get(&offset)?;
+        let position = positions
+            .iter()
+            .filter(|position| !self.taken.contains(position))
+            .min_by_key(|position| (**position < near, position.abs_diff(near)))
+            .copied()?;
+        self.taken.insert(position);
+        Some((position, self.by_position[&position]))
+    }
+
+    /// The next offset after `offset` and before `end` at which a bin can be found, either in place after
+    /// `first` or elsewhere in the image
+    fn next_offset(&self, first: u64, offset: u64, end: u64) -> Option<u64> {
+        let in_place = self
+            .by_position
+            .range(first + offset + 1..first + end)
+            .find(|(position, bin)| {
+                !self.taken.contains(position) && bin.offset as u64 == **position - first
+            })
+            .map(|(position, _)| position - first);
+        let relocated = self
+            .by_offset
+            .range(offset as u32 + 1..)
+            .take_while(|(other, _)| (**other as u64) < end)
+            .find(|(_, positions)| positions.iter().any(|p| !self.taken.contains(p)))
+            .map(|(other, _)| *other as u64);
+        match (in_place, relocated) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Puts the bins between `position` and `position + size` back, so they can be carved on their own
+    fn release(&mut self, position: u64, size: u32) {
+        for (position, _) in self.by_position.range(position..position + size as u64) {
+            self.taken.remove(position);
+        }
+    }
+
+    /// The bins not placed in a hive, split into runs contiguous in both the image and their offsets
+    fn runs(&self) -> Vec<Vec<(u64, Bin)>> {
+        let mut runs: Vec<Vec<(u64, Bin)>> = Vec::new();
+        for (position, bin) in &self.by_position {
+            if self.taken.contains(position) {
+                continue;
+            }
+            let follows = runs
+                .last()
+                .and_then(|run| run.last())
+                .is_some_and(|(last, previous)| {
+                    last + previous.size as u64 == *position
+                        && previous.offset as u64 + previous.size as u64 == bin.offset as u64
+                });
+            if follows {
+                runs.last_mut().unwrap().push((*position, *bin));
+            } else {
+                runs.push(vec![(*position, *bin)]);
+            }
+        }
+        runs
+    }
+}
+
+/// Assembles the hive bins of a carved hive
+#[derive(Default)]
+struct Builder {
+    regions: Vec<CarvedRegion>,
+}
+
+impl Builder {
+    fn region(&mut self, offset: u32, size: u32, origin: RegionOrigin) {
+        if let Some(last) = self.regions.last_mut() {
+            let adjacent = match (last.origin, origin) {
+                (RegionOrigin::InPlace(a), RegionOrigin::InPlace(b))
+                | (RegionOrigin::Relocated(a), RegionOrigin::Relocated(b)) => {
+                    a + last.size as u64 == b
+                }
+                (RegionOrigin::Missing, RegionOrigin::Missing) => true,
+                _ => false,
+            };
+            if adjacent && last.offset + last.size == offset {
+                last.size += size;
+                return;
+            }
+        }
+        self.regions.push(CarvedRegion {
+            offset,
+            size,
+            origin,
+        });
+    }
+
+    fn push(&mut self, bin: Bin, origin: RegionOrigin) {
+        self.region(bin.offset, bin.size, origin);
+    }
+
+    fn missing(&mut self, offset: u32, size: u32) {
+        if size > 0 {
+            self.region(offset, size, RegionOrigin::Missing);
+        }
+    }
+
+    /// Ends the hive before the first missing region that takes it past [`MAX_MISSING_RATIO`], giving the
+    /// bins after it back to `bins`
+    fn limit_missing(&mut self, bins: &mut Bins) {
+        let found = self
+            .regions
+            .iter()
+            .filter(|region| region.origin != RegionOrigin::Missing)
+            .map(|region| region.size as u64)
+            .sum::<u64>();
+        let mut missing = 0u64;
+        let end = self.regions.iter().position(|region| {
+            if region.origin == RegionOrigin::Missing {
+                missing += region.size as u64;
+            }
+            missing > found * MAX_MISSING_RATIO
+        });
+        if let Some(end) = end {
+            for region in self.regions.drain(end..) {
+                if let RegionOrigin::InPlace(source) | RegionOrigin::Relocated(source) =
+                    region.origin
+                {
+                    bins.release(source, region.size);
+                }
+            }
+        }
+    }
+
+    /// Copies the regions out of the image behind a base block.
+    ///
+    /// Missing regions become hive bins holding a single free cell. The buffer is allocated zeroed and only
+    /// the headers of those bins are written, so a gap of any size costs little memory.
+    fn finish(self, image: &[u8], offset: u64, found: Option<BaseBlock>) -> CarvedHive {
+        let size = self
+            .regions
+            .last()
+            .map_or(0, |region| region.offset as usize + region.size as usize);
+        let mut data = vec![0; BASE_BLOCK_SIZE + size];
+        let bins = &mut data[BASE_BLOCK_SIZE..];
+        for region in &self.regions {
+            let start = region.offset as usize;
+            let end = start + region.size as usize;
+            match region.origin {
+                RegionOrigin::InPlace(source) | RegionOrigin::Relocated(source) => {
+                    let source = source as usize;
+                    bins[start..end].copy_from_slice(&image[source..source + region.size as usize]);
+                    // Bins moved to the start of a hive get headers that agree with where they are now
+                    let mut bin = start;
+                    while bin < end {
+                        bins[bin + 4..bin + 8].copy_from_slice(&(bin as u32).to_le_bytes());
+                        bin += u32_at(bins, bin + 8)
+                            .unwrap_or(0)
+                            .max(HBIN_ALIGNMENT as u32) as usize;
+                    }
+                }
+                RegionOrigin::Missing => {
+                    let mut bin = start;
+                    while bin < end {
+                        let size = (end - bin).min(MAX_MISSING_BIN);
+                        bins[bin..bin + 4].copy_from_slice(b"hbin");
+                        bins[bin + 4..bin + 8].copy_from_slice(&(bin as u32).to_le_bytes());
+                        bins[bin + 8..bin + 12].copy_from_slice(&(size as u32).to_le_bytes());
+                        let free = (size - HBIN_HEADER_SIZE) as u32;
+                        bins[bin + HBIN_HEADER_SIZE..bin + HBIN_HEADER_SIZE + 4]
+                            .copy_from_slice(&free.to_le_bytes());
+                        bin += size;
+                    }
+                }
+            }
+        }
+
+        let mut base_block = found.clone().unwrap_or(BaseBlock {
+            primary_sequence: 1,
+            secondary_sequence: 1,
+            last_written: 0,
+            major_version: 1,
+            minor_version: CARVED_MINOR_VERSION,
+            file_type: 0,
+            file_format: 1,
+            root_cell: NO_CELL,
+            hive_bins_size: 0,
+            clustering_factor: 1,
+            file_name: Vec::new(),
+            checksum: 0,
+        });
+        base_block.hive_bins_size = size as u32;
+        if !is_root(bins, base_block.root_cell) {
+            base_block.root_cell = allocated_cells(bins)
+                .find(|offset| is_root(bins, *offset))
+                .unwrap_or(NO_CELL);
+        }
+
+        let block = base_block.to_bytes();
+        base_block.checksum = checksum(&block);
+        data[..BASE_BLOCK_SIZE].copy_from_slice(&block);
+        CarvedHive {
+            offset,
+            base_block: found,
+            regions: self.regions,
+            hive: Hive::from_parts(data, base_block),
+        }
+    }
+}
+
+/// `true` if the cell at `offset` is an allocated key node marked as the entry of a hive
+fn is_root(bins: &[u8], offset: u32) -> bool {
+    let cell = offset as usize;
+    let flags = bins.get(cell + 6..cell + 8);
+    u32_at(bins, cell).is_some_and(|size| (size as i32) < 0)
+        && bins.get(cell + 4..cell + 6) == Some(b"nk")
+        && flags.is_some_and(|flags| flags[0] as u16 & KEY_HIVE_ENTRY != 0)
+}
+
+/// The offsets of the allocated cells in a run of hive bins
+fn allocated_cells(bins: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    let mut bin = 0usize;
+    let mut cell = 0usize;
+    let mut end = 0usize;
+    std::iter::from_fn(move || loop {
+        if cell >= end {
+            if bins.get(bin..bin + 4) != Some(b"hbin") {
+                return None;
+            }
+            let size = u32_at(bins, bin + 8)? as usize;
+            if size < HBIN_ALIGNMENT {
+                return None;
+            }
+            cell = bin + HBIN_HEADER_SIZE;
+            end = (bin + size).min(bins.len());
+            bin += size;
+        }
+        let raw = u32_at(bins, cell)? as i32;
+        let length = raw.unsigned_abs() as usize;
+        if length < 8 {
+            cell = end;
+            continue;
+        }
+        let offset = cell;
+        cell += length;
+        if raw < 0 {
+            return Some(offset as u32);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        snapshot::KeySnapshot,
+        test_support::{damaged, hive},
+        value::RawValue,
+    };
+
+    fn sample(prefix: &str, keys: usize) -> Vec<u8> {
+        hive(|root| {
+            for i in 0..keys {
+                root.child_mut(format!("{}{:03}", prefix, i))
+                    .values
+                    .push(("Value".into(), RawValue::new(3, vec![i as u8; 40])));
+            }
+        })
+    }
+
+    fn snapshot(hive: &Hive) -> KeySnapshot {
+        hive.root().unwrap().to_snapshot().unwrap()
+    }
+
+    fn junk(image: &mut Vec<u8>, size: usize) {
+        image.extend((0..size).map(|i| (i * 7) as u8));
+    }
+
+    #[test]
+    fn carves_hives() {
+        let complete = sample("Complete", 20);
+        let relocated = sample("Relocated", 200);
+        let orphaned = sample("Orphaned", 200);
+        assert!(relocated.len() >= BASE_BLOCK_SIZE + 3 * HBIN_ALIGNMENT);
+        assert_eq!(u32_at(&relocated, BASE_BLOCK_SIZE + 0x1008), Some(0x1000));
+
+        let mut image = Vec::new();
+        junk(&mut image, 2048);
+        let complete_at = image.len();
+        image.extend_from_slice(&complete);
+        junk(&mut image, 1536);
+        let relocated_at = image.len();
+        image.extend_from_slice(&relocated);
+        let moved = relocated_at + BASE_BLOCK_SIZE + 0x1000;
+        let bin = image[moved..moved + 0x1000].to_vec();
+        image[moved..moved + 0x1000].iter_mut().for_each(|b| *b = 0);
+        junk(&mut image, 512);
+        let moved_to = image.len();
+        image.extend_from_slice(&bin);
+        junk(&mut image, 512);
+        let orphaned_at = image.len();
+        image.extend_from_slice(&orphaned[BASE_BLOCK_SIZE + 0x1000..]);
+        junk(&mut image, 1000);
+
+        let hives = carve(&image);
+        assert_eq!(hives.len(), 3);
+
+        let carved = &hives[0];
+        assert_eq!(carved.offset(), complete_at as u64);
+        assert!(carved.is_complete());
+        assert!(!carved.is_reconstructed(carved.hive().base_block().root_cell));
+        assert_eq!(
+            snapshot(carved.hive()),
+            snapshot(&Hive::from_bytes(complete).unwrap())
+        );
+        assert!(carved.orphans().is_empty());
+
+        let carved = &hives[1];
+        assert_eq!(carved.offset(), relocated_at as u64);
+        assert!(!carved.is_complete());
+        let first = (relocated_at + BASE_BLOCK_SIZE) as u64;
+        let size = (relocated.len() - BASE_BLOCK_SIZE) as u32;
+        assert_eq!(
+            carved.regions(),
+            &[
+                CarvedRegion {
+                    offset: 0,
+                    size: 0x1000,
+                    origin: RegionOrigin::InPlace(first),
+                },
+                CarvedRegion {
+                    offset: 0x1000,
+                    size: 0x1000,
+                    origin: RegionOrigin::Relocated(moved_to as u64),
+                },
+                CarvedRegion {
+                    offset: 0x2000,
+                    size: size - 0x2000,
+                    origin: RegionOrigin::InPlace(first + 0x2000),
+                },
+            ]
+        );
+        assert!(carved.is_reconstructed(0x1800));
+        assert!(!carved.is_reconstructed(0x2800));
+        assert_eq!(
+            snapshot(carved.hive()),
+            snapshot(&Hive::from_bytes(relocated).unwrap())
+        );
+
+        let carved = &hives[2];
+        assert_eq!(carved.offset(), orphaned_at as u64);
+        assert!(carved.base_block().is_none());
+        assert_eq!(
+            carved.regions()[..1],
+            [CarvedRegion {
+                offset: 0,
+                size: 0x1000,
+                origin: RegionOrigin::Missing,
+            }]
+        );
+        assert!(carved.hive().root().is_err());
+        let orphans = carved.orphans();
+        assert!(!orphans.is_empty());
+        for orphan in orphans {
+            assert!(orphan.name().to_string().starts_with("Orphaned"));
+            assert!(carved.is_reconstructed(orphan.offset()));
+            assert_eq!(orphan.values().unwrap().count(), 1);
+        }
+    }
+
+    /// A hive bin of `size` bytes holding one free cell, claiming to be at `offset` of its hive
+    fn empty_bin(offset: u32, size: u32) -> Vec<u8> {
+        let mut bin = vec![0; size as usize];
+        bin[..4].copy_from_slice(b"hbin");
+        bin[4..8].copy_from_slice(&offset.to_le_bytes());
+        bin[8..12].copy_from_slice(&size.to_le_bytes());
+        bin[HBIN_HEADER_SIZE..HBIN_HEADER_SIZE + 4]
+            .copy_from_slice(&(size - HBIN_HEADER_SIZE as u32).to_le_bytes());
+        bin
+    }
+
+    #[test]
+    fn far_offsets() {
+        // A stray bin near the 4 GiB mark is carved at the start of its hive instead of after a huge gap
+        let mut image = vec![0; 512];
+        image.extend(empty_bin(0xffff_e000, 0x1000));
+        let hives = carve(&image);
+        assert_eq!(hives.len(), 1);
+        assert_eq!(hives[0].hive().base_block().hive_bins_size, 0x1000);
+        assert_eq!(
+            hives[0].regions(),
+            &[CarvedRegion {
+                offset: 0,
+                size: 0x1000,
+                origin: RegionOrigin::Relocated(512),
+            }]
+        );
+        assert_eq!(
+            u32_at(hives[0].hive().as_bytes(), BASE_BLOCK_SIZE + 4),
+            Some(0)
+        );
+
+        // A base block claiming almost 4 GiB of bins stops before the gap to a bin far away
+        let mut base_block = sample("Key", 1)[..BASE_BLOCK_SIZE + 0x1000].to_vec();
+        base_block[40..44].copy_from_slice(&0xffff_f000u32.to_le_bytes());
+        let checksum = checksum(&base_block[..BASE_BLOCK_SIZE]);
+        base_block[508..512].copy_from_slice(&checksum.to_le_bytes());
+        let mut image = base_block;
+        image.extend(empty_bin(0xfff0_0000, 0x1000));
+        let hives = carve(&image);
+        assert_eq!(hives.len(), 2);
+        assert_eq!(hives[0].hive().base_block().hive_bins_size, 0x1000);
+        assert!(hives[0].hive().root().is_ok());
+        assert_eq!(hives[1].hive().base_block().hive_bins_size, 0x1000);
+        let total = hives
+            .iter()
+            .map(|carved| carved.hive().as_bytes().len())
+            .sum::<usize>();
+        assert!(total < 0x10000);
+    }
+
+    #[test]
+    fn truncated_and_damaged_images() {
+        let hive = sample("Key", 200);
+        let truncated = &hive[..BASE_BLOCK_SIZE + 0x2000];
+        let hives = carve(truncated);
+        assert_eq!(hives.len(), 1);
+        assert!(!hives[0].is_complete());
+        assert_eq!(hives[0].hive().base_block().hive_bins_size, 0x2000);
+        assert!(hives[0].hive().root().is_ok());
+
+        damaged(&hive, 0, 100, 32, |image| {
+            for carved in carve(&image) {
+                let _ = carved.orphans();
+                if let Ok(root) = carved.hive().root_node() {
+                    let _ = root.subkeys().map(|subkeys| subkeys.count());
+                }
+            }
+        });
+
+        assert!(carve(&[]).is_empty());
+        assert!(carve(b"regfhbin").is_empty());
+    }
+}
@@ -0,0 +1,647 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    hive::{decode_name, u16_at, u32_at},
+    path::{RegPath, RegPathBuf},
+    snapshot::KeySnapshot,
+    source::KeySource,
+    value::{RawValue, RegValueItem, ValueType},
+    Result,
+};
+use std::{convert::TryFrom, path::Path};
+
+/// Size of the `CREG` file header, which the `RGKN` block follows
+const CREG_HEADER_SIZE: usize = 0x20;
+
+/// Size of the header of the `RGKN` block
+const RGKN_HEADER_SIZE: usize = 0x20;
+
+/// Size of a key node in the `RGKN` block
+const KEY_NODE_SIZE: usize = 0x1c;
+
+/// Size of the header of an `RGDB` block
+const RGDB_HEADER_SIZE: usize = 0x20;
+
+/// Size of a key entry in an `RGDB` block, before its name
+const KEY_ENTRY_SIZE: usize = 0x14;
+
+/// Size of a value entry, before its name
+const VALUE_ENTRY_SIZE: usize = 0x0c;
+
+/// Marks an unused key node
+const KEY_NODE_FREE: u32 = 0x8000_0000;
+
+/// A missing key node offset
+const NO_KEY: u32 = 0xffff_ffff;
+
+/// A Windows 95, 98 or Me registry file, such as `SYSTEM.DAT` or `USER.DAT`.
+///
+/// These files start with a `CREG` header and hold the key tree in an `RGKN` block, with the names and
+/// values of the keys in a series of `RGDB` blocks. Keys are exposed through [`KeySource`] with the same
+/// paths, value names and [`RawValue`]s as keys of NT hives, so code written against [`Hive`] works on
+/// both.
+///
+/// The files predate Unicode support: names and string data are stored in the ANSI code page of the
+/// system, which is decoded as Latin-1, and string values are converted to the null terminated UTF-16LE
+/// their NT counterparts hold. Keys have no last write time, class name or security descriptor.
+///
+/// [`Hive`]: crate::Hive
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::{CregHive, KeySource};
+/// let registry = CregHive::open("SYSTEM.DAT").unwrap();
+/// let root = registry.root_at(r"\Registry\Machine").unwrap();
+/// for name in root.subkey_names().unwrap() {
+///     println!("{}", name);
+/// }
+/// ```
+pub struct CregHive<B = Vec<u8>> {
+    data: B,
+    root: u32,
+    rgkn_size: usize,
+    /// Start and size of each `RGDB` block, by block number
+    blocks: Vec<(usize, usize)>,
+}
+
+impl CregHive<Vec<u8>> {
+    /// Reads the registry file at `path` into memory
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CregHive> {
+        let data = std::fs::read(path)
+            .map_err(|e| Error::new(Operation::OpenHive, RegPathBuf::new(), ErrorKind::Io(e)))?;
+        CregHive::from_bytes(data)
+    }
+}
+
+impl<B: AsRef<[u8]>> CregHive<B> {
+    /// Reads a registry from the contents of a `CREG` file.
+    ///
+    /// The headers of the file and of its `RGKN` block must be intact. `RGDB` blocks are read up to the
+    /// first damaged one; keys whose entries are in missing blocks fail to open with
+    /// [`ErrorKind::Corrupt`].
+    pub fn from_bytes(data: B) -> Result<CregHive<B>> {
+        let corrupt = |offset: usize, reason| {
+            Error::new(
+                Operation::OpenHive,
+                RegPathBuf::new(),
+                ErrorKind::Corrupt {
+                    offset: offset as u64,
+                    reason,
+                },
+            )
+        };
+
+        let bytes = data.as_ref();
+        if bytes.get(..4) != Some(b"CREG") {
+            return Err(corrupt(0, "missing CREG signature"));
+        }
+        let rgkn = &bytes[CREG_HEADER_SIZE.min(bytes.len())..];
+        if rgkn.get(..4) != Some(b"RGKN") {
+            return Err(corrupt(CREG_HEADER_SIZE, "missing RGKN signature"));
+        }
+        let rgkn_size = u32_at(rgkn, 4).unwrap_or(0) as usize;
+        if rgkn_size < RGKN_HEADER_SIZE || rgkn_size > rgkn.len() {
+            return Err(corrupt(CREG_HEADER_SIZE + 4, "invalid RGKN block size"));
+        }
+        let root = u32_at(rgkn, 8).unwrap_or(NO_KEY);
+
+        let mut blocks = Vec::new();
+        let mut start = u32_at(bytes, 8).unwrap_or(0) as usize;
+        let count = u16_at(bytes, 16).unwrap_or(0);
+        for _ in 0..count {
+            let size = match bytes.get(start..start + 4) {
+                Some(b"RGDB") => u32_at(bytes, start + 4).unwrap_or(0) as usize,
+                _ => break,
+            };
+            if size < RGDB_HEADER_SIZE || start.saturating_add(size) > bytes.len() {
+                break;
+            }
+            blocks.push((start, size));
+            start += size;
+        }
+
+        Ok(CregHive {
+            data,
+            root,
+            rgkn_size,
+            blocks,
+        })
+    }
+
+    /// The raw contents of the registry file
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    /// Returns the underlying buffer
+    pub fn into_inner(self) -> B {
+        self.data
+    }
+
+    /// The root key of the registry, with the path `\`
+    pub fn root(&self) -> Result<CregKey<'_>> {
+        self.root_at(r"\")
+    }
+
+    /// The root key of the registry as if it were loaded at `path`, such as `\Registry\Machine`
+    pub fn root_at<P: Into<RegPathBuf>>(&self, path: P) -> Result<CregKey<'_>> {
+        let file = CregFile {
+            data: self.data.as_ref(),
+            rgkn_size: self.rgkn_size,
+            blocks: &self.blocks,
+        };
+        CregKey::new(file, self.root, path.into(), Operation::OpenKey)
+    }
+}
+
+/// Bounds checked access to the blocks of a `CREG` file
+#[derive(Clone, Copy, Debug)]
+struct CregFile<'a> {
+    data: &'a [u8],
+    rgkn_size: usize,
+    blocks: &'a [(usize, usize)],
+}
+
+impl<'a> CregFile<'a> {
+    /// The key node at `offset`, relative to the start of the `RGKN` block
+    fn key_node(&self, offset: u32) -> Option<KeyNode> {
+        let start = offset as usize;
+        if start < RGKN_HEADER_SIZE || start.checked_add(KEY_NODE_SIZE)? > self.rgkn_size {
+            return None;
+        }
+        let node = self.data.get(CREG_HEADER_SIZE + start..)?;
+        if u32_at(node, 0)? & KEY_NODE_FREE != 0 {
+            return None;
+        }
+        Some(KeyNode {
+            parent: u32_at(node, 12)?,
+            child: u32_at(node, 16)?,
+            next: u32_at(node, 20)?,
+            id: u16_at(node, 24)?,
+            block: u16_at(node, 26)?,
+        })
+    }
+
+    /// The key entry with the name and values of `node`, with its offset in the file
+    fn key_entry(&self, node: &KeyNode) -> Option<(usize, &'a [u8])> {
+        let (start, size) = *self.blocks.get(node.block as usize)?;
+        let block = self.data.get(start..start + size)?;
+        let mut offset = RGDB_HEADER_SIZE;
+        while offset + KEY_ENTRY_SIZE <= block.len() {
+            let length = u32_at(block, offset)? as usize;
+            if length < KEY_ENTRY_SIZE {
+                return None;
+            }
+            if u16_at(block, offset + 4)? == node.id && u16_at(block, offset + 6)? == node.block {
+                return Some((
+                    start + offset,
+                    block.get(offset..offset.checked_add(length)?)?,
+                ));
+            }
+            offset = offset.checked_add(length)?;
+        }
+        None
+    }
+}
+
+/// A key node in the `RGKN` block
+#[derive(Clone, Copy, Debug)]
+struct KeyNode {
+    parent: u32,
+    child: u32,
+    next: u32,
+    /// Number of the entry of the key in its `RGDB` block
+    id: u16,
+    /// Number of the `RGDB` block holding the entry of the key
+    block: u16,
+}
+
+/// A key in a [`CregHive`]
+#[derive(Clone, Debug)]
+pub struct CregKey<'a> {
+    file: CregFile<'a>,
+    offset: u32,
+    node: KeyNode,
+    /// The key entry and its offset in the file
+    entry: Option<(usize, &'a [u8])>,
+    path: RegPathBuf,
+}
+
+impl<'a> CregKey<'a> {
+    fn new(
+        file: CregFile<'a>,
+        offset: u32,
+        path: RegPathBuf,
+        operation: Operation,
+    ) -> Result<CregKey<'a>> {
+        let node = file.key_node(offset).ok_or_else(|| {
+            corrupt(
+                operation,
+                &path,
+                CREG_HEADER_SIZE + offset as usize,
+                "invalid key node",
+            )
+        })?;
+        Ok(CregKey {
+            file,
+            offset,
+            node,
+            entry: file.key_entry(&node),
+            path,
+        })
+    }
+
+    fn corrupt(&self, operation: Operation, offset: usize, reason: &'static str) -> Error {
+        corrupt(operation, &self.path, offset, reason)
+    }
+
+    fn entry(&self, operation: Operation) -> Result<(usize, &'a [u8])> {
+        self.entry.ok_or_else(|| {
+            self.corrupt(
+                operation,
+                CREG_HEADER_SIZE + self.offset as usize,
+                "missing key entry",
+            )
+        })
+    }
+
+    /// The name of the key as stored in the registry
+    pub fn name(&self) -> Result<RegPathBuf> {
+        let (_, entry) = self.entry(Operation::OpenKey)?;
+        let length = u16_at(entry, 12).unwrap_or(0) as usize;
+        Ok(RegPathBuf::from(decode_name(
+            entry
+                .get(KEY_ENTRY_SIZE..KEY_ENTRY_SIZE + length)
+                .unwrap_or_default(),
+            true,
+        )))
+    }
+
+    /// The offset of the key node, relative to the start of the `RGKN` block
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// The direct subkeys of the key, in the order the registry stores them
+    pub fn subkeys(&self) -> Result<Vec<CregKey<'a>>> {
+        let mut subkeys = Vec::new();
+        let mut next = self.node.child;
+        while next != NO_KEY {
+            let offset = CREG_HEADER_SIZE + next as usize;
+            if subkeys.len() > self.file.rgkn_size / KEY_NODE_SIZE {
+                return Err(self.corrupt(Operation::EnumerateKeys, offset, "subkey list loops"));
+            }
+            let mut subkey =
+                CregKey::new(self.file, next, RegPathBuf::new(), Operation::EnumerateKeys)
+                    .map_err(|_| {
+                        self.corrupt(Operation::EnumerateKeys, offset, "invalid key node")
+                    })?;
+            if subkey.node.parent != self.offset {
+                return Err(self.corrupt(
+                    Operation::EnumerateKeys,
+                    offset,
+                    "key node has the wrong parent",
+                ));
+            }
+            let name = subkey
+                .name()
+                .map_err(|_| self.corrupt(Operation::EnumerateKeys, offset, "missing key entry"))?;
+            subkey.path = self.path.join(&name);
+            next = subkey.node.next;
+            subkeys.push(subkey);
+        }
+        Ok(subkeys)
+    }
+
+    /// Copies the key and everything below it into a [`KeySnapshot`]
+    pub fn to_snapshot(&self) -> Result<KeySnapshot> {
+        self.snapshot_below(&mut Vec::new())
+    }
+
+    /// `ancestors` holds the key nodes on the path down to this key, so a node listed below itself is
+    /// reported instead of being followed forever
+    fn snapshot_below(&self, ancestors: &mut Vec<u32>) -> Result<KeySnapshot> {
+        if ancestors.contains(&self.offset) {
+            return Err(self.corrupt(
+                Operation::EnumerateKeys,
+                CREG_HEADER_SIZE + self.offset as usize,
+                "subkey cycle",
+            ));
+        }
+        ancestors.push(self.offset);
+        let children = self
+            .subkeys()?
+            .iter()
+            .map(|subkey| subkey.snapshot_below(ancestors))
+            .collect::<Result<_>>()?;
+        ancestors.pop();
+
+        Ok(KeySnapshot {
+            path: self.path.clone(),
+            last_write_time: 0,
+            class: None,
+            security: None,
+            values: self
+                .values()?
+                .into_iter()
+                .map(|value| (value.value_name().clone(), value.raw().clone()))
+                .collect(),
+            children,
+        })
+    }
+}
+
+fn corrupt(operation: Operation, path: &RegPath, offset: usize, reason: &'static str) -> Error {
+    Error::new(
+        operation,
+        path,
+        ErrorKind::Corrupt {
+            offset: offset as u64,
+            reason,
+        },
+    )
+}
+
+/// Converts string data from the ANSI code page to the UTF-16LE used by NT
+fn widen(value_type: u32, data: &[u8]) -> Vec<u8> {
+    match ValueType::try_from(value_type) {
+        Ok(ValueType::REG_SZ) | Ok(ValueType::REG_EXPAND_SZ) | Ok(ValueType::REG_MULTI_SZ) => {
+            let mut wide: Vec<u8> = data.iter().flat_map(|b| [*b, 0]).collect();
+            if !wide.ends_with(&[0, 0]) {
+                wide.extend_from_slice(&[0, 0]);
+            }
+            wide
+        }
+        _ => data.to_vec(),
+    }
+}
+
+impl<'a> KeySource for CregKey<'a> {
+    fn path(&self) -> &RegPath {
+        &self.path
+    }
+
+    fn values(&self) -> Result<Vec<RegValueItem>> {
+        let (start, entry) = self.entry(Operation::EnumerateValues)?;
+        let invalid = |offset: usize| {
+            self.corrupt(
+                Operation::EnumerateValues,
+                start + offset,
+                "invalid value entry",
+            )
+        };
+
+        let count = u16_at(entry, 14).unwrap_or(0);
+        let mut offset = KEY_ENTRY_SIZE + u16_at(entry, 12).unwrap_or(0) as usize;
+        let mut values = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let value_type = u32_at(entry, offset).ok_or_else(|| invalid(offset))?;
+            let name_length = u16_at(entry, offset + 8).ok_or_else(|| invalid(offset))? as usize;
+            let data_length = u16_at(entry, offset + 10).ok_or_else(|| invalid(offset))? as usize;
+            let name_start = offset + VALUE_ENTRY_SIZE;
+            let data_start = name_start + name_length;
+            let name = entry
+                .get(name_start..data_start)
+                .ok_or_else(|| invalid(offset))?;
+            let data = entry
+                .get(data_start..data_start + data_length)
+                .ok_or_else(|| invalid(offset))?;
+            values.push(RegValueItem::new(
+                &self.path,
+                decode_name(name, true),
+                RawValue::new(value_type, widen(value_type, data)),
+            ));
+            offset = data_start + data_length;
+        }
+        Ok(values)
+    }
+
+    fn subkey_names(&self) -> Result<Vec<RegPathBuf>> {
+        self.subkeys()?.iter().map(CregKey::name).collect()
+    }
+
+    fn open_child(&self, name: &RegPath) -> Result<CregKey<'a>> {
+        self.subkeys()?
+            .into_iter()
+            .find(|key| key.path.name() == Some(name))
+            .ok_or_else(|| {
+                Error::new(
+                    Operation::OpenKey,
+                    self.path.join(name),
+                    ErrorKind::NotFound,
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{damaged, truncated},
+        value::RegValue,
+    };
+
+    type Value<'a> = (u32, &'a str, &'a [u8]);
+
+    /// Builds a `CREG` file from keys given as their parent, name and values, parents first
+    fn creg(keys: &[(Option<usize>, &str, Vec<Value>)]) -> Vec<u8> {
+        let node = |i: usize| (RGKN_HEADER_SIZE + i * KEY_NODE_SIZE) as u32;
+        let rgkn_size = RGKN_HEADER_SIZE + keys.len() * KEY_NODE_SIZE;
+        let mut rgkn = vec![0; rgkn_size];
+        rgkn[..4].copy_from_slice(b"RGKN");
+        rgkn[4..8].copy_from_slice(&(rgkn_size as u32).to_le_bytes());
+        rgkn[8..12].copy_from_slice(&node(0).to_le_bytes());
+        for (i, (parent, _, _)) in keys.iter().enumerate() {
+            let child = keys.iter().position(|(p, _, _)| *p == Some(i));
+            let next = keys
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .find(|(_, (p, _, _))| parent.is_some() && p == parent)
+                .map(|(next, _)| next);
+            let start = node(i) as usize;
+            for (field, value) in [
+                (0, 0),
+                (8, NO_KEY),
+                (12, parent.map_or(NO_KEY, node)),
+                (16, child.map_or(NO_KEY, node)),
+                (20, next.map_or(NO_KEY, node)),
+            ]
+            .iter()
+            {
+                rgkn[start + field..start + field + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            rgkn[start + 24..start + 26].copy_from_slice(&(i as u16).to_le_bytes());
+        }
+
+        let mut rgdb = vec![0; RGDB_HEADER_SIZE];
+        rgdb[..4].copy_from_slice(b"RGDB");
+        for (i, (_, name, values)) in keys.iter().enumerate().rev() {
+            let mut entry = vec![0; KEY_ENTRY_SIZE];
+            entry[4..6].copy_from_slice(&(i as u16).to_le_bytes());
+            entry[12..14].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            entry[14..16].copy_from_slice(&(values.len() as u16).to_le_bytes());
+            entry.extend_from_slice(name.as_bytes());
+            for (value_type, name, data) in values {
+                entry.extend_from_slice(&value_type.to_le_bytes());
+                entry.extend_from_slice(&[0; 4]);
+                entry.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                entry.extend_from_slice(&(data.len() as u16).to_le_bytes());
+                entry.extend_from_slice(name.as_bytes());
+                entry.extend_from_slice(data);
+            }
+            let size = (entry.len() as u32).to_le_bytes();
+            entry[..4].copy_from_slice(&size);
+            entry[8..12].copy_from_slice(&size);
+            rgdb.extend_from_slice(&entry);
+        }
+        let size = (rgdb.len() as u32).to_le_bytes();
+        rgdb[4..8].copy_from_slice(&size);
+
+        let mut file = vec![0; CREG_HEADER_SIZE];
+        file[..4].copy_from_slice(b"CREG");
+        file[4..8].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        file[8..12].copy_from_slice(&((CREG_HEADER_SIZE + rgkn_size) as u32).to_le_bytes());
+        file[16..18].copy_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&rgkn);
+        file.extend_from_slice(&rgdb);
+        file
+    }
+
+    fn sample() -> Vec<u8> {
+        creg(&[
+            (None, "", vec![]),
+            (Some(0), "Software", vec![]),
+            (
+                Some(1),
+                "Vendor",
+                vec![
+                    (1, "Name", b"Caf\xe9\0"),
+                    (4, "Count", &[5, 0, 0, 0]),
+                    (3, "Blob", &[1, 2, 3]),
+                    (7, "List", b"a\0bc\0\0"),
+                    (1, "", b"unterminated"),
+                ],
+            ),
+            (Some(0), "System", vec![(4, "Flag", &[1, 0, 0, 0])]),
+            (Some(1), "Other", vec![]),
+        ])
+    }
+
+    fn path(s: &str) -> RegPathBuf {
+        RegPathBuf::from(s)
+    }
+
+    #[test]
+    fn reads_keys_and_values() {
+        let registry = CregHive::from_bytes(sample()).unwrap();
+        let root = registry.root_at(r"\Registry\Machine").unwrap();
+        assert_eq!(
+            root.subkey_names().unwrap(),
+            vec![path("Software"), path("System")]
+        );
+
+        let vendor = root
+            .open_child(&path("SOFTWARE"))
+            .unwrap()
+            .open_child(&path("vendor"))
+            .unwrap();
+        assert_eq!(
+            vendor.path().to_string(),
+            r"\Registry\Machine\Software\Vendor"
+        );
+        let values = vendor
+            .values()
+            .unwrap()
+            .into_iter()
+            .map(|value| (value.value_name().to_string(), value.value()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                ("Name".to_string(), RegValue::String("Caf\u{e9}".into())),
+                ("Count".to_string(), RegValue::Dword(5)),
+                ("Blob".to_string(), RegValue::Binary(vec![1, 2, 3])),
+                (
+                    "List".to_string(),
+                    RegValue::MultiString(vec!["a".into(), "bc".into()])
+                ),
+                ("".to_string(), RegValue::String("unterminated".into())),
+            ]
+        );
+
+        let snapshot = registry.root().unwrap().to_snapshot().unwrap();
+        assert_eq!(snapshot.children.len(), 2);
+        assert_eq!(
+            snapshot.children[0].children[1].path.to_string(),
+            r"\Software\Other"
+        );
+        assert_eq!(snapshot.children[1].values[0].0.to_string(), "Flag");
+        assert!(root
+            .open_child(&path("Missing"))
+            .unwrap_err()
+            .is_not_found());
+    }
+
+    #[test]
+    fn damaged_files() {
+        assert!(CregHive::from_bytes(&b"regf"[..]).is_err());
+        assert!(CregHive::from_bytes(&b"CREG"[..]).is_err());
+
+        let mut looped = sample();
+        // Point the next sibling of `System` back at `Software`
+        let system = CREG_HEADER_SIZE + RGKN_HEADER_SIZE + 3 * KEY_NODE_SIZE;
+        looped[system + 20..system + 24]
+            .copy_from_slice(&((RGKN_HEADER_SIZE + KEY_NODE_SIZE) as u32).to_le_bytes());
+        let registry = CregHive::from_bytes(looped).unwrap();
+        assert!(matches!(
+            registry.root().unwrap().subkeys().unwrap_err().kind(),
+            ErrorKind::Corrupt {
+                reason: "subkey list loops",
+                ..
+            }
+        ));
+
+        let mut adopted = sample();
+        // Make `Other` claim `System` as its parent
+        let other = CREG_HEADER_SIZE + RGKN_HEADER_SIZE + 4 * KEY_NODE_SIZE;
+        adopted[other + 12..other + 16]
+            .copy_from_slice(&((RGKN_HEADER_SIZE + 3 * KEY_NODE_SIZE) as u32).to_le_bytes());
+        let registry = CregHive::from_bytes(adopted).unwrap();
+        assert!(registry.root().unwrap().to_snapshot().is_err());
+
+        let mut cyclic = sample();
+        // Make the root its own parent and only child
+        let root = CREG_HEADER_SIZE + RGKN_HEADER_SIZE;
+        let offset = (RGKN_HEADER_SIZE as u32).to_le_bytes();
+        cyclic[root + 12..root + 16].copy_from_slice(&offset);
+        cyclic[root + 16..root + 20].copy_from_slice(&offset);
+        let registry = CregHive::from_bytes(cyclic).unwrap();
+        let root = registry.root().unwrap();
+        assert_eq!(root.subkeys().unwrap().len(), 1);
+        assert!(matches!(
+            root.to_snapshot().unwrap_err().kind(),
+            ErrorKind::Corrupt {
+                reason: "subkey cycle",
+                ..
+            }
+        ));
+
+        let bytes = sample();
+        damaged(&bytes, 0, 500, 4, |bytes| {
+            if let Ok(registry) = CregHive::from_bytes(bytes) {
+                if let Ok(root) = registry.root() {
+                    let _ = root.to_snapshot();
+                }
+            }
+        });
+        truncated(&bytes, 0, 1, |bytes| {
+            if let Ok(registry) = CregHive::from_bytes(bytes) {
+                if let Ok(root) = registry.root() {
+                    let _ = root.to_snapshot();
+                }
+            }
+        });
+    }
+}
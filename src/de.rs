@@ -0,0 +1,713 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::RegPathBuf,
+    serde_impl::type_name,
+    source::KeySource,
+    value::{RawValue, RegValue},
+    value_name::ValueName,
+    Result,
+};
+use serde::de::{
+    self,
+    value::{SeqDeserializer, StringDeserializer},
+    DeserializeOwned, DeserializeSeed, Expected, IntoDeserializer, MapAccess, SeqAccess,
+    Unexpected, Visitor,
+};
+use std::{convert::TryFrom, fmt::Display};
+
+/// Deserializes a Rust value from the key `source`, which can be a live `RegKey`, a `KeySnapshot`, an
+/// offline hive or any other [`KeySource`].
+///
+/// Structs and maps are read from keys: each value of the key, and then each subkey, is an entry named after
+/// it (subkeys with the same name as a value are skipped). Values convert as follows:
+///
+/// * `REG_DWORD` and `REG_QWORD` into integers, floats or `bool` (non-zero is `true`); a `REG_DWORD` holding
+///   `0xffffffff` reads as `-1` into an `i32`
+/// * `REG_SZ`, `REG_EXPAND_SZ` and `REG_LINK` into strings, unit enum variants, or numbers and `bool`s
+///   written as text
+/// * `REG_MULTI_SZ` into sequences of strings
+/// * the data of any value into `Vec<u8>` or byte buffers
+/// * any value into a `RawValue` or `RegValue`, keeping its type
+///
+/// Sequences can also be read from keys, one element per subkey in order. Missing values deserialize into
+/// `None` for `Option` fields. Errors name the key and value that could not be converted.
+///
+/// # Examples
+///
+/// ```
+/// use serde::Deserialize;
+/// use winregnt::{from_source, KeySnapshot, RawValue};
+///
+/// #[derive(Deserialize)]
+/// #[serde(rename_all = "PascalCase")]
+/// struct Config {
+///     install_dir: String,
+///     port: u16,
+///     proxy: Option<String>,
+/// }
+///
+/// let mut key = KeySnapshot::new(r"\Registry\Machine\Software\Vendor");
+/// key.values.push(("InstallDir".into(), RawValue::new(1, b"C\0:\0\0\0".to_vec())));
+/// key.values.push(("Port".into(), RawValue::new(4, 8080u32.to_le_bytes().to_vec())));
+///
+/// let config: Config = from_source(&&key).unwrap();
+/// assert_eq!((config.install_dir.as_str(), config.port, config.proxy), ("C:", 8080, None));
+/// ```
+pub fn from_source<T: DeserializeOwned, S: KeySource>(source: &S) -> Result<T> {
+    T::deserialize(KeyDeserializer::new(source))
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::new(
+            Operation::Deserialize,
+            RegPathBuf::new(),
+            ErrorKind::Serde(msg.to_string()),
+        )
+    }
+}
+
+/// A `serde` deserializer reading from a key, see [`from_source`]
+pub struct KeyDeserializer<'a, S> {
+    key: &'a S,
+}
+
+impl<'a, S: KeySource> KeyDeserializer<'a, S> {
+    /// Creates a deserializer reading from `key`
+    pub fn new(key: &'a S) -> KeyDeserializer<'a, S> {
+        KeyDeserializer { key }
+    }
+
+    fn entries(&self) -> Result<KeyEntries<'a, S>> {
+        let values = self
+            .key
+            .values()?
+            .into_iter()
+            .map(|item| (item.value_name().clone(), item.raw().clone()))
+            .collect::<Vec<_>>();
+        let subkeys = self
+            .key
+            .subkey_names()?
+            .into_iter()
+            .filter(|subkey| {
+                let name = ValueName::from(subkey.as_wide());
+                !values.iter().any(|(value_name, _)| *value_name == name)
+            })
+            .collect::<Vec<_>>();
+        Ok(KeyEntries {
+            key: self.key,
+            values: values.into_iter(),
+            subkeys: subkeys.into_iter(),
+            pending: None,
+        })
+    }
+}
+
+impl<'de, 'a, S: KeySource> de::Deserializer<'de> for KeyDeserializer<'a, S> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let path = self.key.path();
+        let entries = self.entries()?;
+        visitor
+            .visit_map(entries)
+            .map_err(|e| e.in_context(path, None))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let path = self.key.path();
+        let subkeys = KeySubkeys {
+            key: self.key,
+            names: self.key.subkey_names()?.into_iter(),
+        };
+        visitor
+            .visit_seq(subkeys)
+            .map_err(|e| e.in_context(path, None))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct enum identifier
+    }
+}
+
+/// An entry of `KeyEntries` whose name has been read but not its contents
+enum Pending {
+    Value(ValueName, RawValue),
+    Key(RegPathBuf),
+}
+
+/// The values and then the subkeys of a key, as map entries
+struct KeyEntries<'a, S> {
+    key: &'a S,
+    values: std::vec::IntoIter<(ValueName, RawValue)>,
+    subkeys: std::vec::IntoIter<RegPathBuf>,
+    pending: Option<Pending>,
+}
+
+impl<'de, 'a, S: KeySource> MapAccess<'de> for KeyEntries<'a, S> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let (name, pending) = if let Some((name, raw)) = self.values.next() {
+            (name.to_string_lossy(), Pending::Value(name, raw))
+        } else if let Some(subkey) = self.subkeys.next() {
+            (subkey.to_string_lossy().into_owned(), Pending::Key(subkey))
+        } else {
+            return Ok(None);
+        };
+        self.pending = Some(pending);
+        let name: StringDeserializer<Error> = name.into_deserializer();
+        seed.deserialize(name).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        match self.pending.take() {
+            Some(Pending::Value(name, raw)) => seed
+                .deserialize(ValueDeserializer {
+                    raw: &raw,
+                    human_readable: true,
+                })
+                .map_err(|e| e.in_context(self.key.path(), Some(&name))),
+            Some(Pending::Key(name)) => {
+                let child = self.key.open_child(&name)?;
+                seed.deserialize(KeyDeserializer::new(&child))
+            }
+            None => Err(de::Error::custom("value requested before its name")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len() + self.subkeys.len())
+    }
+}
+
+/// The subkeys of a key, as sequence elements
+struct KeySubkeys<'a, S> {
+    key: &'a S,
+    names: std::vec::IntoIter<RegPathBuf>,
+}
+
+impl<'de, 'a, S: KeySource> SeqAccess<'de> for KeySubkeys<'a, S> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.names.next() {
+            Some(name) => {
+                let child = self.key.open_child(&name)?;
+                seed.deserialize(KeyDeserializer::new(&child)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.names.len())
+    }
+}
+
+/// Deserializes the data of a single value
+struct ValueDeserializer<'a> {
+    raw: &'a RawValue,
+    /// `false` for the data of a value read as a `RawValue` or `RegValue`, so it is taken as bytes
+    human_readable: bool,
+}
+
+impl<'a> ValueDeserializer<'a> {
+    fn invalid_type(&self, expected: &dyn Expected) -> Error {
+        let value = self.raw.decode();
+        let unexpected = match &value {
+            RegValue::None => Unexpected::Unit,
+            RegValue::String(s) | RegValue::ExpandString(s) | RegValue::Link(s) => {
+                Unexpected::Str(s)
+            }
+            RegValue::MultiString(_) => Unexpected::Seq,
+            RegValue::Dword(n) | RegValue::DwordBigEndian(n) => Unexpected::Unsigned((*n).into()),
+            RegValue::Qword(n) => Unexpected::Unsigned(*n),
+            RegValue::Binary(data) => Unexpected::Bytes(data),
+            RegValue::Unknown => Unexpected::Bytes(&self.raw.data),
+        };
+        de::Error::invalid_type(unexpected, expected)
+    }
+
+    /// The value as a number; a `REG_DWORD` or `REG_QWORD` as wide as a signed target reads as two's complement
+    fn integer(&self, signed_bits: u32, expected: &dyn Expected) -> Result<i128> {
+        match self.raw.decode() {
+            RegValue::Dword(n) | RegValue::DwordBigEndian(n) if signed_bits == 32 => {
+                Ok((n as i32).into())
+            }
+            RegValue::Dword(n) | RegValue::DwordBigEndian(n) => Ok(n.into()),
+            RegValue::Qword(n) if signed_bits == 64 => Ok((n as i64).into()),
+            RegValue::Qword(n) => Ok(n.into()),
+            RegValue::String(s) => s
+                .trim()
+                .parse()
+                .map_err(|_| de::Error::invalid_value(Unexpected::Str(&s), expected)),
+            _ => Err(self.invalid_type(expected)),
+        }
+    }
+
+    fn string(&self, expected: &dyn Expected) -> Result<String> {
+        match self.raw.decode() {
+            RegValue::String(s) | RegValue::ExpandString(s) | RegValue::Link(s) => Ok(s),
+            _ => Err(self.invalid_type(expected)),
+        }
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($($method:ident => $visit:ident: $ty:ty, $signed_bits:expr;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                let n = self.integer($signed_bits, &visitor)?;
+                match <$ty>::try_from(n) {
+                    Ok(n) => visitor.$visit(n),
+                    Err(_) => Err(de::Error::invalid_value(
+                        Unexpected::Other(&n.to_string()),
+                        &visitor,
+                    )),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.raw.decode() {
+            RegValue::None => visitor.visit_unit(),
+            RegValue::String(s) | RegValue::ExpandString(s) | RegValue::Link(s) => {
+                visitor.visit_string(s)
+            }
+            RegValue::MultiString(_) => self.deserialize_seq(visitor),
+            RegValue::Dword(n) | RegValue::DwordBigEndian(n) => visitor.visit_u32(n),
+            RegValue::Qword(n) => visitor.visit_u64(n),
+            RegValue::Binary(data) => visitor.visit_byte_buf(data),
+            RegValue::Unknown => visitor.visit_bytes(&self.raw.data),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.raw.decode() {
+            RegValue::Dword(n) | RegValue::DwordBigEndian(n) => visitor.visit_bool(n != 0),
+            RegValue::Qword(n) => visitor.visit_bool(n != 0),
+            RegValue::String(s) => match s.trim() {
+                "true" | "1" => visitor.visit_bool(true),
+                "false" | "0" => visitor.visit_bool(false),
+                _ => Err(de::Error::invalid_value(Unexpected::Str(&s), &visitor)),
+            },
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    deserialize_integer! {
+        deserialize_i8 => visit_i8: i8, 8;
+        deserialize_i16 => visit_i16: i16, 16;
+        deserialize_i32 => visit_i32: i32, 32;
+        deserialize_i64 => visit_i64: i64, 64;
+        deserialize_u8 => visit_u8: u8, 0;
+        deserialize_u16 => visit_u16: u16, 0;
+        deserialize_u32 => visit_u32: u32, 0;
+        deserialize_u64 => visit_u64: u64, 0;
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.raw.decode() {
+            RegValue::String(s) => match s.trim().parse() {
+                Ok(n) => visitor.visit_f64(n),
+                Err(_) => Err(de::Error::invalid_value(Unexpected::Str(&s), &visitor)),
+            },
+            _ => {
+                let n = self.integer(0, &visitor)?;
+                visitor.visit_f64(n as f64)
+            }
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let s = self.string(&visitor)?;
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(de::Error::invalid_value(Unexpected::Str(&s), &visitor)),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let s = self.string(&visitor)?;
+        visitor.visit_string(s)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_bytes(&self.raw.data)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.raw.data.clone())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.raw.decode() {
+            RegValue::None => visitor.visit_unit(),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.raw.decode() {
+            RegValue::MultiString(strings) => {
+                let mut seq = SeqDeserializer::new(strings.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            _ => {
+                let mut seq = SeqDeserializer::new(self.raw.data.iter().copied());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        Err(self.invalid_type(&visitor))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        // `RawValue` and `RegValue` are read as their `serde` form, `{"type": .., "data": ..}`
+        if fields.contains(&"type") && fields.contains(&"data") {
+            let decoded = if name == "RegValue" {
+                Some(self.raw.decode())
+            } else {
+                None
+            };
+            visitor.visit_map(RawEntries {
+                raw: self.raw,
+                decoded,
+                next: 0,
+            })
+        } else {
+            Err(self.invalid_type(&visitor))
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let variant: StringDeserializer<Error> = self.string(&visitor)?.into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.human_readable
+    }
+}
+
+/// The type and data of a value as map entries, for deserializing a `RawValue` or (when `decoded` is set)
+/// a `RegValue`
+struct RawEntries<'a> {
+    raw: &'a RawValue,
+    decoded: Option<RegValue>,
+    next: usize,
+}
+
+impl<'a> RawEntries<'a> {
+    fn has_data(&self) -> bool {
+        !matches!(self.decoded, Some(RegValue::None) | Some(RegValue::Unknown))
+    }
+}
+
+impl<'de, 'a> MapAccess<'de> for RawEntries<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let key = match self.next {
+            0 => "type",
+            1 if self.has_data() => "data",
+            _ => return Ok(None),
+        };
+        seed.deserialize(key.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        self.next += 1;
+        if self.next > 1 {
+            return seed.deserialize(ValueDeserializer {
+                raw: self.raw,
+                human_readable: false,
+            });
+        }
+        let value_type = match &self.decoded {
+            Some(decoded) => Some(decoded.value_type().map_or("unknown", type_name)),
+            None => self.raw.value_type().map(type_name),
+        };
+        match value_type {
+            Some(name) => seed.deserialize(name.into_deserializer()),
+            None => seed.deserialize(self.raw.value_type.into_deserializer()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::KeySnapshot;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "PascalCase")]
+    struct Config {
+        name: String,
+        port: u16,
+        enabled: bool,
+        offset: i32,
+        servers: Vec<String>,
+        key: Vec<u8>,
+        ratio: f64,
+        mode: Mode,
+        proxy: Option<String>,
+        window: Window,
+        plugins: BTreeMap<String, Plugin>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Mode {
+        Fast,
+        Safe,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "PascalCase")]
+    struct Window {
+        width: u32,
+        height: u64,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "PascalCase")]
+    struct Plugin {
+        path: String,
+    }
+
+    fn string(s: &str) -> RawValue {
+        RegValue::String(s.to_owned()).to_raw().unwrap()
+    }
+
+    fn config_key() -> KeySnapshot {
+        let mut root = KeySnapshot::new(r"\Registry\Machine\Software\Vendor");
+        root.values = vec![
+            ("Name".into(), string("app")),
+            ("Port".into(), RegValue::Dword(8080).to_raw().unwrap()),
+            ("Enabled".into(), RegValue::Dword(1).to_raw().unwrap()),
+            ("Offset".into(), RegValue::Dword(u32::MAX).to_raw().unwrap()),
+            (
+                "Servers".into(),
+                RegValue::MultiString(vec!["a".into(), "b".into()])
+                    .to_raw()
+                    .unwrap(),
+            ),
+            ("Key".into(), RawValue::new(3, vec![1, 2, 3])),
+            ("Ratio".into(), string("0.5")),
+            ("Mode".into(), string("Safe")),
+        ];
+        let window = root.child_mut("Window");
+        window.values = vec![
+            ("Width".into(), string("640")),
+            ("Height".into(), RegValue::Qword(480).to_raw().unwrap()),
+        ];
+        root.child_mut(r"Plugins\Spell")
+            .values
+            .push(("Path".into(), string("spell.dll")));
+        root.child_mut(r"Plugins\Sync")
+            .values
+            .push(("Path".into(), string("sync.dll")));
+        root
+    }
+
+    #[test]
+    fn structs() {
+        let root = config_key();
+        let config: Config = from_source(&&root).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "app".into(),
+                port: 8080,
+                enabled: true,
+                offset: -1,
+                servers: vec!["a".into(), "b".into()],
+                key: vec![1, 2, 3],
+                ratio: 0.5,
+                mode: Mode::Safe,
+                proxy: None,
+                window: Window {
+                    width: 640,
+                    height: 480
+                },
+                plugins: vec![
+                    (
+                        "Spell".to_owned(),
+                        Plugin {
+                            path: "spell.dll".into()
+                        }
+                    ),
+                    (
+                        "Sync".to_owned(),
+                        Plugin {
+                            path: "sync.dll".into()
+                        }
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            }
+        );
+
+        let plugins: Vec<Plugin> = from_source(&root.child("Plugins").unwrap()).unwrap();
+        assert_eq!(plugins.len(), 2);
+        let values: BTreeMap<String, RegValue> =
+            from_source(&root.child("Window").unwrap()).unwrap();
+        assert_eq!(values["Width"], RegValue::String("640".into()));
+        assert_eq!(values["Height"], RegValue::Qword(480));
+        let raw: BTreeMap<String, RawValue> = from_source(&root.child("Window").unwrap()).unwrap();
+        assert_eq!(
+            raw["Height"],
+            RawValue::new(11, 480u64.to_le_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut root = config_key();
+        root.values[1].1 = RegValue::Dword(70000).to_raw().unwrap();
+        let error = from_source::<Config, _>(&&root).unwrap_err();
+        assert_eq!(error.operation(), Operation::Deserialize);
+        assert_eq!(
+            error.to_string(),
+            "Could not deserialize \\Registry\\Machine\\Software\\Vendor (value \"Port\"): \
+             invalid value: 70000, expected u16"
+        );
+
+        let mut root = config_key();
+        root.child_mut("Window").values[0].1 = string("wide");
+        let error = from_source::<Config, _>(&&root).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not deserialize \\Registry\\Machine\\Software\\Vendor\\Window (value \"Width\"): \
+             invalid value: string \"wide\", expected u32"
+        );
+
+        let mut root = config_key();
+        root.values
+            .retain(|(name, _)| *name != ValueName::from("Port"));
+        let error = from_source::<Config, _>(&&root).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not deserialize \\Registry\\Machine\\Software\\Vendor: missing field `Port`"
+        );
+        assert!(error.value_name().is_none());
+    }
+}
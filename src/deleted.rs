@@ -0,0 +1,498 @@
+use crate::{
+    hive::{
+        u32_at, Hive, KeyNode, ValueKey, HBIN_ALIGNMENT, HBIN_HEADER_SIZE, KEY_NODE_SIZE,
+        VALUE_KEY_SIZE,
+    },
+    hive_writer::round_up,
+    path::{RegPath, RegPathBuf},
+    source::KeySource,
+    value::{RawValue, RegValue},
+    value_name::ValueName,
+};
+use std::collections::HashMap;
+
+/// How far a recovered key or value can be trusted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Confidence {
+    /// Found in the slack space of an allocated cell, or its data is missing or has been reused
+    Low,
+    /// Intact, but merged into a larger free region or without a known parent
+    Medium,
+    /// A free cell of its own, with its parent and data still in place
+    High,
+}
+
+/// A key node found in the free space of a hive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeletedKey {
+    /// Offset of the key node, relative to the first hive bin
+    pub offset: u32,
+    /// The name of the key
+    pub name: RegPathBuf,
+    /// When the key was last written, as a `FILETIME`
+    pub last_write_time: u64,
+    /// Offset of the key node of its parent when the key was deleted
+    pub parent: u32,
+    /// The full path of the key, if its parent is a live key or a recovered key whose path is known
+    pub path: Option<RegPathBuf>,
+    /// How far the key can be trusted
+    pub confidence: Confidence,
+}
+
+/// A value key found in the free space of a hive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeletedValue {
+    /// Offset of the value key, relative to the first hive bin
+    pub offset: u32,
+    /// The name of the value
+    pub name: ValueName,
+    /// The type and data of the value, if the data could still be read
+    pub raw: Option<RawValue>,
+    /// Offset of the recovered key whose value list refers to the value
+    pub key: Option<u32>,
+    /// The full path of that key, if it is known
+    pub path: Option<RegPathBuf>,
+    /// How far the value can be trusted
+    pub confidence: Confidence,
+}
+
+impl DeletedValue {
+    /// Decodes the recovered data, see `RegValue::from_raw`
+    pub fn value(&self) -> Option<RegValue> {
+        self.raw.as_ref().map(RawValue::decode)
+    }
+}
+
+/// Keys and values recovered from the free space of a hive, ordered by offset
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeletedItems {
+    /// Recovered keys
+    pub keys: Vec<DeletedKey>,
+    /// Recovered values, both those of recovered keys and those deleted from live keys
+    pub values: Vec<DeletedValue>,
+}
+
+impl<B: AsRef<[u8]>> Hive<B> {
+    /// Scans the free cells and the slack space of allocated cells for remnants of deleted keys and values.
+    ///
+    /// Deleted cells keep their contents until they are reused, so key nodes and value keys can often be
+    /// recovered with their names, timestamps and data. Each remnant is linked to its likely parent: the
+    /// key node its parent field or a recovered value list points to, which is either a live key (giving a
+    /// full path relative to the root, `\`) or another recovered key. Damaged structures are skipped rather
+    /// than reported as errors.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::{Confidence, Hive};
+    /// let hive = Hive::open("NTUSER.DAT").unwrap();
+    /// for value in hive.deleted().values {
+    ///     if value.confidence >= Confidence::Medium {
+    ///         println!("{:?} {} = {:?}", value.path, value.name, value.value());
+    ///     }
+    /// }
+    /// ```
+    pub fn deleted(&self) -> DeletedItems {
+        let bins = self.cells().bins;
+        let layout = Layout::scan(bins);
+        let live = live_keys(self);
+
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for region in &layout.regions {
+            let mut position = region.start;
+            while position + 8 <= region.end {
+                let length = match Remnant::find(bins, position, region) {
+                    Some(Remnant::Key(key)) => {
+                        let length = KEY_NODE_SIZE + key.name.len();
+                        keys.push((position, key, region));
+                        length
+                    }
+                    Some(Remnant::Value(value)) => {
+                        let length = VALUE_KEY_SIZE + value.name.len();
+                        values.push((position, value, region));
+                        length
+                    }
+                    None => 0,
+                };
+                position += round_up(4 + length, 8) as u32;
+            }
+        }
+
+        let cells = self.cells();
+        let by_offset = keys
+            .iter()
+            .map(|(offset, key, _)| (*offset, key))
+            .collect::<HashMap<_, _>>();
+        let mut owners = HashMap::new();
+        for (offset, key, _) in &keys {
+            if key.value_count > 0 && key.value_count < 0x10000 {
+                for value in cells
+                    .values(key.value_list, key.value_count)
+                    .unwrap_or_default()
+                {
+                    owners.entry(value).or_insert(*offset);
+                }
+            }
+        }
+
+        let resolve = |offset: u32| resolve_path(offset, &by_offset, &live);
+        let mut items = DeletedItems::default();
+        for (offset, key, region) in &keys {
+            let parent = key.parent;
+            let known_parent = live.contains_key(&parent) || by_offset.contains_key(&parent);
+            items.keys.push(DeletedKey {
+                offset: *offset,
+                name: RegPathBuf::from(key.name()),
+                last_write_time: key.last_write_time,
+                parent,
+                path: resolve(*offset),
+                confidence: if region.slack {
+                    Confidence::Low
+                } else if *offset == region.start && known_parent {
+                    Confidence::High
+                } else {
+                    Confidence::Medium
+                },
+            });
+        }
+
+        for (offset, value, region) in &values {
+            let data = cells.value_data(value);
+            let reused = cells
+                .data_cells(value)
+                .is_none_or(|data| data.iter().any(|cell| layout.is_allocated(*cell)));
+            let key = owners.get(offset).copied();
+            items.values.push(DeletedValue {
+                offset: *offset,
+                name: ValueName::from(value.name()),
+                raw: data.map(|data| RawValue::new(value.data_type, data.into_owned())),
+                key,
+                path: key.and_then(resolve),
+                confidence: if region.slack || reused {
+                    Confidence::Low
+                } else if *offset == region.start {
+                    Confidence::High
+                } else {
+                    Confidence::Medium
+                },
+            });
+        }
+        items
+    }
+}
+
+/// A stretch of the hive bins that may hold deleted cells
+#[derive(Debug)]
+struct Region {
+    start: u32,
+    end: u32,
+    /// The unused end of an allocated cell rather than a free cell
+    slack: bool,
+}
+
+/// Where the free and allocated cells of a hive are
+struct Layout {
+    regions: Vec<Region>,
+    /// Allocated cells as start and end offsets, in order
+    allocated: Vec<(u32, u32)>,
+}
+
+impl Layout {
+    /// Walks the hive bins, skipping any bin whose header or cells do not add up
+    fn scan(bins: &[u8]) -> Layout {
+        let mut layout = Layout {
+            regions: Vec::new(),
+            allocated: Vec::new(),
+        };
+
+        let mut bin = 0usize;
+        while bin + HBIN_HEADER_SIZE <= bins.len() {
+            let size = u32_at(bins, bin + 8).unwrap_or(0) as usize;
+            let size = if &bins[bin..bin + 4] == b"hbin"
+                && size >= HBIN_ALIGNMENT
+                && size.is_multiple_of(HBIN_ALIGNMENT)
+                && size <= bins.len() - bin
+            {
+                size
+            } else {
+                HBIN_ALIGNMENT
+            };
+            let end = (bin + size).min(bins.len());
+
+            let mut cell = bin + HBIN_HEADER_SIZE;
+            while cell + 8 <= end {
+                let raw = u32_at(bins, cell).unwrap_or(0) as i32;
+                let length = raw.unsigned_abs() as usize;
+                if length < 8 || !length.is_multiple_of(8) || length > end - cell {
+                    break;
+                }
+
+                let (start, stop) = (cell as u32, (cell + length) as u32);
+                if raw > 0 {
+                    layout.regions.push(Region {
+                        start,
+                        end: stop,
+                        slack: false,
+                    });
+                } else {
+                    layout.allocated.push((start, stop));
+                    let payload = &bins[cell + 4..cell + length];
+                    let used = match payload.get(..2) {
+                        Some(b"nk") => {
+                            KeyNode::parse(payload).map(|key| KEY_NODE_SIZE + key.name.len())
+                        }
+                        Some(b"vk") => {
+                            ValueKey::parse(payload).map(|value| VALUE_KEY_SIZE + value.name.len())
+                        }
+                        _ => None,
+                    };
+                    if let Some(used) = used {
+                        let slack = round_up(4 + used, 8);
+                        if slack < length {
+                            layout.regions.push(Region {
+                                start: start + slack as u32,
+                                end: stop,
+                                slack: true,
+                            });
+                        }
+                    }
+                }
+                cell += length;
+            }
+            bin += size;
+        }
+        layout
+    }
+
+    fn is_allocated(&self, cell: u32) -> bool {
+        let index = self.allocated.partition_point(|(start, _)| *start <= cell);
+        index > 0 && cell < self.allocated[index - 1].1
+    }
+}
+
+enum Remnant<'a> {
+    Key(KeyNode<'a>),
+    Value(ValueKey<'a>),
+}
+
+impl<'a> Remnant<'a> {
+    /// A key node or value key whose cell starts at `position`, bounded by its own size if that is sane
+    fn find(bins: &'a [u8], position: u32, region: &Region) -> Option<Remnant<'a>> {
+        let start = position as usize;
+        let size = (u32_at(bins, start)? as i32).unsigned_abs() as usize;
+        let end = if size >= 8 && start + size <= region.end as usize {
+            start + size
+        } else {
+            region.end as usize
+        };
+        let cell = bins.get(start + 4..end)?;
+
+        match cell.get(..2)? {
+            b"nk" => KeyNode::parse(cell)
+                .filter(|key| !key.name.is_empty() && key.name.len() <= 512)
+                .map(Remnant::Key),
+            b"vk" => ValueKey::parse(cell)
+                .filter(|value| value.name.len() <= 0x8000)
+                .map(Remnant::Value),
+            _ => None,
+        }
+    }
+}
+
+/// The paths of the live keys by the offsets of their key nodes
+fn live_keys<B: AsRef<[u8]>>(hive: &Hive<B>) -> HashMap<u32, RegPathBuf> {
+    let mut live = HashMap::new();
+    let mut pending = hive.root().into_iter().collect::<Vec<_>>();
+    while let Some(key) = pending.pop() {
+        if live
+            .insert(key.offset(), key.path().to_reg_path_buf())
+            .is_none()
+        {
+            pending.extend(key.subkeys().unwrap_or_default());
+        }
+    }
+    live
+}
+
+/// The path of a recovered key, following recovered parents until a live key is reached
+fn resolve_path(
+    offset: u32,
+    deleted: &HashMap<u32, &KeyNode>,
+    live: &HashMap<u32, RegPathBuf>,
+) -> Option<RegPathBuf> {
+    let mut names = Vec::new();
+    let mut current = offset;
+    // A recovered parent may point back at its own child once its cell has been reused
+    for _ in 0..512 {
+        if let Some(path) = live.get(&current) {
+            let mut path = path.clone();
+            names
+                .iter()
+                .rev()
+                .for_each(|name: &Vec<u16>| path.push(RegPath::from_wide(name)));
+            return Some(path);
+        }
+        let key = deleted.get(&current)?;
+        names.push(key.name());
+        current = key.parent;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hive_editor::HiveEditor,
+        test_support::{damaged, hive},
+    };
+
+    const WRITTEN: u64 = 132_500_000_000_000_000;
+
+    fn string(s: &str) -> RawValue {
+        RegValue::String(s.to_string()).to_raw().unwrap()
+    }
+
+    fn sample() -> Vec<u8> {
+        hive(|root| {
+            let evil = root.child_mut(r"Software\Evil");
+            evil.last_write_time = WRITTEN;
+            evil.values = vec![
+                ("Payload".into(), string(r"C:\Users\Public\implant.exe")),
+                ("Flag".into(), RawValue::new(4, vec![1, 0, 0, 0])),
+                ("Config".into(), RawValue::new(3, vec![0x5a; 20_000])),
+            ];
+            evil.child_mut("Child")
+                .values
+                .push(("Beacon".into(), string("https://example.invalid")));
+            root.child_mut(r"Software\Run")
+                .values
+                .push(("Updater".into(), string(r"C:\updater.exe")));
+            root.child_mut(r"Software\Run")
+                .values
+                .push(("Keep".into(), string(r"C:\keep.exe")));
+        })
+    }
+
+    fn edited() -> Vec<u8> {
+        let mut editor = HiveEditor::from_bytes(sample()).unwrap();
+        editor.delete_key(r"Software\Evil").unwrap();
+        editor.delete_value(r"Software\Run", "Updater").unwrap();
+        editor.into_bytes()
+    }
+
+    #[test]
+    fn recovers_keys_and_values() {
+        let original = Hive::from_bytes(sample()).unwrap();
+        let evil = original
+            .root()
+            .unwrap()
+            .open_child(RegPath::from_wide(
+                &"Software".encode_utf16().collect::<Vec<_>>(),
+            ))
+            .unwrap()
+            .open_child(RegPath::from_wide(
+                &"Evil".encode_utf16().collect::<Vec<_>>(),
+            ))
+            .unwrap()
+            .offset();
+
+        let hive = Hive::from_bytes(edited()).unwrap();
+        let deleted = hive.deleted();
+
+        let key = deleted.keys.iter().find(|key| key.offset == evil).unwrap();
+        assert_eq!(key.name.to_string(), "Evil");
+        assert_eq!(key.last_write_time, WRITTEN);
+        assert_eq!(key.path.as_ref().unwrap().to_string(), r"\Software\Evil");
+        assert_eq!(key.confidence, Confidence::High);
+
+        let child = deleted
+            .keys
+            .iter()
+            .find(|key| key.name.to_string() == "Child")
+            .unwrap();
+        assert_eq!(child.parent, evil);
+        assert_eq!(
+            child.path.as_ref().unwrap().to_string(),
+            r"\Software\Evil\Child"
+        );
+        assert_eq!(deleted.keys.len(), 2);
+
+        let value = |name: &str| {
+            deleted
+                .values
+                .iter()
+                .find(|value| value.name == ValueName::from(name))
+                .unwrap()
+        };
+        let payload = value("Payload");
+        assert_eq!(payload.key, Some(evil));
+        assert_eq!(
+            payload.path.as_ref().unwrap().to_string(),
+            r"\Software\Evil"
+        );
+        assert_eq!(
+            payload.value(),
+            Some(RegValue::String(r"C:\Users\Public\implant.exe".to_string()))
+        );
+        assert!(payload.confidence >= Confidence::Medium);
+        assert_eq!(value("Flag").value(), Some(RegValue::Dword(1)));
+        assert_eq!(
+            value("Config").raw.as_ref().unwrap().data,
+            vec![0x5a; 20_000]
+        );
+        assert_eq!(
+            value("Beacon").path.as_ref().unwrap().to_string(),
+            r"\Software\Evil\Child"
+        );
+
+        // Deleted from a live key: no recovered key refers to it
+        let updater = value("Updater");
+        assert_eq!(updater.key, None);
+        assert_eq!(
+            updater.value(),
+            Some(RegValue::String(r"C:\updater.exe".to_string()))
+        );
+        assert!(deleted
+            .values
+            .iter()
+            .all(|value| value.name != ValueName::from("Keep")));
+    }
+
+    #[test]
+    fn reused_cells() {
+        let mut editor = HiveEditor::from_bytes(edited()).unwrap();
+        editor
+            .set_value(
+                r"Software\Run",
+                "Overwrite",
+                &RawValue::new(3, vec![0xee; 20_000]),
+            )
+            .unwrap();
+        let hive = Hive::from_bytes(editor.into_bytes()).unwrap();
+        let deleted = hive.deleted();
+        let config = deleted
+            .values
+            .iter()
+            .find(|value| value.name == ValueName::from("Config"))
+            .unwrap();
+        assert_eq!(config.confidence, Confidence::Low);
+        assert!(deleted
+            .values
+            .iter()
+            .all(|value| value.name != ValueName::from("Overwrite")));
+    }
+
+    #[test]
+    fn clean_and_damaged_hives() {
+        assert_eq!(
+            Hive::from_bytes(sample()).unwrap().deleted(),
+            DeletedItems::default()
+        );
+
+        damaged(&edited(), 0x1000, 100, 32, |bytes| {
+            Hive::from_bytes(bytes).unwrap().deleted();
+        });
+    }
+}
@@ -0,0 +1,574 @@
+use crate::{
+    path::{RegPath, RegPathBuf},
+    reg_import::RegOperation,
+    snapshot::KeySnapshot,
+    value::{RawValue, RegValue, ValueType},
+    value_name::ValueName,
+};
+use std::{collections::BTreeMap, fmt};
+
+/// One difference between two snapshots.
+///
+/// With the `serde` feature, differences serialize as objects with a `"change"` field naming the variant in
+/// snake case (`"key_added"`, `"value_changed"`, ...) next to the fields of the variant, with values written
+/// as `{"type", "data"}` like [`RawValue`]. This layout is stable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "change", rename_all = "snake_case")
+)]
+pub enum Difference {
+    /// The key only exists in the new snapshot
+    KeyAdded {
+        /// Path of the key
+        path: RegPathBuf,
+    },
+    /// The key only exists in the old snapshot
+    KeyRemoved {
+        /// Path of the key
+        path: RegPathBuf,
+    },
+    /// The value only exists in the new snapshot
+    ValueAdded {
+        /// Path of the key holding the value
+        path: RegPathBuf,
+        /// Name of the value
+        name: ValueName,
+        /// The new value
+        value: RawValue,
+    },
+    /// The value only exists in the old snapshot
+    ValueRemoved {
+        /// Path of the key holding the value
+        path: RegPathBuf,
+        /// Name of the value
+        name: ValueName,
+        /// The old value
+        value: RawValue,
+    },
+    /// The value exists in both snapshots, with a different type or data
+    ValueChanged {
+        /// Path of the key holding the value
+        path: RegPathBuf,
+        /// Name of the value
+        name: ValueName,
+        /// The old value
+        old: RawValue,
+        /// The new value
+        new: RawValue,
+    },
+    /// The key exists in both snapshots, with a different last write time
+    TimestampChanged {
+        /// Path of the key
+        path: RegPathBuf,
+        /// The old last write time, as a `FILETIME`
+        old: u64,
+        /// The new last write time, as a `FILETIME`
+        new: u64,
+    },
+}
+
+impl Difference {
+    /// The path of the key that differs, or that holds the value that differs
+    pub fn path(&self) -> &RegPath {
+        match self {
+            Difference::KeyAdded { path }
+            | Difference::KeyRemoved { path }
+            | Difference::ValueAdded { path, .. }
+            | Difference::ValueRemoved { path, .. }
+            | Difference::ValueChanged { path, .. }
+            | Difference::TimestampChanged { path, .. } => path,
+        }
+    }
+
+    /// The name of the value that differs, `None` for differences in keys
+    pub fn value_name(&self) -> Option<&ValueName> {
+        match self {
+            Difference::ValueAdded { name, .. }
+            | Difference::ValueRemoved { name, .. }
+            | Difference::ValueChanged { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a value as its type and data, such as `REG_SZ "text"` or `REG_BINARY 01ff`
+pub(crate) struct ValueText<'a>(pub(crate) &'a RawValue);
+
+impl fmt::Display for ValueText<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let raw = self.0;
+        match raw.value_type() {
+            Some(value_type) => write!(fmt, "{:?} ", value_type)?,
+            None => write!(fmt, "0x{:x} ", raw.value_type)?,
+        }
+        match (raw.value_type(), raw.decode()) {
+            (
+                Some(ValueType::REG_SZ)
+                | Some(ValueType::REG_EXPAND_SZ)
+                | Some(ValueType::REG_LINK),
+                RegValue::String(s) | RegValue::ExpandString(s) | RegValue::Link(s),
+            ) => write!(fmt, "{:?}", s),
+            (Some(ValueType::REG_MULTI_SZ), RegValue::MultiString(strings)) => {
+                write!(fmt, "{:?}", strings)
+            }
+            (_, RegValue::Dword(n)) | (_, RegValue::DwordBigEndian(n)) => write!(fmt, "{}", n),
+            (_, RegValue::Qword(n)) => write!(fmt, "{}", n),
+            _ => raw.data.iter().try_for_each(|b| write!(fmt, "{:02x}", b)),
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    /// One line per difference, starting with `+` for additions, `-` for removals and `*` for changes:
+    ///
+    /// ```text
+    /// + key \Registry\Machine\Software\Vendor
+    /// + value \Registry\Machine\Software\Vendor "Name": REG_SZ "text"
+    /// * value \Registry\Machine\Software\Vendor "Port": REG_DWORD 80 -> REG_DWORD 8080
+    /// * time \Registry\Machine\Software\Vendor: 133000000000000000 -> 133000000010000000
+    /// ```
+    ///
+    /// Value names and strings are quoted and escaped like Rust strings, numbers are decimal and any other
+    /// data is lowercase hex.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::KeyAdded { path } => write!(fmt, "+ key {}", path),
+            Difference::KeyRemoved { path } => write!(fmt, "- key {}", path),
+            Difference::ValueAdded { path, name, value } => {
+                write!(fmt, "+ value {} {:?}: {}", path, name, ValueText(value))
+            }
+            Difference::ValueRemoved { path, name, value } => {
+                write!(fmt, "- value {} {:?}: {}", path, name, ValueText(value))
+            }
+            Difference::ValueChanged {
+                path,
+                name,
+                old,
+                new,
+            } => write!(
+                fmt,
+                "* value {} {:?}: {} -> {}",
+                path,
+                name,
+                ValueText(old),
+                ValueText(new)
+            ),
+            Difference::TimestampChanged { path, old, new } => {
+                write!(fmt, "* time {}: {} -> {}", path, old, new)
+            }
+        }
+    }
+}
+
+/// All differences between two snapshots, as found by [`diff`].
+///
+/// Keys are visited depth first with subkeys in registry order (case-insensitive), so the same two
+/// snapshots always give the same list. Within a key the difference in the key itself comes first, then
+/// its values in the order of the old snapshot, followed by values only the new one has. Displaying the diff
+/// gives one line per difference, see [`Difference`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct SnapshotDiff {
+    /// The differences, in the order described above
+    pub differences: Vec<Difference>,
+}
+
+impl SnapshotDiff {
+    /// Returns `true` if the snapshots are the same, as far as the options of the diff cover
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    /// The operations that turn the old snapshot into the new one, for `apply` or
+    /// [`RegExporter::write_operations`](crate::RegExporter::write_operations).
+    ///
+    /// Keys and values below a removed key are covered by deleting that key, and changed timestamps are
+    /// left out since they cannot be set. Paths are those of the differences, so for snapshots taken at
+    /// different paths they are the paths of the new one.
+    pub fn to_operations(&self) -> Vec<RegOperation> {
+        let mut removed: Vec<&RegPath> = Vec::new();
+        let mut operations = Vec::new();
+        for difference in &self.differences {
+            let path = difference.path();
+            let below_removed =
+                |removed: &[&RegPath]| removed.iter().any(|key| path.starts_with(key));
+            let operation = match difference {
+                Difference::KeyAdded { path } => RegOperation::CreateKey { path: path.clone() },
+                Difference::KeyRemoved { path } if !below_removed(&removed) => {
+                    removed.push(path);
+                    RegOperation::DeleteKey { path: path.clone() }
+                }
+                Difference::ValueAdded { path, name, value }
+                | Difference::ValueChanged {
+                    path,
+                    name,
+                    new: value,
+                    ..
+                } => RegOperation::SetValue {
+                    path: path.clone(),
+                    name: name.clone(),
+                    raw: value.clone(),
+                },
+                Difference::ValueRemoved { path, name, .. } if !below_removed(&removed) => {
+                    RegOperation::DeleteValue {
+                        path: path.clone(),
+                        name: name.clone(),
+                    }
+                }
+                _ => continue,
+            };
+            operations.push(operation);
+        }
+        operations
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(fmt, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which parts of two snapshots [`diff`] compares
+#[derive(Clone, Debug)]
+pub struct DiffOptions {
+    include: Vec<RegPathBuf>,
+    exclude: Vec<RegPathBuf>,
+    timestamps: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            timestamps: true,
+        }
+    }
+}
+
+impl DiffOptions {
+    /// compare everything, including last write times
+    pub fn new() -> DiffOptions {
+        DiffOptions::default()
+    }
+
+    /// only report differences at or below the absolute key path `path`; may be given more than once
+    pub fn include<P: Into<RegPathBuf>>(&mut self, path: P) -> &mut DiffOptions {
+        self.include.push(path.into());
+        self
+    }
+
+    /// skip the key at the absolute path `path` and everything below it, even if it is included
+    pub fn exclude<P: Into<RegPathBuf>>(&mut self, path: P) -> &mut DiffOptions {
+        self.exclude.push(path.into());
+        self
+    }
+
+    /// report keys whose last write time changed; times of `0`, for sources that do not record them, are
+    /// never compared
+    pub fn compare_timestamps(&mut self, compare: bool) -> &mut DiffOptions {
+        self.timestamps = compare;
+        self
+    }
+
+    fn excluded(&self, path: &RegPath) -> bool {
+        self.exclude.iter().any(|exclude| path.starts_with(exclude))
+    }
+
+    fn included(&self, path: &RegPath) -> bool {
+        self.include.is_empty() || self.include.iter().any(|include| path.starts_with(include))
+    }
+
+    /// whether anything at or below `path` can be included
+    fn leads_to_included(&self, path: &RegPath) -> bool {
+        self.included(path) || self.include.iter().any(|include| include.starts_with(path))
+    }
+}
+
+/// Compares the snapshot `old` with `new`.
+///
+/// Keys are matched by name, so the two snapshots may be taken at different paths, such as the same hive
+/// loaded at two mount points; differences are reported with the path of the `new` snapshot, or the `old` one
+/// for removed keys and their values. Added and removed keys are reported along with all of their values
+/// and subkeys. The paths given to [`DiffOptions::include`] and [`DiffOptions::exclude`] may point into
+/// either snapshot: a key is included if its path in either one is, and excluded if its path in either one is.
+///
+/// # Examples
+///
+/// ```
+/// use winregnt::{diff, DiffOptions, KeySnapshot, RawValue};
+/// let before = KeySnapshot::new(r"\Registry\Machine\Software");
+/// let mut after = before.clone();
+/// after
+///     .child_mut(r"Vendor")
+///     .values
+///     .push(("Port".into(), RawValue::new(4, vec![0x50, 0, 0, 0])));
+/// assert_eq!(
+///     diff(&before, &after, &DiffOptions::new()).to_string(),
+///     "+ key \\Registry\\Machine\\Software\\Vendor\n\
+///      + value \\Registry\\Machine\\Software\\Vendor \"Port\": REG_DWORD 80\n"
+/// );
+/// ```
+pub fn diff(old: &KeySnapshot, new: &KeySnapshot, options: &DiffOptions) -> SnapshotDiff {
+    let mut differences = Vec::new();
+    compare(Some(old), Some(new), options, &mut differences);
+    SnapshotDiff { differences }
+}
+
+fn compare(
+    old: Option<&KeySnapshot>,
+    new: Option<&KeySnapshot>,
+    options: &DiffOptions,
+    differences: &mut Vec<Difference>,
+) {
+    let path = match new.or(old) {
+        Some(key) => &key.path,
+        None => return,
+    };
+    // the filters are absolute, so they are matched against the key's path in each snapshot it is in
+    let paths = || old.iter().chain(new.iter()).map(|key| &key.path);
+    if paths().any(|path| options.excluded(path))
+        || !paths().any(|path| options.leads_to_included(path))
+    {
+        return;
+    }
+
+    if paths().any(|path| options.included(path)) {
+        match (old, new) {
+            (None, Some(_)) => differences.push(Difference::KeyAdded { path: path.clone() }),
+            (Some(_), None) => differences.push(Difference::KeyRemoved { path: path.clone() }),
+            (Some(old), Some(new))
+                if options.timestamps
+                    && old.last_write_time != 0
+                    && new.last_write_time != 0
+                    && old.last_write_time != new.last_write_time =>
+            {
+                differences.push(Difference::TimestampChanged {
+                    path: path.clone(),
+                    old: old.last_write_time,
+                    new: new.last_write_time,
+                })
+            }
+            _ => {}
+        }
+
+        let no_values = Vec::new();
+        let old_values = old.map_or(&no_values, |old| &old.values);
+        let new_values = new.map_or(&no_values, |new| &new.values);
+        let find = |values: &[(ValueName, RawValue)], name: &ValueName| {
+            values
+                .iter()
+                .find(|(value_name, _)| value_name == name)
+                .map(|(_, raw)| raw.clone())
+        };
+        for (name, raw) in old_values {
+            match find(new_values, name) {
+                Some(new) if new == *raw => {}
+                Some(new) => differences.push(Difference::ValueChanged {
+                    path: path.clone(),
+                    name: name.clone(),
+                    old: raw.clone(),
+                    new,
+                }),
+                None => differences.push(Difference::ValueRemoved {
+                    path: path.clone(),
+                    name: name.clone(),
+                    value: raw.clone(),
+                }),
+            }
+        }
+        for (name, raw) in new_values {
+            if find(old_values, name).is_none() {
+                differences.push(Difference::ValueAdded {
+                    path: path.clone(),
+                    name: name.clone(),
+                    value: raw.clone(),
+                });
+            }
+        }
+    }
+
+    let mut children: BTreeMap<&RegPath, (Option<&KeySnapshot>, Option<&KeySnapshot>)> =
+        BTreeMap::new();
+    for child in old.iter().flat_map(|old| &old.children) {
+        children.entry(child.name()).or_default().0 = Some(child);
+    }
+    for child in new.iter().flat_map(|new| &new.children) {
+        children.entry(child.name()).or_default().1 = Some(child);
+    }
+    for (old, new) in children.into_values() {
+        compare(old, new, options, differences);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dword(n: u32) -> RawValue {
+        RawValue::new(4, n.to_le_bytes().to_vec())
+    }
+
+    fn snapshots() -> (KeySnapshot, KeySnapshot) {
+        let mut old = KeySnapshot::new(r"\Registry\Machine\Software");
+        old.last_write_time = 100;
+        let vendor = old.child_mut("Vendor");
+        vendor.last_write_time = 100;
+        vendor.values.push(("Port".into(), dword(80)));
+        vendor
+            .values
+            .push(("Legacy".into(), RawValue::new(3, vec![1, 0xff])));
+        vendor.values.push(("Same".into(), dword(1)));
+        old.child_mut(r"Vendor\Old\Deep")
+            .values
+            .push(("Gone".into(), RawValue::new(1, b"x\0\0\0".to_vec())));
+        old.child_mut("Noise")
+            .values
+            .push(("Count".into(), dword(1)));
+
+        // the new snapshot lists subkeys in another order and spells them differently
+        let mut new = KeySnapshot::new(r"\Registry\Machine\Software");
+        new.last_write_time = 100;
+        new.child_mut("NOISE")
+            .values
+            .push(("Count".into(), dword(2)));
+        let vendor = new.child_mut("VENDOR");
+        vendor.last_write_time = 200;
+        vendor.values.push(("Same".into(), dword(1)));
+        vendor
+            .values
+            .push(("Port".into(), RawValue::new(1, b"8\0\0\0".to_vec())));
+        vendor.values.push((
+            "Servers".into(),
+            RawValue::new(7, b"a\0\0\0b\0\0\0\0\0".to_vec()),
+        ));
+        new.child_mut(r"VENDOR\New");
+        (old, new)
+    }
+
+    #[test]
+    fn differences() {
+        let (old, new) = snapshots();
+        assert_eq!(
+            diff(&old, &new, &DiffOptions::new()).to_string(),
+            "* value \\Registry\\Machine\\Software\\NOISE \"Count\": REG_DWORD 1 -> REG_DWORD 2\n\
+             * time \\Registry\\Machine\\Software\\VENDOR: 100 -> 200\n\
+             * value \\Registry\\Machine\\Software\\VENDOR \"Port\": REG_DWORD 80 -> REG_SZ \"8\"\n\
+             - value \\Registry\\Machine\\Software\\VENDOR \"Legacy\": REG_BINARY 01ff\n\
+             + value \\Registry\\Machine\\Software\\VENDOR \"Servers\": REG_MULTI_SZ [\"a\", \"b\"]\n\
+             + key \\Registry\\Machine\\Software\\VENDOR\\New\n\
+             - key \\Registry\\Machine\\Software\\Vendor\\Old\n\
+             - key \\Registry\\Machine\\Software\\Vendor\\Old\\Deep\n\
+             - value \\Registry\\Machine\\Software\\Vendor\\Old\\Deep \"Gone\": REG_SZ \"x\"\n"
+        );
+        assert!(diff(&new, &new, &DiffOptions::new()).is_empty());
+    }
+
+    #[test]
+    fn operations() {
+        let (old, new) = snapshots();
+        let operations = diff(&old, &new, &DiffOptions::new()).to_operations();
+        let report = crate::reg_import::dry_run(&operations, &&old);
+        assert!(report.is_clean());
+        assert_eq!(
+            operations
+                .iter()
+                .map(|operation| operation.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "set value \"Count\" in \\Registry\\Machine\\Software\\NOISE to 2",
+                "set value \"Port\" in \\Registry\\Machine\\Software\\VENDOR to 8",
+                "delete value \"Legacy\" in \\Registry\\Machine\\Software\\VENDOR",
+                "set value \"Servers\" in \\Registry\\Machine\\Software\\VENDOR to [\"a\", \"b\"]",
+                "create key \\Registry\\Machine\\Software\\VENDOR\\New",
+                "delete key \\Registry\\Machine\\Software\\Vendor\\Old",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let (old, new) = snapshots();
+        let lines = |options: &DiffOptions| {
+            diff(&old, &new, options)
+                .differences
+                .iter()
+                .map(|difference| difference.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let included = lines(
+            DiffOptions::new()
+                .include(r"\Registry\Machine\Software\Vendor")
+                .exclude(r"\Registry\Machine\Software\Vendor\Old")
+                .compare_timestamps(false),
+        );
+        assert_eq!(included.len(), 4);
+        assert!(included.iter().all(|line| line.contains("VENDOR")));
+
+        let excluded = lines(DiffOptions::new().exclude(r"\Registry\Machine\Software\Vendor"));
+        assert_eq!(
+            excluded,
+            vec![
+                "* value \\Registry\\Machine\\Software\\NOISE \"Count\": REG_DWORD 1 -> REG_DWORD 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_across_roots() {
+        let mut old = KeySnapshot::new(r"\Registry\Machine\Before");
+        old.child_mut(r"Vendor\Kept");
+        old.child_mut(r"Vendor\Old");
+        let mut new = KeySnapshot::new(r"\Registry\Machine\After");
+        new.child_mut(r"Vendor\Kept");
+        new.child_mut(r"Vendor\Added");
+        let lines = |options: &DiffOptions| {
+            diff(&old, &new, options)
+                .differences
+                .iter()
+                .map(|difference| difference.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lines(DiffOptions::new().include(r"\Registry\Machine\Before\Vendor")),
+            vec!["- key \\Registry\\Machine\\Before\\Vendor\\Old"]
+        );
+        assert_eq!(
+            lines(DiffOptions::new().include(r"\Registry\Machine\After\Vendor")),
+            vec!["+ key \\Registry\\Machine\\After\\Vendor\\Added"]
+        );
+        assert_eq!(
+            lines(DiffOptions::new().exclude(r"\Registry\Machine\Before\Vendor\Old")),
+            vec!["+ key \\Registry\\Machine\\After\\Vendor\\Added"]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json() {
+        let (old, new) = snapshots();
+        let diff = diff(
+            &old,
+            &new,
+            DiffOptions::new().include(r"\Registry\Machine\Software\Noise"),
+        );
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"change":"value_changed","path":"\\Registry\\Machine\\Software\\NOISE","name":"Count","old":{"type":"REG_DWORD","data":"01000000"},"new":{"type":"REG_DWORD","data":"02000000"}}]"#
+        );
+        assert_eq!(serde_json::from_str::<SnapshotDiff>(&json).unwrap(), diff);
+    }
+}
@@ -1,59 +1,104 @@
+use crate::{
+    path::{RegPath, RegPathBuf},
+    status::NtStatus,
+    value_name::ValueName,
+};
 use thiserror::Error;
 
-/// Errors produced by parsing registry
-#[derive(Debug, Error)]
-pub enum Error {
-    /// Error open registry key
-    #[error("Could not open registry key {0} error code 0x{1:08x}")]
-    KeyError(String, u32),
-
-    /// Subkey error
-    #[error("Error processing sub key: {source}")]
-    SubKeyError {
-        /// Source of this error
-        #[from]
-        source: SubKeyError,
-    },
-
-    /// Registry value error
-    #[error("Error processing registry value: {source}")]
-    RegValueError {
-        /// Source of this error
-        #[from]
-        source: RegValueError,
-    },
-
-    /// Converting registry data to string failed
-    #[error("Could not convert registry data to string: {0}")]
-    StringConversion(#[from] std::string::FromUtf16Error),
-
-    /// Problem operating on registry key
-    #[error("A problem occurred while operating on key: {source}")]
-    RegKeyError {
-        /// Source of this error
-        #[from]
-        source: RegKeyError,
-    },
+/// The registry operation that produced an error
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Operation {
+    /// Opening a key
+    OpenKey,
+    /// Deleting a key
+    DeleteKey,
+    /// Deleting a value
+    DeleteValue,
+    /// Creating or updating a value
+    SetValue,
+    /// Enumerating the subkeys of a key
+    EnumerateKeys,
+    /// Enumerating the values of a key
+    EnumerateValues,
+    /// Exporting a key to a file
+    ExportKey,
+    /// Creating a key
+    CreateKey,
+    /// Importing a key from a file
+    ImportKey,
+    /// Opening an offline hive file
+    OpenHive,
+    /// Writing an offline hive file
+    WriteHive,
+    /// Creating a kernel transaction
+    CreateTransaction,
+    /// Committing a kernel transaction
+    CommitTransaction,
+    /// Rolling back a kernel transaction
+    RollbackTransaction,
+    /// Saving a key to a hive file
+    SaveKey,
+    /// Restoring a key from a hive file
+    RestoreKey,
+    /// Loading a hive file into the registry
+    LoadHive,
+    /// Unloading a hive from the registry
+    UnloadHive,
+    /// Enabling a privilege in the caller's token
+    EnablePrivilege,
+    /// Deserializing a key into a Rust value
+    Deserialize,
+    /// Serializing a Rust value into a key
+    Serialize,
+    /// Querying information about a key
+    QueryKey,
 }
 
-/// Errors encountered while processing subkeys
-#[derive(Debug, Error)]
-pub enum SubKeyError {
-    /// Converting subkey name to string failed
-    #[error("Could not convert name into string")]
-    ConvertName,
+impl std::fmt::Display for Operation {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operation::OpenKey => write!(fmt, "open key"),
+            Operation::DeleteKey => write!(fmt, "delete key"),
+            Operation::DeleteValue => write!(fmt, "delete value in"),
+            Operation::SetValue => write!(fmt, "set value in"),
+            Operation::EnumerateKeys => write!(fmt, "enumerate subkeys of"),
+            Operation::EnumerateValues => write!(fmt, "enumerate values of"),
+            Operation::ExportKey => write!(fmt, "export key"),
+            Operation::CreateKey => write!(fmt, "create key"),
+            Operation::ImportKey => write!(fmt, "import key"),
+            Operation::OpenHive => write!(fmt, "open hive file"),
+            Operation::WriteHive => write!(fmt, "write hive for"),
+            Operation::CreateTransaction => write!(fmt, "create transaction"),
+            Operation::CommitTransaction => write!(fmt, "commit transaction"),
+            Operation::RollbackTransaction => write!(fmt, "roll back transaction"),
+            Operation::SaveKey => write!(fmt, "save key"),
+            Operation::RestoreKey => write!(fmt, "restore key"),
+            Operation::LoadHive => write!(fmt, "load hive at"),
+            Operation::UnloadHive => write!(fmt, "unload hive at"),
+            Operation::EnablePrivilege => write!(fmt, "enable privilege"),
+            Operation::Deserialize => write!(fmt, "deserialize"),
+            Operation::Serialize => write!(fmt, "serialize into"),
+            Operation::QueryKey => write!(fmt, "query key"),
+        }
+    }
 }
 
-/// Errors encountered while parsing registry values
+/// What went wrong, without the context of where it happened
 #[derive(Debug, Error)]
-pub enum RegValueError {
-    /// Extracting registry value's name failed
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An `Nt*` function returned a status that is not `NT_SUCCESS`
+    #[error("{0}")]
+    Status(NtStatus),
+
+    /// A name could not be converted into a `String`
     #[error("Could not convert name into string")]
     ConvertName,
 
-    /// Parsing registry value's data failed
-    #[error("Could not parse value data: {0}")]
-    ValueData(String),
+    /// A key or value name cannot be passed to the kernel
+    #[error("Invalid name: {0}")]
+    InvalidName(&'static str),
 
     /// The size of the name data is too small
     #[error("Name blob is too small")]
@@ -63,51 +108,216 @@ pub enum RegValueError {
     #[error("Data blob is too small")]
     SmallDataBlob,
 
-    /// Registry type not supported (yet)
-    #[error("Encountered unsupported registry type")]
-    UnknownType,
+    /// Reading or writing a file failed
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 
-    /// Could not convert data to DWORD
-    #[error("Could not convert registry data to DWORD")]
-    DwordConversion,
+    /// The key or value does not exist in an offline source
+    #[error("The key or value does not exist")]
+    NotFound,
 
-    /// Could not read key value information
-    #[error("Could not read key value full information: {0}")]
-    ReadKeyValueFullInformation(#[source] std::io::Error),
+    /// A structure in an offline hive is damaged or inconsistent
+    #[error("Corrupt hive data at offset {offset:#x}: {reason}")]
+    Corrupt {
+        /// Offset of the damaged structure, relative to the start of the file
+        offset: u64,
+        /// What is wrong with it
+        reason: &'static str,
+    },
 
-    /// Could not read key information
-    #[error("Could not read key basic information: {0}")]
-    ReadKeyBasicInformation(#[source] std::io::Error),
+    /// The request cannot be represented or carried out
+    #[error("Not supported: {0}")]
+    Unsupported(&'static str),
 
-    /// Could not operate on value due to permission denied
-    #[error("Could not operate on value, permission denied")]
-    AccessDenied,
+    /// A file being imported is malformed
+    #[error("Line {line}: {message}")]
+    Parse {
+        /// The line (starting at 1) the problem was found on
+        line: usize,
+        /// What is wrong with the line
+        message: String,
+    },
 
-    /// Could not operate on value because the handle is not valid
-    #[error("Could not operate on value, handle is no longer valid")]
-    InvalidHandle,
+    /// The operation needs a privilege, such as `SeBackupPrivilege`, that the caller's token does not contain
+    #[error("{0} is not held")]
+    PrivilegeNotHeld(&'static str),
 
-    /// Could not operate on value because there are insufficient resources
-    #[error("Could not operate on value, insufficient resources")]
-    InsufficientResources,
+    /// A Rust value could not be converted to or from registry data
+    #[error("{0}")]
+    Serde(String),
 
-    /// The specified value name was not found
-    #[error("Could not operate on value, the specified name was not found")]
-    NameNotFound,
+    /// Could not read information returned by the kernel
+    #[error("Could not read key information: {0}")]
+    ReadInformation(#[source] std::io::Error),
+}
 
-    /// Unable to write a value
-    #[error("Unable to write value to registry key: {0}")]
-    Write(u32),
+/// Errors produced by the registry functions.
+///
+/// Every error records the operation that failed, the full path of the key it was operating on, the value
+/// involved (if any) and, when the kernel reported the failure, the `NTSTATUS` it returned.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(windows)]
+/// # fn main() {
+/// use winregnt::RegKey;
+/// match RegKey::open(r"\Registry\Machine\Software\DoesNotExist") {
+///     Err(e) if e.is_not_found() => println!("{} is missing", e.path()),
+///     Err(e) => println!("{}", e),
+///     Ok(_) => {}
+/// }
+/// # }
+/// # #[cfg(not(windows))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct Error {
+    operation: Operation,
+    path: RegPathBuf,
+    value_name: Option<ValueName>,
+    kind: ErrorKind,
 }
 
-/// Errors while operating on a registry key
-#[derive(Debug, Error)]
-pub enum RegKeyError {
-    /// Could not delete key due to permission denied
-    #[error("Could not delete key, permission denied")]
-    DeleteAccessDenied,
-
-    /// Could not delete key because the handle is not valid
-    #[error("Could not delete key, handle is no longer valid")]
-    DeleteInvalidHandle,
+impl Error {
+    /// Creates an error for `operation` on the key at `path`
+    pub fn new<P: Into<RegPathBuf>>(operation: Operation, path: P, kind: ErrorKind) -> Error {
+        Error {
+            operation,
+            path: path.into(),
+            value_name: None,
+            kind,
+        }
+    }
+
+    /// Attaches the name of the value that was being operated on
+    pub fn with_value_name<N: Into<ValueName>>(mut self, value_name: N) -> Error {
+        self.value_name = Some(value_name.into());
+        self
+    }
+
+    /// Fills in the key (and value) an error was reported for, if it was created without a path
+    #[cfg(feature = "serde")]
+    pub(crate) fn in_context(mut self, path: &RegPath, value_name: Option<&ValueName>) -> Error {
+        if self.path.is_empty() {
+            self.path = path.to_reg_path_buf();
+            if self.value_name.is_none() {
+                self.value_name = value_name.cloned();
+            }
+        }
+        self
+    }
+
+    /// The operation that failed
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    /// The full path of the key that was being operated on
+    pub fn path(&self) -> &RegPath {
+        &self.path
+    }
+
+    /// The name of the value that was being operated on, if any
+    pub fn value_name(&self) -> Option<&ValueName> {
+        self.value_name.as_ref()
+    }
+
+    /// The status returned by the kernel, if the failure came from an `Nt*` call
+    pub fn status(&self) -> Option<NtStatus> {
+        match self.kind {
+            ErrorKind::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// What went wrong
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// `true` if the key or value does not exist
+    pub fn is_not_found(&self) -> bool {
+        matches!(self.kind, ErrorKind::NotFound)
+            || matches!(
+                self.status(),
+                Some(NtStatus::OBJECT_NAME_NOT_FOUND) | Some(NtStatus::OBJECT_PATH_NOT_FOUND)
+            )
+    }
+
+    /// `true` if the security descriptor of the key denied the requested access
+    pub fn is_access_denied(&self) -> bool {
+        self.status() == Some(NtStatus::ACCESS_DENIED)
+    }
+
+    /// `true` if the operation requires a privilege the caller does not hold
+    pub fn is_privilege_not_held(&self) -> bool {
+        matches!(self.kind, ErrorKind::PrivilegeNotHeld(_))
+            || self.status() == Some(NtStatus::PRIVILEGE_NOT_HELD)
+    }
+
+    /// `true` if the key was deleted while a handle to it was still open
+    pub fn is_key_deleted(&self) -> bool {
+        self.status() == Some(NtStatus::KEY_DELETED)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "Could not {}", self.operation)?;
+        if !self.path.as_wide().is_empty() {
+            write!(fmt, " {}", self.path)?;
+        }
+        if let Some(value_name) = &self.value_name {
+            write!(fmt, " (value {:?})", value_name)?;
+        }
+        write!(fmt, ": {}", self.kind)
+    }
+}
+
+impl std::error::Error for Error {
+    // the kind is already part of the message, so the chain continues with whatever caused it
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context() {
+        let error = Error::new(
+            Operation::DeleteValue,
+            r"\Registry\Machine\Software\Test",
+            ErrorKind::Status(NtStatus::OBJECT_NAME_NOT_FOUND),
+        )
+        .with_value_name(vec![b'a' as u16, 0, b'b' as u16]);
+
+        assert!(error.is_not_found());
+        assert!(!error.is_access_denied());
+        assert_eq!(error.operation(), Operation::DeleteValue);
+        assert_eq!(error.status(), Some(NtStatus::OBJECT_NAME_NOT_FOUND));
+        assert_eq!(
+            error.value_name().unwrap().as_wide(),
+            &[b'a' as u16, 0, b'b' as u16]
+        );
+        assert_eq!(
+            error.to_string(),
+            "Could not delete value in \\Registry\\Machine\\Software\\Test (value \"a\\0b\"): \
+             STATUS_OBJECT_NAME_NOT_FOUND (0xC0000034): The object name was not found."
+        );
+        assert!(std::error::Error::source(&error).is_none());
+    }
+
+    #[test]
+    fn source_skips_kind() {
+        let io = std::io::Error::other("disk on fire");
+        let error = Error::new(Operation::ExportKey, r"\Registry\User", ErrorKind::Io(io));
+
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(source.to_string(), "disk on fire");
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+    }
 }
@@ -0,0 +1,852 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::{RegPath, RegPathBuf},
+    snapshot::KeySnapshot,
+    source::KeySource,
+    value::{RawValue, RegValueItem},
+    Result,
+};
+use std::{borrow::Cow, convert::TryInto, path::Path};
+
+/// Size of the base block at the start of every hive file
+pub(crate) const BASE_BLOCK_SIZE: usize = 0x1000;
+
+/// Hive bins are allocated in multiples of this size
+pub(crate) const HBIN_ALIGNMENT: usize = 0x1000;
+
+/// Size of the header at the start of every hive bin
+pub(crate) const HBIN_HEADER_SIZE: usize = 0x20;
+
+/// Stored in a cell reference that does not point anywhere
+pub(crate) const NO_CELL: u32 = 0xffff_ffff;
+
+/// Data of values larger than this is split into big data (`db`) segments from hive version 1.4 on
+pub(crate) const BIG_DATA_SEGMENT_SIZE: usize = 16344;
+
+/// Set in the data size of a value whose data (at most four bytes) is stored in the data offset field
+pub(crate) const DATA_INLINE: u32 = 0x8000_0000;
+
+/// Set on the root key of a hive
+pub(crate) const KEY_HIVE_ENTRY: u16 = 0x0004;
+
+/// Set on keys that cannot be deleted, such as the root key
+pub(crate) const KEY_NO_DELETE: u16 = 0x0008;
+
+/// The key name is stored as Latin-1 rather than UTF-16
+pub(crate) const KEY_COMP_NAME: u16 = 0x0020;
+
+/// The value name is stored as Latin-1 rather than UTF-16
+pub(crate) const VALUE_COMP_NAME: u16 = 0x0001;
+
+/// Offset of the fixed part of a key node before its name
+pub(crate) const KEY_NODE_SIZE: usize = 76;
+
+/// Offset of the fixed part of a value key before its name
+pub(crate) const VALUE_KEY_SIZE: usize = 20;
+
+/// The header of a hive file (its base block).
+///
+/// Every offline hive starts with a 4 KiB base block describing the rest of the file. Only the fields that
+/// are in use on current versions of Windows are exposed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BaseBlock {
+    /// Incremented before the hive is written
+    pub primary_sequence: u32,
+    /// Set to the primary sequence number once a write has completed
+    pub secondary_sequence: u32,
+    /// When the hive was last written, as a `FILETIME`
+    pub last_written: u64,
+    /// Major version of the format, always 1
+    pub major_version: u32,
+    /// Minor version of the format, 3 to 6 on current versions of Windows
+    pub minor_version: u32,
+    /// 0 for a primary hive file, 1 or 6 for transaction logs
+    pub file_type: u32,
+    /// 1 for a hive stored directly in memory layout
+    pub file_format: u32,
+    /// Offset of the root key node, relative to the first hive bin
+    pub root_cell: u32,
+    /// Total size of the hive bins following the base block
+    pub hive_bins_size: u32,
+    /// Sector size of the underlying disk in 512 byte units, normally 1
+    pub clustering_factor: u32,
+    /// The end of the file name the hive was last loaded from, for debugging
+    pub file_name: Vec<u16>,
+    /// XOR-32 checksum of the first 508 bytes
+    pub checksum: u32,
+}
+
+impl BaseBlock {
+    /// Parses a base block, returning `None` if `data` does not start with one
+    pub fn parse(data: &[u8]) -> Option<BaseBlock> {
+        if data.get(..4)? != b"regf" || data.len() < 512 {
+            return None;
+        }
+
+        let file_name = data[48..112]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|c| *c != 0)
+            .collect();
+        Some(BaseBlock {
+            primary_sequence: u32_at(data, 4)?,
+            secondary_sequence: u32_at(data, 8)?,
+            last_written: u64_at(data, 12)?,
+            major_version: u32_at(data, 20)?,
+            minor_version: u32_at(data, 24)?,
+            file_type: u32_at(data, 28)?,
+            file_format: u32_at(data, 32)?,
+            root_cell: u32_at(data, 36)?,
+            hive_bins_size: u32_at(data, 40)?,
+            clustering_factor: u32_at(data, 44)?,
+            file_name,
+            checksum: u32_at(data, 508)?,
+        })
+    }
+
+    /// `true` if a write to the hive did not complete, so recent changes may only be in its logs
+    pub fn is_dirty(&self) -> bool {
+        self.primary_sequence != self.secondary_sequence
+    }
+
+    /// Serializes the base block into a full 4 KiB block, computing its checksum
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut block = vec![0; BASE_BLOCK_SIZE];
+        block[..4].copy_from_slice(b"regf");
+        for (offset, field) in [
+            (4, self.primary_sequence),
+            (8, self.secondary_sequence),
+            (20, self.major_version),
+            (24, self.minor_version),
+            (28, self.file_type),
+            (32, self.file_format),
+            (36, self.root_cell),
+            (40, self.hive_bins_size),
+            (44, self.clustering_factor),
+        ]
+        .iter()
+        {
+            block[*offset..offset + 4].copy_from_slice(&field.to_le_bytes());
+        }
+        block[12..20].copy_from_slice(&self.last_written.to_le_bytes());
+
+        let skip = self.file_name.len().saturating_sub(31);
+        for (i, c) in self.file_name[skip..].iter().enumerate() {
+            block[48 + i * 2..50 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+
+        let checksum = checksum(&block);
+        block[508..512].copy_from_slice(&checksum.to_le_bytes());
+        block
+    }
+}
+
+/// The checksum of a base block: the XOR of its first 127 little endian `u32`s, avoiding 0 and `!0`
+pub(crate) fn checksum(block: &[u8]) -> u32 {
+    let sum = block[..508].chunks_exact(4).fold(0, |sum, c| {
+        sum ^ u32::from_le_bytes([c[0], c[1], c[2], c[3]])
+    });
+    match sum {
+        0xffff_ffff => 0xffff_fffe,
+        0 => 1,
+        sum => sum,
+    }
+}
+
+pub(crate) fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Decodes a key or value name stored either as Latin-1 (`compressed`) or UTF-16LE
+pub(crate) fn decode_name(name: &[u8], compressed: bool) -> Vec<u16> {
+    if compressed {
+        name.iter().map(|b| *b as u16).collect()
+    } else {
+        name.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect()
+    }
+}
+
+/// A key node (`nk`) cell
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct KeyNode<'a> {
+    pub flags: u16,
+    pub last_write_time: u64,
+    pub parent: u32,
+    pub subkey_count: u32,
+    pub subkey_list: u32,
+    pub value_count: u32,
+    pub value_list: u32,
+    pub security: u32,
+    pub class: u32,
+    pub class_length: u16,
+    pub name: &'a [u8],
+}
+
+impl<'a> KeyNode<'a> {
+    pub fn parse(cell: &'a [u8]) -> Option<KeyNode<'a>> {
+        if cell.get(..2)? != b"nk" {
+            return None;
+        }
+        let name_length = u16_at(cell, 72)? as usize;
+        Some(KeyNode {
+            flags: u16_at(cell, 2)?,
+            last_write_time: u64_at(cell, 4)?,
+            parent: u32_at(cell, 16)?,
+            subkey_count: u32_at(cell, 20)?,
+            subkey_list: u32_at(cell, 28)?,
+            value_count: u32_at(cell, 36)?,
+            value_list: u32_at(cell, 40)?,
+            security: u32_at(cell, 44)?,
+            class: u32_at(cell, 48)?,
+            class_length: u16_at(cell, 74)?,
+            name: cell.get(KEY_NODE_SIZE..KEY_NODE_SIZE + name_length)?,
+        })
+    }
+
+    pub fn name(&self) -> Vec<u16> {
+        decode_name(self.name, self.flags & KEY_COMP_NAME != 0)
+    }
+}
+
+/// A value key (`vk`) cell
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ValueKey<'a> {
+    pub data_size: u32,
+    pub data_offset: u32,
+    pub data_type: u32,
+    pub flags: u16,
+    pub name: &'a [u8],
+    /// The data offset field, which holds the data itself when it is stored inline
+    pub inline: &'a [u8],
+}
+
+impl<'a> ValueKey<'a> {
+    pub fn parse(cell: &'a [u8]) -> Option<ValueKey<'a>> {
+        if cell.get(..2)? != b"vk" {
+            return None;
+        }
+        let name_length = u16_at(cell, 2)? as usize;
+        Some(ValueKey {
+            data_size: u32_at(cell, 4)?,
+            data_offset: u32_at(cell, 8)?,
+            data_type: u32_at(cell, 12)?,
+            flags: u16_at(cell, 16)?,
+            name: cell.get(VALUE_KEY_SIZE..VALUE_KEY_SIZE + name_length)?,
+            inline: cell.get(8..12)?,
+        })
+    }
+
+    pub fn name(&self) -> Vec<u16> {
+        decode_name(self.name, self.flags & VALUE_COMP_NAME != 0)
+    }
+}
+
+/// Bounds checked access to the cells in the hive bins of a hive.
+///
+/// Cell offsets are relative to the start of the first hive bin, which follows the base block. Every
+/// accessor returns `None` instead of panicking when a structure does not fit in the data.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Cells<'a> {
+    pub bins: &'a [u8],
+    pub minor_version: u32,
+}
+
+impl<'a> Cells<'a> {
+    /// The payload of the cell at `offset`, allocated or free
+    pub fn cell(&self, offset: u32) -> Option<&'a [u8]> {
+        let start = offset as usize;
+        let size = u32_at(self.bins, start)? as i32;
+        let size = size.checked_abs()? as usize;
+        if size < 8 {
+            return None;
+        }
+        self.bins.get(start + 4..start.checked_add(size)?)
+    }
+
+    pub fn key(&self, offset: u32) -> Option<KeyNode<'a>> {
+        KeyNode::parse(self.cell(offset)?)
+    }
+
+    pub fn value(&self, offset: u32) -> Option<ValueKey<'a>> {
+        ValueKey::parse(self.cell(offset)?)
+    }
+
+    /// The key node offsets in the subkey list at `offset`, following index roots (`ri`) one level down
+    pub fn subkeys(&self, offset: u32) -> Option<Vec<u32>> {
+        let mut offsets = Vec::new();
+        self.subkey_list(offset, true, &mut offsets)?;
+        Some(offsets)
+    }
+
+    fn subkey_list(&self, offset: u32, allow_index: bool, offsets: &mut Vec<u32>) -> Option<()> {
+        let cell = self.cell(offset)?;
+        let count = u16_at(cell, 2)? as usize;
+        let (stride, index) = match cell.get(..2)? {
+            b"lf" | b"lh" => (8, false),
+            b"li" => (4, false),
+            b"ri" if allow_index => (4, true),
+            _ => return None,
+        };
+
+        for i in 0..count {
+            let entry = u32_at(cell, 4 + i * stride)?;
+            if index {
+                self.subkey_list(entry, false, offsets)?;
+            } else {
+                offsets.push(entry);
+            }
+        }
+        Some(())
+    }
+
+    /// The value key offsets in the value list at `offset`
+    pub fn values(&self, offset: u32, count: u32) -> Option<Vec<u32>> {
+        let cell = self.cell(offset)?;
+        (0..count as usize).map(|i| u32_at(cell, i * 4)).collect()
+    }
+
+    /// The data of a value, borrowed from the hive unless it has to be reassembled from big data segments
+    pub fn value_data(&self, value: &ValueKey<'a>) -> Option<Cow<'a, [u8]>> {
+        let size = (value.data_size & !DATA_INLINE) as usize;
+        if value.data_size & DATA_INLINE != 0 {
+            return Some(Cow::Borrowed(value.inline.get(..size)?));
+        }
+        if size == 0 {
+            return Some(Cow::Borrowed(&[]));
+        }
+
+        let cell = self.cell(value.data_offset)?;
+        if self.minor_version >= 4 && size > BIG_DATA_SEGMENT_SIZE && cell.get(..2)? == b"db" {
+            let count = u16_at(cell, 2)? as u32;
+            let segments = self
+                .values(u32_at(cell, 4)?, count)?
+                .into_iter()
+                .map(|segment| self.cell(segment))
+                .collect::<Option<Vec<_>>>()?;
+            // the size comes from the hive, so it is only trusted as far as the segments actually hold it
+            let held = segments
+                .iter()
+                .map(|segment| segment.len().min(BIG_DATA_SEGMENT_SIZE))
+                .sum::<usize>();
+            if size > held {
+                return None;
+            }
+            let mut data = Vec::with_capacity(size);
+            for segment in segments {
+                let take = (size - data.len())
+                    .min(BIG_DATA_SEGMENT_SIZE)
+                    .min(segment.len());
+                data.extend_from_slice(&segment[..take]);
+            }
+            return if data.len() == size {
+                Some(Cow::Owned(data))
+            } else {
+                None
+            };
+        }
+        Some(Cow::Borrowed(cell.get(..size)?))
+    }
+
+    /// The cells holding the data of a value: none if it is stored inline, otherwise the data cell and,
+    /// for big data, its segment list and segments
+    pub fn data_cells(&self, value: &ValueKey) -> Option<Vec<u32>> {
+        let size = (value.data_size & !DATA_INLINE) as usize;
+        if value.data_size & DATA_INLINE != 0 || size == 0 {
+            return Some(Vec::new());
+        }
+
+        let cell = self.cell(value.data_offset)?;
+        if self.minor_version >= 4 && size > BIG_DATA_SEGMENT_SIZE && cell.get(..2)? == b"db" {
+            let list = u32_at(cell, 4)?;
+            let mut cells = self.values(list, u16_at(cell, 2)? as u32)?;
+            cells.push(list);
+            cells.push(value.data_offset);
+            return Some(cells);
+        }
+        Some(vec![value.data_offset])
+    }
+
+    /// The class name of a key, as UTF-16LE bytes
+    pub fn class(&self, key: &KeyNode) -> Option<Option<&'a [u8]>> {
+        if key.class == NO_CELL || key.class_length == 0 {
+            return Some(None);
+        }
+        Some(Some(
+            self.cell(key.class)?.get(..key.class_length as usize)?,
+        ))
+    }
+
+    /// The security descriptor held by a security (`sk`) cell
+    pub fn security(&self, offset: u32) -> Option<&'a [u8]> {
+        let cell = self.cell(offset)?;
+        if cell.get(..2)? != b"sk" {
+            return None;
+        }
+        let length = u32_at(cell, 16)? as usize;
+        cell.get(20..20usize.checked_add(length)?)
+    }
+}
+
+/// An offline registry hive, such as a copy of `SOFTWARE` or `NTUSER.DAT`.
+///
+/// The hive is read from any byte buffer; no Windows APIs are involved, so hives can be examined on any
+/// platform. Keys are decoded on demand as the tree is walked.
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::{Hive, KeySource};
+/// let hive = Hive::open("SOFTWARE").unwrap();
+/// let root = hive.root_at(r"\Registry\Machine\SOFTWARE").unwrap();
+/// for name in root.subkey_names().unwrap() {
+///     println!("{}", name);
+/// }
+/// ```
+pub struct Hive<B = Vec<u8>> {
+    data: B,
+    base_block: BaseBlock,
+}
+
+impl Hive<Vec<u8>> {
+    /// Reads the hive file at `path` into memory.
+    ///
+    /// Transaction logs are not replayed; use [`Hive::open_with_logs`] for hives copied from running systems.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Hive> {
+        let data = std::fs::read(path)
+            .map_err(|e| Error::new(Operation::OpenHive, RegPathBuf::new(), ErrorKind::Io(e)))?;
+        Hive::from_bytes(data)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Hive<memmap2::Mmap> {
+    /// Maps the hive file at `path` into memory instead of reading it.
+    ///
+    /// Pages are only read as the keys using them are visited, so walking part of a large hive touches
+    /// little of the file. Combined with [`Hive::root_node`], nothing is copied out of the mapping unless
+    /// asked for.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while the hive is in use, which is undefined behavior for
+    /// any memory mapped file. Map copies of hives rather than hives a running system may write to.
+    pub unsafe fn map<P: AsRef<Path>>(path: P) -> Result<Hive<memmap2::Mmap>> {
+        let io = |e| Error::new(Operation::OpenHive, RegPathBuf::new(), ErrorKind::Io(e));
+        let file = std::fs::File::open(path).map_err(io)?;
+        let map = memmap2::Mmap::map(&file).map_err(io)?;
+        Hive::from_bytes(map)
+    }
+}
+
+impl<B: AsRef<[u8]>> Hive<B> {
+    /// Reads a hive from the contents of a hive file
+    pub fn from_bytes(data: B) -> Result<Hive<B>> {
+        let corrupt = |reason| {
+            Error::new(
+                Operation::OpenHive,
+                RegPathBuf::new(),
+                ErrorKind::Corrupt { offset: 0, reason },
+            )
+        };
+
+        let bytes = data.as_ref();
+        let base_block =
+            BaseBlock::parse(bytes).ok_or_else(|| corrupt("missing regf signature"))?;
+        if base_block.major_version != 1 {
+            return Err(corrupt("unsupported major version"));
+        }
+        if bytes.len() < BASE_BLOCK_SIZE {
+            return Err(corrupt("truncated base block"));
+        }
+        Ok(Hive { data, base_block })
+    }
+
+    /// A hive over data whose base block has already been validated
+    pub(crate) fn from_parts(data: B, base_block: BaseBlock) -> Hive<B> {
+        Hive { data, base_block }
+    }
+
+    /// The header of the hive file
+    pub fn base_block(&self) -> &BaseBlock {
+        &self.base_block
+    }
+
+    /// The raw contents of the hive file
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    /// Returns the underlying buffer
+    pub fn into_inner(self) -> B {
+        self.data
+    }
+
+    /// The root key of the hive, with the path `\`
+    pub fn root(&self) -> Result<HiveKey<'_>> {
+        self.root_at(r"\")
+    }
+
+    /// The root key of the hive as if it were loaded at `path`, such as `\Registry\Machine\SOFTWARE`.
+    ///
+    /// The paths of all keys and values read from the hive are built from `path`, which keeps them usable
+    /// with the exporters and the live registry.
+    pub fn root_at<P: Into<RegPathBuf>>(&self, path: P) -> Result<HiveKey<'_>> {
+        HiveKey::new(self.cells(), self.base_block.root_cell, path.into())
+    }
+
+    pub(crate) fn cells(&self) -> Cells<'_> {
+        let bytes = self.data.as_ref();
+        let end = (BASE_BLOCK_SIZE + self.base_block.hive_bins_size as usize).min(bytes.len());
+        Cells {
+            bins: &bytes[BASE_BLOCK_SIZE..end],
+            minor_version: self.base_block.minor_version,
+        }
+    }
+}
+
+/// A key in an offline [`Hive`]
+#[derive(Clone, Debug)]
+pub struct HiveKey<'a> {
+    pub(crate) cells: Cells<'a>,
+    pub(crate) offset: u32,
+    pub(crate) node: KeyNode<'a>,
+    pub(crate) path: RegPathBuf,
+}
+
+impl<'a> HiveKey<'a> {
+    fn new(cells: Cells<'a>, offset: u32, path: RegPathBuf) -> Result<HiveKey<'a>> {
+        match cells.key(offset) {
+            Some(node) => Ok(HiveKey {
+                cells,
+                offset,
+                node,
+                path,
+            }),
+            None => Err(corrupt(
+                Operation::OpenKey,
+                &path,
+                offset,
+                "invalid key node",
+            )),
+        }
+    }
+
+    /// The name of the key as stored in the hive
+    pub fn name(&self) -> RegPathBuf {
+        RegPathBuf::from(self.node.name())
+    }
+
+    /// The offset of the key node, relative to the first hive bin
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// When the key was last written, as a `FILETIME`
+    pub fn last_write_time(&self) -> u64 {
+        self.node.last_write_time
+    }
+
+    /// The class name of the key, if it has one
+    pub fn class(&self) -> Result<Option<Vec<u16>>> {
+        self.cells
+            .class(&self.node)
+            .map(|class| class.map(|class| decode_name(class, false)))
+            .ok_or_else(|| self.corrupt(Operation::OpenKey, self.node.class, "invalid class name"))
+    }
+
+    /// The self-relative security descriptor of the key
+    pub fn security(&self) -> Result<Vec<u8>> {
+        self.cells
+            .security(self.node.security)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| {
+                self.corrupt(
+                    Operation::OpenKey,
+                    self.node.security,
+                    "invalid security cell",
+                )
+            })
+    }
+
+    /// The direct subkeys of the key, in the order the hive stores them
+    pub fn subkeys(&self) -> Result<Vec<HiveKey<'a>>> {
+        if self.node.subkey_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let offsets = self.cells.subkeys(self.node.subkey_list).ok_or_else(|| {
+            self.corrupt(
+                Operation::EnumerateKeys,
+                self.node.subkey_list,
+                "invalid subkey list",
+            )
+        })?;
+        offsets
+            .into_iter()
+            .map(|offset| {
+                let node = self.cells.key(offset).ok_or_else(|| {
+                    self.corrupt(Operation::EnumerateKeys, offset, "invalid key node")
+                })?;
+                Ok(HiveKey {
+                    cells: self.cells,
+                    offset,
+                    node,
+                    path: self.path.join(RegPath::from_wide(&node.name())),
+                })
+            })
+            .collect()
+    }
+
+    /// Copies the key and everything below it into a [`KeySnapshot`]
+    pub fn to_snapshot(&self) -> Result<KeySnapshot> {
+        self.snapshot_below(&mut Vec::new())
+    }
+
+    /// `ancestors` holds the key cells on the path down to this key, so a subkey list pointing back up
+    /// the tree is reported instead of being followed forever
+    fn snapshot_below(&self, ancestors: &mut Vec<u32>) -> Result<KeySnapshot> {
+        if ancestors.contains(&self.offset) {
+            return Err(self.corrupt(Operation::EnumerateKeys, self.offset, "subkey cycle"));
+        }
+        ancestors.push(self.offset);
+        let children = self
+            .subkeys()?
+            .iter()
+            .map(|subkey| subkey.snapshot_below(ancestors))
+            .collect::<Result<_>>()?;
+        ancestors.pop();
+
+        Ok(KeySnapshot {
+            path: self.path.clone(),
+            last_write_time: self.last_write_time(),
+            class: self.class()?,
+            security: Some(self.security()?),
+            values: self
+                .values()?
+                .into_iter()
+                .map(|value| (value.value_name().clone(), value.raw().clone()))
+                .collect(),
+            children,
+        })
+    }
+
+    fn corrupt(&self, operation: Operation, offset: u32, reason: &'static str) -> Error {
+        corrupt(operation, &self.path, offset, reason)
+    }
+}
+
+pub(crate) fn corrupt(
+    operation: Operation,
+    path: &RegPath,
+    offset: u32,
+    reason: &'static str,
+) -> Error {
+    Error::new(
+        operation,
+        path,
+        ErrorKind::Corrupt {
+            offset: BASE_BLOCK_SIZE as u64 + offset as u64,
+            reason,
+        },
+    )
+}
+
+impl<'a> KeySource for HiveKey<'a> {
+    fn path(&self) -> &RegPath {
+        &self.path
+    }
+
+    fn values(&self) -> Result<Vec<RegValueItem>> {
+        if self.node.value_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let offsets = self
+            .cells
+            .values(self.node.value_list, self.node.value_count)
+            .ok_or_else(|| {
+                self.corrupt(
+                    Operation::EnumerateValues,
+                    self.node.value_list,
+                    "invalid value list",
+                )
+            })?;
+        offsets
+            .into_iter()
+            .map(|offset| {
+                let value = self.cells.value(offset).ok_or_else(|| {
+                    self.corrupt(Operation::EnumerateValues, offset, "invalid value key")
+                })?;
+                let data = self.cells.value_data(&value).ok_or_else(|| {
+                    self.corrupt(Operation::EnumerateValues, offset, "invalid value data")
+                        .with_value_name(value.name())
+                })?;
+                Ok(RegValueItem::new(
+                    &self.path,
+                    value.name(),
+                    RawValue::new(value.data_type, data.into_owned()),
+                ))
+            })
+            .collect()
+    }
+
+    fn subkey_names(&self) -> Result<Vec<RegPathBuf>> {
+        Ok(self.subkeys()?.iter().map(HiveKey::name).collect())
+    }
+
+    fn last_write_time(&self) -> Result<u64> {
+        Ok(HiveKey::last_write_time(self))
+    }
+
+    fn open_child(&self, name: &RegPath) -> Result<HiveKey<'a>> {
+        self.subkeys()?
+            .into_iter()
+            .find(|key| key.path.name() == Some(name))
+            .ok_or_else(|| {
+                Error::new(
+                    Operation::OpenKey,
+                    self.path.join(name),
+                    ErrorKind::NotFound,
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{damaged, hive, truncated};
+
+    fn walk(key: &HiveKey) -> usize {
+        let _ = key.values();
+        let _ = key.class();
+        let _ = key.security();
+        match key.subkeys() {
+            Ok(subkeys) => 1 + subkeys.iter().map(walk).sum::<usize>(),
+            Err(_) => 1,
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        hive(|root| {
+            for i in 0..20 {
+                let child = root.child_mut(format!("Key{}", i));
+                child.class = Some(vec![b'c' as u16; i]);
+                child
+                    .values
+                    .push(("Value".into(), RawValue::new(3, vec![i as u8; i * 3])));
+            }
+        })
+    }
+
+    #[test]
+    fn base_block() {
+        let bytes = sample();
+        let base_block = BaseBlock::parse(&bytes).unwrap();
+        assert_eq!(base_block.to_bytes(), &bytes[..BASE_BLOCK_SIZE]);
+        assert_eq!(checksum(&bytes), base_block.checksum);
+
+        assert!(Hive::from_bytes(&b"not a hive"[..]).is_err());
+        let mut version_2 = bytes.clone();
+        version_2[20] = 2;
+        assert!(Hive::from_bytes(version_2).is_err());
+    }
+
+    #[test]
+    fn damaged_input_does_not_panic() {
+        let bytes = sample();
+        truncated(&bytes, BASE_BLOCK_SIZE, 97, |bytes| {
+            if let Ok(hive) = Hive::from_bytes(bytes) {
+                if let Ok(root) = hive.root() {
+                    walk(&root);
+                }
+            }
+        });
+        damaged(&bytes, BASE_BLOCK_SIZE, 200, 16, |bytes| {
+            let hive = Hive::from_bytes(bytes).unwrap();
+            if let Ok(root) = hive.root() {
+                walk(&root);
+            }
+        });
+
+        let mut cyclic = bytes.clone();
+        let root = BaseBlock::parse(&bytes).unwrap().root_cell;
+        let list = Hive::from_bytes(&bytes[..])
+            .unwrap()
+            .root()
+            .unwrap()
+            .node
+            .subkey_list;
+        let entry = BASE_BLOCK_SIZE + list as usize + 8;
+        cyclic[entry..entry + 4].copy_from_slice(&root.to_le_bytes());
+        let hive = Hive::from_bytes(cyclic).unwrap();
+        let error = hive.root().unwrap().to_snapshot().unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::Corrupt {
+                reason: "subkey cycle",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn corrupt_errors() {
+        let mut bytes = sample();
+        let root = BaseBlock::parse(&bytes).unwrap().root_cell as usize;
+        bytes[BASE_BLOCK_SIZE + root + 4] = b'x';
+        let error = Hive::from_bytes(bytes).unwrap().root().unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::Corrupt { offset, .. } if *offset == (BASE_BLOCK_SIZE + root) as u64
+        ));
+    }
+
+    #[test]
+    fn oversized_big_data() {
+        let mut bytes = hive(|root| {
+            root.values.push((
+                "Big".into(),
+                RawValue::new(3, vec![7; BIG_DATA_SEGMENT_SIZE * 2]),
+            ))
+        });
+
+        let hive = Hive::from_bytes(&bytes[..]).unwrap();
+        let node = hive.root().unwrap().node;
+        let value = hive.cells().values(node.value_list, 1).unwrap()[0] as usize;
+        assert_eq!(
+            hive.root().unwrap().values().unwrap()[0].raw().data.len(),
+            BIG_DATA_SEGMENT_SIZE * 2
+        );
+
+        // two segments cannot hold 2 GiB, so the size is not believed
+        let size = BASE_BLOCK_SIZE + value + 8;
+        bytes[size..size + 4].copy_from_slice(&0x7fff_0000u32.to_le_bytes());
+        let error = Hive::from_bytes(bytes)
+            .unwrap()
+            .root()
+            .unwrap()
+            .values()
+            .unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::Corrupt {
+                reason: "invalid value data",
+                ..
+            }
+        ));
+    }
+}
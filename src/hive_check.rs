@@ -0,0 +1,975 @@
+use crate::{
+    hive::{
+        checksum, u16_at, u32_at, Cells, Hive, KeyNode, ValueKey, BASE_BLOCK_SIZE, HBIN_ALIGNMENT,
+        HBIN_HEADER_SIZE, KEY_HIVE_ENTRY, NO_CELL,
+    },
+    hive_writer::name_hash,
+    path::{RegPath, RegPathBuf},
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+/// Something wrong with the structure of a hive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// The base block checksum does not match its contents
+    Checksum {
+        /// The checksum in the base block
+        stored: u32,
+        /// The checksum of the base block as it is
+        computed: u32,
+    },
+    /// The primary and secondary sequence numbers differ, so the last write did not complete
+    Dirty {
+        /// The primary sequence number
+        primary: u32,
+        /// The secondary sequence number
+        secondary: u32,
+    },
+    /// A field of the base block holds a value no hive uses
+    BaseBlock(&'static str),
+    /// The hive bins do not match the size the base block gives them
+    HiveBinsSize {
+        /// The size in the base block
+        stated: u32,
+        /// The size of the data following the base block
+        actual: u64,
+    },
+    /// A hive bin header has a bad signature, offset or size
+    Bin(&'static str),
+    /// A cell size is zero, not a multiple of 8 or runs past the end of its bin
+    CellSize(i32),
+    /// A reference does not point to the start of an allocated cell of the right kind
+    Reference {
+        /// What the reference should point to
+        target: &'static str,
+        /// Where it points, relative to the first hive bin
+        offset: u32,
+    },
+    /// A list claims more entries than its cell can hold
+    ListBounds {
+        /// The kind of list
+        list: &'static str,
+        /// The number of entries claimed
+        count: u32,
+        /// The number of entries the cell can hold
+        capacity: u32,
+    },
+    /// The subkey count of a key node does not match its subkey list
+    SubkeyCount {
+        /// The count in the key node
+        stated: u32,
+        /// The number of entries in the subkey list
+        actual: u32,
+    },
+    /// A subkey list is not in ascending order of names
+    SubkeyOrder,
+    /// A subkey list entry holds the wrong name hash or hint
+    SubkeyHash {
+        /// The hash or hint in the list
+        stated: u32,
+        /// The hash or hint of the name of the subkey
+        computed: u32,
+    },
+    /// The parent field of a key node does not point to the key listing it
+    Parent {
+        /// The parent in the key node
+        stated: u32,
+        /// The key whose subkey list holds the key node
+        actual: u32,
+    },
+    /// A key node is reachable more than once
+    Cycle,
+    /// The reference count of a security cell does not match the number of keys using it
+    SecurityReferences {
+        /// The count in the security cell
+        stated: u32,
+        /// The number of keys using it
+        actual: u32,
+    },
+    /// The links between security cells do not form a single circular list
+    SecurityList(&'static str),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Checksum { stored, computed } => write!(
+                fmt,
+                "base block checksum is {:#010x}, expected {:#010x}",
+                stored, computed
+            ),
+            Problem::Dirty { primary, secondary } => write!(
+                fmt,
+                "sequence numbers {} and {} differ, the hive is dirty",
+                primary, secondary
+            ),
+            Problem::BaseBlock(reason) => write!(fmt, "{}", reason),
+            Problem::HiveBinsSize { stated, actual } => write!(
+                fmt,
+                "hive bins size is {:#x} but {:#x} bytes follow the base block",
+                stated, actual
+            ),
+            Problem::Bin(reason) => write!(fmt, "{}", reason),
+            Problem::CellSize(size) => write!(fmt, "invalid cell size {}", size),
+            Problem::Reference { target, offset } => {
+                write!(fmt, "reference to {:#x} is not a valid {}", offset, target)
+            }
+            Problem::ListBounds {
+                list,
+                count,
+                capacity,
+            } => write!(
+                fmt,
+                "{} claims {} entries but its cell holds {}",
+                list, count, capacity
+            ),
+            Problem::SubkeyCount { stated, actual } => write!(
+                fmt,
+                "key node has {} subkeys but its subkey list has {}",
+                stated, actual
+            ),
+            Problem::SubkeyOrder => write!(fmt, "subkey list is not sorted by name"),
+            Problem::SubkeyHash { stated, computed } => write!(
+                fmt,
+                "subkey list entry has hash {:#010x}, expected {:#010x}",
+                stated, computed
+            ),
+            Problem::Parent { stated, actual } => write!(
+                fmt,
+                "parent of key node is {:#x} but it is listed by {:#x}",
+                stated, actual
+            ),
+            Problem::Cycle => write!(fmt, "key node is reachable more than once"),
+            Problem::SecurityReferences { stated, actual } => write!(
+                fmt,
+                "security cell has {} references but is used by {} keys",
+                stated, actual
+            ),
+            Problem::SecurityList(reason) => write!(fmt, "{}", reason),
+        }
+    }
+}
+
+/// A problem and where it was found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    /// File offset of the structure with the problem
+    pub offset: u64,
+    /// Path of the key the structure belongs to, if it belongs to one
+    pub path: Option<RegPathBuf>,
+    /// What is wrong
+    pub problem: Problem,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:#010x}", self.offset)?;
+        if let Some(path) = &self.path {
+            write!(fmt, " {}", path)?;
+        }
+        write!(fmt, ": {}", self.problem)
+    }
+}
+
+/// Everything wrong with a hive, in the order it was found.
+///
+/// Displaying the report gives one line per issue.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// One entry per problem
+    pub issues: Vec<Issue>,
+}
+
+impl CheckReport {
+    /// `true` if nothing is wrong with the hive
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(fmt, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl<B: AsRef<[u8]>> Hive<B> {
+    /// Checks the structure of the hive, reporting every problem with its offset.
+    ///
+    /// The base block checksum, sequence numbers and sizes are checked, every hive bin and cell is walked,
+    /// and the key tree is followed from the root, checking that references point to allocated cells of
+    /// the right kind, that subkey lists are sorted with correct hashes and counts, that value lists fit
+    /// in their cells, and that security cells form a single circular list with correct reference counts.
+    /// Problems are collected rather than returned as errors, so a damaged hive is checked as far as
+    /// possible.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::Hive;
+    /// let hive = Hive::open("SYSTEM").unwrap();
+    /// let report = hive.check();
+    /// if !report.is_clean() {
+    ///     print!("{}", report);
+    /// }
+    /// ```
+    pub fn check(&self) -> CheckReport {
+        let mut checker = Checker {
+            cells: self.cells(),
+            allocated: HashSet::new(),
+            visited: HashSet::new(),
+            security: BTreeMap::new(),
+            report: CheckReport::default(),
+        };
+        checker.base_block(self.as_bytes(), self.base_block().hive_bins_size);
+        checker.bins();
+        checker.keys(self.base_block().root_cell);
+        checker.security_cells();
+        checker.report
+    }
+}
+
+/// A subkey list entry: its file offset, the key node it refers to and, for `lf` and `lh` lists, its hint
+/// or hash and whether it is a hash
+type ListEntry = (u64, u32, Option<(u32, bool)>);
+
+struct Checker<'a> {
+    cells: Cells<'a>,
+    /// Offsets of the allocated cells
+    allocated: HashSet<u32>,
+    /// Key nodes already checked
+    visited: HashSet<u32>,
+    /// Security cells with the number of keys using each
+    security: BTreeMap<u32, u32>,
+    report: CheckReport,
+}
+
+impl<'a> Checker<'a> {
+    fn issue(&mut self, offset: u64, path: Option<&RegPath>, problem: Problem) {
+        self.report.issues.push(Issue {
+            offset,
+            path: path.map(RegPath::to_reg_path_buf),
+            problem,
+        });
+    }
+
+    /// Records an issue at a cell, given relative to the first hive bin
+    fn cell_issue(&mut self, cell: u32, path: Option<&RegPath>, problem: Problem) {
+        self.issue(BASE_BLOCK_SIZE as u64 + cell as u64, path, problem);
+    }
+
+    fn base_block(&mut self, data: &[u8], hive_bins_size: u32) {
+        let stored = u32_at(data, 508).unwrap_or(0);
+        let computed = checksum(data);
+        if stored != computed {
+            self.issue(508, None, Problem::Checksum { stored, computed });
+        }
+
+        let field = |offset| u32_at(data, offset).unwrap_or(0);
+        let (primary, secondary) = (field(4), field(8));
+        if primary != secondary {
+            self.issue(4, None, Problem::Dirty { primary, secondary });
+        }
+        if !(3..=6).contains(&field(24)) {
+            self.issue(24, None, Problem::BaseBlock("unsupported minor version"));
+        }
+        if field(28) != 0 {
+            self.issue(
+                28,
+                None,
+                Problem::BaseBlock("file type is not a primary hive"),
+            );
+        }
+        if field(32) != 1 {
+            self.issue(32, None, Problem::BaseBlock("unsupported file format"));
+        }
+
+        let actual = data.len().saturating_sub(BASE_BLOCK_SIZE) as u64;
+        if hive_bins_size == 0
+            || !(hive_bins_size as usize).is_multiple_of(HBIN_ALIGNMENT)
+            || (hive_bins_size as u64) > actual
+        {
+            self.issue(
+                40,
+                None,
+                Problem::HiveBinsSize {
+                    stated: hive_bins_size,
+                    actual,
+                },
+            );
+        }
+    }
+
+    /// Walks every hive bin and cell, remembering where the allocated cells are
+    fn bins(&mut self) {
+        let bins = self.cells.bins;
+        let mut bin = 0usize;
+        while bin + HBIN_HEADER_SIZE <= bins.len() {
+            let offset = bin as u32;
+            if &bins[bin..bin + 4] != b"hbin" {
+                self.cell_issue(offset, None, Problem::Bin("invalid hive bin signature"));
+                bin += HBIN_ALIGNMENT;
+                continue;
+            }
+            if u32_at(bins, bin + 4) != Some(offset) {
+                self.cell_issue(
+                    offset,
+                    None,
+                    Problem::Bin("hive bin offset does not match its position"),
+                );
+            }
+            let size = u32_at(bins, bin + 8).unwrap_or(0) as usize;
+            if size < HBIN_ALIGNMENT
+                || !size.is_multiple_of(HBIN_ALIGNMENT)
+                || size > bins.len() - bin
+            {
+                self.cell_issue(offset, None, Problem::Bin("invalid hive bin size"));
+                bin += HBIN_ALIGNMENT;
+                continue;
+            }
+
+            let end = bin + size;
+            let mut cell = bin + HBIN_HEADER_SIZE;
+            while cell < end {
+                let raw = u32_at(bins, cell).unwrap_or(0) as i32;
+                let length = raw.unsigned_abs() as usize;
+                if length < 8 || !length.is_multiple_of(8) || length > end - cell {
+                    self.cell_issue(cell as u32, None, Problem::CellSize(raw));
+                    break;
+                }
+                if raw < 0 {
+                    self.allocated.insert(cell as u32);
+                }
+                cell += length;
+            }
+            bin = end;
+        }
+    }
+
+    /// The payload of the allocated cell at `target`, if it starts with `signature`
+    fn reference(
+        &mut self,
+        from: u64,
+        path: Option<&RegPath>,
+        target: &'static str,
+        offset: u32,
+        signature: Option<&[u8]>,
+    ) -> Option<&'a [u8]> {
+        let cell = self
+            .cells
+            .cell(offset)
+            .filter(|_| self.allocated.contains(&offset))
+            .filter(|cell| signature.is_none_or(|signature| cell.get(..2) == Some(signature)));
+        if cell.is_none() {
+            self.issue(from, path, Problem::Reference { target, offset });
+        }
+        cell
+    }
+
+    /// Follows the key tree from the root
+    fn keys(&mut self, root: u32) {
+        let mut pending = vec![(root, None, RegPathBuf::from(r"\"), 36)];
+        while let Some((offset, parent, path, from)) = pending.pop() {
+            let node = match self
+                .reference(from, Some(&path), "key node", offset, Some(b"nk"))
+                .and_then(KeyNode::parse)
+            {
+                Some(node) => node,
+                None => continue,
+            };
+            if !self.visited.insert(offset) {
+                self.cell_issue(offset, Some(&path), Problem::Cycle);
+                continue;
+            }
+            pending.extend(self.key(offset, &node, parent, &path));
+        }
+    }
+
+    /// Checks a key node, returning its subkeys with the file offsets of the references to them
+    fn key(
+        &mut self,
+        offset: u32,
+        node: &KeyNode<'a>,
+        parent: Option<u32>,
+        path: &RegPath,
+    ) -> Vec<(u32, Option<u32>, RegPathBuf, u64)> {
+        let at = BASE_BLOCK_SIZE as u64 + offset as u64 + 4;
+        match parent {
+            Some(parent) if node.parent != parent => self.cell_issue(
+                offset,
+                Some(path),
+                Problem::Parent {
+                    stated: node.parent,
+                    actual: parent,
+                },
+            ),
+            None if node.flags & KEY_HIVE_ENTRY == 0 => self.cell_issue(
+                offset,
+                Some(path),
+                Problem::BaseBlock("root key node is not marked as the hive entry"),
+            ),
+            _ => {}
+        }
+
+        if self
+            .reference(
+                at + 44,
+                Some(path),
+                "security cell",
+                node.security,
+                Some(b"sk"),
+            )
+            .is_some()
+        {
+            *self.security.entry(node.security).or_insert(0) += 1;
+        }
+        if node.class_length > 0 {
+            if let Some(class) = self.reference(at + 48, Some(path), "class name", node.class, None)
+            {
+                if class.len() < node.class_length as usize {
+                    self.cell_issue(
+                        node.class,
+                        Some(path),
+                        Problem::ListBounds {
+                            list: "class name",
+                            count: node.class_length as u32,
+                            capacity: class.len() as u32,
+                        },
+                    );
+                }
+            }
+        }
+        if node.value_count > 0 {
+            self.values(at + 40, node, path);
+        }
+        if node.subkey_count == 0 && node.subkey_list == NO_CELL {
+            return Vec::new();
+        }
+
+        let mut entries = Vec::new();
+        if let Some(list) =
+            self.reference(at + 28, Some(path), "subkey list", node.subkey_list, None)
+        {
+            self.subkey_list(node.subkey_list, list, true, path, &mut entries);
+        }
+        if entries.len() as u32 != node.subkey_count {
+            self.cell_issue(
+                offset,
+                Some(path),
+                Problem::SubkeyCount {
+                    stated: node.subkey_count,
+                    actual: entries.len() as u32,
+                },
+            );
+        }
+
+        let mut subkeys = Vec::with_capacity(entries.len());
+        let mut previous: Option<Vec<u16>> = None;
+        let mut sorted = true;
+        for (from, child, hint) in entries {
+            let name = match self.cells.key(child) {
+                Some(child) => child.name(),
+                None => {
+                    subkeys.push((child, Some(offset), path.to_reg_path_buf(), from));
+                    continue;
+                }
+            };
+            if let Some((stated, lh)) = hint {
+                let computed = if lh {
+                    name_hash(&name)
+                } else {
+                    let mut hint = [0; 4];
+                    for (slot, c) in hint.iter_mut().zip(name.iter()) {
+                        *slot = if *c <= 0xff { *c as u8 } else { 0 };
+                    }
+                    u32::from_le_bytes(hint)
+                };
+                if stated != computed {
+                    self.issue(
+                        from + 4,
+                        Some(path),
+                        Problem::SubkeyHash { stated, computed },
+                    );
+                }
+            }
+            if let Some(previous) = &previous {
+                sorted &= RegPath::from_wide(previous) < RegPath::from_wide(&name);
+            }
+            subkeys.push((
+                child,
+                Some(offset),
+                path.join(RegPath::from_wide(&name)),
+                from,
+            ));
+            previous = Some(name);
+        }
+        if !sorted {
+            self.cell_issue(node.subkey_list, Some(path), Problem::SubkeyOrder);
+        }
+
+        // Popped from the end, so reverse to check the subkeys in list order
+        subkeys.reverse();
+        subkeys
+    }
+
+    /// Collects the entries of a subkey list, following index roots (`ri`) one level down
+    fn subkey_list(
+        &mut self,
+        offset: u32,
+        list: &'a [u8],
+        allow_index: bool,
+        path: &RegPath,
+        entries: &mut Vec<ListEntry>,
+    ) {
+        let (stride, kind) = match list.get(..2) {
+            Some(b"lf") => (8, Some(false)),
+            Some(b"lh") => (8, Some(true)),
+            Some(b"li") => (4, None),
+            Some(b"ri") if allow_index => (4, None),
+            _ => {
+                self.cell_issue(
+                    offset,
+                    Some(path),
+                    Problem::Reference {
+                        target: "subkey list",
+                        offset,
+                    },
+                );
+                return;
+            }
+        };
+
+        let count = u16_at(list, 2).unwrap_or(0) as u32;
+        let capacity = (list.len().saturating_sub(4) / stride) as u32;
+        if count > capacity {
+            self.cell_issue(
+                offset,
+                Some(path),
+                Problem::ListBounds {
+                    list: "subkey list",
+                    count,
+                    capacity,
+                },
+            );
+        }
+
+        let index = list.get(..2) == Some(b"ri");
+        for i in 0..count.min(capacity) as usize {
+            let position = 4 + i * stride;
+            let from = BASE_BLOCK_SIZE as u64 + offset as u64 + 4 + position as u64;
+            let target = u32_at(list, position).unwrap_or(NO_CELL);
+            if index {
+                if let Some(leaf) = self.reference(from, Some(path), "subkey list", target, None) {
+                    self.subkey_list(target, leaf, false, path, entries);
+                }
+            } else {
+                let hint = kind.map(|lh| (u32_at(list, position + 4).unwrap_or(0), lh));
+                entries.push((from, target, hint));
+            }
+        }
+    }
+
+    fn values(&mut self, from: u64, node: &KeyNode, path: &RegPath) {
+        let list = match self.reference(from, Some(path), "value list", node.value_list, None) {
+            Some(list) => list,
+            None => return,
+        };
+        let capacity = (list.len() / 4) as u32;
+        if node.value_count > capacity {
+            self.cell_issue(
+                node.value_list,
+                Some(path),
+                Problem::ListBounds {
+                    list: "value list",
+                    count: node.value_count,
+                    capacity,
+                },
+            );
+        }
+
+        for i in 0..node.value_count.min(capacity) as usize {
+            let entry = BASE_BLOCK_SIZE as u64 + node.value_list as u64 + 4 + i as u64 * 4;
+            let offset = u32_at(list, i * 4).unwrap_or(NO_CELL);
+            let value = match self
+                .reference(entry, Some(path), "value key", offset, Some(b"vk"))
+                .and_then(ValueKey::parse)
+            {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let data = self.cells.data_cells(&value);
+            let valid = data
+                .as_ref()
+                .is_some_and(|cells| cells.iter().all(|cell| self.allocated.contains(cell)))
+                && self.cells.value_data(&value).is_some();
+            if !valid {
+                self.cell_issue(
+                    offset,
+                    Some(path),
+                    Problem::Reference {
+                        target: "value data",
+                        offset: value.data_offset,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Checks the reference counts of the security cells in use and that they form one circular list
+    fn security_cells(&mut self) {
+        let used = std::mem::take(&mut self.security);
+        for (offset, actual) in &used {
+            let stated = self
+                .cells
+                .cell(*offset)
+                .and_then(|cell| u32_at(cell, 12))
+                .unwrap_or(0);
+            if stated != *actual {
+                self.cell_issue(
+                    *offset,
+                    None,
+                    Problem::SecurityReferences {
+                        stated,
+                        actual: *actual,
+                    },
+                );
+            }
+        }
+
+        let start = match used.keys().next() {
+            Some(start) => *start,
+            None => return,
+        };
+        let mut listed = HashSet::new();
+        listed.insert(start);
+        let mut current = start;
+        loop {
+            let next = self
+                .cells
+                .cell(current)
+                .and_then(|cell| u32_at(cell, 4))
+                .unwrap_or(NO_CELL);
+            let from = BASE_BLOCK_SIZE as u64 + current as u64 + 8;
+            let cell = match self.reference(from, None, "security cell", next, Some(b"sk")) {
+                Some(cell) => cell,
+                None => return,
+            };
+            if u32_at(cell, 8) != Some(current) {
+                self.cell_issue(
+                    next,
+                    None,
+                    Problem::SecurityList("backward link does not point to the previous cell"),
+                );
+            }
+            if next == start {
+                break;
+            }
+            if !listed.insert(next) {
+                self.cell_issue(
+                    next,
+                    None,
+                    Problem::SecurityList("forward links loop without returning to the start"),
+                );
+                return;
+            }
+            current = next;
+        }
+
+        for offset in used.keys().filter(|offset| !listed.contains(offset)) {
+            self.cell_issue(
+                *offset,
+                None,
+                Problem::SecurityList("security cell is not in the list"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hive_editor::HiveEditor,
+        test_support::{damaged, hive_with},
+        value::RawValue,
+    };
+
+    fn sample(minor_version: u32) -> Vec<u8> {
+        hive_with(minor_version, |root| {
+            for name in &["Alpha", "Beta", "Gamma"] {
+                root.child_mut(*name)
+                    .values
+                    .push(("Value".into(), RawValue::new(4, vec![1, 0, 0, 0])));
+            }
+            root.child_mut("Beta")
+                .values
+                .push(("Large".into(), RawValue::new(3, vec![0x5a; 20_000])));
+            for i in 0..1100 {
+                root.child_mut(format!(r"Many\Key{:04}", i));
+            }
+        })
+    }
+
+    fn get(data: &[u8], offset: usize) -> u32 {
+        u32_at(data, offset).unwrap()
+    }
+
+    fn set(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        let sum = checksum(data);
+        data[508..512].copy_from_slice(&sum.to_le_bytes());
+    }
+
+    /// File offset of the payload of the cell at `cell`
+    fn payload(cell: u32) -> usize {
+        BASE_BLOCK_SIZE + cell as usize + 4
+    }
+
+    fn root_cell(data: &[u8]) -> u32 {
+        get(data, 36)
+    }
+
+    fn check(data: Vec<u8>) -> CheckReport {
+        Hive::from_bytes(data).unwrap().check()
+    }
+
+    fn problems(report: &CheckReport) -> Vec<(u64, Problem)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.offset, issue.problem.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn clean_hives() {
+        for minor_version in 3..=6 {
+            let report = check(sample(minor_version));
+            assert!(report.is_clean(), "{}", report);
+        }
+
+        let mut editor = HiveEditor::from_bytes(sample(5)).unwrap();
+        editor.delete_key("Beta").unwrap();
+        editor.delete_key(r"Many\Key0500").unwrap();
+        editor.create_key(r"Delta\Epsilon").unwrap();
+        editor
+            .set_value("Alpha", "Other", &RawValue::new(1, vec![0x41, 0, 0, 0]))
+            .unwrap();
+        let report = check(editor.into_bytes());
+        assert!(report.is_clean(), "{}", report);
+    }
+
+    #[test]
+    fn base_block() {
+        let mut data = sample(5);
+        data[100] ^= 1;
+        let stored = get(&data, 508);
+        let computed = checksum(&data);
+        assert_eq!(
+            problems(&check(data)),
+            vec![(508, Problem::Checksum { stored, computed })]
+        );
+
+        let mut data = sample(5);
+        set(&mut data, 4, 7);
+        let report = check(data);
+        assert_eq!(
+            problems(&report),
+            vec![(
+                4,
+                Problem::Dirty {
+                    primary: 7,
+                    secondary: 1
+                }
+            )]
+        );
+        assert_eq!(
+            report.to_string(),
+            "0x00000004: sequence numbers 7 and 1 differ, the hive is dirty\n"
+        );
+    }
+
+    #[test]
+    fn bins_and_cells() {
+        let mut data = sample(5);
+        data[BASE_BLOCK_SIZE] = b'x';
+        let report = check(data);
+        assert_eq!(
+            report.issues[0],
+            Issue {
+                offset: BASE_BLOCK_SIZE as u64,
+                path: None,
+                problem: Problem::Bin("invalid hive bin signature"),
+            }
+        );
+        // The cells of the damaged bin are no longer known, so references into it are reported too
+        assert!(report.issues.len() > 1);
+
+        let mut data = sample(5);
+        let root = root_cell(&data);
+        set(&mut data, payload(root) - 4, -12i32 as u32);
+        let report = check(data);
+        assert_eq!(
+            report.issues[0],
+            Issue {
+                offset: (BASE_BLOCK_SIZE + root as usize) as u64,
+                path: None,
+                problem: Problem::CellSize(-12),
+            }
+        );
+    }
+
+    #[test]
+    fn subkey_lists() {
+        let data = sample(5);
+        let root = root_cell(&data);
+        let list = get(&data, payload(root) + 28);
+        let entries = payload(list) + 4;
+
+        let mut swapped = data.clone();
+        let (first, second) = (get(&data, entries), get(&data, entries + 8));
+        let (hash1, hash2) = (get(&data, entries + 4), get(&data, entries + 12));
+        set(&mut swapped, entries, second);
+        set(&mut swapped, entries + 4, hash2);
+        set(&mut swapped, entries + 8, first);
+        set(&mut swapped, entries + 12, hash1);
+        let report = check(swapped);
+        assert_eq!(
+            problems(&report),
+            vec![(
+                (BASE_BLOCK_SIZE + list as usize) as u64,
+                Problem::SubkeyOrder
+            )]
+        );
+        assert_eq!(report.issues[0].path.as_ref().unwrap().to_string(), r"\");
+
+        let mut hashed = data.clone();
+        set(&mut hashed, entries + 4, hash1 ^ 1);
+        assert_eq!(
+            problems(&check(hashed)),
+            vec![(
+                (entries + 4) as u64,
+                Problem::SubkeyHash {
+                    stated: hash1 ^ 1,
+                    computed: hash1
+                }
+            )]
+        );
+
+        let mut counted = data.clone();
+        set(&mut counted, payload(root) + 20, 5);
+        assert_eq!(
+            problems(&check(counted)),
+            vec![(
+                (BASE_BLOCK_SIZE + root as usize) as u64,
+                Problem::SubkeyCount {
+                    stated: 5,
+                    actual: 4
+                }
+            )]
+        );
+
+        let mut parent = data.clone();
+        let beta = get(&data, entries + 8);
+        set(&mut parent, payload(beta) + 16, first);
+        let report = check(parent);
+        assert_eq!(
+            problems(&report),
+            vec![(
+                (BASE_BLOCK_SIZE + beta as usize) as u64,
+                Problem::Parent {
+                    stated: first,
+                    actual: root
+                }
+            )]
+        );
+        assert_eq!(
+            report.issues[0].path.as_ref().unwrap().to_string(),
+            r"\Beta"
+        );
+
+        let mut dangling = data;
+        set(&mut dangling, entries + 8, 0x10);
+        let report = check(dangling);
+        assert_eq!(
+            problems(&report)[0],
+            (
+                (entries + 8) as u64,
+                Problem::Reference {
+                    target: "key node",
+                    offset: 0x10
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn value_lists() {
+        let mut data = sample(5);
+        let root = root_cell(&data);
+        let alpha = get(&data, payload(get(&data, payload(root) + 28)) + 4);
+        let list = get(&data, payload(alpha) + 40);
+        set(&mut data, payload(alpha) + 36, 40);
+        let report = check(data);
+        assert_eq!(
+            report.issues[0].problem,
+            Problem::ListBounds {
+                list: "value list",
+                count: 40,
+                capacity: 1
+            }
+        );
+        assert_eq!(
+            report.issues[0].offset,
+            (BASE_BLOCK_SIZE + list as usize) as u64
+        );
+    }
+
+    #[test]
+    fn security_cells() {
+        let data = sample(5);
+        let security = get(&data, payload(root_cell(&data)) + 44);
+        let references = get(&data, payload(security) + 12);
+        let at = (BASE_BLOCK_SIZE + security as usize) as u64;
+
+        let mut counted = data.clone();
+        set(&mut counted, payload(security) + 12, references + 1);
+        assert_eq!(
+            problems(&check(counted)),
+            vec![(
+                at,
+                Problem::SecurityReferences {
+                    stated: references + 1,
+                    actual: references
+                }
+            )]
+        );
+
+        let mut linked = data;
+        let root = root_cell(&linked);
+        set(&mut linked, payload(security) + 8, root);
+        assert_eq!(
+            problems(&check(linked)),
+            vec![(
+                at,
+                Problem::SecurityList("backward link does not point to the previous cell")
+            )]
+        );
+    }
+
+    #[test]
+    fn random_damage() {
+        damaged(&sample(3), 0, 100, 16, |bytes| {
+            if let Ok(hive) = Hive::from_bytes(bytes) {
+                let _ = hive.check().to_string();
+            }
+        });
+    }
+}
@@ -0,0 +1,1070 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    hive::{
+        checksum, corrupt, u16_at, u32_at, BaseBlock, Cells, Hive, KeyNode, BASE_BLOCK_SIZE,
+        HBIN_ALIGNMENT, HBIN_HEADER_SIZE, NO_CELL,
+    },
+    hive_writer::{
+        key_node, round_up, value_key, write_subkey_list, write_value_data, CellAllocator,
+    },
+    path::{RegPath, RegPathBuf},
+    value::RawValue,
+    value_name::ValueName,
+    Result,
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Modifies an existing offline hive file in place.
+///
+/// Keys and values are created, changed and deleted directly in the hive bins, the way Windows does it:
+/// cells are taken from free space before the file grows, freed cells are merged with their free
+/// neighbours, subkey lists are rebuilt in sorted order with fresh hashes, and security cells are shared
+/// with the parent key and reference counted. Saving bumps the sequence numbers and recomputes the base
+/// block checksum, so Windows loads the result as a clean hive.
+///
+/// Paths are relative to the root of the hive. Hives with pending changes in their transaction logs are
+/// refused, as editing them would lose those changes.
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::{HiveEditor, RawValue};
+/// // Disable a service in the SYSTEM hive of an offline image
+/// let mut hive = HiveEditor::open("/mnt/windows/System32/config/SYSTEM").unwrap();
+/// hive.set_value(
+///     r"ControlSet001\Services\Malicious",
+///     "Start",
+///     &RawValue::new(4, 4u32.to_le_bytes().to_vec()),
+/// )
+/// .unwrap();
+/// hive.save().unwrap();
+/// ```
+pub struct HiveEditor {
+    data: Vec<u8>,
+    base_block: BaseBlock,
+    /// Free cells by offset, with their sizes
+    free: BTreeMap<u32, u32>,
+    file: Option<PathBuf>,
+    timestamp: Option<u64>,
+}
+
+impl HiveEditor {
+    /// Reads the hive file at `path` for editing; [`save`](HiveEditor::save) writes it back
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<HiveEditor> {
+        let data = std::fs::read(&path)
+            .map_err(|e| Error::new(Operation::OpenHive, RegPathBuf::new(), ErrorKind::Io(e)))?;
+        let mut editor = HiveEditor::from_bytes(data)?;
+        editor.file = Some(path.as_ref().to_path_buf());
+        Ok(editor)
+    }
+
+    /// Edits the contents of a hive file held in memory.
+    ///
+    /// Anything after the last hive bin is dropped.
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<HiveEditor> {
+        let base_block = Hive::from_bytes(&data[..])?.base_block().clone();
+        if base_block.is_dirty() {
+            return Err(Error::new(
+                Operation::OpenHive,
+                RegPathBuf::new(),
+                ErrorKind::Unsupported("editing a dirty hive; replay its transaction logs first"),
+            ));
+        }
+
+        let size = base_block.hive_bins_size as usize;
+        if data.len() < BASE_BLOCK_SIZE + size || !size.is_multiple_of(HBIN_ALIGNMENT) {
+            return Err(Error::new(
+                Operation::OpenHive,
+                RegPathBuf::new(),
+                ErrorKind::Corrupt {
+                    offset: 40,
+                    reason: "hive bins size does not match the file",
+                },
+            ));
+        }
+        data.truncate(BASE_BLOCK_SIZE + size);
+
+        let free = free_cells(&data[BASE_BLOCK_SIZE..])?;
+        Ok(HiveEditor {
+            data,
+            base_block,
+            free,
+            file: None,
+            timestamp: None,
+        })
+    }
+
+    /// The last write time given to modified keys and recorded in the base block, as a `FILETIME`.
+    ///
+    /// Defaults to the current time.
+    pub fn timestamp(&mut self, filetime: u64) -> &mut HiveEditor {
+        self.timestamp = Some(filetime);
+        self
+    }
+
+    /// A read-only view of the hive as edited so far
+    pub fn hive(&self) -> Hive<&[u8]> {
+        Hive::from_parts(&self.data[..], self.base_block.clone())
+    }
+
+    /// Creates the key at `path` and any missing keys above it, doing nothing if it already exists
+    pub fn create_key<P: Into<RegPathBuf>>(&mut self, path: P) -> Result<()> {
+        let path = key_path(path);
+        let mut key = self.base_block.root_cell;
+        let mut current = RegPathBuf::from(r"\");
+        for name in path.components() {
+            let parent = current.clone();
+            current.push(name);
+            key = match self.find_child(Operation::CreateKey, &parent, key, name)? {
+                Some((_, child)) => child,
+                None => self.create_child(&parent, key, &current)?,
+            };
+        }
+        Ok(())
+    }
+
+    /// Deletes the key at `path` with all of its subkeys and values
+    pub fn delete_key<P: Into<RegPathBuf>>(&mut self, path: P) -> Result<()> {
+        let path = key_path(path);
+        let (parent_path, name) = match (path.parent(), path.name()) {
+            (Some(parent), Some(name)) => (parent.to_reg_path_buf(), name),
+            _ => {
+                return Err(Error::new(
+                    Operation::DeleteKey,
+                    &path,
+                    ErrorKind::Unsupported("deleting the root key of a hive"),
+                ))
+            }
+        };
+
+        let parent = self.locate(Operation::DeleteKey, &parent_path)?;
+        let mut entries = self.children(Operation::DeleteKey, &parent_path, parent)?;
+        let index = entries
+            .iter()
+            .position(|(_, child)| RegPath::from_wide(child) == name)
+            .ok_or_else(|| Error::new(Operation::DeleteKey, &path, ErrorKind::NotFound))?;
+        let (key, _) = entries.remove(index);
+
+        // Everything is collected before anything is freed, so a corrupt subtree leaves the hive untouched
+        let mut cells = Vec::new();
+        let mut security = Vec::new();
+        self.collect_subtree(&path, key, &mut HashSet::new(), &mut cells, &mut security)?;
+
+        let old_list = self
+            .node(Operation::DeleteKey, &parent_path, parent)?
+            .subkey_list;
+        self.replace_subkeys(parent, old_list, entries);
+        self.touch(parent);
+        security
+            .into_iter()
+            .for_each(|sk| self.release_security(sk));
+        cells.into_iter().for_each(|cell| self.release(cell));
+        Ok(())
+    }
+
+    /// Sets the value called `name` in the key at `key`, replacing its type and data if it exists
+    pub fn set_value<P: Into<RegPathBuf>, N: Into<ValueName>>(
+        &mut self,
+        key: P,
+        name: N,
+        raw: &RawValue,
+    ) -> Result<()> {
+        let path = key_path(key);
+        let name = name.into();
+
+        let offset = self.locate(Operation::SetValue, &path)?;
+        let (value_count, value_list) = {
+            let node = self.node(Operation::SetValue, &path, offset)?;
+            (node.value_count, node.value_list)
+        };
+        let values = self.value_offsets(Operation::SetValue, &path, offset)?;
+        let existing = self.find_value(Operation::SetValue, &path, &values, &name)?;
+
+        match existing {
+            Some((_, value)) => {
+                let old_data = self
+                    .cells()
+                    .value(value)
+                    .and_then(|vk| self.cells().data_cells(&vk))
+                    .ok_or_else(|| {
+                        corrupt(Operation::SetValue, &path, value, "invalid value data")
+                            .with_value_name(&name)
+                    })?;
+                let (data_size, data_offset) =
+                    write_value_data(self, self.base_block.minor_version, &path, &raw.data)
+                        .map_err(|e| e.with_value_name(&name))?;
+                self.set_u32(value, 4, data_size);
+                self.set_u32(value, 8, data_offset);
+                self.set_u32(value, 12, raw.value_type);
+                old_data.into_iter().for_each(|cell| self.release(cell));
+            }
+            None => {
+                let mut value = value_key(&path, &name, 0, 0, raw.value_type)
+                    .map_err(|e| e.with_value_name(&name))?;
+                let (data_size, data_offset) =
+                    write_value_data(self, self.base_block.minor_version, &path, &raw.data)
+                        .map_err(|e| e.with_value_name(&name))?;
+                value[4..8].copy_from_slice(&data_size.to_le_bytes());
+                value[8..12].copy_from_slice(&data_offset.to_le_bytes());
+                let value = self.alloc(&value);
+
+                let list = values
+                    .iter()
+                    .chain(std::iter::once(&value))
+                    .flat_map(|offset| offset.to_le_bytes())
+                    .collect::<Vec<u8>>();
+                let list = self.alloc(&list);
+                self.set_u32(offset, 36, value_count + 1);
+                self.set_u32(offset, 40, list);
+                if value_count > 0 {
+                    self.release(value_list);
+                }
+            }
+        }
+
+        self.raise(offset, 60, name.as_wide().len() as u32 * 2);
+        self.raise(offset, 64, raw.data.len() as u32);
+        self.touch(offset);
+        Ok(())
+    }
+
+    /// Deletes the value called `name` from the key at `key`
+    pub fn delete_value<P: Into<RegPathBuf>, N: Into<ValueName>>(
+        &mut self,
+        key: P,
+        name: N,
+    ) -> Result<()> {
+        let path = key_path(key);
+        let name = name.into();
+
+        let offset = self.locate(Operation::DeleteValue, &path)?;
+        let value_list = self.node(Operation::DeleteValue, &path, offset)?.value_list;
+        let mut values = self.value_offsets(Operation::DeleteValue, &path, offset)?;
+        let (index, value) = self
+            .find_value(Operation::DeleteValue, &path, &values, &name)?
+            .ok_or_else(|| {
+                Error::new(Operation::DeleteValue, &path, ErrorKind::NotFound)
+                    .with_value_name(&name)
+            })?;
+        let data = self
+            .cells()
+            .value(value)
+            .and_then(|vk| self.cells().data_cells(&vk))
+            .ok_or_else(|| {
+                corrupt(Operation::DeleteValue, &path, value, "invalid value data")
+                    .with_value_name(&name)
+            })?;
+
+        values.remove(index);
+        if values.is_empty() {
+            self.set_u32(offset, 40, NO_CELL);
+            self.release(value_list);
+        } else {
+            let list = values
+                .iter()
+                .flat_map(|offset| offset.to_le_bytes())
+                .collect::<Vec<u8>>();
+            self.set(value_list, 0, &list);
+        }
+        self.set_u32(offset, 36, values.len() as u32);
+        self.touch(offset);
+        data.into_iter().for_each(|cell| self.release(cell));
+        self.release(value);
+        Ok(())
+    }
+
+    /// Writes the edited hive back to the file it was opened from
+    pub fn save(&mut self) -> Result<()> {
+        match self.file.clone() {
+            Some(file) => self.save_as(file),
+            None => Err(Error::new(
+                Operation::WriteHive,
+                RegPathBuf::from(r"\"),
+                ErrorKind::Unsupported("saving a hive that was not read from a file"),
+            )),
+        }
+    }
+
+    /// Writes the edited hive to `path`
+    pub fn save_as<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.commit();
+        std::fs::write(path, &self.data).map_err(|e| {
+            Error::new(
+                Operation::WriteHive,
+                RegPathBuf::from(r"\"),
+                ErrorKind::Io(e),
+            )
+        })
+    }
+
+    /// The contents of the edited hive file
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.commit();
+        self.data
+    }
+
+    /// Brings the base block up to date, as after a completed write
+    fn commit(&mut self) {
+        let sequence = self.base_block.primary_sequence.wrapping_add(1);
+        self.base_block.primary_sequence = sequence;
+        self.base_block.secondary_sequence = sequence;
+        self.base_block.last_written = self.now();
+
+        // Fields this crate does not model are kept as they are
+        let base_block = &self.base_block;
+        for (offset, field) in [
+            (4, base_block.primary_sequence),
+            (8, base_block.secondary_sequence),
+            (40, base_block.hive_bins_size),
+        ]
+        .iter()
+        {
+            self.data[*offset..offset + 4].copy_from_slice(&field.to_le_bytes());
+        }
+        self.data[12..20].copy_from_slice(&base_block.last_written.to_le_bytes());
+        let checksum = checksum(&self.data);
+        self.data[508..512].copy_from_slice(&checksum.to_le_bytes());
+        self.base_block.checksum = checksum;
+    }
+
+    fn now(&self) -> u64 {
+        self.timestamp.unwrap_or_else(filetime_now)
+    }
+
+    fn cells(&self) -> Cells<'_> {
+        Cells {
+            bins: &self.data[BASE_BLOCK_SIZE..],
+            minor_version: self.base_block.minor_version,
+        }
+    }
+
+    fn node(&self, operation: Operation, path: &RegPath, offset: u32) -> Result<KeyNode<'_>> {
+        self.cells()
+            .key(offset)
+            .ok_or_else(|| corrupt(operation, path, offset, "invalid key node"))
+    }
+
+    /// The key node offset of the key at `path`
+    fn locate(&self, operation: Operation, path: &RegPath) -> Result<u32> {
+        let mut key = self.base_block.root_cell;
+        let mut current = RegPathBuf::from(r"\");
+        for name in path.components() {
+            key = match self.find_child(operation, &current, key, name)? {
+                Some((_, child)) => child,
+                None => {
+                    return Err(Error::new(
+                        operation,
+                        current.join(name),
+                        ErrorKind::NotFound,
+                    ))
+                }
+            };
+            current.push(name);
+        }
+        Ok(key)
+    }
+
+    /// The subkeys of a key, with their names
+    fn children(
+        &self,
+        operation: Operation,
+        path: &RegPath,
+        key: u32,
+    ) -> Result<Vec<(u32, Vec<u16>)>> {
+        let node = self.node(operation, path, key)?;
+        if node.subkey_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let cells = self.cells();
+        let offsets = cells
+            .subkeys(node.subkey_list)
+            .ok_or_else(|| corrupt(operation, path, node.subkey_list, "invalid subkey list"))?;
+        offsets
+            .into_iter()
+            .map(|offset| {
+                let child = self.node(operation, path, offset)?;
+                Ok((offset, child.name()))
+            })
+            .collect()
+    }
+
+    fn find_child(
+        &self,
+        operation: Operation,
+        path: &RegPath,
+        key: u32,
+        name: &RegPath,
+    ) -> Result<Option<(usize, u32)>> {
+        Ok(self
+            .children(operation, path, key)?
+            .into_iter()
+            .enumerate()
+            .find(|(_, (_, child))| RegPath::from_wide(child) == name)
+            .map(|(index, (offset, _))| (index, offset)))
+    }
+
+    fn value_offsets(&self, operation: Operation, path: &RegPath, key: u32) -> Result<Vec<u32>> {
+        let node = self.node(operation, path, key)?;
+        if node.value_count == 0 {
+            return Ok(Vec::new());
+        }
+        self.cells()
+            .values(node.value_list, node.value_count)
+            .ok_or_else(|| corrupt(operation, path, node.value_list, "invalid value list"))
+    }
+
+    fn find_value(
+        &self,
+        operation: Operation,
+        path: &RegPath,
+        values: &[u32],
+        name: &ValueName,
+    ) -> Result<Option<(usize, u32)>> {
+        for (index, offset) in values.iter().enumerate() {
+            let value = self
+                .cells()
+                .value(*offset)
+                .ok_or_else(|| corrupt(operation, path, *offset, "invalid value key"))?;
+            if ValueName::from(value.name()) == *name {
+                return Ok(Some((index, *offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The subkey list at `list` and the leaves of an index root
+    fn list_cells(&self, list: u32) -> Option<Vec<u32>> {
+        let cell = self.cells().cell(list)?;
+        let mut cells = Vec::new();
+        if cell.get(..2)? == b"ri" {
+            let count = u16_at(cell, 2)? as usize;
+            for i in 0..count {
+                cells.push(u32_at(cell, 4 + i * 4)?);
+            }
+        }
+        cells.push(list);
+        Some(cells)
+    }
+
+    /// Collects every cell used by a key and its subtree, and the security cells it references.
+    ///
+    /// `visited` holds the key cells already collected, so a subkey list leading back to one of them is
+    /// reported instead of being followed forever or freed twice.
+    fn collect_subtree(
+        &self,
+        path: &RegPath,
+        key: u32,
+        visited: &mut HashSet<u32>,
+        cells: &mut Vec<u32>,
+        security: &mut Vec<u32>,
+    ) -> Result<()> {
+        let operation = Operation::DeleteKey;
+        if !visited.insert(key) {
+            return Err(corrupt(operation, path, key, "subkey cycle"));
+        }
+        for (child, name) in self.children(operation, path, key)? {
+            self.collect_subtree(
+                &path.join(RegPath::from_wide(&name)),
+                child,
+                visited,
+                cells,
+                security,
+            )?;
+        }
+
+        let node = self.node(operation, path, key)?;
+        if node.subkey_count > 0 {
+            cells.extend(self.list_cells(node.subkey_list).ok_or_else(|| {
+                corrupt(operation, path, node.subkey_list, "invalid subkey list")
+            })?);
+        }
+        for value in self.value_offsets(operation, path, key)? {
+            let data = self
+                .cells()
+                .value(value)
+                .and_then(|vk| self.cells().data_cells(&vk))
+                .ok_or_else(|| corrupt(operation, path, value, "invalid value data"))?;
+            cells.extend(data);
+            cells.push(value);
+        }
+        if node.value_count > 0 {
+            cells.push(node.value_list);
+        }
+        if node.class != NO_CELL {
+            cells.push(node.class);
+        }
+        if self.cells().security(node.security).is_none() {
+            return Err(corrupt(
+                operation,
+                path,
+                node.security,
+                "invalid security cell",
+            ));
+        }
+        security.push(node.security);
+        cells.push(key);
+        Ok(())
+    }
+
+    /// Adds the key at `path` under `parent`, sharing the security cell of the parent
+    fn create_child(&mut self, parent_path: &RegPath, parent: u32, path: &RegPath) -> Result<u32> {
+        let operation = Operation::CreateKey;
+        let (security, old_list) = {
+            let node = self.node(operation, parent_path, parent)?;
+            (node.security, node.subkey_list)
+        };
+        if self.cells().security(security).is_none() {
+            return Err(corrupt(
+                operation,
+                parent_path,
+                security,
+                "invalid security cell",
+            ));
+        }
+
+        let name = path.name().map(RegPath::as_wide).unwrap_or(&[]);
+        let mut entries = self.children(operation, parent_path, parent)?;
+        let node = key_node(path, name, 0, self.now(), parent)?;
+        let key = self.alloc(&node);
+        self.set_u32(key, 44, security);
+        self.set_u32(security, 12, self.get_u32(security, 12).saturating_add(1));
+
+        entries.push((key, name.to_vec()));
+        self.replace_subkeys(parent, old_list, entries);
+        self.touch(parent);
+        Ok(key)
+    }
+
+    /// Replaces the subkey list of `parent` with one holding `entries`, in sorted order
+    fn replace_subkeys(&mut self, parent: u32, old_list: u32, mut entries: Vec<(u32, Vec<u16>)>) {
+        entries.sort_by(|(_, a), (_, b)| RegPath::from_wide(a).cmp(RegPath::from_wide(b)));
+        let list = if entries.is_empty() {
+            NO_CELL
+        } else {
+            let entries = entries
+                .iter()
+                .map(|(offset, name)| (*offset, &name[..]))
+                .collect::<Vec<_>>();
+            write_subkey_list(self, self.base_block.minor_version, &entries)
+        };
+
+        let old_cells = if old_list == NO_CELL {
+            Vec::new()
+        } else {
+            self.list_cells(old_list).unwrap_or_default()
+        };
+        self.set_u32(parent, 20, entries.len() as u32);
+        self.set_u32(parent, 28, list);
+        // Only the low word holds the longest name; the high word holds flags
+        let longest = entries
+            .iter()
+            .map(|(_, name)| name.len() * 2)
+            .max()
+            .unwrap_or(0) as u32;
+        let field = self.get_u32(parent, 52);
+        self.set_u32(parent, 52, (field & 0xffff_0000) | longest.min(0xffff));
+        old_cells.into_iter().for_each(|cell| self.release(cell));
+    }
+
+    /// Drops a reference to a security cell, unlinking and freeing it once nothing uses it
+    fn release_security(&mut self, sk: u32) {
+        let count = self.get_u32(sk, 12);
+        if count > 1 {
+            self.set_u32(sk, 12, count - 1);
+            return;
+        }
+
+        let next = self.get_u32(sk, 4);
+        let previous = self.get_u32(sk, 8);
+        let cells = self.cells();
+        if next != sk && cells.security(next).is_some() && cells.security(previous).is_some() {
+            self.set_u32(previous, 4, next);
+            self.set_u32(next, 8, previous);
+        }
+        self.release(sk);
+    }
+
+    /// Records the edit time on a key
+    fn touch(&mut self, key: u32) {
+        let now = self.now();
+        self.set(key, 4, &now.to_le_bytes());
+    }
+
+    /// Raises a maximum length field of a key node to at least `length`
+    fn raise(&mut self, key: u32, field: usize, length: u32) {
+        if self.get_u32(key, field) < length {
+            self.set_u32(key, field, length);
+        }
+    }
+
+    /// Reads a `u32` at `field` in the payload of a cell that has already been validated
+    fn get_u32(&self, cell: u32, field: usize) -> u32 {
+        u32_at(&self.data, BASE_BLOCK_SIZE + cell as usize + 4 + field).unwrap_or(0)
+    }
+
+    fn set_u32(&mut self, cell: u32, field: usize, value: u32) {
+        self.set(cell, field, &value.to_le_bytes());
+    }
+
+    /// Overwrites bytes at `field` in the payload of a cell that has already been validated
+    fn set(&mut self, cell: u32, field: usize, bytes: &[u8]) {
+        let start = BASE_BLOCK_SIZE + cell as usize + 4 + field;
+        if let Some(target) = self.data.get_mut(start..start + bytes.len()) {
+            target.copy_from_slice(bytes);
+        }
+    }
+
+    /// Marks a cell as free, merging it with free cells directly before and after it
+    fn release(&mut self, cell: u32) {
+        let size = match u32_at(&self.data, BASE_BLOCK_SIZE + cell as usize) {
+            Some(size) if (size as i32) < 0 => (size as i32).unsigned_abs(),
+            _ => return,
+        };
+        // Like Windows, the cell keeps its own (now positive) size when merged into a neighbour, which
+        // leaves its contents recoverable
+        let header = BASE_BLOCK_SIZE + cell as usize;
+        self.data[header..header + 4].copy_from_slice(&size.to_le_bytes());
+
+        let (mut start, mut size) = (cell, size);
+        if let Some(next) = self.free.remove(&(cell + size)) {
+            size += next;
+        }
+        if let Some((&previous, &previous_size)) = self.free.range(..cell).next_back() {
+            if previous + previous_size == cell {
+                self.free.remove(&previous);
+                start = previous;
+                size += previous_size;
+            }
+        }
+        self.mark_free(start, size);
+    }
+
+    fn mark_free(&mut self, cell: u32, size: u32) {
+        let start = BASE_BLOCK_SIZE + cell as usize;
+        self.data[start..start + 4].copy_from_slice(&size.to_le_bytes());
+        self.free.insert(cell, size);
+    }
+
+    /// Appends a hive bin large enough for a cell of `size` bytes, returning the offset of that cell
+    fn append_bin(&mut self, size: u32) -> u32 {
+        let offset = self.base_block.hive_bins_size;
+        let bin_size = round_up(HBIN_HEADER_SIZE + size as usize, HBIN_ALIGNMENT) as u32;
+
+        let mut header = [0; HBIN_HEADER_SIZE];
+        header[..4].copy_from_slice(b"hbin");
+        header[4..8].copy_from_slice(&offset.to_le_bytes());
+        header[8..12].copy_from_slice(&bin_size.to_le_bytes());
+        self.data.extend_from_slice(&header);
+        self.data
+            .resize(BASE_BLOCK_SIZE + (offset + bin_size) as usize, 0);
+        self.base_block.hive_bins_size = offset + bin_size;
+
+        let cell = offset + HBIN_HEADER_SIZE as u32;
+        let remaining = bin_size - HBIN_HEADER_SIZE as u32 - size;
+        if remaining > 0 {
+            self.mark_free(cell + size, remaining);
+        }
+        cell
+    }
+}
+
+impl CellAllocator for HiveEditor {
+    /// Allocates from the first free cell that is large enough, splitting off what is left of it, or from a
+    /// new hive bin at the end of the file
+    fn alloc(&mut self, payload: &[u8]) -> u32 {
+        let size = round_up(payload.len() + 4, 8) as u32;
+        let free = self
+            .free
+            .iter()
+            .find(|(_, free)| **free >= size)
+            .map(|(offset, free)| (*offset, *free));
+        let offset = match free {
+            Some((offset, free)) => {
+                self.free.remove(&offset);
+                if free > size {
+                    self.mark_free(offset + size, free - size);
+                }
+                offset
+            }
+            None => self.append_bin(size),
+        };
+
+        let start = BASE_BLOCK_SIZE + offset as usize;
+        let end = start + size as usize;
+        self.data[start..start + 4].copy_from_slice(&(-(size as i32)).to_le_bytes());
+        self.data[start + 4..start + 4 + payload.len()].copy_from_slice(payload);
+        self.data[start + 4 + payload.len()..end]
+            .iter_mut()
+            .for_each(|b| *b = 0);
+        offset
+    }
+}
+
+/// Walks every hive bin, checking the bin headers and cell sizes, and returns the free cells
+fn free_cells(bins: &[u8]) -> Result<BTreeMap<u32, u32>> {
+    let corrupt = |offset: usize, reason| {
+        corrupt(
+            Operation::OpenHive,
+            &RegPathBuf::new(),
+            offset as u32,
+            reason,
+        )
+    };
+
+    let mut free = BTreeMap::new();
+    let mut bin = 0;
+    while bin < bins.len() {
+        if bins.get(bin..bin + 4) != Some(&b"hbin"[..]) {
+            return Err(corrupt(bin, "invalid hive bin signature"));
+        }
+        let bin_size = u32_at(bins, bin + 8).unwrap_or(0) as usize;
+        if bin_size == 0 || !bin_size.is_multiple_of(HBIN_ALIGNMENT) || bin_size > bins.len() - bin
+        {
+            return Err(corrupt(bin, "invalid hive bin size"));
+        }
+
+        let mut cell = bin + HBIN_HEADER_SIZE;
+        while cell < bin + bin_size {
+            let size = u32_at(bins, cell).unwrap_or(0) as i32;
+            let length = size.unsigned_abs() as usize;
+            if length < 8 || !length.is_multiple_of(8) || length > bin + bin_size - cell {
+                return Err(corrupt(cell, "invalid cell size"));
+            }
+            if size > 0 {
+                free.insert(cell as u32, length as u32);
+            }
+            cell += length;
+        }
+        bin += bin_size;
+    }
+    Ok(free)
+}
+
+/// A path relative to the root of the hive, made absolute so errors show where in the hive it is
+fn key_path<P: Into<RegPathBuf>>(path: P) -> RegPathBuf {
+    RegPathBuf::from(r"\").join(path.into())
+}
+
+/// The current time as a `FILETIME`
+fn filetime_now() -> u64 {
+    const UNIX_EPOCH_FILETIME: u64 = 116_444_736_000_000_000;
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    UNIX_EPOCH_FILETIME
+        + since_epoch.as_secs() * 10_000_000
+        + since_epoch.subsec_nanos() as u64 / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hive_writer::{default_security, name_hash, HiveWriter},
+        snapshot::KeySnapshot,
+        source::KeySource,
+    };
+
+    const WRITTEN: u64 = 131_000_000_000_000_000;
+    const EDITED: u64 = 133_000_000_000_000_000;
+
+    fn dword(value: u32) -> RawValue {
+        RawValue::new(4, value.to_le_bytes().to_vec())
+    }
+
+    fn sample() -> KeySnapshot {
+        let mut root = KeySnapshot::new(r"\");
+        root.last_write_time = WRITTEN;
+        for name in &["Good", "Evil"] {
+            let service = root.child_mut(format!(r"ControlSet001\Services\{}", name));
+            service.values.push(("Start".into(), dword(2)));
+            service.values.push((
+                "ImagePath".into(),
+                RawValue::new(2, vec![b'x', 0, b'.', 0, b's', 0, b'y', 0, b's', 0, 0, 0]),
+            ));
+        }
+        root.child_mut("Software")
+            .values
+            .push(("Blob".into(), RawValue::new(3, vec![7; 40_000])));
+        set_times(&mut root);
+        root
+    }
+
+    fn set_times(key: &mut KeySnapshot) {
+        key.last_write_time = WRITTEN;
+        key.children.iter_mut().for_each(set_times);
+    }
+
+    /// `key` as the reader reports it: subkeys sorted and the default security descriptor everywhere
+    fn normalized(mut key: KeySnapshot) -> KeySnapshot {
+        key.security = Some(default_security());
+        key.children = key.children.into_iter().map(normalized).collect();
+        key.children.sort_by(|a, b| a.name().cmp(b.name()));
+        key
+    }
+
+    fn editor(minor_version: u32) -> HiveEditor {
+        let bytes = HiveWriter::new()
+            .minor_version(minor_version)
+            .to_bytes(&sample())
+            .unwrap();
+        let mut editor = HiveEditor::from_bytes(bytes).unwrap();
+        editor.timestamp(EDITED);
+        editor
+    }
+
+    #[test]
+    fn edits() {
+        for minor_version in 3..=6 {
+            let mut editor = editor(minor_version);
+            editor
+                .set_value(r"ControlSet001\Services\Evil", "start", &dword(4))
+                .unwrap();
+            editor.delete_key(r"ControlSet001\Services\Good").unwrap();
+            editor.create_key(r"Software\Vendor\Tool").unwrap();
+            editor.create_key(r"SOFTWARE\vendor").unwrap();
+            editor
+                .set_value(
+                    r"Software\Vendor\Tool",
+                    "Big",
+                    &RawValue::new(3, vec![1; 50_000]),
+                )
+                .unwrap();
+            editor
+                .set_value(
+                    r"Software\Vendor\Tool",
+                    "",
+                    &RawValue::new(1, vec![b'a', 0, 0, 0]),
+                )
+                .unwrap();
+            editor.delete_value("Software", "Blob").unwrap();
+
+            let mut expected = sample();
+            let services = expected.child_mut(r"ControlSet001\Services");
+            services.last_write_time = EDITED;
+            services
+                .children
+                .retain(|child| child.name().to_string() != "Good");
+            let evil = services.child_mut("Evil");
+            evil.last_write_time = EDITED;
+            evil.values[0].1 = dword(4);
+            let software = expected.child_mut("Software");
+            software.last_write_time = EDITED;
+            software.values.clear();
+            let vendor = software.child_mut("Vendor");
+            vendor.last_write_time = EDITED;
+            let tool = vendor.child_mut("Tool");
+            tool.last_write_time = EDITED;
+            tool.values
+                .push(("Big".into(), RawValue::new(3, vec![1; 50_000])));
+            tool.values
+                .push(("".into(), RawValue::new(1, vec![b'a', 0, 0, 0])));
+
+            let bytes = editor.into_bytes();
+            let hive = Hive::from_bytes(&bytes[..]).unwrap();
+            let base_block = hive.base_block();
+            assert_eq!(base_block.primary_sequence, 2);
+            assert_eq!(base_block.secondary_sequence, 2);
+            assert_eq!(base_block.last_written, EDITED);
+            assert_eq!(base_block.checksum, checksum(&bytes));
+            assert_eq!(
+                base_block.hive_bins_size as usize,
+                bytes.len() - BASE_BLOCK_SIZE
+            );
+            assert_eq!(
+                hive.root().unwrap().to_snapshot().unwrap(),
+                normalized(expected)
+            );
+
+            // The result can be edited again
+            free_cells(&bytes[BASE_BLOCK_SIZE..]).unwrap();
+        }
+    }
+
+    #[test]
+    fn subkey_lists() {
+        for minor_version in &[4, 5] {
+            let mut editor = editor(*minor_version);
+            for i in (0..1100).rev() {
+                editor.create_key(format!(r"Software\Key{:04}", i)).unwrap();
+            }
+            editor.delete_key(r"Software\Key0500").unwrap();
+
+            let hive = editor.hive();
+            let cells = hive.cells();
+            let software = hive
+                .root()
+                .unwrap()
+                .open_child(RegPath::from_wide(
+                    &"Software".encode_utf16().collect::<Vec<_>>(),
+                ))
+                .unwrap();
+            let node = cells.key(software.offset()).unwrap();
+            assert_eq!(node.subkey_count, 1099);
+            assert_eq!(u32_at(cells.cell(software.offset()).unwrap(), 52), Some(14));
+
+            let index = cells.cell(node.subkey_list).unwrap();
+            assert_eq!(&index[..2], b"ri");
+            let mut names = Vec::new();
+            for leaf in
+                (0..u16_at(index, 2).unwrap() as usize).map(|i| u32_at(index, 4 + i * 4).unwrap())
+            {
+                let leaf = cells.cell(leaf).unwrap();
+                assert_eq!(&leaf[..2], if *minor_version >= 5 { b"lh" } else { b"lf" });
+                for i in 0..u16_at(leaf, 2).unwrap() as usize {
+                    let key = cells.key(u32_at(leaf, 4 + i * 8).unwrap()).unwrap();
+                    let hash = u32_at(leaf, 8 + i * 8).unwrap();
+                    if *minor_version >= 5 {
+                        assert_eq!(hash, name_hash(&key.name()));
+                    } else {
+                        assert_eq!(hash.to_le_bytes(), key.name[..4]);
+                    }
+                    names.push(String::from_utf16(&key.name()).unwrap());
+                }
+            }
+            let expected = (0..1100)
+                .filter(|i| *i != 500)
+                .map(|i| format!("Key{:04}", i))
+                .collect::<Vec<_>>();
+            assert_eq!(names, expected);
+        }
+    }
+
+    #[test]
+    fn reuses_free_space() {
+        let mut editor = editor(5);
+        let size = editor.base_block.hive_bins_size;
+        let free = editor.free.values().sum::<u32>();
+
+        editor.delete_value("Software", "Blob").unwrap();
+        assert!(editor.free.values().sum::<u32>() > free + 40_000);
+        editor
+            .set_value("Software", "Blob", &RawValue::new(3, vec![8; 40_000]))
+            .unwrap();
+        assert_eq!(editor.free.values().sum::<u32>(), free);
+        assert_eq!(editor.base_block.hive_bins_size, size);
+
+        // Freed neighbours are merged into a single cell
+        editor.delete_key(r"ControlSet001").unwrap();
+        let cells = Cells {
+            bins: &editor.data[BASE_BLOCK_SIZE..],
+            minor_version: 5,
+        };
+        for (offset, size) in &editor.free {
+            assert_eq!(cells.cell(*offset).unwrap().len() as u32 + 4, *size);
+            assert!(!editor.free.contains_key(&(offset + size)));
+        }
+
+        let mut grown = HiveEditor::from_bytes(editor.into_bytes()).unwrap();
+        grown
+            .set_value("Software", "Huge", &RawValue::new(3, vec![9; 100_000]))
+            .unwrap();
+        assert!(grown.base_block.hive_bins_size > size);
+        let hive = grown.hive();
+        let value = hive.root_at(r"\").unwrap().to_snapshot().unwrap();
+        assert_eq!(
+            value
+                .child("Software")
+                .unwrap()
+                .value("Huge")
+                .unwrap()
+                .data
+                .len(),
+            100_000
+        );
+    }
+
+    #[test]
+    fn security_references() {
+        let mut tree = sample();
+        tree.child_mut("Private").security = Some(vec![
+            1, 0, 4, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        tree.child_mut(r"Private\Inner");
+        let mut editor =
+            HiveEditor::from_bytes(HiveWriter::new().to_bytes(&tree).unwrap()).unwrap();
+
+        let security = |editor: &HiveEditor| {
+            let cells = editor.cells();
+            let root = cells.key(editor.base_block.root_cell).unwrap().security;
+            let mut list = vec![(root, u32_at(cells.cell(root).unwrap(), 12).unwrap())];
+            let mut sk = root;
+            loop {
+                let next = u32_at(cells.cell(sk).unwrap(), 4).unwrap();
+                assert_eq!(u32_at(cells.cell(next).unwrap(), 8), Some(sk));
+                if next == root {
+                    return list;
+                }
+                list.push((next, u32_at(cells.cell(next).unwrap(), 12).unwrap()));
+                sk = next;
+            }
+        };
+
+        let before = security(&editor);
+        assert_eq!(before.len(), 2);
+        editor.create_key(r"Private\Inner\New").unwrap();
+        editor.create_key("New").unwrap();
+        let after = security(&editor);
+        assert_eq!(after[0].1, before[0].1 + 1);
+        assert_eq!(after[1].1, before[1].1 + 1);
+
+        editor.delete_key("private").unwrap();
+        assert_eq!(security(&editor), vec![after[0]]);
+        assert!(editor.free.contains_key(&after[1].0));
+    }
+
+    #[test]
+    fn errors() {
+        let mut editor = editor(5);
+        assert!(matches!(
+            editor.delete_key(r"\").unwrap_err().kind(),
+            ErrorKind::Unsupported(_)
+        ));
+        let error = editor.delete_key(r"Software\Missing\Key").unwrap_err();
+        assert!(error.is_not_found());
+        assert_eq!(error.path().to_string(), r"\Software\Missing");
+        let error = editor.delete_value("Software", "Missing").unwrap_err();
+        assert!(error.is_not_found());
+        assert_eq!(error.value_name(), Some(&ValueName::from("Missing")));
+        assert!(editor.save().is_err());
+
+        let mut dirty = HiveWriter::new().to_bytes(&sample()).unwrap();
+        dirty[4] = 2;
+        assert!(matches!(
+            HiveEditor::from_bytes(dirty).err().unwrap().kind(),
+            ErrorKind::Unsupported(_)
+        ));
+
+        let mut damaged = HiveWriter::new().to_bytes(&sample()).unwrap();
+        damaged[BASE_BLOCK_SIZE + HBIN_HEADER_SIZE] = 3;
+        assert!(matches!(
+            HiveEditor::from_bytes(damaged).err().unwrap().kind(),
+            ErrorKind::Corrupt { offset, .. } if *offset == (BASE_BLOCK_SIZE + HBIN_HEADER_SIZE) as u64
+        ));
+
+        let mut cyclic = HiveWriter::new().to_bytes(&sample()).unwrap();
+        let hive = Hive::from_bytes(&cyclic[..]).unwrap();
+        let control_set = hive
+            .root()
+            .unwrap()
+            .open_child(&RegPathBuf::from("ControlSet001"))
+            .unwrap();
+        let entry = BASE_BLOCK_SIZE + control_set.node.subkey_list as usize + 8;
+        let offset = control_set.offset;
+        cyclic[entry..entry + 4].copy_from_slice(&offset.to_le_bytes());
+        let mut editor = HiveEditor::from_bytes(cyclic).unwrap();
+        let error = editor.delete_key("ControlSet001").unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::Corrupt {
+                reason: "subkey cycle",
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,784 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    hive::{checksum, u32_at, u64_at, BaseBlock, Hive, BASE_BLOCK_SIZE, HBIN_ALIGNMENT},
+    path::RegPathBuf,
+    Result,
+};
+use std::path::Path;
+
+/// Size of the base block copy at the start of a transaction log
+const LOG_BASE_BLOCK_SIZE: usize = 512;
+
+/// Dirty pages of legacy logs cover this many bytes of hive bins each
+const LEGACY_PAGE_SIZE: usize = 512;
+
+/// Size of the fixed header of an incremental log entry
+const LOG_ENTRY_HEADER_SIZE: usize = 40;
+
+/// Seed of the Marvin32 hashes protecting incremental log entries
+const MARVIN_SEED: u64 = 0x82ef_4d88_7a4e_55c5;
+
+/// The format of a transaction log file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// A dirty vector followed by the dirty pages of a single write, used before Windows 8.1
+    Legacy,
+    /// A sequence of `HvLE` entries, each holding the pages of one write, used from Windows 8.1 on
+    Incremental,
+}
+
+/// What recovery did with one transaction log
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogStatus {
+    /// The format of the log, `None` if it could not be recognized
+    pub format: Option<LogFormat>,
+    /// Sequence numbers of the writes replayed from the log, in order
+    pub applied: Vec<u32>,
+    /// Why the log, or the rest of it, was not replayed
+    pub skipped: Option<&'static str>,
+}
+
+/// The outcome of replaying transaction logs over a hive.
+///
+/// Returned by [`Hive::recover`] and [`Hive::open_with_logs`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recovery {
+    /// Primary sequence number of the hive file as found on disk
+    pub primary_sequence: u32,
+    /// Secondary sequence number of the hive file as found on disk
+    pub secondary_sequence: u32,
+    /// `false` if the base block of the hive file was damaged and restored from a log
+    pub checksum_valid: bool,
+    /// One entry for every log given, in the same order
+    pub logs: Vec<LogStatus>,
+}
+
+impl Recovery {
+    /// `true` if the hive file did not reflect its most recent writes, so the logs were needed
+    pub fn was_dirty(&self) -> bool {
+        self.primary_sequence != self.secondary_sequence || !self.checksum_valid
+    }
+
+    /// `true` if anything was replayed from the logs
+    pub fn replayed(&self) -> bool {
+        self.logs.iter().any(|log| !log.applied.is_empty())
+    }
+}
+
+impl Hive<Vec<u8>> {
+    /// Reads the hive file at `path` together with its transaction logs, replaying them if it is dirty.
+    ///
+    /// The logs are the files next to the hive named like it with a `.LOG1`, `.LOG2` or `.LOG` suffix, in
+    /// any case. None of the files are modified.
+    pub fn open_with_logs<P: AsRef<Path>>(path: P) -> Result<(Hive, Recovery)> {
+        let path = path.as_ref();
+        let io = |e| Error::new(Operation::OpenHive, RegPathBuf::new(), ErrorKind::Io(e));
+        let data = std::fs::read(path).map_err(io)?;
+
+        let mut logs = Vec::new();
+        if let (Some(directory), Some(name)) = (path.parent(), path.file_name()) {
+            let directory = if directory.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                directory
+            };
+            let name = name.to_string_lossy().to_lowercase();
+            for suffix in &[".log1", ".log2", ".log"] {
+                let expected = format!("{}{}", name, suffix);
+                for entry in std::fs::read_dir(directory).map_err(io)? {
+                    let entry = entry.map_err(io)?;
+                    if entry.file_name().to_string_lossy().to_lowercase() == expected {
+                        logs.push(std::fs::read(entry.path()).map_err(io)?);
+                    }
+                }
+            }
+        }
+
+        let logs = logs.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        Hive::recover(data, &logs)
+    }
+
+    /// Builds a consistent view of a hive from the contents of its file and its transaction logs.
+    ///
+    /// A hive is dirty when its primary and secondary sequence numbers differ (or its base block is
+    /// damaged): a write was interrupted and its changes are only in the logs. Legacy logs are replayed
+    /// from the newest one that is complete; incremental logs are replayed entry by entry, in sequence
+    /// number order across all of the logs, skipping entries the hive file already contains and stopping
+    /// at the first damaged entry or gap in the sequence. Clean hives are returned unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::Hive;
+    /// let data = std::fs::read("SOFTWARE").unwrap();
+    /// let log1 = std::fs::read("SOFTWARE.LOG1").unwrap();
+    /// let log2 = std::fs::read("SOFTWARE.LOG2").unwrap();
+    /// let (hive, recovery) = Hive::recover(data, &[&log1, &log2]).unwrap();
+    /// if recovery.replayed() {
+    ///     println!("replayed up to sequence {}", hive.base_block().primary_sequence);
+    /// }
+    /// ```
+    pub fn recover(mut data: Vec<u8>, logs: &[&[u8]]) -> Result<(Hive, Recovery)> {
+        let corrupt = |reason| {
+            Error::new(
+                Operation::OpenHive,
+                RegPathBuf::new(),
+                ErrorKind::Corrupt { offset: 0, reason },
+            )
+        };
+
+        let mut base_block =
+            BaseBlock::parse(&data).ok_or_else(|| corrupt("missing regf signature"))?;
+        let checksum_valid =
+            data.len() >= BASE_BLOCK_SIZE && checksum(&data) == base_block.checksum;
+        let mut recovery = Recovery {
+            primary_sequence: base_block.primary_sequence,
+            secondary_sequence: base_block.secondary_sequence,
+            checksum_valid,
+            logs: vec![LogStatus::default(); logs.len()],
+        };
+
+        let parsed = logs
+            .iter()
+            .zip(recovery.logs.iter_mut())
+            .map(|(log, status)| {
+                let parsed = LogFile::parse(log);
+                match &parsed {
+                    Ok(log) => status.format = Some(log.format()),
+                    Err(reason) => status.skipped = Some(reason),
+                }
+                parsed.ok()
+            })
+            .collect::<Vec<_>>();
+
+        if !recovery.was_dirty() {
+            recovery
+                .logs
+                .iter_mut()
+                .filter(|status| status.skipped.is_none())
+                .for_each(|status| status.skipped = Some("the hive is clean"));
+            return Ok((Hive::from_bytes(data)?, recovery));
+        }
+
+        if data.len() < BASE_BLOCK_SIZE {
+            data.resize(BASE_BLOCK_SIZE, 0);
+        }
+        let incremental = parsed
+            .iter()
+            .flatten()
+            .any(|log| log.format() == LogFormat::Incremental);
+        let replayed = if incremental {
+            replay_incremental(&mut data, &mut base_block, &parsed, &mut recovery)
+        } else {
+            replay_legacy(&mut data, &mut base_block, &parsed, &mut recovery)
+        }
+        .map_err(corrupt)?;
+
+        if replayed {
+            // Fields this crate does not model are kept as they are
+            for (offset, field) in [
+                (4, base_block.primary_sequence),
+                (8, base_block.secondary_sequence),
+                (28, 0),
+                (40, base_block.hive_bins_size),
+            ]
+            .iter()
+            {
+                data[*offset..offset + 4].copy_from_slice(&field.to_le_bytes());
+            }
+            let checksum = checksum(&data);
+            data[508..512].copy_from_slice(&checksum.to_le_bytes());
+        }
+        Ok((Hive::from_bytes(data)?, recovery))
+    }
+}
+
+/// Replays the newest complete legacy log that is more recent than the hive file
+fn replay_legacy(
+    data: &mut Vec<u8>,
+    base_block: &mut BaseBlock,
+    logs: &[Option<LogFile>],
+    recovery: &mut Recovery,
+) -> std::result::Result<bool, &'static str> {
+    let newest = logs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, log)| match log {
+            Some(
+                log @ LogFile {
+                    contents: LogContents::Legacy(pages),
+                    ..
+                },
+            ) => Some((index, log, pages)),
+            _ => None,
+        })
+        .filter(|(_, log, _)| log.base_block.primary_sequence >= recovery.secondary_sequence)
+        .max_by_key(|(_, log, _)| log.base_block.primary_sequence);
+
+    for (index, log) in logs.iter().enumerate() {
+        if log.is_some() && newest.map(|(newest, ..)| newest) != Some(index) {
+            recovery.logs[index].skipped = Some("an older write than the hive or another log");
+        }
+    }
+
+    let (index, log, pages) = match newest {
+        Some(newest) => newest,
+        None => return Ok(false),
+    };
+    restore_base_block(data, base_block, log, recovery.checksum_valid);
+    resize_bins(data, log.base_block.hive_bins_size, pages)?;
+    for (offset, page) in pages {
+        if let Some(target) =
+            data.get_mut(BASE_BLOCK_SIZE + offset..BASE_BLOCK_SIZE + offset + page.len())
+        {
+            target.copy_from_slice(page);
+        }
+    }
+
+    let sequence = log.base_block.primary_sequence;
+    base_block.hive_bins_size = log.base_block.hive_bins_size;
+    base_block.primary_sequence = sequence;
+    base_block.secondary_sequence = sequence;
+    recovery.logs[index].applied.push(sequence);
+    Ok(true)
+}
+
+/// Replays incremental log entries in sequence number order, starting after the last write the hive has
+fn replay_incremental(
+    data: &mut Vec<u8>,
+    base_block: &mut BaseBlock,
+    logs: &[Option<LogFile>],
+    recovery: &mut Recovery,
+) -> std::result::Result<bool, &'static str> {
+    let mut order = logs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, log)| match log {
+            Some(
+                log @ LogFile {
+                    contents: LogContents::Incremental(entries),
+                    ..
+                },
+            ) => Some((index, log, entries)),
+            Some(_) => {
+                recovery.logs[index].skipped = Some("a legacy log next to incremental logs");
+                None
+            }
+            None => None,
+        })
+        .collect::<Vec<_>>();
+    order.sort_by_key(|(_, _, entries)| entries.first().map(|entry| entry.sequence));
+
+    let mut expected = None;
+    let mut last = None;
+    for (index, log, entries) in order {
+        if entries.is_empty() {
+            recovery.logs[index].skipped = Some("no valid log entries");
+            continue;
+        }
+        if last.is_none() {
+            restore_base_block(data, base_block, log, recovery.checksum_valid);
+        }
+
+        for entry in entries {
+            if entry.sequence < base_block.secondary_sequence {
+                continue;
+            }
+            if expected.is_some() && expected != Some(entry.sequence) {
+                recovery.logs[index].skipped = Some("a gap in the sequence numbers");
+                break;
+            }
+
+            resize_bins(data, entry.hive_bins_size, &entry.pages)?;
+            for (offset, page) in &entry.pages {
+                if let Some(target) =
+                    data.get_mut(BASE_BLOCK_SIZE + offset..BASE_BLOCK_SIZE + offset + page.len())
+                {
+                    target.copy_from_slice(page);
+                }
+            }
+            base_block.hive_bins_size = entry.hive_bins_size;
+            recovery.logs[index].applied.push(entry.sequence);
+            expected = entry.sequence.checked_add(1);
+            last = Some(entry.sequence);
+        }
+        if recovery.logs[index].applied.is_empty() && recovery.logs[index].skipped.is_none() {
+            recovery.logs[index].skipped = Some("every entry is already in the hive");
+        }
+    }
+
+    match last {
+        Some(last) => {
+            base_block.primary_sequence = last.wrapping_add(1);
+            base_block.secondary_sequence = last.wrapping_add(1);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Takes the base block from a log when the hive's own is damaged
+fn restore_base_block(data: &mut [u8], base_block: &mut BaseBlock, log: &LogFile, valid: bool) {
+    if !valid {
+        *base_block = log.base_block.clone();
+        data[..LOG_BASE_BLOCK_SIZE].copy_from_slice(log.header);
+        data[LOG_BASE_BLOCK_SIZE..BASE_BLOCK_SIZE]
+            .iter_mut()
+            .for_each(|b| *b = 0);
+    }
+}
+
+/// Grows or shrinks the hive bins to `size` bytes.
+///
+/// The size comes from the log, so it has to be a whole number of hive bins and may only grow the hive by
+/// as much as the pages written along with it.
+fn resize_bins(
+    data: &mut Vec<u8>,
+    size: u32,
+    pages: &[(usize, &[u8])],
+) -> std::result::Result<(), &'static str> {
+    let size = size as usize;
+    let written = pages.iter().map(|(_, page)| page.len()).sum::<usize>();
+    if !size.is_multiple_of(HBIN_ALIGNMENT) {
+        return Err("the hive bins size in the log is not a multiple of 4096");
+    }
+    if size > data.len() - BASE_BLOCK_SIZE + written {
+        return Err("the log grows the hive bins beyond the pages it holds");
+    }
+    data.resize(BASE_BLOCK_SIZE + size, 0);
+    Ok(())
+}
+
+/// A single write recorded in an incremental log
+#[derive(Debug)]
+struct LogEntry<'a> {
+    sequence: u32,
+    hive_bins_size: u32,
+    /// Pages by their offset in the hive bins
+    pages: Vec<(usize, &'a [u8])>,
+}
+
+/// A transaction log, checked and split into the pages it holds
+#[derive(Debug)]
+struct LogFile<'a> {
+    /// The copy of the hive's base block at the start of the log
+    header: &'a [u8],
+    base_block: BaseBlock,
+    contents: LogContents<'a>,
+}
+
+#[derive(Debug)]
+enum LogContents<'a> {
+    /// Pages by their offset in the hive bins
+    Legacy(Vec<(usize, &'a [u8])>),
+    Incremental(Vec<LogEntry<'a>>),
+}
+
+impl<'a> LogFile<'a> {
+    fn parse(log: &'a [u8]) -> std::result::Result<LogFile<'a>, &'static str> {
+        let base_block = BaseBlock::parse(log).ok_or("not a transaction log")?;
+        if checksum(log) != base_block.checksum {
+            return Err("the base block checksum does not match");
+        }
+
+        let contents = match base_block.file_type {
+            1 | 2 => {
+                if base_block.is_dirty() {
+                    return Err("the log was not completely written");
+                }
+                LogContents::Legacy(
+                    legacy_pages(log, &base_block).ok_or("the dirty vector is damaged")?,
+                )
+            }
+            6 => LogContents::Incremental(log_entries(log)),
+            _ => return Err("not a transaction log"),
+        };
+        Ok(LogFile {
+            header: &log[..LOG_BASE_BLOCK_SIZE],
+            base_block,
+            contents,
+        })
+    }
+
+    fn format(&self) -> LogFormat {
+        match self.contents {
+            LogContents::Legacy(_) => LogFormat::Legacy,
+            LogContents::Incremental(_) => LogFormat::Incremental,
+        }
+    }
+}
+
+/// The dirty pages of a legacy log: a `DIRT` bitmap with one bit per 512 bytes of hive bins, followed by
+/// the pages whose bits are set, starting on the next sector
+fn legacy_pages<'a>(log: &'a [u8], base_block: &BaseBlock) -> Option<Vec<(usize, &'a [u8])>> {
+    let sector = LOG_BASE_BLOCK_SIZE * base_block.clustering_factor.max(1) as usize;
+    if log.get(sector..sector + 4)? != b"DIRT" {
+        return None;
+    }
+    let bits = base_block.hive_bins_size as usize / LEGACY_PAGE_SIZE;
+    let bitmap = log.get(sector + 4..sector + 4 + bits.div_ceil(8))?;
+
+    let mut position = (sector + 4 + bitmap.len()).div_ceil(sector) * sector;
+    let mut pages = Vec::new();
+    for page in (0..bits).filter(|page| bitmap[page / 8] & (1 << (page % 8)) != 0) {
+        pages.push((
+            page * LEGACY_PAGE_SIZE,
+            log.get(position..position + LEGACY_PAGE_SIZE)?,
+        ));
+        position += LEGACY_PAGE_SIZE;
+    }
+    Some(pages)
+}
+
+/// The valid `HvLE` entries at the start of an incremental log, up to the first damaged one or gap
+fn log_entries(log: &[u8]) -> Vec<LogEntry<'_>> {
+    let mut entries: Vec<LogEntry> = Vec::new();
+    let mut position = LOG_BASE_BLOCK_SIZE;
+    while let Some(entry) = log_entry(log, position) {
+        if let Some(previous) = entries.last() {
+            if previous.sequence.checked_add(1) != Some(entry.0.sequence) {
+                break;
+            }
+        }
+        position += entry.1;
+        entries.push(entry.0);
+    }
+    entries
+}
+
+/// Parses the log entry at `position`, returning it with its size
+fn log_entry(log: &[u8], position: usize) -> Option<(LogEntry<'_>, usize)> {
+    if log.get(position..position + 4)? != b"HvLE" {
+        return None;
+    }
+    let size = u32_at(log, position + 4)? as usize;
+    if size < LOG_ENTRY_HEADER_SIZE || !size.is_multiple_of(LOG_BASE_BLOCK_SIZE) {
+        return None;
+    }
+    let entry = log.get(position..position.checked_add(size)?)?;
+    if u64_at(entry, 24)? != marvin32(&entry[LOG_ENTRY_HEADER_SIZE..], MARVIN_SEED)
+        || u64_at(entry, 32)? != marvin32(&entry[..32], MARVIN_SEED)
+    {
+        return None;
+    }
+
+    let count = u32_at(entry, 20)? as usize;
+    let mut data = LOG_ENTRY_HEADER_SIZE.checked_add(count.checked_mul(8)?)?;
+    let mut pages = Vec::with_capacity(count.min(size / 8));
+    for i in 0..count {
+        let offset = u32_at(entry, LOG_ENTRY_HEADER_SIZE + i * 8)? as usize;
+        let length = u32_at(entry, LOG_ENTRY_HEADER_SIZE + i * 8 + 4)? as usize;
+        pages.push((offset, entry.get(data..data.checked_add(length)?)?));
+        data += length;
+    }
+
+    let hive_bins_size = u32_at(entry, 16)?;
+    if pages
+        .iter()
+        .any(|(offset, page)| offset + page.len() > hive_bins_size as usize)
+    {
+        return None;
+    }
+    Some((
+        LogEntry {
+            sequence: u32_at(entry, 12)?,
+            hive_bins_size,
+            pages,
+        },
+        size,
+    ))
+}
+
+/// The Marvin32 hash, as a 64-bit value with the high state word on top
+pub(crate) fn marvin32(data: &[u8], seed: u64) -> u64 {
+    fn block(low: &mut u32, high: &mut u32) {
+        *high ^= *low;
+        *low = low.rotate_left(20).wrapping_add(*high);
+        *high = high.rotate_left(9) ^ *low;
+        *low = low.rotate_left(27).wrapping_add(*high);
+        *high = high.rotate_left(19);
+    }
+
+    let mut low = seed as u32;
+    let mut high = (seed >> 32) as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        low = low.wrapping_add(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        block(&mut low, &mut high);
+    }
+
+    let last = tail
+        .iter()
+        .rev()
+        .fold(0x80u32, |last, byte| (last << 8) | *byte as u32);
+    low = low.wrapping_add(last);
+    block(&mut low, &mut high);
+    block(&mut low, &mut high);
+    ((high as u64) << 32) | low as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hive_writer::HiveWriter, snapshot::KeySnapshot, value::RawValue};
+
+    /// Three successive states of a hive
+    fn states() -> Vec<Vec<u8>> {
+        let mut root = KeySnapshot::new(r"\");
+        root.child_mut(r"Software\Microsoft\Windows\CurrentVersion\Run");
+        let mut states = vec![HiveWriter::new().to_bytes(&root).unwrap()];
+
+        let run = root.child_mut(r"Software\Microsoft\Windows\CurrentVersion\Run");
+        run.values.push((
+            "Updater".into(),
+            RawValue::new(
+                1,
+                "C:\\evil.exe\0"
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes)
+                    .collect::<Vec<u8>>(),
+            ),
+        ));
+        states.push(HiveWriter::new().to_bytes(&root).unwrap());
+
+        for i in 0..300 {
+            root.child_mut(format!(r"Software\Vendor{}", i))
+                .values
+                .push(("Data".into(), RawValue::new(3, vec![i as u8; 200])));
+        }
+        states.push(HiveWriter::new().to_bytes(&root).unwrap());
+        states
+    }
+
+    fn with_sequence(hive: &[u8], primary: u32, secondary: u32, file_type: u32) -> Vec<u8> {
+        let mut hive = hive.to_vec();
+        hive[4..8].copy_from_slice(&primary.to_le_bytes());
+        hive[8..12].copy_from_slice(&secondary.to_le_bytes());
+        hive[28..32].copy_from_slice(&file_type.to_le_bytes());
+        let checksum = checksum(&hive);
+        hive[508..512].copy_from_slice(&checksum.to_le_bytes());
+        hive
+    }
+
+    /// The pages of `page_size` bytes that differ between the hive bins of `old` and `new`
+    fn dirty_pages(old: &[u8], new: &[u8], page_size: usize) -> Vec<(usize, Vec<u8>)> {
+        new[BASE_BLOCK_SIZE..]
+            .chunks(page_size)
+            .enumerate()
+            .filter(|(i, page)| {
+                old.get(BASE_BLOCK_SIZE + i * page_size..BASE_BLOCK_SIZE + (i + 1) * page_size)
+                    != Some(*page)
+            })
+            .map(|(i, page)| (i * page_size, page.to_vec()))
+            .collect()
+    }
+
+    fn legacy_log(old: &[u8], new: &[u8], sequence: u32) -> Vec<u8> {
+        let mut log = with_sequence(new, sequence, sequence, 1)[..LOG_BASE_BLOCK_SIZE].to_vec();
+        let pages = dirty_pages(old, new, LEGACY_PAGE_SIZE);
+        let mut bitmap = vec![0u8; (new.len() - BASE_BLOCK_SIZE) / LEGACY_PAGE_SIZE / 8];
+        for (offset, _) in &pages {
+            let page = offset / LEGACY_PAGE_SIZE;
+            bitmap[page / 8] |= 1 << (page % 8);
+        }
+        log.extend_from_slice(b"DIRT");
+        log.extend(bitmap);
+        log.resize(log.len().div_ceil(512) * 512, 0);
+        pages.into_iter().for_each(|(_, page)| log.extend(page));
+        log
+    }
+
+    fn log_entry(old: &[u8], new: &[u8], sequence: u32) -> Vec<u8> {
+        let pages = dirty_pages(old, new, 0x1000);
+        let mut entry = vec![0; LOG_ENTRY_HEADER_SIZE];
+        entry[..4].copy_from_slice(b"HvLE");
+        entry[12..16].copy_from_slice(&sequence.to_le_bytes());
+        entry[16..20].copy_from_slice(&((new.len() - BASE_BLOCK_SIZE) as u32).to_le_bytes());
+        entry[20..24].copy_from_slice(&(pages.len() as u32).to_le_bytes());
+        for (offset, page) in &pages {
+            entry.extend_from_slice(&(*offset as u32).to_le_bytes());
+            entry.extend_from_slice(&(page.len() as u32).to_le_bytes());
+        }
+        pages.into_iter().for_each(|(_, page)| entry.extend(page));
+        entry.resize(entry.len().div_ceil(512) * 512, 0);
+
+        let size = entry.len() as u32;
+        entry[4..8].copy_from_slice(&size.to_le_bytes());
+        let hash = marvin32(&entry[LOG_ENTRY_HEADER_SIZE..], MARVIN_SEED);
+        entry[24..32].copy_from_slice(&hash.to_le_bytes());
+        let hash = marvin32(&entry[..32], MARVIN_SEED);
+        entry[32..40].copy_from_slice(&hash.to_le_bytes());
+        entry
+    }
+
+    fn incremental_log(header: &[u8], sequence: u32, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut log = with_sequence(header, sequence, sequence, 6)[..LOG_BASE_BLOCK_SIZE].to_vec();
+        entries
+            .iter()
+            .for_each(|entry| log.extend_from_slice(entry));
+        log
+    }
+
+    fn snapshot(hive: &Hive) -> KeySnapshot {
+        hive.root().unwrap().to_snapshot().unwrap()
+    }
+
+    #[test]
+    fn marvin() {
+        const SEED: u64 = 0x004f_b61a_001b_dbcc;
+        assert_eq!(marvin32(&[], SEED), 0x30ed_35c1_00cd_3c7d);
+        assert_eq!(marvin32(&[0xaf], SEED), 0x48e7_3fc7_7d75_ddc1);
+        assert_eq!(marvin32(&[0xe7, 0x0f], SEED), 0xb5f6_e1fc_485d_bff8);
+    }
+
+    #[test]
+    fn legacy() {
+        let states = states();
+        let dirty = with_sequence(&states[0], 2, 1, 0);
+        let log = legacy_log(&states[0], &states[2], 2);
+        let stale = legacy_log(&states[0], &states[1], 1);
+
+        let (hive, recovery) = Hive::recover(dirty.clone(), &[b"junk", &stale, &log]).unwrap();
+        assert!(recovery.was_dirty());
+        assert_eq!(recovery.logs[0].format, None);
+        assert!(recovery.logs[1].skipped.is_some());
+        assert_eq!(recovery.logs[2].applied, vec![2]);
+        assert_eq!(
+            snapshot(&hive),
+            snapshot(&Hive::from_bytes(states[2].clone()).unwrap())
+        );
+        let base_block = hive.base_block();
+        assert!(!base_block.is_dirty());
+        assert_eq!(base_block.file_type, 0);
+        assert_eq!(base_block.checksum, checksum(hive.as_bytes()));
+
+        // A damaged base block is taken from the log
+        let mut damaged = dirty;
+        damaged[36] ^= 0xff;
+        let (hive, recovery) = Hive::recover(damaged, &[&log]).unwrap();
+        assert!(!recovery.checksum_valid);
+        assert_eq!(
+            snapshot(&hive),
+            snapshot(&Hive::from_bytes(states[2].clone()).unwrap())
+        );
+    }
+
+    #[test]
+    fn incremental() {
+        let states = states();
+        let dirty = with_sequence(&states[0], 6, 5, 0);
+        let stale = log_entry(&states[2], &states[0], 4);
+        let log1 = incremental_log(
+            &states[0],
+            4,
+            &[stale, log_entry(&states[0], &states[1], 5)],
+        );
+        let log2 = incremental_log(&states[1], 6, &[log_entry(&states[1], &states[2], 6)]);
+
+        // Entries are ordered by sequence number, whichever log they are in
+        let (hive, recovery) = Hive::recover(dirty.clone(), &[&log2, &log1]).unwrap();
+        assert_eq!(recovery.logs[0].applied, vec![6]);
+        assert_eq!(recovery.logs[1].applied, vec![5]);
+        assert_eq!(
+            snapshot(&hive),
+            snapshot(&Hive::from_bytes(states[2].clone()).unwrap())
+        );
+        assert_eq!(hive.base_block().primary_sequence, 7);
+        assert_eq!(hive.base_block().secondary_sequence, 7);
+        assert_eq!(hive.base_block().checksum, checksum(hive.as_bytes()));
+        assert_eq!(
+            hive.base_block().hive_bins_size as usize,
+            states[2].len() - BASE_BLOCK_SIZE
+        );
+
+        // Replay stops at a damaged entry
+        let mut damaged = log2.clone();
+        damaged[LOG_BASE_BLOCK_SIZE + 100] ^= 1;
+        let (hive, recovery) = Hive::recover(dirty.clone(), &[&log1, &damaged]).unwrap();
+        assert_eq!(recovery.logs[1].skipped, Some("no valid log entries"));
+        assert_eq!(
+            snapshot(&hive),
+            snapshot(&Hive::from_bytes(states[1].clone()).unwrap())
+        );
+
+        // ... and at a gap in the sequence
+        let gap = incremental_log(&states[1], 7, &[log_entry(&states[1], &states[2], 7)]);
+        let (hive, recovery) = Hive::recover(dirty.clone(), &[&log1, &gap]).unwrap();
+        assert_eq!(
+            recovery.logs[1].skipped,
+            Some("a gap in the sequence numbers")
+        );
+        assert_eq!(
+            snapshot(&hive),
+            snapshot(&Hive::from_bytes(states[1].clone()).unwrap())
+        );
+
+        // Clean hives are left alone
+        let clean = with_sequence(&states[0], 5, 5, 0);
+        let (hive, recovery) = Hive::recover(clean.clone(), &[&log1, &log2]).unwrap();
+        assert!(!recovery.was_dirty() && !recovery.replayed());
+        assert_eq!(recovery.logs[0].skipped, Some("the hive is clean"));
+        assert_eq!(hive.as_bytes(), &clean[..]);
+    }
+
+    #[test]
+    fn oversized_bins() {
+        let states = states();
+        let dirty = with_sequence(&states[0], 2, 1, 0);
+
+        // A 1 KiB log whose single entry claims almost 4 GiB of hive bins
+        let mut entry = vec![0; LOG_BASE_BLOCK_SIZE];
+        entry[..4].copy_from_slice(b"HvLE");
+        entry[4..8].copy_from_slice(&(LOG_BASE_BLOCK_SIZE as u32).to_le_bytes());
+        entry[12..16].copy_from_slice(&2u32.to_le_bytes());
+        entry[16..20].copy_from_slice(&0xffff_f000u32.to_le_bytes());
+        let hash = marvin32(&entry[LOG_ENTRY_HEADER_SIZE..], MARVIN_SEED);
+        entry[24..32].copy_from_slice(&hash.to_le_bytes());
+        let hash = marvin32(&entry[..32], MARVIN_SEED);
+        entry[32..40].copy_from_slice(&hash.to_le_bytes());
+        let log = incremental_log(&states[0], 2, &[entry]);
+        assert_eq!(log.len(), 1024);
+        let error = Hive::recover(dirty.clone(), &[&log]).err().unwrap();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::Corrupt { reason, .. } if reason.contains("beyond the pages")
+        ));
+
+        // ... and a size that is not a whole number of bins
+        let mut log = legacy_log(&states[0], &states[1], 2);
+        let size = states[1].len() as u32 - BASE_BLOCK_SIZE as u32 - 512;
+        log[40..44].copy_from_slice(&size.to_le_bytes());
+        let checksum = checksum(&log);
+        log[508..512].copy_from_slice(&checksum.to_le_bytes());
+        let error = Hive::recover(dirty, &[&log]).err().unwrap();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::Corrupt { reason, .. } if reason.contains("multiple of 4096")
+        ));
+    }
+
+    #[test]
+    fn open_with_logs() {
+        let states = states();
+        let directory = std::env::temp_dir().join(format!("winregnt-logs-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let hive = directory.join("NTUSER.DAT");
+        let dirty = with_sequence(&states[0], 2, 1, 0);
+        std::fs::write(&hive, &dirty).unwrap();
+        std::fs::write(
+            directory.join("ntuser.dat.LOG1"),
+            legacy_log(&states[0], &states[1], 2),
+        )
+        .unwrap();
+
+        let (recovered, recovery) = Hive::open_with_logs(&hive).unwrap();
+        assert!(recovery.replayed());
+        assert_eq!(
+            snapshot(&recovered),
+            snapshot(&Hive::from_bytes(states[1].clone()).unwrap())
+        );
+        assert_eq!(std::fs::read(&hive).unwrap(), dirty);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,640 @@
+use crate::{
+    error::{Error, Operation},
+    hive::{
+        corrupt, u16_at, u32_at, Cells, Hive, HiveKey, KeyNode, ValueKey, KEY_COMP_NAME,
+        VALUE_COMP_NAME,
+    },
+    path::{upcase, RegPath, RegPathBuf},
+    value::RawValue,
+    value_name::ValueName,
+    Result,
+};
+use std::{borrow::Cow, fmt};
+
+/// A key or value name borrowed from a hive.
+///
+/// Names are stored either as Latin-1 ("compressed") or as UTF-16LE; `HiveName` decodes them one code unit
+/// at a time instead of copying them.
+#[derive(Clone, Copy)]
+pub struct HiveName<'a> {
+    bytes: &'a [u8],
+    compressed: bool,
+}
+
+impl<'a> HiveName<'a> {
+    /// The name as stored in the hive
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// `true` if the name is stored as Latin-1 rather than UTF-16LE
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// The length of the name in UTF-16 code units
+    pub fn len(&self) -> usize {
+        if self.compressed {
+            self.bytes.len()
+        } else {
+            self.bytes.len() / 2
+        }
+    }
+
+    /// `true` if the name is empty, as it is for the default value
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The UTF-16 code units of the name
+    pub fn units(&self) -> impl Iterator<Item = u16> + 'a {
+        let (bytes, compressed) = (self.bytes, self.compressed);
+        (0..self.len()).map(move |i| {
+            if compressed {
+                bytes[i] as u16
+            } else {
+                u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]])
+            }
+        })
+    }
+
+    /// Copies the name into a UTF-16 buffer
+    pub fn to_wide(&self) -> Vec<u16> {
+        self.units().collect()
+    }
+
+    /// Compares the name with the UTF-16 `name` the way the configuration manager does, ignoring case
+    pub fn eq_ignore_case(&self, name: &[u16]) -> bool {
+        self.len() == name.len() && self.units().zip(name).all(|(a, b)| upcase(a) == upcase(*b))
+    }
+}
+
+impl<'a> fmt::Debug for HiveName<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:?}", self.to_string())
+    }
+}
+
+impl<'a> fmt::Display for HiveName<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        std::char::decode_utf16(self.units())
+            .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+            .try_for_each(|c| fmt::Write::write_char(fmt, c))
+    }
+}
+
+/// A key in an offline [`Hive`], borrowed without its path.
+///
+/// `HiveNode` is the zero-copy counterpart of [`HiveKey`]: names, class names, security descriptors and value
+/// data are borrowed from the hive buffer, and subkeys and values are decoded lazily as they are iterated, so
+/// walking a large (possibly memory mapped) hive allocates nothing per key. Errors carry the offset of the
+/// damaged structure but, as a node does not know where it is in the tree, an empty path; use
+/// [`HiveNode::with_path`] to switch to the owned API.
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::Hive;
+/// let hive = Hive::open("SOFTWARE").unwrap();
+/// let root = hive.root_node().unwrap();
+/// for key in root.subkeys().unwrap() {
+///     let key = key.unwrap();
+///     println!("{} ({} values)", key.name(), key.value_count());
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct HiveNode<'a> {
+    cells: Cells<'a>,
+    offset: u32,
+    node: KeyNode<'a>,
+}
+
+impl<B: AsRef<[u8]>> Hive<B> {
+    /// The root key of the hive, borrowed without a path
+    pub fn root_node(&self) -> Result<HiveNode<'_>> {
+        HiveNode::new(
+            self.cells(),
+            self.base_block().root_cell,
+            Operation::OpenKey,
+        )
+    }
+    /// The key node at `offset`, relative to the first hive bin
+    pub(crate) fn node_at(&self, offset: u32) -> Option<HiveNode<'_>> {
+        HiveNode::new(self.cells(), offset, Operation::OpenKey).ok()
+    }
+}
+
+impl<'a> HiveKey<'a> {
+    /// The key as a borrowed [`HiveNode`]
+    pub fn node(&self) -> HiveNode<'a> {
+        HiveNode {
+            cells: self.cells,
+            offset: self.offset,
+            node: self.node,
+        }
+    }
+}
+
+impl<'a> HiveNode<'a> {
+    fn new(cells: Cells<'a>, offset: u32, operation: Operation) -> Result<HiveNode<'a>> {
+        let node = cells
+            .key(offset)
+            .ok_or_else(|| corrupt(operation, &RegPathBuf::new(), offset, "invalid key node"))?;
+        Ok(HiveNode {
+            cells,
+            offset,
+            node,
+        })
+    }
+
+    fn corrupt(&self, operation: Operation, offset: u32, reason: &'static str) -> Error {
+        corrupt(operation, &RegPathBuf::new(), offset, reason)
+    }
+
+    /// The name of the key
+    pub fn name(&self) -> HiveName<'a> {
+        HiveName {
+            bytes: self.node.name,
+            compressed: self.node.flags & KEY_COMP_NAME != 0,
+        }
+    }
+
+    /// The offset of the key node, relative to the first hive bin
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// When the key was last written, as a `FILETIME`
+    pub fn last_write_time(&self) -> u64 {
+        self.node.last_write_time
+    }
+
+    /// The number of subkeys the key node claims to have
+    pub fn subkey_count(&self) -> u32 {
+        self.node.subkey_count
+    }
+
+    /// The number of values the key node claims to have
+    pub fn value_count(&self) -> u32 {
+        self.node.value_count
+    }
+
+    /// The class name of the key as UTF-16LE bytes, if it has one
+    pub fn class(&self) -> Result<Option<&'a [u8]>> {
+        self.cells
+            .class(&self.node)
+            .ok_or_else(|| self.corrupt(Operation::OpenKey, self.node.class, "invalid class name"))
+    }
+
+    /// The self-relative security descriptor of the key
+    pub fn security(&self) -> Result<&'a [u8]> {
+        self.cells.security(self.node.security).ok_or_else(|| {
+            self.corrupt(
+                Operation::OpenKey,
+                self.node.security,
+                "invalid security cell",
+            )
+        })
+    }
+
+    /// Iterates over the direct subkeys of the key, in the order the hive stores them.
+    ///
+    /// The iterator stops after the first error.
+    pub fn subkeys(&self) -> Result<Subkeys<'a>> {
+        let mut subkeys = Subkeys {
+            cells: self.cells,
+            index: None,
+            leaf: None,
+        };
+        if self.node.subkey_count == 0 {
+            return Ok(subkeys);
+        }
+
+        let list = List::new(self.cells, self.node.subkey_list, true).ok_or_else(|| {
+            self.corrupt(
+                Operation::EnumerateKeys,
+                self.node.subkey_list,
+                "invalid subkey list",
+            )
+        })?;
+        if list.index {
+            subkeys.index = Some(list);
+        } else {
+            subkeys.leaf = Some(list);
+        }
+        Ok(subkeys)
+    }
+
+    /// Finds the direct subkey called `name`, ignoring case
+    pub fn subkey(&self, name: &RegPath) -> Result<Option<HiveNode<'a>>> {
+        for subkey in self.subkeys()? {
+            let subkey = subkey?;
+            if subkey.name().eq_ignore_case(name.as_wide()) {
+                return Ok(Some(subkey));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Iterates over the values of the key, in the order the hive stores them.
+    ///
+    /// The iterator stops after the first error.
+    pub fn values(&self) -> Result<Values<'a>> {
+        if self.node.value_count == 0 {
+            return Ok(Values {
+                cells: self.cells,
+                list: &[],
+                next: 0,
+            });
+        }
+
+        let list = self
+            .cells
+            .cell(self.node.value_list)
+            .and_then(|cell| cell.get(..self.node.value_count as usize * 4))
+            .ok_or_else(|| {
+                self.corrupt(
+                    Operation::EnumerateValues,
+                    self.node.value_list,
+                    "invalid value list",
+                )
+            })?;
+        Ok(Values {
+            cells: self.cells,
+            list,
+            next: 0,
+        })
+    }
+
+    /// Finds the value called `name`, ignoring case
+    pub fn value(&self, name: &ValueName) -> Result<Option<HiveValue<'a>>> {
+        for value in self.values()? {
+            let value = value?;
+            if value.name().eq_ignore_case(name.as_wide()) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The key as an owned [`HiveKey`] at `path`
+    pub fn with_path<P: Into<RegPathBuf>>(&self, path: P) -> HiveKey<'a> {
+        HiveKey {
+            cells: self.cells,
+            offset: self.offset,
+            node: self.node,
+            path: path.into(),
+        }
+    }
+}
+
+/// A subkey list being walked
+#[derive(Clone, Copy, Debug)]
+struct List<'a> {
+    offset: u32,
+    cell: &'a [u8],
+    stride: usize,
+    index: bool,
+    count: usize,
+    next: usize,
+}
+
+impl<'a> List<'a> {
+    fn new(cells: Cells<'a>, offset: u32, allow_index: bool) -> Option<List<'a>> {
+        let cell = cells.cell(offset)?;
+        let (stride, index) = match cell.get(..2)? {
+            b"lf" | b"lh" => (8, false),
+            b"li" => (4, false),
+            b"ri" if allow_index => (4, true),
+            _ => return None,
+        };
+        Some(List {
+            offset,
+            cell,
+            stride,
+            index,
+            count: u16_at(cell, 2)? as usize,
+            next: 0,
+        })
+    }
+
+    /// The next offset in the list, `Some(None)` if it does not fit in the cell
+    fn next(&mut self) -> Option<Option<u32>> {
+        if self.next == self.count {
+            return None;
+        }
+        let offset = u32_at(self.cell, 4 + self.next * self.stride);
+        self.next += 1;
+        Some(offset)
+    }
+}
+
+/// An iterator over the subkeys of a [`HiveNode`]
+#[derive(Clone, Debug)]
+pub struct Subkeys<'a> {
+    cells: Cells<'a>,
+    index: Option<List<'a>>,
+    leaf: Option<List<'a>>,
+}
+
+impl<'a> Subkeys<'a> {
+    fn fail(&mut self, offset: u32, reason: &'static str) -> Option<Result<HiveNode<'a>>> {
+        self.index = None;
+        self.leaf = None;
+        Some(Err(corrupt(
+            Operation::EnumerateKeys,
+            &RegPathBuf::new(),
+            offset,
+            reason,
+        )))
+    }
+}
+
+impl<'a> Iterator for Subkeys<'a> {
+    type Item = Result<HiveNode<'a>>;
+
+    fn next(&mut self) -> Option<Result<HiveNode<'a>>> {
+        loop {
+            if let Some(leaf) = &mut self.leaf {
+                match leaf.next() {
+                    Some(Some(offset)) => {
+                        let node = HiveNode::new(self.cells, offset, Operation::EnumerateKeys);
+                        if node.is_err() {
+                            self.index = None;
+                            self.leaf = None;
+                        }
+                        return Some(node);
+                    }
+                    Some(None) => {
+                        let offset = leaf.offset;
+                        return self.fail(offset, "invalid subkey list");
+                    }
+                    None => self.leaf = None,
+                }
+            }
+
+            let index = self.index.as_mut()?;
+            match index.next() {
+                Some(Some(offset)) => match List::new(self.cells, offset, false) {
+                    Some(leaf) => self.leaf = Some(leaf),
+                    None => return self.fail(offset, "invalid subkey list"),
+                },
+                Some(None) => {
+                    let offset = index.offset;
+                    return self.fail(offset, "invalid subkey list");
+                }
+                None => {
+                    self.index = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the values of a [`HiveNode`]
+#[derive(Clone, Debug)]
+pub struct Values<'a> {
+    cells: Cells<'a>,
+    list: &'a [u8],
+    next: usize,
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = Result<HiveValue<'a>>;
+
+    fn next(&mut self) -> Option<Result<HiveValue<'a>>> {
+        let offset = u32_at(self.list, self.next)?;
+        self.next += 4;
+        match self.cells.value(offset) {
+            Some(value) => Some(Ok(HiveValue {
+                cells: self.cells,
+                offset,
+                value,
+            })),
+            None => {
+                self.list = &[];
+                Some(Err(corrupt(
+                    Operation::EnumerateValues,
+                    &RegPathBuf::new(),
+                    offset,
+                    "invalid value key",
+                )))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.list.len().saturating_sub(self.next) / 4))
+    }
+}
+
+/// A value of a [`HiveNode`], borrowed from the hive
+#[derive(Clone, Copy, Debug)]
+pub struct HiveValue<'a> {
+    cells: Cells<'a>,
+    offset: u32,
+    value: ValueKey<'a>,
+}
+
+impl<'a> HiveValue<'a> {
+    /// The name of the value, empty for the default value
+    pub fn name(&self) -> HiveName<'a> {
+        HiveName {
+            bytes: self.value.name,
+            compressed: self.value.flags & VALUE_COMP_NAME != 0,
+        }
+    }
+
+    /// The offset of the value key, relative to the first hive bin
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// The registry type of the value, such as `REG_SZ` (1)
+    pub fn value_type(&self) -> u32 {
+        self.value.data_type
+    }
+
+    /// The data of the value.
+    ///
+    /// The data is borrowed from the hive, except for values larger than 16344 bytes in hives of version 1.4
+    /// and later, which are split into segments and have to be reassembled.
+    pub fn data(&self) -> Result<Cow<'a, [u8]>> {
+        self.cells.value_data(&self.value).ok_or_else(|| {
+            corrupt(
+                Operation::EnumerateValues,
+                &RegPathBuf::new(),
+                self.offset,
+                "invalid value data",
+            )
+            .with_value_name(self.name().to_wide())
+        })
+    }
+
+    /// Copies the type and data of the value into a [`RawValue`]
+    pub fn to_raw(&self) -> Result<RawValue> {
+        Ok(RawValue::new(self.value_type(), self.data()?.into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        source::KeySource,
+        test_support::{damaged, hive, truncated},
+    };
+
+    fn sample() -> Vec<u8> {
+        hive(|root| {
+            for i in 0..600 {
+                let child = root.child_mut(format!("Key{:03}", i));
+                child
+                    .values
+                    .push(("Small".into(), RawValue::new(4, vec![i as u8, 0, 0, 0])));
+            }
+            let child = root.child_mut("Key042");
+            child.class = Some("Class".encode_utf16().collect());
+            child
+                .values
+                .push(("".into(), RawValue::new(3, vec![7; 100])));
+            child
+                .values
+                .push(("Big".into(), RawValue::new(3, vec![9; 40_000])));
+            root.child_mut("Wide\u{263a}");
+        })
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn borrowed_walk() {
+        let bytes = sample();
+        let hive = Hive::from_bytes(&bytes[..]).unwrap();
+        let root = hive.root_node().unwrap();
+        let names = root
+            .subkeys()
+            .unwrap()
+            .map(|key| key.unwrap().name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 601);
+        assert_eq!(names[0], "Key000");
+        assert_eq!(names[600], "Wide\u{263a}");
+
+        let key = root
+            .subkey(RegPath::from_wide(&wide("KEY042")))
+            .unwrap()
+            .unwrap();
+        assert!(key.name().is_compressed());
+        assert_eq!(key.class().unwrap().unwrap(), &b"C\0l\0a\0s\0s\0"[..]);
+        assert!(!key.security().unwrap().is_empty());
+        assert_eq!(key.value_count(), 3);
+
+        let small = key.value(&ValueName::from("small")).unwrap().unwrap();
+        assert!(matches!(
+            small.data().unwrap(),
+            Cow::Borrowed(&[42, 0, 0, 0])
+        ));
+        let default = key.value(&ValueName::default()).unwrap().unwrap();
+        assert!(default.name().is_empty());
+        assert!(matches!(default.data().unwrap(), Cow::Borrowed(data) if data == &[7; 100][..]));
+        let big = key.value(&ValueName::from("Big")).unwrap().unwrap();
+        assert!(matches!(big.data().unwrap(), Cow::Owned(data) if data == vec![9; 40_000]));
+        assert!(key.value(&ValueName::from("Missing")).unwrap().is_none());
+
+        let smiley = root
+            .subkey(RegPath::from_wide(&wide("wide\u{263a}")))
+            .unwrap()
+            .unwrap();
+        assert!(!smiley.name().is_compressed());
+        assert_eq!(smiley.name().to_wide(), wide("Wide\u{263a}"));
+    }
+
+    #[test]
+    fn owned_and_borrowed_agree() {
+        let hive = Hive::from_bytes(sample()).unwrap();
+        let owned = hive.root().unwrap().subkeys().unwrap();
+        let borrowed = hive.root_node().unwrap().subkeys().unwrap();
+        for (owned, borrowed) in owned.iter().zip(borrowed) {
+            let borrowed = borrowed.unwrap();
+            assert_eq!(owned.offset(), borrowed.offset());
+            assert_eq!(owned.node().offset(), borrowed.offset());
+            assert_eq!(owned.name().as_wide(), &borrowed.name().to_wide()[..]);
+            let values = borrowed
+                .values()
+                .unwrap()
+                .map(|value| value.unwrap().to_raw().unwrap())
+                .collect::<Vec<_>>();
+            let expected = owned
+                .values()
+                .unwrap()
+                .into_iter()
+                .map(|value| value.raw().clone())
+                .collect::<Vec<_>>();
+            assert_eq!(values, expected);
+        }
+
+        let key = hive
+            .root_node()
+            .unwrap()
+            .with_path(r"\Registry\Machine\SOFTWARE");
+        assert_eq!(key.path().to_string(), r"\Registry\Machine\SOFTWARE");
+        assert_eq!(key.subkey_names().unwrap().len(), 601);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped() {
+        let path = std::env::temp_dir().join(format!("winregnt-map-{}.dat", std::process::id()));
+        std::fs::write(&path, sample()).unwrap();
+        let hive = unsafe { Hive::map(&path) }.unwrap();
+        assert_eq!(hive.root_node().unwrap().subkeys().unwrap().count(), 601);
+        drop(hive);
+        std::fs::remove_file(&path).unwrap();
+        assert!(unsafe { Hive::map(&path) }.is_err());
+    }
+
+    fn walk(node: HiveNode, depth: usize) -> usize {
+        let _ = node.class();
+        let _ = node.security();
+        let _ = node.name().to_string();
+        if let Ok(values) = node.values() {
+            for value in values.flatten() {
+                let _ = value.name().to_string();
+                let _ = value.data();
+            }
+        }
+        match node.subkeys() {
+            Ok(subkeys) if depth < 8 => {
+                1 + subkeys
+                    .take(1000)
+                    .flatten()
+                    .map(|subkey| walk(subkey, depth + 1))
+                    .sum::<usize>()
+            }
+            _ => 1,
+        }
+    }
+
+    #[test]
+    fn damaged_input_does_not_panic() {
+        let bytes = sample();
+        truncated(&bytes, 0, 211, |bytes| {
+            if let Ok(hive) = Hive::from_bytes(bytes) {
+                if let Ok(root) = hive.root_node() {
+                    walk(root, 0);
+                }
+            }
+        });
+        damaged(&bytes, 0, 200, 32, |bytes| {
+            if let Ok(hive) = Hive::from_bytes(&bytes[..]) {
+                if let Ok(root) = hive.root_node() {
+                    walk(root, 0);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,723 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    hive::{
+        BaseBlock, BIG_DATA_SEGMENT_SIZE, DATA_INLINE, HBIN_ALIGNMENT, HBIN_HEADER_SIZE,
+        KEY_COMP_NAME, KEY_HIVE_ENTRY, KEY_NODE_SIZE, KEY_NO_DELETE, NO_CELL, VALUE_COMP_NAME,
+        VALUE_KEY_SIZE,
+    },
+    path::{upcase, RegPath},
+    snapshot::KeySnapshot,
+    value::RawValue,
+    value_name::ValueName,
+    Result,
+};
+use std::{collections::HashMap, io::Write};
+
+/// Largest number of entries written to a single subkey list before it is split under an index root
+const MAX_LEAF_ENTRIES: usize = 1012;
+
+/// Writes [`KeySnapshot`] trees as offline hive files (the `regf` format used by Windows NT and later).
+///
+/// The output is a complete, clean hive: a base block with a valid checksum, followed by hive bins holding
+/// a key node for every key, value keys and data (split into big data segments where the format requires
+/// it), sorted and hashed subkey lists, class names and shared security cells. Names containing embedded
+/// `null`s or characters outside Latin-1 are stored exactly.
+///
+/// Keys without a security descriptor inherit the one of their parent; if the root has none, a default
+/// descriptor granting full control to `SYSTEM` and `Administrators` and read access to `Users` is used.
+///
+/// # Examples
+///
+/// ```
+/// use winregnt::{Hive, HiveWriter, KeySnapshot, KeySource, RawValue, RegPathBuf};
+/// let mut root = KeySnapshot::new(r"\Registry\Machine\SOFTWARE");
+/// root.child_mut("Vendor")
+///     .values
+///     .push(("Enabled".into(), RawValue::new(4, vec![1, 0, 0, 0])));
+///
+/// let bytes = HiveWriter::new().to_bytes(&root).unwrap();
+/// let hive = Hive::from_bytes(bytes).unwrap();
+/// let vendor = hive.root().unwrap().open_child(&RegPathBuf::from("Vendor")).unwrap();
+/// assert_eq!(vendor.values().unwrap().len(), 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct HiveWriter {
+    minor_version: u32,
+    file_name: Vec<u16>,
+    last_written: Option<u64>,
+}
+
+impl Default for HiveWriter {
+    fn default() -> Self {
+        HiveWriter {
+            minor_version: 5,
+            file_name: Vec::new(),
+            last_written: None,
+        }
+    }
+}
+
+impl HiveWriter {
+    /// A writer producing version 1.5 hives, as written by Windows Vista and later
+    pub fn new() -> HiveWriter {
+        HiveWriter::default()
+    }
+
+    /// The minor format version to write, from 3 to 6.
+    ///
+    /// Versions before 1.5 use `lf` instead of `lh` subkey lists, and version 1.3 stores large values in a
+    /// single cell instead of big data segments.
+    pub fn minor_version(&mut self, minor_version: u32) -> &mut HiveWriter {
+        self.minor_version = minor_version;
+        self
+    }
+
+    /// The file name recorded in the base block (only its last 31 characters are kept)
+    pub fn file_name<S: AsRef<str>>(&mut self, file_name: S) -> &mut HiveWriter {
+        self.file_name = file_name.as_ref().encode_utf16().collect();
+        self
+    }
+
+    /// The last written time recorded in the base block, as a `FILETIME`.
+    ///
+    /// Defaults to the last write time of the root key.
+    pub fn last_written(&mut self, filetime: u64) -> &mut HiveWriter {
+        self.last_written = Some(filetime);
+        self
+    }
+
+    /// Builds the complete hive file for `root` and its subkeys
+    pub fn to_bytes(&self, root: &KeySnapshot) -> Result<Vec<u8>> {
+        if !(3..=6).contains(&self.minor_version) {
+            return Err(Error::new(
+                Operation::WriteHive,
+                &root.path,
+                ErrorKind::Unsupported("hive minor versions other than 3 to 6"),
+            ));
+        }
+
+        let last_written = self.last_written.unwrap_or(root.last_write_time);
+        let mut builder = Builder {
+            bins: Bins::new(last_written),
+            minor_version: self.minor_version,
+            security: HashMap::new(),
+        };
+
+        let default_security = default_security();
+        let root_security = root.security.as_deref().unwrap_or(&default_security);
+        builder.write_security(root, root_security)?;
+        let root_cell = builder.write_key(root, NO_CELL, root_security, true)?;
+        let bins = builder.bins.finish();
+
+        let base_block = BaseBlock {
+            primary_sequence: 1,
+            secondary_sequence: 1,
+            last_written,
+            major_version: 1,
+            minor_version: self.minor_version,
+            file_type: 0,
+            file_format: 1,
+            root_cell,
+            hive_bins_size: bins.len() as u32,
+            clustering_factor: 1,
+            file_name: self.file_name.clone(),
+            checksum: 0,
+        };
+        let mut hive = base_block.to_bytes();
+        hive.extend(bins);
+        Ok(hive)
+    }
+
+    /// Writes the hive file for `root` and its subkeys to `writer`
+    pub fn write<W: Write>(&self, root: &KeySnapshot, mut writer: W) -> Result<W> {
+        let hive = self.to_bytes(root)?;
+        writer
+            .write_all(&hive)
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::new(Operation::WriteHive, &root.path, ErrorKind::Io(e)))?;
+        Ok(writer)
+    }
+}
+
+/// Hive bins under construction
+struct Bins {
+    data: Vec<u8>,
+    /// Offset of the bin cells are currently allocated from
+    bin: usize,
+    timestamp: u64,
+}
+
+impl Bins {
+    fn new(timestamp: u64) -> Bins {
+        let mut bins = Bins {
+            data: Vec::new(),
+            bin: 0,
+            timestamp,
+        };
+        bins.open_bin(0);
+        bins
+    }
+
+    fn bin_end(&self) -> usize {
+        self.bin
+            + u32::from_le_bytes([
+                self.data[self.bin + 8],
+                self.data[self.bin + 9],
+                self.data[self.bin + 10],
+                self.data[self.bin + 11],
+            ]) as usize
+    }
+
+    /// Starts a new bin large enough for a cell of `cell_size` bytes
+    fn open_bin(&mut self, cell_size: usize) {
+        let size = round_up(HBIN_HEADER_SIZE + cell_size, HBIN_ALIGNMENT);
+        self.bin = self.data.len();
+
+        let mut header = [0; HBIN_HEADER_SIZE];
+        header[..4].copy_from_slice(b"hbin");
+        header[4..8].copy_from_slice(&(self.bin as u32).to_le_bytes());
+        header[8..12].copy_from_slice(&(size as u32).to_le_bytes());
+        if self.bin == 0 {
+            header[20..28].copy_from_slice(&self.timestamp.to_le_bytes());
+        }
+        self.data.extend_from_slice(&header);
+    }
+
+    /// Marks the rest of the current bin as a single free cell
+    fn close_bin(&mut self) {
+        let remaining = self.bin_end() - self.data.len();
+        if remaining > 0 {
+            self.data
+                .extend_from_slice(&(remaining as i32).to_le_bytes());
+            self.data.resize(self.data.len() + remaining - 4, 0);
+        }
+    }
+
+    /// Overwrites a `u32` at `field` in the payload of the cell at `cell`
+    fn patch(&mut self, cell: u32, field: usize, value: u32) {
+        let start = cell as usize + 4 + field;
+        self.data[start..start + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.close_bin();
+        self.data
+    }
+}
+
+impl CellAllocator for Bins {
+    fn alloc(&mut self, payload: &[u8]) -> u32 {
+        let size = round_up(payload.len() + 4, 8);
+        if self.data.len() + size > self.bin_end() {
+            self.close_bin();
+            self.open_bin(size);
+        }
+
+        let offset = self.data.len();
+        self.data.extend_from_slice(&(-(size as i32)).to_le_bytes());
+        self.data.extend_from_slice(payload);
+        self.data.resize(offset + size, 0);
+        offset as u32
+    }
+}
+
+struct Builder {
+    bins: Bins,
+    minor_version: u32,
+    /// Security cells by descriptor, with the number of keys using each
+    security: HashMap<Vec<u8>, (u32, u32)>,
+}
+
+impl Builder {
+    /// Writes one security cell for every distinct descriptor in the tree, linked into a circular list
+    fn write_security(&mut self, root: &KeySnapshot, root_security: &[u8]) -> Result<()> {
+        let mut descriptors: Vec<(&[u8], u32)> = Vec::new();
+        let mut pending = vec![(root, root_security)];
+        while let Some((key, inherited)) = pending.pop() {
+            let descriptor = key.security.as_deref().unwrap_or(inherited);
+            match descriptors.iter_mut().find(|(d, _)| *d == descriptor) {
+                Some((_, count)) => *count += 1,
+                None => descriptors.push((descriptor, 1)),
+            }
+            pending.extend(key.children.iter().rev().map(|child| (child, descriptor)));
+        }
+
+        let mut offsets = Vec::with_capacity(descriptors.len());
+        for (descriptor, count) in &descriptors {
+            let mut payload = Vec::with_capacity(20 + descriptor.len());
+            payload.extend_from_slice(b"sk");
+            payload.extend_from_slice(&[0; 10]);
+            payload.extend_from_slice(&count.to_le_bytes());
+            payload
+                .extend_from_slice(&length(descriptor.len(), u32::MAX, &root.path)?.to_le_bytes());
+            payload.extend_from_slice(descriptor);
+            let offset = self.bins.alloc(&payload);
+            offsets.push(offset);
+            self.security.insert(descriptor.to_vec(), (offset, *count));
+        }
+
+        for (i, offset) in offsets.iter().enumerate() {
+            let next = offsets[(i + 1) % offsets.len()];
+            let previous = offsets[(i + offsets.len() - 1) % offsets.len()];
+            self.bins.patch(*offset, 4, next);
+            self.bins.patch(*offset, 8, previous);
+        }
+        Ok(())
+    }
+
+    /// Writes `key` and everything below it, returning the offset of its key node
+    fn write_key(
+        &mut self,
+        key: &KeySnapshot,
+        parent: u32,
+        inherited_security: &[u8],
+        root: bool,
+    ) -> Result<u32> {
+        let name = match key.path.name() {
+            Some(name) => name.as_wide(),
+            None => &ROOT_NAME,
+        };
+        let flags = if root {
+            KEY_HIVE_ENTRY | KEY_NO_DELETE
+        } else {
+            0
+        };
+        let node = key_node(&key.path, name, flags, key.last_write_time, parent)?;
+        let offset = self.bins.alloc(&node);
+
+        let security = key.security.as_deref().unwrap_or(inherited_security);
+        self.bins.patch(offset, 44, self.security[security].0);
+
+        if !key.values.is_empty() {
+            let mut list = Vec::with_capacity(key.values.len() * 4);
+            for (name, raw) in &key.values {
+                list.extend_from_slice(&self.write_value(key, name, raw)?.to_le_bytes());
+            }
+            let list = self.bins.alloc(&list);
+            self.bins.patch(offset, 36, key.values.len() as u32);
+            self.bins.patch(offset, 40, list);
+            self.bins.patch(
+                offset,
+                60,
+                max(key.values.iter().map(|(name, _)| name.as_wide().len() * 2)),
+            );
+            self.bins.patch(
+                offset,
+                64,
+                max(key.values.iter().map(|(_, raw)| raw.data.len())),
+            );
+        }
+
+        if let Some(class) = &key.class {
+            let bytes = class
+                .iter()
+                .flat_map(|c| c.to_le_bytes())
+                .collect::<Vec<u8>>();
+            let class_length = length(bytes.len(), u16::MAX as u32, &key.path)?;
+            let class_cell = self.bins.alloc(&bytes);
+            self.bins.patch(offset, 48, class_cell);
+            let field = offset as usize + 4 + 74;
+            self.bins.data[field..field + 2].copy_from_slice(&class_length.to_le_bytes()[..2]);
+        }
+
+        if !key.children.is_empty() {
+            let mut children = key.children.iter().collect::<Vec<_>>();
+            children.sort_by(|a, b| a.name().cmp(b.name()));
+            if children
+                .windows(2)
+                .any(|pair| pair[0].name() == pair[1].name())
+            {
+                return Err(Error::new(
+                    Operation::WriteHive,
+                    &key.path,
+                    ErrorKind::Unsupported("subkeys with the same name"),
+                ));
+            }
+
+            let mut entries = Vec::with_capacity(children.len());
+            for child in &children {
+                let child_offset = self.write_key(child, offset, security, false)?;
+                entries.push((child_offset, child.name().as_wide()));
+            }
+            let list = write_subkey_list(&mut self.bins, self.minor_version, &entries);
+            self.bins.patch(offset, 20, children.len() as u32);
+            self.bins.patch(offset, 28, list);
+            self.bins.patch(
+                offset,
+                52,
+                max(children
+                    .iter()
+                    .map(|child| child.name().as_wide().len() * 2)),
+            );
+            self.bins.patch(
+                offset,
+                56,
+                max(children
+                    .iter()
+                    .map(|child| child.class.as_ref().map_or(0, |class| class.len() * 2))),
+            );
+        }
+        Ok(offset)
+    }
+
+    /// Writes a value key and its data, returning the offset of the value key
+    fn write_value(&mut self, key: &KeySnapshot, name: &ValueName, raw: &RawValue) -> Result<u32> {
+        let (data_size, data_offset) =
+            write_value_data(&mut self.bins, self.minor_version, &key.path, &raw.data)?;
+        let value = value_key(&key.path, name, data_size, data_offset, raw.value_type)?;
+        Ok(self.bins.alloc(&value))
+    }
+}
+
+/// Allocates cells in the hive bins of a hive being written or edited
+pub(crate) trait CellAllocator {
+    /// Allocates a cell holding `payload`, returning its offset
+    fn alloc(&mut self, payload: &[u8]) -> u32;
+}
+
+/// The payload of a key node called `name` with no subkeys, values, class or security yet
+pub(crate) fn key_node(
+    path: &RegPath,
+    name: &[u16],
+    flags: u16,
+    last_write_time: u64,
+    parent: u32,
+) -> Result<Vec<u8>> {
+    let (name_bytes, compressed) = encode_name(name);
+    let flags = if compressed {
+        flags | KEY_COMP_NAME
+    } else {
+        flags
+    };
+
+    let mut node = vec![0; KEY_NODE_SIZE];
+    node[..2].copy_from_slice(b"nk");
+    node[2..4].copy_from_slice(&flags.to_le_bytes());
+    node[4..12].copy_from_slice(&last_write_time.to_le_bytes());
+    node[16..20].copy_from_slice(&parent.to_le_bytes());
+    for field in &[28, 32, 40, 48] {
+        node[*field..field + 4].copy_from_slice(&NO_CELL.to_le_bytes());
+    }
+    node[72..74]
+        .copy_from_slice(&length(name_bytes.len(), u16::MAX as u32, path)?.to_le_bytes()[..2]);
+    node.extend_from_slice(&name_bytes);
+    Ok(node)
+}
+
+/// The payload of a value key whose data has already been written
+pub(crate) fn value_key(
+    path: &RegPath,
+    name: &ValueName,
+    data_size: u32,
+    data_offset: u32,
+    value_type: u32,
+) -> Result<Vec<u8>> {
+    let (name_bytes, compressed) = encode_name(name.as_wide());
+    let mut value = Vec::with_capacity(VALUE_KEY_SIZE + name_bytes.len());
+    value.extend_from_slice(b"vk");
+    value.extend_from_slice(&length(name_bytes.len(), u16::MAX as u32, path)?.to_le_bytes()[..2]);
+    value.extend_from_slice(&data_size.to_le_bytes());
+    value.extend_from_slice(&data_offset.to_le_bytes());
+    value.extend_from_slice(&value_type.to_le_bytes());
+    let flags = if compressed { VALUE_COMP_NAME } else { 0 };
+    value.extend_from_slice(&flags.to_le_bytes());
+    value.extend_from_slice(&[0; 2]);
+    value.extend_from_slice(&name_bytes);
+    Ok(value)
+}
+
+/// Stores value data inline, in a single cell or in big data segments, as the hive version requires.
+///
+/// Returns the data size and data offset fields for the value key.
+pub(crate) fn write_value_data<A: CellAllocator>(
+    alloc: &mut A,
+    minor_version: u32,
+    path: &RegPath,
+    data: &[u8],
+) -> Result<(u32, u32)> {
+    let size = length(data.len(), DATA_INLINE - 1, path)?;
+    if data.len() <= 4 {
+        let mut inline = [0; 4];
+        inline[..data.len()].copy_from_slice(data);
+        return Ok((size | DATA_INLINE, u32::from_le_bytes(inline)));
+    }
+    if data.len() <= BIG_DATA_SEGMENT_SIZE || minor_version < 4 {
+        return Ok((size, alloc.alloc(data)));
+    }
+
+    let segments = data
+        .chunks(BIG_DATA_SEGMENT_SIZE)
+        .map(|segment| alloc.alloc(segment))
+        .flat_map(u32::to_le_bytes)
+        .collect::<Vec<u8>>();
+    let count = segments.len() / 4;
+    let list = alloc.alloc(&segments);
+
+    let mut big_data = Vec::with_capacity(8);
+    big_data.extend_from_slice(b"db");
+    big_data.extend_from_slice(&(count as u16).to_le_bytes());
+    big_data.extend_from_slice(&list.to_le_bytes());
+    Ok((size, alloc.alloc(&big_data)))
+}
+
+/// Writes the subkey list for `entries` (key node offsets and names, already sorted), splitting it under an
+/// index root if needed
+pub(crate) fn write_subkey_list<A: CellAllocator>(
+    alloc: &mut A,
+    minor_version: u32,
+    entries: &[(u32, &[u16])],
+) -> u32 {
+    if entries.len() <= MAX_LEAF_ENTRIES {
+        return write_leaf(alloc, minor_version, entries);
+    }
+
+    let leaves = entries
+        .chunks(MAX_LEAF_ENTRIES)
+        .map(|chunk| write_leaf(alloc, minor_version, chunk))
+        .collect::<Vec<u32>>();
+    let mut index = Vec::with_capacity(4 + leaves.len() * 4);
+    index.extend_from_slice(b"ri");
+    index.extend_from_slice(&(leaves.len() as u16).to_le_bytes());
+    leaves
+        .iter()
+        .for_each(|leaf| index.extend_from_slice(&leaf.to_le_bytes()));
+    alloc.alloc(&index)
+}
+
+fn write_leaf<A: CellAllocator>(
+    alloc: &mut A,
+    minor_version: u32,
+    entries: &[(u32, &[u16])],
+) -> u32 {
+    let mut leaf = Vec::with_capacity(4 + entries.len() * 8);
+    leaf.extend_from_slice(if minor_version >= 5 { b"lh" } else { b"lf" });
+    leaf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (offset, name) in entries {
+        leaf.extend_from_slice(&offset.to_le_bytes());
+        if minor_version >= 5 {
+            leaf.extend_from_slice(&name_hash(name).to_le_bytes());
+        } else {
+            let mut hint = [0; 4];
+            for (slot, c) in hint.iter_mut().zip(name.iter()) {
+                *slot = if *c <= 0xff { *c as u8 } else { 0 };
+            }
+            leaf.extend_from_slice(&hint);
+        }
+    }
+    alloc.alloc(&leaf)
+}
+
+/// Name of the root key when the snapshot has no name
+const ROOT_NAME: [u16; 4] = [b'R' as u16, b'O' as u16, b'O' as u16, b'T' as u16];
+
+/// The hash stored in `lh` subkey lists: the upcased name folded with a multiplier of 37
+pub(crate) fn name_hash(name: &[u16]) -> u32 {
+    name.iter().fold(0u32, |hash, c| {
+        hash.wrapping_mul(37).wrapping_add(upcase(*c) as u32)
+    })
+}
+
+/// Encodes a name as Latin-1 if every character fits, otherwise as UTF-16LE
+fn encode_name(name: &[u16]) -> (Vec<u8>, bool) {
+    if name.iter().all(|c| *c <= 0xff) {
+        (name.iter().map(|c| *c as u8).collect(), true)
+    } else {
+        (name.iter().flat_map(|c| c.to_le_bytes()).collect(), false)
+    }
+}
+
+/// Checks that a length fits in a field whose largest value is `limit`
+fn length(length: usize, limit: u32, path: &RegPath) -> Result<u32> {
+    if length > limit as usize {
+        Err(Error::new(
+            Operation::WriteHive,
+            path,
+            ErrorKind::Unsupported("a name, class or value larger than the hive format allows"),
+        ))
+    } else {
+        Ok(length as u32)
+    }
+}
+
+fn max<I: Iterator<Item = usize>>(lengths: I) -> u32 {
+    lengths.max().unwrap_or(0) as u32
+}
+
+pub(crate) fn round_up(size: usize, alignment: usize) -> usize {
+    size.div_ceil(alignment) * alignment
+}
+
+/// A self-relative security descriptor owned by `SYSTEM`, granting `KEY_ALL_ACCESS` to `SYSTEM` and
+/// `Administrators` and `KEY_READ` to `Users`, inherited by subkeys
+pub(crate) fn default_security() -> Vec<u8> {
+    const SYSTEM: &[u8] = &[1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+    const ADMINISTRATORS: &[u8] = &[1, 2, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0, 0x20, 2, 0, 0];
+    const USERS: &[u8] = &[1, 2, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0, 0x21, 2, 0, 0];
+    const KEY_ALL_ACCESS: u32 = 0x000f_003f;
+    const KEY_READ: u32 = 0x0002_0019;
+    const CONTAINER_INHERIT_ACE: u8 = 0x02;
+
+    let mut aces = Vec::new();
+    for (sid, mask) in &[
+        (SYSTEM, KEY_ALL_ACCESS),
+        (ADMINISTRATORS, KEY_ALL_ACCESS),
+        (USERS, KEY_READ),
+    ] {
+        aces.push(0);
+        aces.push(CONTAINER_INHERIT_ACE);
+        aces.extend_from_slice(&(8 + sid.len() as u16).to_le_bytes());
+        aces.extend_from_slice(&mask.to_le_bytes());
+        aces.extend_from_slice(sid);
+    }
+
+    let mut dacl = vec![2, 0];
+    dacl.extend_from_slice(&(8 + aces.len() as u16).to_le_bytes());
+    dacl.extend_from_slice(&3u16.to_le_bytes());
+    dacl.extend_from_slice(&[0, 0]);
+    dacl.extend(aces);
+
+    let dacl_offset = 20u32;
+    let owner_offset = dacl_offset + dacl.len() as u32;
+    let group_offset = owner_offset + SYSTEM.len() as u32;
+
+    let mut descriptor = vec![1, 0];
+    descriptor.extend_from_slice(&0x8004u16.to_le_bytes());
+    descriptor.extend_from_slice(&owner_offset.to_le_bytes());
+    descriptor.extend_from_slice(&group_offset.to_le_bytes());
+    descriptor.extend_from_slice(&0u32.to_le_bytes());
+    descriptor.extend_from_slice(&dacl_offset.to_le_bytes());
+    descriptor.extend(dacl);
+    descriptor.extend_from_slice(SYSTEM);
+    descriptor.extend_from_slice(SYSTEM);
+    descriptor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hive::Hive, path::RegPathBuf, source::KeySource};
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    /// A tree exercising every structure the writer produces
+    fn crafted() -> KeySnapshot {
+        let mut root = KeySnapshot::new(r"\Registry\User\S-1-5-21-1000");
+        root.last_write_time = 132_000_000_000_000_000;
+        root.values = vec![
+            (ValueName::default(), RawValue::new(1, vec![b'x', 0, 0, 0])),
+            (ValueName::from("Small"), RawValue::new(4, vec![1, 2, 3, 4])),
+            (ValueName::from("Empty"), RawValue::new(3, vec![])),
+            (
+                ValueName::from("Oversized"),
+                RawValue::new(3, (0..100_000u32).map(|i| i as u8).collect::<Vec<u8>>()),
+            ),
+            (
+                ValueName::from("\u{444}\u{430}\u{439}\u{43b}"),
+                RawValue::new(0x1234, vec![9; 9]),
+            ),
+        ];
+
+        let mut hidden = wide("Run");
+        hidden.push(0);
+        hidden.extend(wide("Hidden"));
+        let run = root.child_mut(RegPathBuf::from(r"Software\Microsoft"));
+        run.class = Some(wide("Shell"));
+        run.security = Some(vec![
+            1, 0, 4, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        run.child_mut(hidden.clone()).values.push((
+            ValueName::from(hidden),
+            RawValue::new(1, vec![b'c', 0, b':', 0, 0, 0]),
+        ));
+
+        let wide_keys = root.child_mut("Many");
+        for i in (0..1100).rev() {
+            wide_keys.child_mut(format!("Key{:04}", i));
+        }
+        root
+    }
+
+    /// `key` as the reader reports it: subkeys sorted and security descriptors inherited
+    fn expected(key: &KeySnapshot, inherited: &[u8]) -> KeySnapshot {
+        let security = key.security.clone().unwrap_or_else(|| inherited.to_vec());
+        let mut children = key
+            .children
+            .iter()
+            .map(|child| expected(child, &security))
+            .collect::<Vec<_>>();
+        children.sort_by(|a, b| a.name().cmp(b.name()));
+        KeySnapshot {
+            security: Some(security),
+            children,
+            ..key.clone()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let tree = crafted();
+        for minor_version in 3..=6 {
+            let bytes = HiveWriter::new()
+                .minor_version(minor_version)
+                .file_name(r"\??\C:\Users\laboratory\ntuser.dat")
+                .to_bytes(&tree)
+                .unwrap();
+            let hive = Hive::from_bytes(&bytes[..]).unwrap();
+
+            let base_block = hive.base_block();
+            assert_eq!(base_block.checksum, crate::hive::checksum(&bytes));
+            assert_eq!(base_block.hive_bins_size as usize, bytes.len() - 0x1000);
+            assert_eq!(base_block.hive_bins_size % 0x1000, 0);
+            assert_eq!(base_block.last_written, tree.last_write_time);
+            assert_eq!(
+                String::from_utf16_lossy(&base_block.file_name),
+                r"\C:\Users\laboratory\ntuser.dat"
+            );
+            assert!(!base_block.is_dirty());
+
+            let root = hive.root_at(&tree.path).unwrap();
+            assert_eq!(
+                root.to_snapshot().unwrap(),
+                expected(&tree, &default_security())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let mut tree = KeySnapshot::new(r"\");
+        tree.children.push(KeySnapshot::new(r"\a"));
+        tree.children.push(KeySnapshot::new(r"\A"));
+        let error = HiveWriter::new().to_bytes(&tree).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Unsupported(_)));
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(name_hash(&wide("")), 0);
+        assert_eq!(name_hash(&wide("a")), 'A' as u32);
+        assert_eq!(name_hash(&wide("ab")), 'A' as u32 * 37 + 'B' as u32);
+    }
+
+    #[test]
+    fn lookup() {
+        let tree = crafted();
+        let bytes = HiveWriter::new().to_bytes(&tree).unwrap();
+        let hive = Hive::from_bytes(bytes).unwrap();
+        let root = hive.root().unwrap();
+        let many = root.open_child(&RegPathBuf::from("many")).unwrap();
+        assert_eq!(many.subkey_names().unwrap().len(), 1100);
+        assert!(many
+            .open_child(&RegPathBuf::from("KEY1099"))
+            .unwrap()
+            .path()
+            .to_string()
+            .ends_with(r"\Many\Key1099"));
+        assert!(many
+            .open_child(&RegPathBuf::from("Key1100"))
+            .unwrap_err()
+            .is_not_found());
+    }
+}
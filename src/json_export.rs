@@ -0,0 +1,380 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::RegPath,
+    source::{check_depth, KeySource},
+    value::{RawValue, RegValue},
+    value_name::ValueName,
+    Result,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+/// Writes keys and values as [JSON Lines](https://jsonlines.org): one JSON object per line, for every key and
+/// every value.
+///
+/// Trees are written depth first, each key followed by its values and then its subkeys, opening one subkey
+/// at a time, so exporting never holds more than one level of the tree in memory. Lines are terminated by a
+/// single `\n` and the output is UTF-8.
+///
+/// # Keys
+///
+/// ```json
+/// {"kind":"key","path":"\\Registry\\Machine\\SOFTWARE\\Vendor","last_write_time":133497696000000000,"last_write":"2024-01-15T05:20:00.0000000Z"}
+/// ```
+///
+/// * `path`: the full path of the key
+/// * `last_write_time`: when the key was last written, as a `FILETIME` (100ns intervals since 1601-01-01
+///   UTC), `null` if the source does not record it
+/// * `last_write`: the same time in RFC 3339 form with 7 fractional digits, or `null`
+///
+/// # Values
+///
+/// ```json
+/// {"kind":"value","path":"\\Registry\\Machine\\SOFTWARE\\Vendor","name":"Port","type":"REG_DWORD","type_id":4,"data":8080,"raw":"kB8AAA==","last_write_time":133497696000000000,"last_write":"2024-01-15T05:20:00.0000000Z"}
+/// ```
+///
+/// * `path`: the full path of the key holding the value
+/// * `name`: the name of the value, `""` for the default value
+/// * `type`: the name of the type, such as `REG_SZ`, or `null` for types outside of `REG_NONE` to
+///   `REG_QWORD`
+/// * `type_id`: the type number
+/// * `data`: the decoded data: a string for `REG_SZ`, `REG_EXPAND_SZ` and `REG_LINK` (without the
+///   terminating `null`s), an array of strings for `REG_MULTI_SZ`, a number for well formed `REG_DWORD`,
+///   `REG_DWORD_BIG_ENDIAN` and `REG_QWORD` values, and `null` for everything else. Decoding is lossy in the
+///   same way as [`RawValue::decode`]; note that `REG_QWORD`s above 2<sup>53</sup> lose precision in
+///   parsers that read numbers as doubles.
+/// * `raw`: the data exactly as stored, in standard base64 with padding
+/// * `last_write_time` and `last_write`: those of the key holding the value
+///
+/// # Escaping
+///
+/// Strings are escaped as JSON requires and nothing more: `"` and `\` are escaped with a backslash,
+/// backspace, form feed, line feed, carriage return and tab as `\b`, `\f`, `\n`, `\r` and `\t`, and every
+/// other character below U+0020, including `null`, as `\u00XX` with lowercase hex digits. Everything else
+/// is written as is in UTF-8.
+///
+/// Key and value names are UTF-16 and need not be valid. Unpaired surrogates are written as U+FFFD in `path`
+/// and `name`, and such lines get an additional `path_utf16` or `name_utf16` field holding the exact code
+/// units as an array of numbers. Strings in `data` are decoded lossily without such a field; `raw` always
+/// holds the exact bytes.
+///
+/// Fields are written in the order shown above. New fields may be added at the end of an object in later
+/// versions; existing fields keep their names and meaning.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(windows)]
+/// # fn main() {
+/// use winregnt::{JsonLinesExporter, RegKey};
+/// let key = RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").unwrap();
+/// let file = std::fs::File::create("run.jsonl").unwrap();
+/// let mut exporter = JsonLinesExporter::new(std::io::BufWriter::new(file));
+/// exporter.write_tree(&key).unwrap();
+/// exporter.finish().unwrap();
+/// # }
+/// # #[cfg(not(windows))]
+/// # fn main() {}
+/// ```
+pub struct JsonLinesExporter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesExporter<W> {
+    /// Starts writing lines to `writer`; nothing is written until the first key
+    pub fn new(writer: W) -> JsonLinesExporter<W> {
+        JsonLinesExporter { writer }
+    }
+
+    /// Writes the line for `source` and a line for each of its values, but not its subkeys
+    pub fn write_key<S: KeySource>(&mut self, source: &S) -> Result<()> {
+        let io_error = |e: io::Error| Error::new(Operation::ExportKey, source.path(), e.into());
+
+        let path = source.path();
+        let last_write_time = source.last_write_time()?;
+
+        let mut line = String::from(r#"{"kind":"key","#);
+        push_path(&mut line, path);
+        push_time(&mut line, last_write_time);
+        line.push('}');
+        self.write_line(&line).map_err(io_error)?;
+
+        for value in source.values()? {
+            let mut line = String::from(r#"{"kind":"value","#);
+            push_path(&mut line, path);
+            push_name(&mut line, value.value_name());
+            push_value(&mut line, value.raw());
+            push_time(&mut line, last_write_time);
+            line.push('}');
+            self.write_line(&line)
+                .map_err(|e| io_error(e).with_value_name(value.value_name()))?;
+        }
+        Ok(())
+    }
+
+    /// Writes `source` and everything below it, depth first
+    pub fn write_tree<S: KeySource>(&mut self, source: &S) -> Result<()> {
+        self.write_subtree(source, 0)
+    }
+
+    fn write_subtree<S: KeySource>(&mut self, source: &S, depth: usize) -> Result<()> {
+        check_depth(Operation::ExportKey, source, depth)?;
+        self.write_key(source)?;
+        for name in source.subkey_names()? {
+            self.write_subtree(&source.open_child(&name)?, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the output and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+/// Exports `source` and all of its subkeys as JSON Lines, see [`JsonLinesExporter`] for the format
+pub fn export_json_lines<W: Write, S: KeySource>(writer: W, source: &S) -> Result<W> {
+    let mut exporter = JsonLinesExporter::new(writer);
+    exporter.write_tree(source)?;
+    exporter
+        .finish()
+        .map_err(|e| Error::new(Operation::ExportKey, source.path(), ErrorKind::Io(e)))
+}
+
+fn push_path(line: &mut String, path: &RegPath) {
+    push_wide(line, "path", path.as_wide());
+}
+
+fn push_name(line: &mut String, name: &ValueName) {
+    line.push(',');
+    push_wide(line, "name", name.as_wide());
+}
+
+/// Writes `"field":"..."`, followed by `"field_utf16":[...]` if `wide` is not valid UTF-16
+fn push_wide(line: &mut String, field: &str, wide: &[u16]) {
+    let _ = write!(line, r#""{}":"#, field);
+    match String::from_utf16(wide) {
+        Ok(string) => push_string(line, &string),
+        Err(_) => {
+            push_string(line, &String::from_utf16_lossy(wide));
+            let _ = write!(line, r#","{}_utf16":["#, field);
+            for (i, unit) in wide.iter().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                let _ = write!(line, "{}", unit);
+            }
+            line.push(']');
+        }
+    }
+}
+
+fn push_value(line: &mut String, raw: &RawValue) {
+    line.push_str(r#","type":"#);
+    match raw.value_type() {
+        Some(value_type) => {
+            let _ = write!(line, r#""{:?}""#, value_type);
+        }
+        None => line.push_str("null"),
+    }
+    let _ = write!(line, r#","type_id":{},"data":"#, raw.value_type);
+    match raw.decode() {
+        RegValue::String(s) | RegValue::ExpandString(s) | RegValue::Link(s) => {
+            push_string(line, &s)
+        }
+        RegValue::MultiString(strings) => {
+            line.push('[');
+            for (i, s) in strings.iter().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                push_string(line, s);
+            }
+            line.push(']');
+        }
+        RegValue::Dword(n) | RegValue::DwordBigEndian(n) => {
+            let _ = write!(line, "{}", n);
+        }
+        RegValue::Qword(n) => {
+            let _ = write!(line, "{}", n);
+        }
+        RegValue::None | RegValue::Binary(_) | RegValue::Unknown => line.push_str("null"),
+    }
+    line.push_str(r#","raw":""#);
+    push_base64(line, &raw.data);
+    line.push('"');
+}
+
+fn push_time(line: &mut String, filetime: u64) {
+    if filetime == 0 {
+        line.push_str(r#","last_write_time":null,"last_write":null"#);
+    } else {
+        let _ = write!(
+            line,
+            r#","last_write_time":{},"last_write":"{}""#,
+            filetime,
+            rfc3339(filetime)
+        );
+    }
+}
+
+/// Writes `s` as a quoted JSON string, escaped as documented on [`JsonLinesExporter`]
+fn push_string(line: &mut String, s: &str) {
+    line.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\u{8}' => line.push_str("\\b"),
+            '\u{c}' => line.push_str("\\f"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(line, "\\u{:04x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+fn push_base64(line: &mut String, data: &[u8]) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                line.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                line.push('=');
+            }
+        }
+    }
+}
+
+/// Formats a `FILETIME` as `YYYY-MM-DDThh:mm:ss.fffffffZ`
+fn rfc3339(filetime: u64) -> String {
+    let ticks = filetime % 10_000_000;
+    let seconds = filetime / 10_000_000;
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // days since 1601-01-01 to a civil date, counting in 400 year eras that start on 0000-03-01 so leap
+    // days fall at the end of a year
+    let days = days as i64 - 134_774 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}Z",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60,
+        ticks
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{path::RegPathBuf, reg_export::tests::Endless, snapshot::KeySnapshot};
+
+    #[test]
+    fn lines() {
+        let mut root = KeySnapshot::new(r"\Registry\Machine\SOFTWARE\Vendor");
+        root.last_write_time = 133_497_696_000_000_000;
+        root.values
+            .push(("Port".into(), RawValue::new(4, vec![0x90, 0x1f, 0, 0])));
+        root.values.push((
+            ValueName::default(),
+            RawValue::new(
+                1,
+                "a\"\\\u{1}\n\0"
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes)
+                    .collect::<Vec<_>>(),
+            ),
+        ));
+        root.values.push((
+            ValueName::from_wide(vec![0x41, 0xd800, 0]),
+            RawValue::new(7, b"x\0\0\0y\0\0\0\0\0".to_vec()),
+        ));
+        root.values
+            .push(("Blob".into(), RawValue::new(0x1234, vec![1, 2, 3, 4])));
+        root.child_mut("Empty");
+
+        let output = export_json_lines(Vec::new(), &&root).unwrap();
+        let expected = [
+            r#"{"kind":"key","path":"\\Registry\\Machine\\SOFTWARE\\Vendor","last_write_time":133497696000000000,"last_write":"2024-01-15T05:20:00.0000000Z"}"#,
+            r#"{"kind":"value","path":"\\Registry\\Machine\\SOFTWARE\\Vendor","name":"Port","type":"REG_DWORD","type_id":4,"data":8080,"raw":"kB8AAA==","last_write_time":133497696000000000,"last_write":"2024-01-15T05:20:00.0000000Z"}"#,
+            r#"{"kind":"value","path":"\\Registry\\Machine\\SOFTWARE\\Vendor","name":"","type":"REG_SZ","type_id":1,"data":"a\"\\\u0001\n","raw":"YQAiAFwAAQAKAAAA","last_write_time":133497696000000000,"last_write":"2024-01-15T05:20:00.0000000Z"}"#,
+            r#"{"kind":"value","path":"\\Registry\\Machine\\SOFTWARE\\Vendor","name":"A�\u0000","name_utf16":[65,55296,0],"type":"REG_MULTI_SZ","type_id":7,"data":["x","y"],"raw":"eAAAAHkAAAAAAA==","last_write_time":133497696000000000,"last_write":"2024-01-15T05:20:00.0000000Z"}"#,
+            r#"{"kind":"value","path":"\\Registry\\Machine\\SOFTWARE\\Vendor","name":"Blob","type":null,"type_id":4660,"data":null,"raw":"AQIDBA==","last_write_time":133497696000000000,"last_write":"2024-01-15T05:20:00.0000000Z"}"#,
+            r#"{"kind":"key","path":"\\Registry\\Machine\\SOFTWARE\\Vendor\\Empty","last_write_time":null,"last_write":null}"#,
+        ];
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().collect::<Vec<_>>(), expected);
+        assert!(output.ends_with("}\n"));
+
+        #[cfg(feature = "serde")]
+        for line in output.lines() {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+    }
+
+    #[test]
+    fn cycle() {
+        let error = export_json_lines(
+            Vec::new(),
+            &Endless(RegPathBuf::from(r"\Registry\Machine\Loop")),
+        )
+        .unwrap_err();
+        assert_eq!(error.operation(), Operation::ExportKey);
+        assert!(matches!(error.kind(), ErrorKind::Unsupported(_)));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(rfc3339(1), "1601-01-01T00:00:00.0000001Z");
+        assert_eq!(
+            rfc3339(116_444_736_000_000_000),
+            "1970-01-01T00:00:00.0000000Z"
+        );
+        assert_eq!(
+            rfc3339(125_911_584_000_000_000 + 23_999_999_999),
+            "2000-01-01T00:39:59.9999999Z"
+        );
+        assert_eq!(
+            rfc3339(126_967_679_990_000_000),
+            "2003-05-07T07:59:59.0000000Z"
+        );
+        assert_eq!(
+            rfc3339(125_962_560_000_000_000),
+            "2000-02-29T00:00:00.0000000Z"
+        );
+    }
+}
@@ -15,275 +15,111 @@
 //! `main.rs`:
 //!
 //! ```no_run
+//! # #[cfg(windows)]
 //! use winregnt::RegKey;
 //!
+//! # #[cfg(not(windows))]
+//! # fn main() {}
+//! # #[cfg(windows)]
 //! fn main() {
 //!     let key =
 //!         RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").unwrap();
-//!     key.enum_keys().for_each(|k| println!("- {}", k));
+//!     key.enum_keys().flatten().for_each(|k| println!("- {}", k));
 //! }
 //! ```
 //!
 
-#![cfg(target_os = "windows")]
 #![warn(missing_docs)]
 
+#[cfg(windows)]
 mod api;
+mod carve;
+mod creg;
+#[cfg(feature = "serde")]
+mod de;
+mod deleted;
+mod diff;
 mod error;
+mod hive;
+mod hive_check;
+mod hive_editor;
+mod hive_log;
+mod hive_node;
+mod hive_writer;
+mod json_export;
+mod merge;
+#[cfg(windows)]
+mod open_options;
+mod path;
+#[cfg(windows)]
+mod privilege;
+mod reg_export;
+#[cfg(windows)]
+mod reg_hive;
+mod reg_import;
+#[cfg(windows)]
+mod reg_key;
+#[cfg(windows)]
 mod reg_key_iterator;
+#[cfg(windows)]
 mod reg_value_iterator;
+#[cfg(feature = "serde")]
+mod ser;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod snapshot;
+mod source;
+mod status;
+#[cfg(test)]
+mod test_support;
+#[cfg(windows)]
+mod transaction;
+#[cfg(windows)]
 mod unicode_string;
+mod value;
+mod value_name;
 
+#[cfg(windows)]
 pub use crate::api::*;
+pub use crate::carve::*;
+pub use crate::creg::*;
+#[cfg(feature = "serde")]
+pub use crate::de::*;
+pub use crate::deleted::*;
+pub use crate::diff::*;
 pub use crate::error::*;
-use crate::reg_key_iterator::*;
-use crate::reg_value_iterator::*;
-use crate::unicode_string::*;
-use std::{ffi::OsString, mem::zeroed, os::windows::ffi::OsStrExt, ptr::null_mut};
-use winapi::{
-    shared::{
-        ntdef::{InitializeObjectAttributes, HANDLE, OBJECT_ATTRIBUTES, OBJ_CASE_INSENSITIVE},
-        ntstatus::{
-            STATUS_ACCESS_DENIED, STATUS_INSUFFICIENT_RESOURCES, STATUS_INVALID_HANDLE,
-            STATUS_OBJECT_NAME_NOT_FOUND,
-        },
-    },
-    um::winnt::{
-        DELETE, KEY_READ, KEY_SET_VALUE, KEY_WRITE, REG_BINARY, REG_DWORD, REG_NONE, REG_QWORD,
-        REG_SZ,
-    },
-};
+pub use crate::hive::*;
+pub use crate::hive_check::*;
+pub use crate::hive_editor::*;
+pub use crate::hive_log::*;
+pub use crate::hive_node::*;
+pub use crate::hive_writer::*;
+pub use crate::json_export::*;
+pub use crate::merge::*;
+#[cfg(windows)]
+pub use crate::open_options::*;
+pub use crate::path::*;
+#[cfg(windows)]
+pub use crate::privilege::*;
+pub use crate::reg_export::*;
+#[cfg(windows)]
+pub use crate::reg_hive::*;
+pub use crate::reg_import::*;
+#[cfg(windows)]
+pub use crate::reg_key::*;
+#[cfg(windows)]
+pub use crate::reg_key_iterator::*;
+#[cfg(windows)]
+pub use crate::reg_value_iterator::*;
+#[cfg(feature = "serde")]
+pub use crate::ser::*;
+pub use crate::snapshot::*;
+pub use crate::source::*;
+pub use crate::status::*;
+#[cfg(windows)]
+pub use crate::transaction::*;
+pub use crate::value::*;
+pub use crate::value_name::*;
 
 /// Result wrapping WinRegNt errors
 pub type Result<T> = std::result::Result<T, error::Error>;
-
-/// Entry point for all registry access
-pub struct RegKey {
-    handle: HANDLE,
-    name: Vec<u16>,
-    u: UnicodeString,
-}
-
-impl Drop for RegKey {
-    fn drop(&mut self) {
-        if !self.handle.is_null() {
-            unsafe {
-                NtClose(self.handle);
-            }
-        }
-    }
-}
-
-impl RegKey {
-    /// opens a registry key as read only
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use winregnt::RegKey;
-    /// assert!(RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").is_ok());
-    /// ```
-    ///
-    pub fn open<S: AsRef<str>>(name: S) -> Result<RegKey> {
-        Self::open_key(name, KEY_READ)
-    }
-
-    /// opens a registry key with write permissions
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use winregnt::RegKey;
-    /// assert!(RegKey::open_write(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").is_ok());
-    /// ```
-    ///
-    pub fn open_write<S: AsRef<str>>(name: S) -> Result<RegKey> {
-        Self::open_key(name, KEY_WRITE | DELETE | KEY_SET_VALUE)
-    }
-
-    /// get an sub key enumerator
-    pub fn enum_keys(&self) -> RegKeyIterator {
-        RegKeyIterator::new(&self)
-    }
-
-    /// get a key value iterator
-    pub fn enum_values(&self) -> RegValueIterator {
-        RegValueIterator::new(&self.handle)
-    }
-
-    /// delete the current key
-    pub fn delete(&self) -> Result<()> {
-        match unsafe { api::NtDeleteKey(self.handle) } as i32 {
-            STATUS_ACCESS_DENIED => Err(RegKeyError::DeleteAccessDenied.into()),
-            STATUS_INVALID_HANDLE => Err(RegKeyError::DeleteInvalidHandle.into()),
-            _ => Ok(()),
-        }
-    }
-
-    /// delete a value
-    pub fn delete_value<S: AsRef<str>>(&self, value_name: S) -> Result<()> {
-        let unicode_string = UnicodeString::from(value_name.as_ref());
-        match unsafe { NtDeleteValueKey(self.handle, &unicode_string.0 as *const _ as *mut _) }
-            as i32
-        {
-            STATUS_ACCESS_DENIED => Err(crate::error::RegValueError::AccessDenied.into()),
-            STATUS_INSUFFICIENT_RESOURCES => {
-                Err(crate::error::RegValueError::InsufficientResources.into())
-            }
-            STATUS_INVALID_HANDLE => Err(RegValueError::InvalidHandle.into()),
-            STATUS_OBJECT_NAME_NOT_FOUND => Err(RegValueError::NameNotFound.into()),
-            _ => Ok(()),
-        }
-    }
-
-    fn open_key<S: AsRef<str>>(name: S, permission: u32) -> Result<RegKey> {
-        let mut key = RegKey {
-            handle: unsafe { zeroed() },
-            name: {
-                let mut t = OsString::from(name.as_ref())
-                    .encode_wide()
-                    .collect::<Vec<u16>>();
-                t.push(0x00);
-                t
-            },
-            u: Default::default(),
-        };
-        key.u = UnicodeString::from(&key.name);
-
-        let mut object_attr: OBJECT_ATTRIBUTES = unsafe { zeroed() };
-        unsafe {
-            InitializeObjectAttributes(
-                &mut object_attr,
-                &mut key.u.0,
-                OBJ_CASE_INSENSITIVE,
-                null_mut(),
-                null_mut(),
-            );
-        }
-
-        match unsafe { NtOpenKey(&mut key.handle, permission, &object_attr) } {
-            0 => Ok(key),
-            err => Err(Error::KeyError(name.as_ref().to_string(), err)),
-        }
-    }
-
-    /// Create or update a binary value `name` with `value`
-    pub fn write_binary_value<S: AsRef<str>, V: AsRef<[u8]>>(
-        &mut self,
-        name: S,
-        value: V,
-    ) -> Result<()> {
-        let unicode_name = UnicodeString::from(name.as_ref());
-        match unsafe {
-            NtSetValueKey(
-                self.handle,
-                &unicode_name.0 as *const _ as *mut _,
-                0,
-                REG_BINARY,
-                value.as_ref() as *const _ as *mut _,
-                value.as_ref().len() as _,
-            )
-        } {
-            0 => Ok(()),
-            err => Err(RegValueError::Write(err).into()),
-        }
-    }
-
-    /// Create or update a binary value `name` with `value`
-    pub fn write_string_value<S: AsRef<str>, V: AsRef<str>>(
-        &mut self,
-        name: S,
-        value: V,
-    ) -> Result<()> {
-        let unicode_name = UnicodeString::from(name.as_ref());
-
-        let mut o = OsString::from(value.as_ref())
-            .encode_wide()
-            .collect::<Vec<u16>>();
-        o.push(0x00);
-
-        match unsafe {
-            NtSetValueKey(
-                self.handle,
-                &unicode_name.0 as *const _ as *mut _,
-                0,
-                REG_SZ,
-                o.as_mut_ptr() as _,
-                (o.len() * 2) as _,
-            )
-        } {
-            0 => Ok(()),
-            err => Err(RegValueError::Write(err).into()),
-        }
-    }
-
-    /// Create or update a binary value `name` with `value`
-    pub fn write_dword_value<S: AsRef<str>>(&mut self, name: S, value: u32) -> Result<()> {
-        let unicode_name = UnicodeString::from(name.as_ref());
-        match unsafe {
-            NtSetValueKey(
-                self.handle,
-                &unicode_name.0 as *const _ as *mut _,
-                0,
-                REG_DWORD,
-                &mut value.clone() as *const _ as *mut _,
-                std::mem::size_of::<u32>() as _,
-            )
-        } {
-            0 => Ok(()),
-            err => Err(RegValueError::Write(err).into()),
-        }
-    }
-
-    /// Create or update a `NONE` value `name` with `value`
-    pub fn write_qword_value<S: AsRef<str>>(&mut self, name: S, value: u64) -> Result<()> {
-        let unicode_name = UnicodeString::from(name.as_ref());
-        match unsafe {
-            NtSetValueKey(
-                self.handle,
-                &unicode_name.0 as *const _ as *mut _,
-                0,
-                REG_QWORD,
-                &mut value.clone() as *const _ as *mut _,
-                std::mem::size_of::<u64>() as _,
-            )
-        } {
-            0 => Ok(()),
-            err => Err(RegValueError::Write(err).into()),
-        }
-    }
-
-    /// Create or update a `NONE` value `name` with `value`
-    pub fn write_none_value<S: AsRef<str>, V: AsRef<[u8]>>(
-        &mut self,
-        name: S,
-        value: V,
-    ) -> Result<()> {
-        let unicode_name = UnicodeString::from(name.as_ref());
-        match unsafe {
-            NtSetValueKey(
-                self.handle,
-                &unicode_name.0 as *const _ as *mut _,
-                0,
-                REG_NONE,
-                value.as_ref() as *const _ as *mut _,
-                value.as_ref().len() as _,
-            )
-        } {
-            0 => Ok(()),
-            err => Err(RegValueError::Write(err).into()),
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn open() {
-        use crate::RegKey;
-        assert!(
-            RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run",)
-                .is_ok()
-        );
-    }
-}
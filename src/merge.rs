@@ -0,0 +1,592 @@
+use crate::{
+    diff::{diff, DiffOptions, ValueText},
+    path::{RegPath, RegPathBuf},
+    reg_import::RegOperation,
+    snapshot::KeySnapshot,
+    value::RawValue,
+    value_name::ValueName,
+};
+use std::{collections::BTreeMap, fmt};
+
+#[cfg(windows)]
+use crate::{
+    reg_import::{apply, ImportReport},
+    reg_key::RegKey,
+    Result,
+};
+
+/// Which version [`merge`] keeps when both sides changed the same thing differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Resolution {
+    /// Keep the base version, undoing both changes
+    Base,
+    /// Keep our version
+    Ours,
+    /// Keep their version
+    Theirs,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resolution::Base => write!(fmt, "base"),
+            Resolution::Ours => write!(fmt, "ours"),
+            Resolution::Theirs => write!(fmt, "theirs"),
+        }
+    }
+}
+
+/// One of the two changed versions in a merge
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Side {
+    /// Our version
+    Ours,
+    /// Their version
+    Theirs,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Side::Ours => write!(fmt, "ours"),
+            Side::Theirs => write!(fmt, "theirs"),
+        }
+    }
+}
+
+/// What both sides of a merge disagree on
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "conflict", rename_all = "snake_case")
+)]
+pub enum ConflictKind {
+    /// Both sides changed the value, or one changed it and the other deleted it; `None` where the value
+    /// does not exist
+    Value {
+        /// Name of the value
+        name: ValueName,
+        /// The value in the base
+        base: Option<RawValue>,
+        /// Our value
+        ours: Option<RawValue>,
+        /// Their value
+        theirs: Option<RawValue>,
+    },
+    /// One side deleted the key while the other changed something in it or below it
+    DeletedKey {
+        /// The side that deleted the key
+        deleted_by: Side,
+    },
+}
+
+/// A conflict found by [`merge`] and how it was resolved
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conflict {
+    /// Path of the key, in our snapshot, that the conflict is in or about
+    pub path: RegPathBuf,
+    /// What the conflict is about
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub kind: ConflictKind,
+    /// The version that was kept in the merged tree
+    pub resolution: Resolution,
+}
+
+/// Writes an optional value, or `missing`
+struct MaybeValue<'a>(&'a Option<RawValue>);
+
+impl fmt::Display for MaybeValue<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(raw) => write!(fmt, "{}", ValueText(raw)),
+            None => write!(fmt, "missing"),
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    /// One line per conflict, in the style of [`Difference`](crate::Difference):
+    ///
+    /// ```text
+    /// ! value \Registry\Machine\Software\Vendor "Port": base REG_DWORD 80, ours REG_DWORD 8080, theirs missing; kept ours
+    /// ! key \Registry\Machine\Software\Vendor\Old: deleted by theirs, changed by ours; kept base
+    /// ```
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ConflictKind::Value {
+                name,
+                base,
+                ours,
+                theirs,
+            } => write!(
+                fmt,
+                "! value {} {:?}: base {}, ours {}, theirs {}",
+                self.path,
+                name,
+                MaybeValue(base),
+                MaybeValue(ours),
+                MaybeValue(theirs)
+            )?,
+            ConflictKind::DeletedKey { deleted_by } => {
+                let changed_by = match deleted_by {
+                    Side::Ours => Side::Theirs,
+                    Side::Theirs => Side::Ours,
+                };
+                write!(
+                    fmt,
+                    "! key {}: deleted by {}, changed by {}",
+                    self.path, deleted_by, changed_by
+                )?
+            }
+        }
+        write!(fmt, "; kept {}", self.resolution)
+    }
+}
+
+/// How [`merge`] resolves conflicts
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    resolution: Resolution,
+    overrides: Vec<(RegPathBuf, Resolution)>,
+}
+
+impl Default for MergeOptions {
+    fn default() -> MergeOptions {
+        MergeOptions {
+            resolution: Resolution::Ours,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl MergeOptions {
+    /// resolve every conflict in favour of our version
+    pub fn new() -> MergeOptions {
+        MergeOptions::default()
+    }
+
+    /// how to resolve conflicts that no path given to [`resolve_below`](Self::resolve_below) covers
+    pub fn resolve(&mut self, resolution: Resolution) -> &mut MergeOptions {
+        self.resolution = resolution;
+        self
+    }
+
+    /// how to resolve conflicts at or below the absolute path `path` in our snapshot; where several paths
+    /// cover a conflict, the longest one counts
+    pub fn resolve_below<P: Into<RegPathBuf>>(
+        &mut self,
+        path: P,
+        resolution: Resolution,
+    ) -> &mut MergeOptions {
+        self.overrides.push((path.into(), resolution));
+        self
+    }
+
+    fn resolution(&self, path: &RegPath) -> Resolution {
+        self.overrides
+            .iter()
+            .filter(|(base, _)| path.starts_with(base))
+            .max_by_key(|(base, _)| base.components().count())
+            .map_or(self.resolution, |(_, resolution)| *resolution)
+    }
+}
+
+/// The outcome of a [`merge`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MergeResult {
+    /// The merged tree, at the path of our snapshot
+    pub merged: KeySnapshot,
+    /// Every conflict, depth first in registry order, with the resolution that is reflected in `merged`
+    pub conflicts: Vec<Conflict>,
+}
+
+impl MergeResult {
+    /// Returns `true` if both sides could be merged without conflicts
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// The operations that turn `target` into the merged tree, normally with our snapshot as the target.
+    ///
+    /// They can be written as a `.reg` file with
+    /// [`RegExporter::write_operations`](crate::RegExporter::write_operations) or applied with
+    /// `apply`.
+    pub fn to_operations(&self, target: &KeySnapshot) -> Vec<RegOperation> {
+        diff(
+            target,
+            &self.merged,
+            DiffOptions::new().compare_timestamps(false),
+        )
+        .to_operations()
+    }
+
+    /// Captures `key` and changes it to match the merged tree, or only reports what that would do if
+    /// `dry_run` is set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::{merge, KeySnapshot, MergeOptions, OpenOptions, RegKey};
+    /// # fn load(_: &str) -> KeySnapshot { unimplemented!() }
+    /// let key = RegKey::open_with(
+    ///     r"\Registry\Machine\Software\Vendor",
+    ///     OpenOptions::new().read(true).write(true),
+    /// )
+    /// .unwrap();
+    /// let ours = KeySnapshot::capture(&key).unwrap();
+    /// let result = merge(&load("golden-v1"), &ours, &load("golden-v2"), &MergeOptions::new());
+    /// for conflict in &result.conflicts {
+    ///     println!("{}", conflict);
+    /// }
+    /// print!("{}", result.apply(&key, false).unwrap());
+    /// ```
+    #[cfg(windows)]
+    pub fn apply(&self, key: &RegKey, dry_run: bool) -> Result<ImportReport> {
+        let current = KeySnapshot::capture(key)?;
+        Ok(apply(&self.to_operations(&current), key, dry_run))
+    }
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs` into one tree.
+///
+/// Changes made by only one side are taken over, as are changes both sides made the same way. Where both
+/// sides changed a value differently, or one side deleted a key the other changed, the conflict is
+/// recorded and resolved as `options` ask; by default our version is kept. Values and keys are matched
+/// by name, case-insensitively, so the snapshots may be taken at different paths, and the merged tree is
+/// rooted at the path of `ours`. Last write times are not compared; the merged keys keep the later of the
+/// two.
+///
+/// # Examples
+///
+/// ```
+/// use winregnt::{merge, KeySnapshot, MergeOptions, RawValue, Resolution};
+/// let dword = |n: u32| RawValue::new(4, n.to_le_bytes().to_vec());
+/// let mut base = KeySnapshot::new(r"\Registry\Machine\Software\Vendor");
+/// base.values.push(("Port".into(), dword(80)));
+/// base.values.push(("Timeout".into(), dword(30)));
+///
+/// let mut ours = base.clone();
+/// ours.values[0].1 = dword(8080);
+/// let mut theirs = base.clone();
+/// theirs.values[0].1 = dword(443);
+/// theirs.values[1].1 = dword(60);
+///
+/// let result = merge(&base, &ours, &theirs, MergeOptions::new().resolve(Resolution::Theirs));
+/// assert_eq!(result.merged.value("Port"), Some(&dword(443)));
+/// assert_eq!(result.merged.value("Timeout"), Some(&dword(60)));
+/// assert_eq!(
+///     result.conflicts[0].to_string(),
+///     "! value \\Registry\\Machine\\Software\\Vendor \"Port\": \
+///      base REG_DWORD 80, ours REG_DWORD 8080, theirs REG_DWORD 443; kept theirs"
+/// );
+/// ```
+pub fn merge(
+    base: &KeySnapshot,
+    ours: &KeySnapshot,
+    theirs: &KeySnapshot,
+    options: &MergeOptions,
+) -> MergeResult {
+    let mut merger = Merger {
+        options,
+        conflicts: Vec::new(),
+    };
+    let merged = merger
+        .key(&ours.path, Some(base), Some(ours), Some(theirs))
+        .unwrap_or_else(|| KeySnapshot::new(ours.path.clone()));
+    MergeResult {
+        merged,
+        conflicts: merger.conflicts,
+    }
+}
+
+struct Merger<'a> {
+    options: &'a MergeOptions,
+    conflicts: Vec<Conflict>,
+}
+
+impl Merger<'_> {
+    /// Merges one key, returning `None` if it does not exist in the merged tree
+    fn key(
+        &mut self,
+        path: &RegPath,
+        base: Option<&KeySnapshot>,
+        ours: Option<&KeySnapshot>,
+        theirs: Option<&KeySnapshot>,
+    ) -> Option<KeySnapshot> {
+        let (ours, theirs) = match (ours, theirs) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            (None, None) => return None,
+            (ours, theirs) => {
+                let (kept, deleted_by) = match (ours, theirs) {
+                    (Some(ours), None) => (ours, Side::Theirs),
+                    (None, Some(theirs)) => (theirs, Side::Ours),
+                    _ => unreachable!(),
+                };
+                let base = match base {
+                    // added on one side only
+                    None => return Some(rebase(kept, path)),
+                    Some(base) => base,
+                };
+                if unchanged(base, kept) {
+                    return None;
+                }
+                let resolution = self.options.resolution(path);
+                self.conflicts.push(Conflict {
+                    path: path.to_reg_path_buf(),
+                    kind: ConflictKind::DeletedKey { deleted_by },
+                    resolution,
+                });
+                let deleted = match resolution {
+                    Resolution::Base => return Some(rebase(base, path)),
+                    Resolution::Ours => deleted_by == Side::Ours,
+                    Resolution::Theirs => deleted_by == Side::Theirs,
+                };
+                return if deleted {
+                    None
+                } else {
+                    Some(rebase(kept, path))
+                };
+            }
+        };
+
+        let mut merged = KeySnapshot {
+            path: path.to_reg_path_buf(),
+            last_write_time: ours.last_write_time.max(theirs.last_write_time),
+            class: ours.class.clone().or_else(|| theirs.class.clone()),
+            security: ours.security.clone().or_else(|| theirs.security.clone()),
+            values: Vec::new(),
+            children: Vec::new(),
+        };
+
+        let mut names: Vec<&ValueName> = Vec::new();
+        for (name, _) in ours.values.iter().chain(&theirs.values) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        for name in names {
+            let find =
+                |key: Option<&KeySnapshot>| key.and_then(|key| key.value(name.clone())).cloned();
+            let (base, ours, theirs) = (find(base), find(Some(ours)), find(Some(theirs)));
+            let value = if ours == theirs || theirs == base {
+                ours
+            } else if ours == base {
+                theirs
+            } else {
+                let resolution = self.options.resolution(path);
+                let kept = match resolution {
+                    Resolution::Base => base.clone(),
+                    Resolution::Ours => ours.clone(),
+                    Resolution::Theirs => theirs.clone(),
+                };
+                self.conflicts.push(Conflict {
+                    path: path.to_reg_path_buf(),
+                    kind: ConflictKind::Value {
+                        name: name.clone(),
+                        base,
+                        ours,
+                        theirs,
+                    },
+                    resolution,
+                });
+                kept
+            };
+            if let Some(raw) = value {
+                merged.values.push((name.clone(), raw));
+            }
+        }
+
+        type Versions<'a> = (
+            Option<&'a KeySnapshot>,
+            Option<&'a KeySnapshot>,
+            Option<&'a KeySnapshot>,
+        );
+        let mut children: BTreeMap<&RegPath, Versions> = BTreeMap::new();
+        for child in base.iter().flat_map(|base| &base.children) {
+            children.entry(child.name()).or_default().0 = Some(child);
+        }
+        for child in &ours.children {
+            children.entry(child.name()).or_default().1 = Some(child);
+        }
+        for child in &theirs.children {
+            children.entry(child.name()).or_default().2 = Some(child);
+        }
+        for (base, ours, theirs) in children.into_values() {
+            let name = ours.or(theirs).or(base).map(KeySnapshot::name).unwrap();
+            if let Some(child) = self.key(&path.join(name), base, ours, theirs) {
+                merged.children.push(child);
+            }
+        }
+        Some(merged)
+    }
+}
+
+/// Returns `true` if `changed` holds the same values and subkeys as `base`
+fn unchanged(base: &KeySnapshot, changed: &KeySnapshot) -> bool {
+    diff(base, changed, DiffOptions::new().compare_timestamps(false)).is_empty()
+}
+
+/// A copy of `key` moved to `path`
+fn rebase(key: &KeySnapshot, path: &RegPath) -> KeySnapshot {
+    KeySnapshot {
+        path: path.to_reg_path_buf(),
+        children: key
+            .children
+            .iter()
+            .map(|child| rebase(child, &path.join(child.name())))
+            .collect(),
+        ..key.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reg_import::dry_run;
+
+    fn dword(n: u32) -> RawValue {
+        RawValue::new(4, n.to_le_bytes().to_vec())
+    }
+
+    /// A golden image at version 1 and 2, and a machine set up from version 1 and then customized
+    fn snapshots() -> (KeySnapshot, KeySnapshot, KeySnapshot) {
+        let mut base = KeySnapshot::new(r"\Registry\Machine\Golden\v1\Vendor");
+        base.values.push(("Port".into(), dword(80)));
+        base.values.push(("Timeout".into(), dword(30)));
+        base.values.push(("Retries".into(), dword(3)));
+        base.child_mut("Plugins\\Spell")
+            .values
+            .push(("Enabled".into(), dword(1)));
+        base.child_mut("Cache")
+            .values
+            .push(("Size".into(), dword(10)));
+
+        let mut ours = rebase(
+            &base,
+            &RegPathBuf::from(r"\Registry\Machine\Software\Vendor"),
+        );
+        ours.values[0].1 = dword(8080);
+        ours.values[2].1 = dword(5);
+        ours.values.push(("Local".into(), dword(1)));
+        ours.child_mut("Plugins\\Spell").values[0].1 = dword(0);
+        ours.child_mut("Cache").values.clear();
+
+        let mut theirs = rebase(
+            &base,
+            &RegPathBuf::from(r"\Registry\Machine\Golden\v2\Vendor"),
+        );
+        theirs.values[0].1 = dword(443);
+        theirs.values[1].1 = dword(60);
+        theirs.values[2].1 = dword(5);
+        theirs
+            .children
+            .retain(|child| child.name().to_string() != "Plugins");
+        theirs.child_mut("Cache").values.clear();
+        theirs.child_mut("Telemetry");
+
+        (base, ours, theirs)
+    }
+
+    #[test]
+    fn merges() {
+        let (base, ours, theirs) = snapshots();
+        let result = merge(&base, &ours, &theirs, &MergeOptions::new());
+        assert_eq!(
+            result
+                .conflicts
+                .iter()
+                .map(|conflict| conflict.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "! value \\Registry\\Machine\\Software\\Vendor \"Port\": \
+                 base REG_DWORD 80, ours REG_DWORD 8080, theirs REG_DWORD 443; kept ours",
+                "! key \\Registry\\Machine\\Software\\Vendor\\Plugins: \
+                 deleted by theirs, changed by ours; kept ours",
+            ]
+        );
+
+        let merged = &result.merged;
+        assert_eq!(
+            merged.path.to_string(),
+            r"\Registry\Machine\Software\Vendor"
+        );
+        assert_eq!(merged.value("Port"), Some(&dword(8080)));
+        assert_eq!(merged.value("Timeout"), Some(&dword(60)));
+        assert_eq!(merged.value("Retries"), Some(&dword(5)));
+        assert_eq!(merged.value("Local"), Some(&dword(1)));
+        assert!(merged.child("Cache").unwrap().values.is_empty());
+        assert_eq!(
+            merged.child("Telemetry").unwrap().path.to_string(),
+            r"\Registry\Machine\Software\Vendor\Telemetry"
+        );
+        assert_eq!(
+            merged.child("Plugins").unwrap().children[0]
+                .path
+                .to_string(),
+            r"\Registry\Machine\Software\Vendor\Plugins\Spell"
+        );
+
+        let operations = result.to_operations(&ours);
+        assert_eq!(
+            operations
+                .iter()
+                .map(|operation| operation.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "set value \"Timeout\" in \\Registry\\Machine\\Software\\Vendor to 60",
+                "create key \\Registry\\Machine\\Software\\Vendor\\Telemetry",
+            ]
+        );
+        assert!(dry_run(&operations, &&ours).is_clean());
+    }
+
+    #[test]
+    fn policies() {
+        let (base, ours, theirs) = snapshots();
+        let result = merge(
+            &base,
+            &ours,
+            &theirs,
+            MergeOptions::new()
+                .resolve(Resolution::Theirs)
+                .resolve_below(
+                    r"\Registry\Machine\Software\Vendor\Plugins",
+                    Resolution::Base,
+                ),
+        );
+        assert_eq!(result.conflicts[0].resolution, Resolution::Theirs);
+        assert_eq!(result.merged.value("Port"), Some(&dword(443)));
+        assert_eq!(result.conflicts[1].resolution, Resolution::Base);
+        assert_eq!(
+            result
+                .merged
+                .child(r"Plugins")
+                .and_then(|plugins| plugins.child("Spell"))
+                .and_then(|spell| spell.value("Enabled")),
+            Some(&dword(1))
+        );
+
+        let result = merge(
+            &base,
+            &ours,
+            &theirs,
+            MergeOptions::new().resolve(Resolution::Theirs),
+        );
+        assert!(result.merged.child("Plugins").is_none());
+        assert!(!result.is_clean());
+        assert!(merge(&base, &ours, &ours, &MergeOptions::new()).is_clean());
+    }
+}
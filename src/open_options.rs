@@ -0,0 +1,107 @@
+use crate::privilege::Privilege;
+use winapi::um::winnt::{ACCESS_MASK, DELETE, KEY_READ, KEY_SET_VALUE, KEY_WRITE};
+
+/// Options controlling how a `RegKey` is opened
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::{OpenOptions, RegKey};
+/// let software = RegKey::open(r"\Registry\Machine\Software").unwrap();
+/// let key = software
+///     .open_subkey("Microsoft", OpenOptions::new().read(true).write(true))
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    access: ACCESS_MASK,
+    backup_intent: bool,
+    backup_fallback: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions {
+            read: true,
+            write: false,
+            access: 0,
+            backup_intent: false,
+            backup_fallback: false,
+        }
+    }
+}
+
+impl OpenOptions {
+    /// options for opening a key as read only
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// request read access (`KEY_READ`)
+    pub fn read(&mut self, read: bool) -> &mut OpenOptions {
+        self.read = read;
+        self
+    }
+
+    /// request write access (`KEY_WRITE`, `KEY_SET_VALUE` and `DELETE`)
+    pub fn write(&mut self, write: bool) -> &mut OpenOptions {
+        self.write = write;
+        self
+    }
+
+    /// request additional access rights not covered by `read` and `write`
+    pub fn access(&mut self, access: ACCESS_MASK) -> &mut OpenOptions {
+        self.access = access;
+        self
+    }
+
+    /// open with backup intent (`REG_OPTION_BACKUP_RESTORE`), so access is granted by `SeBackupPrivilege`
+    /// (and `SeRestorePrivilege` for write access) rather than by the security descriptor of the key.
+    ///
+    /// The privileges are enabled for the duration of the open. This lets keys such as `SAM\SAM` be read
+    /// even though their DACL denies administrators. Like `backup_fallback`, this carries over to every subkey
+    /// opened through `KeySource`.
+    pub fn backup_intent(&mut self, backup_intent: bool) -> &mut OpenOptions {
+        self.backup_intent = backup_intent;
+        self
+    }
+
+    /// retry with backup intent when the security descriptor of the key denies the requested access.
+    ///
+    /// This carries over to every subkey opened through `KeySource`, so the exporters and snapshots walking
+    /// a key opened this way are not silently cut short by restrictive ACLs.
+    pub fn backup_fallback(&mut self, backup_fallback: bool) -> &mut OpenOptions {
+        self.backup_fallback = backup_fallback;
+        self
+    }
+
+    pub(crate) fn is_backup_intent(&self) -> bool {
+        self.backup_intent
+    }
+
+    pub(crate) fn is_backup_fallback(&self) -> bool {
+        self.backup_fallback
+    }
+
+    /// the privileges needed to open a key with backup intent using these options
+    pub(crate) fn backup_privileges(&self) -> &'static [Privilege] {
+        if self.write {
+            &[Privilege::Backup, Privilege::Restore]
+        } else {
+            &[Privilege::Backup]
+        }
+    }
+
+    pub(crate) fn desired_access(&self) -> ACCESS_MASK {
+        let mut access = self.access;
+        if self.read {
+            access |= KEY_READ;
+        }
+        if self.write {
+            access |= KEY_WRITE | DELETE | KEY_SET_VALUE;
+        }
+        access
+    }
+}
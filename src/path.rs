@@ -0,0 +1,632 @@
+use std::{
+    borrow::{Borrow, Cow},
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+/// The registry path separator (`\`)
+pub const SEPARATOR: u16 = b'\\' as u16;
+
+/// Upcases a single UTF-16 code unit for comparing names, close to what the configuration manager does.
+///
+/// The kernel compares names with its own upcase table, which this approximates with Rust's Unicode
+/// uppercase mapping. Characters whose uppercase form expands to more than one character (such as `ß`) or
+/// leaves the Basic Multilingual Plane, and surrogates, are left untouched. The two tables are not identical,
+/// so names that differ only in a handful of rarely used characters may compare differently here than in the
+/// registry.
+pub fn upcase(c: u16) -> u16 {
+    if c < 0x80 {
+        return (c as u8).to_ascii_uppercase() as u16;
+    }
+
+    match std::char::from_u32(c as u32) {
+        Some(ch) => {
+            let mut upper = ch.to_uppercase();
+            match (upper.next(), upper.next()) {
+                (Some(u), None) if (u as u32) <= 0xffff => u as u32 as u16,
+                _ => c,
+            }
+        }
+        None => c,
+    }
+}
+
+/// A borrowed registry path, such as `\Registry\Machine\Software`.
+///
+/// The `Nt*` registry functions take counted UTF-16 strings, so a key name may contain characters (such as
+/// embedded `null`s) that cannot survive a round trip through a Rust `str` or a null terminated buffer.
+/// `RegPath` stores the raw UTF-16 code units and, like `std::path::Path`, is always used behind a reference;
+/// [`RegPathBuf`] is its owned counterpart.
+///
+/// Comparison, ordering and hashing are case-insensitive, matching how the configuration manager looks up
+/// key names.
+#[repr(transparent)]
+pub struct RegPath {
+    inner: [u16],
+}
+
+impl RegPath {
+    /// Wraps a UTF-16 slice as a `RegPath` without copying it
+    pub fn from_wide(wide: &[u16]) -> &RegPath {
+        // SAFETY: `RegPath` is `repr(transparent)` over `[u16]`
+        unsafe { &*(wide as *const [u16] as *const RegPath) }
+    }
+
+    /// Returns the raw UTF-16 code units of this path (not null terminated)
+    pub fn as_wide(&self) -> &[u16] {
+        &self.inner
+    }
+
+    /// Copies this path into an owned `RegPathBuf`
+    pub fn to_reg_path_buf(&self) -> RegPathBuf {
+        RegPathBuf {
+            inner: self.inner.to_vec(),
+        }
+    }
+
+    /// Converts the path to a `String`, failing if it is not valid UTF-16
+    pub fn to_string_checked(&self) -> Option<String> {
+        String::from_utf16(&self.inner).ok()
+    }
+
+    /// Converts the path to a `String`, replacing invalid UTF-16 with `U+FFFD`
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        Cow::Owned(String::from_utf16_lossy(&self.inner))
+    }
+
+    /// Returns `true` if the path has no code units at all
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns `true` if the path starts with a separator, e.g. `\Registry\Machine`
+    pub fn is_absolute(&self) -> bool {
+        self.inner.first() == Some(&SEPARATOR)
+    }
+
+    /// Iterates over the non-empty components of the path.
+    ///
+    /// Repeated, leading and trailing separators are ignored, so `\Registry\\Machine\` yields `Registry` and
+    /// `Machine`. Components may contain embedded `null`s.
+    pub fn components(&self) -> Components<'_> {
+        Components { rest: &self.inner }
+    }
+
+    /// Returns the final component of the path, if there is one
+    pub fn name(&self) -> Option<&RegPath> {
+        self.components().next_back()
+    }
+
+    /// Returns the path without its final component.
+    ///
+    /// `\Registry\Machine` has the parent `\Registry`, `\Registry` has the parent `\` and `\` has no parent.
+    /// A single relative component has an empty parent.
+    pub fn parent(&self) -> Option<&RegPath> {
+        let trimmed = trim_end_separators(&self.inner);
+        if trimmed.is_empty() {
+            return None;
+        }
+
+        match trimmed.iter().rposition(|c| *c == SEPARATOR) {
+            Some(index) => {
+                let parent = trim_end_separators(&trimmed[..index]);
+                if parent.is_empty() {
+                    Some(RegPath::from_wide(&trimmed[..1]))
+                } else {
+                    Some(RegPath::from_wide(parent))
+                }
+            }
+            None => Some(RegPath::from_wide(&[])),
+        }
+    }
+
+    /// Creates a new path by appending `other` to this one.
+    ///
+    /// If `other` is absolute it replaces this path entirely, as with `std::path::Path::join`.
+    pub fn join<P: AsRef<RegPath>>(&self, other: P) -> RegPathBuf {
+        let mut buf = self.to_reg_path_buf();
+        buf.push(other);
+        buf
+    }
+
+    /// Returns `true` if `base` is a (case-insensitive) component-wise prefix of this path
+    pub fn starts_with<P: AsRef<RegPath>>(&self, base: P) -> bool {
+        self.strip_prefix(base).is_some()
+    }
+
+    /// Returns the remainder of this path after removing `base`, compared component-wise and case-insensitively
+    pub fn strip_prefix<P: AsRef<RegPath>>(&self, base: P) -> Option<&RegPath> {
+        let base = base.as_ref();
+        if base.is_absolute() != self.is_absolute() && !base.is_empty() {
+            return None;
+        }
+
+        let mut ours = self.components();
+        for theirs in base.components() {
+            match ours.next() {
+                Some(component) if component == theirs => {}
+                _ => return None,
+            }
+        }
+        Some(RegPath::from_wide(trim_start_separators(ours.rest)))
+    }
+
+    /// Converts a kernel path such as `\Registry\Machine\Software` to the form used by the Win32 functions
+    /// and `.reg` files, `HKEY_LOCAL_MACHINE\Software`.
+    ///
+    /// Returns `None` if the path is not below one of the roots that has a Win32 name.
+    pub fn to_win32_path(&self) -> Option<RegPathBuf> {
+        WIN32_ROOTS.iter().find_map(|(nt, win32)| {
+            self.strip_prefix(RegPathBuf::from(*nt))
+                .map(|rest| RegPathBuf::from(*win32).join(rest))
+        })
+    }
+
+    /// Converts a Win32 path such as `HKEY_LOCAL_MACHINE\Software` (or `HKLM\Software`) to the kernel path
+    /// `\Registry\Machine\Software`.
+    ///
+    /// `HKEY_CLASSES_ROOT` and `HKEY_CURRENT_CONFIG` are mapped to the machine keys they are views of.
+    /// Returns `None` for any other root, including `HKEY_CURRENT_USER`, whose kernel path depends on the
+    /// user.
+    pub fn to_nt_path(&self) -> Option<RegPathBuf> {
+        let mut components = self.components();
+        let root = components.next()?;
+        let nt = NT_ROOTS
+            .iter()
+            .find(|(names, _)| names.iter().any(|name| RegPathBuf::from(*name) == *root))
+            .map(|(_, nt)| *nt)?;
+        let mut path = RegPathBuf::from(nt);
+        components.for_each(|component| path.push(component));
+        Some(path)
+    }
+
+    fn upcased(&self) -> impl Iterator<Item = u16> + '_ {
+        self.inner.iter().copied().map(upcase)
+    }
+}
+
+/// Kernel paths of the registry roots and their Win32 names
+const WIN32_ROOTS: &[(&str, &str)] = &[
+    (r"\Registry\Machine", "HKEY_LOCAL_MACHINE"),
+    (r"\Registry\User", "HKEY_USERS"),
+];
+
+/// Win32 root names (and their abbreviations) and the kernel paths they refer to
+const NT_ROOTS: &[(&[&str], &str)] = &[
+    (&["HKEY_LOCAL_MACHINE", "HKLM"], r"\Registry\Machine"),
+    (&["HKEY_USERS", "HKU"], r"\Registry\User"),
+    (
+        &["HKEY_CLASSES_ROOT", "HKCR"],
+        r"\Registry\Machine\Software\Classes",
+    ),
+    (
+        &["HKEY_CURRENT_CONFIG", "HKCC"],
+        r"\Registry\Machine\System\CurrentControlSet\Hardware Profiles\Current",
+    ),
+];
+
+fn trim_start_separators(mut wide: &[u16]) -> &[u16] {
+    while let Some((&SEPARATOR, rest)) = wide.split_first() {
+        wide = rest;
+    }
+    wide
+}
+
+fn trim_end_separators(mut wide: &[u16]) -> &[u16] {
+    while let Some((&SEPARATOR, rest)) = wide.split_last() {
+        wide = rest;
+    }
+    wide
+}
+
+impl PartialEq for RegPath {
+    fn eq(&self, other: &RegPath) -> bool {
+        self.inner.len() == other.inner.len() && self.upcased().eq(other.upcased())
+    }
+}
+
+impl Eq for RegPath {}
+
+impl PartialOrd for RegPath {
+    fn partial_cmp(&self, other: &RegPath) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RegPath {
+    /// Orders paths by their upcased code units, which is the order the registry stores subkeys in
+    fn cmp(&self, other: &RegPath) -> Ordering {
+        self.upcased().cmp(other.upcased())
+    }
+}
+
+impl Hash for RegPath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.inner.len());
+        self.upcased().for_each(|c| state.write_u16(c));
+    }
+}
+
+impl fmt::Debug for RegPath {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:?}", self.to_string_lossy())
+    }
+}
+
+impl fmt::Display for RegPath {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.to_string_lossy())
+    }
+}
+
+impl AsRef<RegPath> for RegPath {
+    fn as_ref(&self) -> &RegPath {
+        self
+    }
+}
+
+impl AsRef<[u16]> for RegPath {
+    fn as_ref(&self) -> &[u16] {
+        &self.inner
+    }
+}
+
+impl ToOwned for RegPath {
+    type Owned = RegPathBuf;
+
+    fn to_owned(&self) -> RegPathBuf {
+        self.to_reg_path_buf()
+    }
+}
+
+/// Iterator over the components of a [`RegPath`]
+#[derive(Clone)]
+pub struct Components<'a> {
+    rest: &'a [u16],
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = &'a RegPath;
+
+    fn next(&mut self) -> Option<&'a RegPath> {
+        let rest = trim_start_separators(self.rest);
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+
+        let end = rest
+            .iter()
+            .position(|c| *c == SEPARATOR)
+            .unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        Some(RegPath::from_wide(&rest[..end]))
+    }
+}
+
+impl<'a> DoubleEndedIterator for Components<'a> {
+    fn next_back(&mut self) -> Option<&'a RegPath> {
+        let rest = trim_end_separators(self.rest);
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+
+        let start = rest
+            .iter()
+            .rposition(|c| *c == SEPARATOR)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        self.rest = &rest[..start];
+        Some(RegPath::from_wide(&rest[start..]))
+    }
+}
+
+/// An owned, mutable registry path
+#[derive(Clone, Default)]
+pub struct RegPathBuf {
+    inner: Vec<u16>,
+}
+
+impl RegPathBuf {
+    /// Creates an empty path
+    pub fn new() -> RegPathBuf {
+        RegPathBuf::default()
+    }
+
+    /// Creates a path from raw UTF-16 code units
+    pub fn from_wide<W: Into<Vec<u16>>>(wide: W) -> RegPathBuf {
+        RegPathBuf { inner: wide.into() }
+    }
+
+    /// Borrows this path as a `RegPath`
+    pub fn as_reg_path(&self) -> &RegPath {
+        RegPath::from_wide(&self.inner)
+    }
+
+    /// Consumes the path, returning its UTF-16 code units
+    pub fn into_wide(self) -> Vec<u16> {
+        self.inner
+    }
+
+    /// Appends `other` with a single separator in between.
+    ///
+    /// If `other` is absolute it replaces the current path, if it is empty nothing is appended.
+    pub fn push<P: AsRef<RegPath>>(&mut self, other: P) {
+        let other = other.as_ref().as_wide();
+        if other.is_empty() {
+            return;
+        }
+        if other.first() == Some(&SEPARATOR) {
+            self.inner.clear();
+        } else if !self.inner.is_empty() && self.inner.last() != Some(&SEPARATOR) {
+            self.inner.push(SEPARATOR);
+        }
+        self.inner.extend_from_slice(other);
+    }
+
+    /// Truncates the path to its parent, returning `false` if there is no parent
+    pub fn pop(&mut self) -> bool {
+        match self.parent().map(|p| p.inner.len()) {
+            Some(len) => {
+                self.inner.truncate(len);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Deref for RegPathBuf {
+    type Target = RegPath;
+
+    fn deref(&self) -> &RegPath {
+        self.as_reg_path()
+    }
+}
+
+impl Borrow<RegPath> for RegPathBuf {
+    fn borrow(&self) -> &RegPath {
+        self.as_reg_path()
+    }
+}
+
+impl AsRef<RegPath> for RegPathBuf {
+    fn as_ref(&self) -> &RegPath {
+        self.as_reg_path()
+    }
+}
+
+impl PartialEq for RegPathBuf {
+    fn eq(&self, other: &RegPathBuf) -> bool {
+        self.as_reg_path() == other.as_reg_path()
+    }
+}
+
+impl Eq for RegPathBuf {}
+
+impl PartialEq<RegPath> for RegPathBuf {
+    fn eq(&self, other: &RegPath) -> bool {
+        self.as_reg_path() == other
+    }
+}
+
+impl PartialEq<RegPathBuf> for RegPath {
+    fn eq(&self, other: &RegPathBuf) -> bool {
+        self == other.as_reg_path()
+    }
+}
+
+impl<'a> PartialEq<&'a RegPath> for RegPathBuf {
+    fn eq(&self, other: &&'a RegPath) -> bool {
+        self.as_reg_path() == *other
+    }
+}
+
+impl PartialOrd for RegPathBuf {
+    fn partial_cmp(&self, other: &RegPathBuf) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RegPathBuf {
+    fn cmp(&self, other: &RegPathBuf) -> Ordering {
+        self.as_reg_path().cmp(other.as_reg_path())
+    }
+}
+
+impl Hash for RegPathBuf {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_reg_path().hash(state)
+    }
+}
+
+impl fmt::Debug for RegPathBuf {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_reg_path(), fmt)
+    }
+}
+
+impl fmt::Display for RegPathBuf {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_reg_path(), fmt)
+    }
+}
+
+impl From<&str> for RegPathBuf {
+    fn from(input: &str) -> Self {
+        RegPathBuf {
+            inner: input.encode_utf16().collect(),
+        }
+    }
+}
+
+impl From<String> for RegPathBuf {
+    fn from(input: String) -> Self {
+        RegPathBuf::from(input.as_str())
+    }
+}
+
+impl From<&String> for RegPathBuf {
+    fn from(input: &String) -> Self {
+        RegPathBuf::from(input.as_str())
+    }
+}
+
+impl From<&RegPath> for RegPathBuf {
+    fn from(input: &RegPath) -> Self {
+        input.to_reg_path_buf()
+    }
+}
+
+impl From<&RegPathBuf> for RegPathBuf {
+    fn from(input: &RegPathBuf) -> Self {
+        input.clone()
+    }
+}
+
+impl From<Vec<u16>> for RegPathBuf {
+    fn from(input: Vec<u16>) -> Self {
+        RegPathBuf { inner: input }
+    }
+}
+
+impl From<&[u16]> for RegPathBuf {
+    fn from(input: &[u16]) -> Self {
+        RegPathBuf {
+            inner: input.to_vec(),
+        }
+    }
+}
+
+impl<P: AsRef<RegPath>> std::iter::FromIterator<P> for RegPathBuf {
+    fn from_iter<I: IntoIterator<Item = P>>(iter: I) -> Self {
+        let mut buf = RegPathBuf::new();
+        iter.into_iter().for_each(|p| buf.push(p));
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn join_and_parent() {
+        let base = RegPathBuf::from(r"\Registry\Machine");
+        let joined = base.join(RegPathBuf::from("Software"));
+        assert_eq!(joined.to_string(), r"\Registry\Machine\Software");
+        assert_eq!(joined.parent().unwrap(), base.as_reg_path());
+        assert_eq!(base.parent().unwrap().to_string(), r"\Registry");
+        assert_eq!(
+            RegPathBuf::from(r"\Registry").parent().unwrap().to_string(),
+            r"\"
+        );
+        assert!(RegPathBuf::from(r"\").parent().is_none());
+        assert!(RegPathBuf::from("Run").parent().unwrap().is_empty());
+
+        let replaced = base.join(RegPathBuf::from(r"\Registry\User"));
+        assert_eq!(replaced.to_string(), r"\Registry\User");
+    }
+
+    #[test]
+    fn components() {
+        let path = RegPathBuf::from(r"\Registry\\Machine\Software\");
+        let parts = path.components().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(parts, vec!["Registry", "Machine", "Software"]);
+        assert_eq!(path.name().unwrap().to_string(), "Software");
+        assert_eq!(
+            path.components().next_back().unwrap().to_string(),
+            "Software"
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let a = RegPathBuf::from(r"\REGISTRY\Machine\SOFTWARE\Ünïcode");
+        let b = RegPathBuf::from(r"\Registry\machine\software\ÜNÏCODE");
+        assert_eq!(a, b);
+
+        let mut set = HashSet::new();
+        set.insert(a.clone());
+        assert!(set.contains(&b));
+
+        assert!(a.starts_with(RegPathBuf::from(r"\registry\MACHINE")));
+        assert!(!a.starts_with(RegPathBuf::from(r"\registry\MACH")));
+        assert_eq!(
+            a.strip_prefix(RegPathBuf::from(r"\registry"))
+                .unwrap()
+                .to_string(),
+            r"Machine\SOFTWARE\Ünïcode"
+        );
+    }
+
+    #[test]
+    fn win32_paths() {
+        assert_eq!(
+            RegPathBuf::from(r"\REGISTRY\MACHINE\Software")
+                .to_win32_path()
+                .unwrap()
+                .to_string(),
+            r"HKEY_LOCAL_MACHINE\Software"
+        );
+        assert_eq!(
+            RegPathBuf::from(r"\Registry\User")
+                .to_win32_path()
+                .unwrap()
+                .to_string(),
+            "HKEY_USERS"
+        );
+        assert!(RegPathBuf::from(r"\Registry\A").to_win32_path().is_none());
+
+        assert_eq!(
+            RegPathBuf::from(r"hklm\Software\\Test\")
+                .to_nt_path()
+                .unwrap()
+                .to_string(),
+            r"\Registry\Machine\Software\Test"
+        );
+        assert_eq!(
+            RegPathBuf::from("HKEY_CLASSES_ROOT")
+                .to_nt_path()
+                .unwrap()
+                .to_string(),
+            r"\Registry\Machine\Software\Classes"
+        );
+        assert!(RegPathBuf::from(r"HKEY_CURRENT_USER\Software")
+            .to_nt_path()
+            .is_none());
+    }
+
+    #[test]
+    fn embedded_nulls() {
+        let mut name = wide("Hidden");
+        name.push(0);
+        name.extend(wide("Key"));
+
+        let path = RegPathBuf::from(r"\Registry\Machine").join(RegPath::from_wide(&name));
+        assert_eq!(path.name().unwrap().as_wide(), &name[..]);
+        assert_eq!(path.components().count(), 3);
+        assert_ne!(
+            path,
+            RegPathBuf::from(r"\Registry\Machine").join(RegPathBuf::from("HiddenKey"))
+        );
+    }
+
+    #[test]
+    fn upcase_rules() {
+        assert_eq!(upcase(b'a' as u16), b'A' as u16);
+        assert_eq!(upcase(0x00e9), 0x00c9);
+        // expands to "SS", left alone
+        assert_eq!(upcase(0x00df), 0x00df);
+        // lone surrogate
+        assert_eq!(upcase(0xd800), 0xd800);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    api::RtlAdjustPrivilege,
+    error::{Error, ErrorKind, Operation},
+    path::RegPathBuf,
+    status::NtStatus,
+    Result,
+};
+use std::sync::Mutex;
+
+/// A privilege some registry operations need enabled in the caller's token
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    /// `SeBackupPrivilege`: read any key regardless of its security descriptor, save keys to files
+    Backup,
+    /// `SeRestorePrivilege`: write any key regardless of its security descriptor, restore and load hives
+    Restore,
+    /// `SeTakeOwnershipPrivilege`: take ownership of any key
+    TakeOwnership,
+}
+
+/// How many process-wide guards hold each privilege, and whether it was enabled before the first one
+static PROCESS: Mutex<[(usize, bool); 3]> = Mutex::new([(0, false); 3]);
+
+impl Privilege {
+    /// The name of the privilege, such as `SeBackupPrivilege`
+    pub fn name(self) -> &'static str {
+        match self {
+            Privilege::Backup => "SeBackupPrivilege",
+            Privilege::Restore => "SeRestorePrivilege",
+            Privilege::TakeOwnership => "SeTakeOwnershipPrivilege",
+        }
+    }
+
+    fn value(self) -> u32 {
+        match self {
+            Privilege::Backup => 17,
+            Privilege::Restore => 18,
+            Privilege::TakeOwnership => 9,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Enables the privilege in the process token until the returned guard is dropped.
+    ///
+    /// Guards are counted, so the privilege is only disabled again when the last guard is dropped, and
+    /// only if it was disabled before the first. Fails with [`ErrorKind::PrivilegeNotHeld`] if the token
+    /// does not contain the privilege at all, such as when the process is not elevated.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::{Privilege, RegKey};
+    /// let _backup = Privilege::Backup.enable().unwrap();
+    /// let sam = RegKey::open(r"\Registry\Machine\SAM\SAM").unwrap();
+    /// ```
+    pub fn enable(self) -> Result<PrivilegeGuard> {
+        self.acquire(false).map_err(enable_error)
+    }
+
+    /// Enables the privilege in the token the current thread is impersonating until the returned guard is
+    /// dropped.
+    ///
+    /// The thread must already be impersonating, for example after `ImpersonateSelf`; otherwise this fails
+    /// with `STATUS_NO_TOKEN`.
+    pub fn enable_for_thread(self) -> Result<PrivilegeGuard> {
+        self.acquire(true).map_err(enable_error)
+    }
+
+    pub(crate) fn acquire(self, thread: bool) -> std::result::Result<PrivilegeGuard, ErrorKind> {
+        let was_enabled = if thread {
+            adjust(self, true, true)?
+        } else {
+            let mut process = PROCESS.lock().unwrap_or_else(|e| e.into_inner());
+            let (count, was_enabled) = &mut process[self.index()];
+            if *count == 0 {
+                *was_enabled = adjust(self, true, false)?;
+            }
+            *count += 1;
+            true
+        };
+        Ok(PrivilegeGuard {
+            privilege: self,
+            thread,
+            was_enabled,
+        })
+    }
+}
+
+impl std::fmt::Display for Privilege {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str(self.name())
+    }
+}
+
+/// Enables or disables `privilege`, returning whether it was enabled before
+fn adjust(
+    privilege: Privilege,
+    enable: bool,
+    thread: bool,
+) -> std::result::Result<bool, ErrorKind> {
+    let mut was_enabled = 0;
+    let status = NtStatus::from(unsafe {
+        RtlAdjustPrivilege(
+            privilege.value(),
+            enable as _,
+            thread as _,
+            &mut was_enabled,
+        )
+    });
+    match status {
+        NtStatus::PRIVILEGE_NOT_HELD | NtStatus::NOT_ALL_ASSIGNED => {
+            Err(ErrorKind::PrivilegeNotHeld(privilege.name()))
+        }
+        status if status.is_success() => Ok(was_enabled != 0),
+        status => Err(ErrorKind::Status(status)),
+    }
+}
+
+fn enable_error(kind: ErrorKind) -> Error {
+    Error::new(Operation::EnablePrivilege, RegPathBuf::new(), kind)
+}
+
+/// Enables `privileges` for the duration of `operation` on the key at `path`
+pub(crate) fn require(
+    operation: Operation,
+    path: &RegPathBuf,
+    privileges: &[Privilege],
+) -> Result<Vec<PrivilegeGuard>> {
+    privileges
+        .iter()
+        .map(|privilege| {
+            privilege
+                .acquire(false)
+                .map_err(|kind| Error::new(operation, path, kind))
+        })
+        .collect()
+}
+
+/// A privilege enabled by [`Privilege::enable`] or [`Privilege::enable_for_thread`], restored to its
+/// previous state when dropped
+#[derive(Debug)]
+pub struct PrivilegeGuard {
+    privilege: Privilege,
+    thread: bool,
+    was_enabled: bool,
+}
+
+impl PrivilegeGuard {
+    /// The privilege that is enabled
+    pub fn privilege(&self) -> Privilege {
+        self.privilege
+    }
+}
+
+impl Drop for PrivilegeGuard {
+    fn drop(&mut self) {
+        if self.thread {
+            if !self.was_enabled {
+                let _ = adjust(self.privilege, false, true);
+            }
+            return;
+        }
+        let mut process = PROCESS.lock().unwrap_or_else(|e| e.into_inner());
+        let (count, was_enabled) = &mut process[self.privilege.index()];
+        *count -= 1;
+        if *count == 0 && !*was_enabled {
+            let _ = adjust(self.privilege, false, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enable_and_restore() {
+        match Privilege::TakeOwnership.enable() {
+            Ok(outer) => {
+                let inner = Privilege::TakeOwnership.enable().unwrap();
+                drop(outer);
+                assert_eq!(PROCESS.lock().unwrap()[2].0, 1);
+                drop(inner);
+                assert_eq!(PROCESS.lock().unwrap()[2].0, 0);
+            }
+            Err(e) => {
+                assert!(e.is_privilege_not_held());
+                assert_eq!(
+                    e.to_string(),
+                    "Could not enable privilege: SeTakeOwnershipPrivilege is not held"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::{RegPath, SEPARATOR},
+    reg_import::RegOperation,
+    source::{check_depth, KeySource},
+    value::{RawValue, ValueType},
+    value_name::ValueName,
+    Result,
+};
+use std::io::{self, Write};
+
+/// The first line of a `.reg` file written by the Registry Editor
+pub const REG_FILE_HEADER: &str = "Windows Registry Editor Version 5.00";
+
+/// Prefix of lines holding entries the Registry Editor cannot represent, see `RegExporter`
+pub const EXTENSION_PREFIX: &str = ";winregnt:";
+
+/// regedit wraps hex data once a line reaches this many characters
+const MAX_HEX_CHARS: usize = 77;
+
+const CR: u16 = 0x0d;
+const LF: u16 = 0x0a;
+
+/// Writes keys and values as a UTF-16LE `.reg` file (Registry Editor Version 5.00).
+///
+/// The output matches what the Registry Editor produces when exporting a key: string values are written as
+/// `"name"="data"`, well formed `REG_DWORD`s as `dword:`, `REG_BINARY` as `hex:` and every other type as
+/// `hex(n):`, with hex data wrapped at the same column as the Registry Editor.
+///
+/// Some registry content has no representation in the format:
+///
+/// * `REG_SZ` data that is not a single null terminated string, or that contains a line break, is written
+///   as `hex(1):` so the exact bytes survive.
+/// * Key and value names containing `null`s or line breaks cannot be written at all. Such entries are
+///   written on lines starting with `;winregnt:` (a comment to the Registry Editor, which skips them), in
+///   the regular syntax but with `%`, `null`, carriage return and line feed in names percent-encoded as
+///   `%25`, `%00`, `%0D` and `%0A`. When a key is written this way, so are all of its values.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(windows)]
+/// # fn main() {
+/// use winregnt::{RegExporter, RegKey};
+/// let key = RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").unwrap();
+/// let file = std::fs::File::create("run.reg").unwrap();
+/// let mut exporter = RegExporter::new(file).unwrap();
+/// exporter.write_tree(&key).unwrap();
+/// exporter.finish().unwrap();
+/// # }
+/// # #[cfg(not(windows))]
+/// # fn main() {}
+/// ```
+pub struct RegExporter<W: Write> {
+    writer: W,
+    extension: bool,
+}
+
+impl<W: Write> RegExporter<W> {
+    /// Starts a new `.reg` file, writing the byte order mark and the header
+    pub fn new(writer: W) -> io::Result<RegExporter<W>> {
+        let mut exporter = RegExporter {
+            writer,
+            extension: false,
+        };
+        exporter.writer.write_all(&[0xff, 0xfe])?;
+        exporter.write_line(&REG_FILE_HEADER.encode_utf16().collect::<Vec<_>>())?;
+        Ok(exporter)
+    }
+
+    /// Starts a new key section.
+    ///
+    /// Paths below `\Registry\Machine` and `\Registry\User` are written with their Win32 root names, any
+    /// other path is written as is, without its leading separator.
+    pub fn write_key(&mut self, path: &RegPath) -> io::Result<()> {
+        self.write_section(path, false)
+    }
+
+    /// Writes a `[-...]` section that deletes the key at `path` and everything below it.
+    ///
+    /// Values cannot follow a deleted key; start a new section with [`write_key`](Self::write_key) first.
+    pub fn write_deleted_key(&mut self, path: &RegPath) -> io::Result<()> {
+        self.write_section(path, true)
+    }
+
+    fn write_section(&mut self, path: &RegPath, delete: bool) -> io::Result<()> {
+        let path = match path.to_win32_path() {
+            Some(path) => path.into_wide(),
+            None => path
+                .as_wide()
+                .iter()
+                .copied()
+                .skip_while(|c| *c == SEPARATOR)
+                .collect(),
+        };
+        self.extension = !representable(&path);
+
+        let mut line = Vec::new();
+        if self.extension {
+            line.extend(EXTENSION_PREFIX.encode_utf16());
+        }
+        line.push('[' as u16);
+        if delete {
+            line.push('-' as u16);
+        }
+        if self.extension {
+            line.extend(percent_encode(&path));
+        } else {
+            line.extend_from_slice(&path);
+        }
+        line.push(']' as u16);
+
+        self.write_line(&[])?;
+        self.write_line(&line)
+    }
+
+    /// Writes a value of the current key
+    pub fn write_value(&mut self, name: &ValueName, raw: &RawValue) -> io::Result<()> {
+        let (extension, mut line) = self.value_name(name);
+
+        let mut lines = Vec::new();
+        if let Some(string) = as_string(raw) {
+            line.push('"' as u16);
+            line.extend(escape(&string));
+            line.push('"' as u16);
+        } else if let (Some(ValueType::REG_DWORD), 4) = (raw.value_type(), raw.data.len()) {
+            let dword = u32::from_le_bytes([raw.data[0], raw.data[1], raw.data[2], raw.data[3]]);
+            line.extend(format!("dword:{:08x}", dword).encode_utf16());
+        } else {
+            if raw.value_type() == Some(ValueType::REG_BINARY) {
+                line.extend("hex:".encode_utf16());
+            } else {
+                line.extend(format!("hex({:x}):", raw.value_type).encode_utf16());
+            }
+
+            let mut line_len = line.len();
+            for (i, byte) in raw.data.iter().enumerate() {
+                line.extend(format!("{:02x}", byte).encode_utf16());
+                if i + 1 == raw.data.len() {
+                    break;
+                }
+                line.push(',' as u16);
+                line_len += 3;
+
+                if line_len >= MAX_HEX_CHARS {
+                    line.push('\\' as u16);
+                    lines.push(line);
+                    line = "  ".encode_utf16().collect();
+                    line_len = 2;
+                }
+            }
+        }
+        lines.push(line);
+
+        for line in lines {
+            self.write_value_line(extension, line)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `"name"=-`, which deletes the value `name` of the current key
+    pub fn write_deleted_value(&mut self, name: &ValueName) -> io::Result<()> {
+        let (extension, mut line) = self.value_name(name);
+        line.push('-' as u16);
+        self.write_value_line(extension, line)
+    }
+
+    /// Writes `operations` in order, starting a new key section whenever an operation is on another key
+    /// than the one before it.
+    ///
+    /// Reading the file back with [`parse_reg`](crate::parse_reg) gives the same operations, except that a
+    /// `CreateKey` is added in front of values whose key section had to be started for them.
+    pub fn write_operations(&mut self, operations: &[RegOperation]) -> io::Result<()> {
+        let mut section: Option<&RegPath> = None;
+        for operation in operations {
+            let path = operation.path();
+            match operation {
+                RegOperation::CreateKey { .. } => {
+                    self.write_key(path)?;
+                    section = Some(path);
+                    continue;
+                }
+                RegOperation::DeleteKey { .. } => {
+                    self.write_deleted_key(path)?;
+                    section = None;
+                    continue;
+                }
+                _ if section != Some(path) => {
+                    self.write_key(path)?;
+                    section = Some(path);
+                }
+                _ => {}
+            }
+            match operation {
+                RegOperation::SetValue { name, raw, .. } => self.write_value(name, raw)?,
+                RegOperation::DeleteValue { name, .. } => self.write_deleted_value(name)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `source` and everything below it, depth first
+    pub fn write_tree<S: KeySource>(&mut self, source: &S) -> Result<()> {
+        self.write_subtree(source, 0)
+    }
+
+    fn write_subtree<S: KeySource>(&mut self, source: &S, depth: usize) -> Result<()> {
+        check_depth(Operation::ExportKey, source, depth)?;
+        let io_error = |e: io::Error| Error::new(Operation::ExportKey, source.path(), e.into());
+
+        self.write_key(source.path()).map_err(io_error)?;
+        for value in source.values()? {
+            self.write_value(value.value_name(), value.raw())
+                .map_err(io_error)?;
+        }
+        for name in source.subkey_names()? {
+            self.write_subtree(&source.open_child(&name)?, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Ends the file and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_line(&[])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// The start of a value line up to and including the `=`, and whether it needs the extension syntax
+    fn value_name(&self, name: &ValueName) -> (bool, Vec<u16>) {
+        let extension = self.extension || !representable(name.as_wide());
+
+        let mut line = if name.is_default() {
+            vec!['@' as u16]
+        } else {
+            let mut quoted = vec!['"' as u16];
+            if extension {
+                quoted.extend(escape(&percent_encode(name.as_wide())));
+            } else {
+                quoted.extend(escape(name.as_wide()));
+            }
+            quoted.push('"' as u16);
+            quoted
+        };
+        line.push('=' as u16);
+        (extension, line)
+    }
+
+    fn write_value_line(&mut self, extension: bool, line: Vec<u16>) -> io::Result<()> {
+        if extension {
+            let mut prefixed = EXTENSION_PREFIX.encode_utf16().collect::<Vec<_>>();
+            prefixed.extend(line);
+            self.write_line(&prefixed)
+        } else {
+            self.write_line(&line)
+        }
+    }
+
+    fn write_line(&mut self, line: &[u16]) -> io::Result<()> {
+        let bytes = line
+            .iter()
+            .chain(&[CR, LF])
+            .flat_map(|c| c.to_le_bytes())
+            .collect::<Vec<u8>>();
+        self.writer.write_all(&bytes)
+    }
+}
+
+/// Exports `source` and all of its subkeys to a complete `.reg` file
+pub fn export_reg<W: Write, S: KeySource>(writer: W, source: &S) -> Result<W> {
+    let io_error = |e: io::Error| Error::new(Operation::ExportKey, source.path(), ErrorKind::Io(e));
+
+    let mut exporter = RegExporter::new(writer).map_err(io_error)?;
+    exporter.write_tree(source)?;
+    exporter.finish().map_err(io_error)
+}
+
+/// `true` if a name can be written without the extension syntax
+fn representable(name: &[u16]) -> bool {
+    !name.iter().any(|c| *c == 0 || *c == CR || *c == LF)
+}
+
+/// The string a `REG_SZ` holds, if it round trips through the quoted string syntax
+fn as_string(raw: &RawValue) -> Option<Vec<u16>> {
+    let chunks = raw.data.chunks_exact(2);
+    if raw.value_type() != Some(ValueType::REG_SZ) || !chunks.remainder().is_empty() {
+        return None;
+    }
+
+    let mut wide = chunks
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect::<Vec<u16>>();
+    match wide.pop() {
+        Some(0) if representable(&wide) => Some(wide),
+        _ => None,
+    }
+}
+
+fn escape(name: &[u16]) -> Vec<u16> {
+    let mut escaped = Vec::with_capacity(name.len());
+    for c in name {
+        if *c == '\\' as u16 || *c == '"' as u16 {
+            escaped.push('\\' as u16);
+        }
+        escaped.push(*c);
+    }
+    escaped
+}
+
+fn percent_encode(name: &[u16]) -> Vec<u16> {
+    let mut encoded = Vec::with_capacity(name.len());
+    for c in name {
+        match *c {
+            0 | CR | LF | 0x25 => encoded.extend(format!("%{:02X}", c).encode_utf16()),
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{path::RegPathBuf, status::NtStatus, value::RegValueItem};
+
+    /// A minimal in-memory tree for exercising the exporters
+    pub(crate) struct Tree {
+        pub path: RegPathBuf,
+        pub values: Vec<(ValueName, RawValue)>,
+        pub children: Vec<Tree>,
+    }
+
+    impl<'a> KeySource for &'a Tree {
+        fn path(&self) -> &RegPath {
+            &self.path
+        }
+
+        fn values(&self) -> Result<Vec<RegValueItem>> {
+            Ok(self
+                .values
+                .iter()
+                .map(|(name, raw)| RegValueItem::new(&self.path, name, raw.clone()))
+                .collect())
+        }
+
+        fn subkey_names(&self) -> Result<Vec<RegPathBuf>> {
+            Ok(self
+                .children
+                .iter()
+                .map(|child| child.path.name().unwrap().to_reg_path_buf())
+                .collect())
+        }
+
+        fn open_child(&self, name: &RegPath) -> Result<&'a Tree> {
+            self.children
+                .iter()
+                .find(|child| child.path.name() == Some(name))
+                .ok_or_else(|| {
+                    Error::new(
+                        Operation::OpenKey,
+                        self.path.join(name),
+                        ErrorKind::Status(NtStatus::OBJECT_NAME_NOT_FOUND),
+                    )
+                })
+        }
+    }
+
+    /// A source whose only subkey is always another copy of itself, like a hive with a subkey cycle
+    pub(crate) struct Endless(pub RegPathBuf);
+
+    impl KeySource for Endless {
+        fn path(&self) -> &RegPath {
+            &self.0
+        }
+
+        fn values(&self) -> Result<Vec<RegValueItem>> {
+            Ok(Vec::new())
+        }
+
+        fn subkey_names(&self) -> Result<Vec<RegPathBuf>> {
+            Ok(vec![RegPathBuf::from("Loop")])
+        }
+
+        fn open_child(&self, name: &RegPath) -> Result<Endless> {
+            Ok(Endless(self.0.join(name)))
+        }
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    pub(crate) fn sample() -> Tree {
+        let root = RegPathBuf::from(r"\Registry\Machine\SOFTWARE\winregnt");
+
+        let mut hidden_name = wide("Hidden");
+        hidden_name.push(0);
+        hidden_name.extend(wide("Value"));
+
+        let mut hidden_key = wide("Hidden");
+        hidden_key.push(0);
+        hidden_key.extend(wide("Key%"));
+
+        Tree {
+            path: root.clone(),
+            values: vec![
+                (ValueName::default(), RawValue::new(1, utf16("default\0"))),
+                (
+                    ValueName::from("Quote\"Back\\slash"),
+                    RawValue::new(1, utf16("C:\\Program Files\\\"quoted\"\0")),
+                ),
+                (
+                    ValueName::from("Dword"),
+                    RawValue::new(4, vec![0x2a, 0, 0, 0]),
+                ),
+                (ValueName::from("ShortDword"), RawValue::new(4, vec![1, 2])),
+                (
+                    ValueName::from("Qword"),
+                    RawValue::new(11, vec![1, 2, 3, 4, 5, 6, 7, 8]),
+                ),
+                (
+                    ValueName::from("Expand"),
+                    RawValue::new(2, utf16("%SystemRoot%\\system32\0")),
+                ),
+                (
+                    ValueName::from("Multi"),
+                    RawValue::new(7, utf16("one\0two\0\0")),
+                ),
+                (
+                    ValueName::from("Binary"),
+                    RawValue::new(3, (0..=99u8).collect::<Vec<u8>>()),
+                ),
+                (ValueName::from("Empty"), RawValue::new(3, vec![])),
+                (ValueName::from("None"), RawValue::new(0, vec![])),
+                (
+                    ValueName::from("EmbeddedNul"),
+                    RawValue::new(1, utf16("a\0b\0")),
+                ),
+                (ValueName::from("Custom"), RawValue::new(0xffff, vec![0xab])),
+                (
+                    ValueName::from(hidden_name),
+                    RawValue::new(4, vec![1, 0, 0, 0]),
+                ),
+            ],
+            children: vec![
+                Tree {
+                    path: root.join(RegPathBuf::from("Child")),
+                    values: vec![],
+                    children: vec![],
+                },
+                Tree {
+                    path: root.join(RegPath::from_wide(&hidden_key)),
+                    values: vec![(
+                        ValueName::from("Inside"),
+                        RawValue::new(3, (0..30u8).collect::<Vec<u8>>()),
+                    )],
+                    children: vec![],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn golden() {
+        let tree = sample();
+        let output = export_reg(Vec::new(), &&tree).unwrap();
+        let expected: &[u8] = include_bytes!("../tests/golden/export.reg");
+        assert_eq!(
+            String::from_utf16_lossy(
+                &output
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>()
+            ),
+            String::from_utf16_lossy(
+                &expected
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>()
+            )
+        );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn cycle() {
+        let error = export_reg(
+            Vec::new(),
+            &Endless(RegPathBuf::from(r"\Registry\Machine\Loop")),
+        )
+        .unwrap_err();
+        assert_eq!(error.operation(), Operation::ExportKey);
+        assert!(matches!(error.kind(), ErrorKind::Unsupported(_)));
+    }
+
+    #[test]
+    fn operations() {
+        let hidden = RegPathBuf::from_wide(wide("\\Registry\\Machine\\SOFTWARE\\Hidden\0Key"));
+        let vendor = RegPathBuf::from(r"\Registry\Machine\SOFTWARE\Vendor");
+        let operations = vec![
+            RegOperation::CreateKey {
+                path: vendor.clone(),
+            },
+            RegOperation::SetValue {
+                path: vendor.clone(),
+                name: "Port".into(),
+                raw: RawValue::new(4, vec![0x50, 0, 0, 0]),
+            },
+            RegOperation::DeleteValue {
+                path: vendor.clone(),
+                name: "Legacy".into(),
+            },
+            RegOperation::DeleteKey {
+                path: vendor.join(RegPathBuf::from("Old")),
+            },
+            RegOperation::DeleteKey {
+                path: hidden.clone(),
+            },
+            RegOperation::CreateKey {
+                path: vendor.clone(),
+            },
+            RegOperation::DeleteValue {
+                path: vendor.clone(),
+                name: "Gone".into(),
+            },
+        ];
+
+        let mut exporter = RegExporter::new(Vec::new()).unwrap();
+        exporter.write_operations(&operations).unwrap();
+        let output = exporter.finish().unwrap();
+        let text = String::from_utf16_lossy(
+            &output[2..]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>(),
+        );
+        assert!(text.contains("\r\n[-HKEY_LOCAL_MACHINE\\SOFTWARE\\Vendor\\Old]\r\n"));
+        assert!(text.contains("\r\n;winregnt:[-HKEY_LOCAL_MACHINE\\SOFTWARE\\Hidden%00Key]\r\n"));
+        assert!(text.contains("\r\n\"Legacy\"=-\r\n"));
+        assert_eq!(crate::reg_import::parse_reg(&output).unwrap(), operations);
+    }
+}
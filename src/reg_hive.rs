@@ -0,0 +1,273 @@
+use crate::{
+    api::{NtLoadKeyEx, NtRestoreKey, NtSaveKeyEx, NtUnloadKey2},
+    error::{Error, ErrorKind, Operation},
+    open_options::OpenOptions,
+    path::{RegPath, RegPathBuf},
+    privilege::{require, Privilege},
+    reg_key::{object_attributes, RegKey},
+    status::NtStatus,
+    unicode_string::UnicodeString,
+    Result,
+};
+use std::{
+    convert::TryFrom,
+    ffi::OsStr,
+    fs::File,
+    os::windows::{ffi::OsStrExt, io::AsRawHandle},
+    path::Path,
+    ptr::null_mut,
+};
+
+/// The file format written by [`RegKey::save_to`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// The format of Windows 2000 (`REG_STANDARD_FORMAT`), readable by every version of Windows
+    Standard,
+    /// The newest format the running system supports (`REG_LATEST_FORMAT`)
+    Latest,
+    /// The newest format, without compacting the hive first (`REG_NO_COMPRESSION`)
+    NoCompression,
+}
+
+impl SaveFormat {
+    fn flags(self) -> u32 {
+        match self {
+            SaveFormat::Standard => 1,
+            SaveFormat::Latest => 2,
+            SaveFormat::NoCompression => 4,
+        }
+    }
+}
+
+/// Converts a file path into the NT form the `Nt*` functions take, such as `\??\C:\Users\Public\NTUSER.DAT`
+fn nt_path(path: &Path) -> std::io::Result<Vec<u16>> {
+    let wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+    let prefix = |p: &str| OsStr::new(p).encode_wide().collect::<Vec<u16>>();
+    let verbatim = prefix(r"\\?\");
+    let verbatim_unc = prefix(r"\\?\UNC\");
+
+    if wide.starts_with(&prefix(r"\??\")) {
+        return Ok(wide);
+    }
+    let mut nt = prefix(r"\??\");
+    if wide.starts_with(&verbatim_unc) || wide.starts_with(&verbatim) {
+        nt.extend_from_slice(&wide[verbatim.len()..]);
+    } else if wide.starts_with(&prefix(r"\\")) {
+        nt.extend(prefix("UNC"));
+        nt.extend_from_slice(&wide[1..]);
+    } else {
+        let absolute = std::env::current_dir()?.join(path);
+        nt.extend(absolute.as_os_str().encode_wide());
+    }
+    Ok(nt)
+}
+
+impl RegKey {
+    /// Saves the key and everything below it into a new hive file at `path`.
+    ///
+    /// The file must not exist yet. `SeBackupPrivilege` is enabled for the duration of the call.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::{RegKey, SaveFormat};
+    /// let key = RegKey::open(r"\Registry\Machine\Software\winregnt").unwrap();
+    /// key.save_to(r"C:\backup\winregnt.hiv", SaveFormat::Latest).unwrap();
+    /// ```
+    pub fn save_to<P: AsRef<Path>>(&self, path: P, format: SaveFormat) -> Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path.as_ref())
+            .map_err(|e| self.error(Operation::SaveKey, ErrorKind::Io(e)))?;
+        let privileges = require(Operation::SaveKey, &self.path, &[Privilege::Backup]);
+        let result = privileges.and_then(|_privileges| {
+            self.check(Operation::SaveKey, None, unsafe {
+                NtSaveKeyEx(self.handle, file.as_raw_handle() as _, format.flags())
+            })
+        });
+        if result.is_err() {
+            drop(file);
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    /// Replaces the values and subkeys of this key with the contents of the hive file at `path`, such as
+    /// one written by [`RegKey::save_to`].
+    ///
+    /// The key must have been opened with write access. `SeRestorePrivilege` is enabled for the duration of
+    /// the call.
+    pub fn restore_from<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file =
+            File::open(path).map_err(|e| self.error(Operation::RestoreKey, ErrorKind::Io(e)))?;
+        let _privileges = require(Operation::RestoreKey, &self.path, &[Privilege::Restore])?;
+        self.check(Operation::RestoreKey, None, unsafe {
+            NtRestoreKey(self.handle, file.as_raw_handle() as _, 0)
+        })
+    }
+
+    /// Loads the hive file at `file` as the key `mount_path`, such as `\Registry\User\Inspect`.
+    ///
+    /// The key must be directly below `\Registry\Machine` or `\Registry\User`. The hive stays loaded until
+    /// the returned guard is dropped or [`LoadedHive::unload`] is called. `SeBackupPrivilege` and
+    /// `SeRestorePrivilege` are enabled for the duration of the call.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::{KeySource, OpenOptions, RegKey};
+    /// let hive = RegKey::load_hive(r"\Registry\User\Inspect", r"C:\Users\alice\NTUSER.DAT").unwrap();
+    /// let run = hive
+    ///     .open(&OpenOptions::new())
+    ///     .unwrap()
+    ///     .open_subkey(r"Software\Microsoft\Windows\CurrentVersion\Run", &OpenOptions::new())
+    ///     .unwrap();
+    /// for value in run.values().unwrap() {
+    ///     println!("{}", value);
+    /// }
+    /// hive.unload().unwrap();
+    /// ```
+    pub fn load_hive<M: Into<RegPathBuf>, P: AsRef<Path>>(
+        mount_path: M,
+        file: P,
+    ) -> Result<LoadedHive> {
+        let mount_path = mount_path.into();
+        let error = |kind| Error::new(Operation::LoadHive, &mount_path, kind);
+
+        let source = nt_path(file.as_ref()).map_err(|e| error(ErrorKind::Io(e)))?;
+        let _privileges = require(
+            Operation::LoadHive,
+            &mount_path,
+            &[Privilege::Backup, Privilege::Restore],
+        )?;
+        let mut target_name = UnicodeString::try_from(mount_path.as_wide()).map_err(error)?;
+        let mut source_name = UnicodeString::try_from(source).map_err(error)?;
+        let target = object_attributes(null_mut(), &mut target_name);
+        let source = object_attributes(null_mut(), &mut source_name);
+
+        let status = NtStatus::from(unsafe {
+            NtLoadKeyEx(
+                &target,
+                &source,
+                0,
+                null_mut(),
+                null_mut(),
+                0,
+                null_mut(),
+                null_mut(),
+            )
+        });
+        if status.is_success() {
+            Ok(LoadedHive {
+                path: mount_path,
+                loaded: true,
+            })
+        } else {
+            Err(error(ErrorKind::Status(status)))
+        }
+    }
+
+    /// Unloads the hive loaded as the key `mount_path`.
+    ///
+    /// Fails with `STATUS_CANNOT_DELETE` while handles to keys in the hive are still open; `force` closes
+    /// them instead (`REG_FORCE_UNLOAD`). `SeRestorePrivilege` is enabled for the duration of the call.
+    pub fn unload_hive<M: Into<RegPathBuf>>(mount_path: M, force: bool) -> Result<()> {
+        let mount_path = mount_path.into();
+        let _privileges = require(Operation::UnloadHive, &mount_path, &[Privilege::Restore])?;
+        let mut name = UnicodeString::try_from(mount_path.as_wide())
+            .map_err(|kind| Error::new(Operation::UnloadHive, &mount_path, kind))?;
+        let target = object_attributes(null_mut(), &mut name);
+        let status = NtStatus::from(unsafe { NtUnloadKey2(&target, force as u32) });
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                Operation::UnloadHive,
+                mount_path,
+                ErrorKind::Status(status),
+            ))
+        }
+    }
+}
+
+/// A hive loaded by [`RegKey::load_hive`], unloaded when dropped
+#[derive(Debug)]
+pub struct LoadedHive {
+    path: RegPathBuf,
+    loaded: bool,
+}
+
+impl LoadedHive {
+    /// The key the hive is loaded as
+    pub fn path(&self) -> &RegPath {
+        &self.path
+    }
+
+    /// Opens the root key of the hive
+    pub fn open(&self, options: &OpenOptions) -> Result<RegKey> {
+        RegKey::open_with(&self.path, options)
+    }
+
+    /// Unloads the hive, reporting any failure that dropping the guard would ignore.
+    ///
+    /// Every key opened in the hive must have been closed first.
+    pub fn unload(mut self) -> Result<()> {
+        self.loaded = false;
+        RegKey::unload_hive(&self.path, false)
+    }
+
+    /// Keeps the hive loaded after the guard is dropped
+    pub fn keep(mut self) -> RegPathBuf {
+        self.loaded = false;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for LoadedHive {
+    fn drop(&mut self) {
+        if self.loaded {
+            let _ = RegKey::unload_hive(&self.path, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn nt_paths() {
+        assert_eq!(
+            nt_path(Path::new(r"C:\Users\Public\NTUSER.DAT")).unwrap(),
+            wide(r"\??\C:\Users\Public\NTUSER.DAT")
+        );
+        assert_eq!(
+            nt_path(Path::new(r"\\?\C:\hive")).unwrap(),
+            wide(r"\??\C:\hive")
+        );
+        assert_eq!(
+            nt_path(Path::new(r"\\server\share\hive")).unwrap(),
+            wide(r"\??\UNC\server\share\hive")
+        );
+        assert_eq!(
+            nt_path(Path::new(r"\\?\UNC\server\share\hive")).unwrap(),
+            wide(r"\??\UNC\server\share\hive")
+        );
+    }
+
+    #[test]
+    fn save_requires_new_file() {
+        let key = RegKey::open(r"\Registry\Machine\Software\Microsoft").unwrap();
+        let path = std::env::temp_dir().join(format!("winregnt-save-{}", std::process::id()));
+        std::fs::write(&path, b"existing").unwrap();
+        let error = key.save_to(&path, SaveFormat::Latest).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Io(_)));
+        assert_eq!(std::fs::read(&path).unwrap(), b"existing");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1071 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::{RegPath, RegPathBuf},
+    reg_export::{EXTENSION_PREFIX, REG_FILE_HEADER},
+    source::KeySource,
+    value::{RawValue, ValueType},
+    value_name::ValueName,
+    Result,
+};
+use std::{collections::HashMap, convert::TryFrom, fmt};
+
+#[cfg(windows)]
+use crate::{open_options::OpenOptions, reg_key::RegKey};
+
+/// The first line of a `.reg` file written by the Windows 9x and NT 4 Registry Editor
+pub const REGEDIT4_HEADER: &str = "REGEDIT4";
+
+/// A single change described by a `.reg` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegOperation {
+    /// `[HKEY_...]`: create the key (and any missing parents) if it does not exist
+    CreateKey {
+        /// Full kernel path of the key
+        path: RegPathBuf,
+    },
+    /// `[-HKEY_...]`: delete the key and everything below it
+    DeleteKey {
+        /// Full kernel path of the key
+        path: RegPathBuf,
+    },
+    /// `"name"=...`: create or replace a value
+    SetValue {
+        /// Full kernel path of the key holding the value
+        path: RegPathBuf,
+        /// Name of the value, empty for the default value (`@`)
+        name: ValueName,
+        /// Type and data of the value
+        raw: RawValue,
+    },
+    /// `"name"=-`: delete a value
+    DeleteValue {
+        /// Full kernel path of the key holding the value
+        path: RegPathBuf,
+        /// Name of the value, empty for the default value (`@`)
+        name: ValueName,
+    },
+}
+
+impl RegOperation {
+    /// The full path of the key this operation applies to
+    pub fn path(&self) -> &RegPath {
+        match self {
+            RegOperation::CreateKey { path }
+            | RegOperation::DeleteKey { path }
+            | RegOperation::SetValue { path, .. }
+            | RegOperation::DeleteValue { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for RegOperation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegOperation::CreateKey { path } => write!(fmt, "create key {}", path),
+            RegOperation::DeleteKey { path } => write!(fmt, "delete key {}", path),
+            RegOperation::SetValue { path, name, raw } => {
+                write!(fmt, "set value {:?} in {} to {}", name, path, raw.decode())
+            }
+            RegOperation::DeleteValue { path, name } => {
+                write!(fmt, "delete value {:?} in {}", name, path)
+            }
+        }
+    }
+}
+
+/// Parses `.reg` files into a list of [`RegOperation`]s.
+///
+/// Both `Windows Registry Editor Version 5.00` (UTF-16LE with a byte order mark, or UTF-8) and `REGEDIT4`
+/// (ANSI) files are understood, as are the `;winregnt:` lines written by [`RegExporter`](crate::RegExporter) for
+/// names the format cannot otherwise hold. Key paths are converted to kernel paths, so
+/// `[HKEY_LOCAL_MACHINE\Software]` becomes `\Registry\Machine\Software`.
+///
+/// The ANSI code page of a `REGEDIT4` file is not recorded in the file. Files that are valid UTF-8 are read
+/// as such, anything else is read as Latin-1. String data given as `hex(1):`, `hex(2):` or `hex(7):` in a
+/// `REGEDIT4` file is ANSI too, and is converted to UTF-16 the same way.
+///
+/// # Examples
+///
+/// ```
+/// use winregnt::{RegOperation, RegParser};
+/// let file = "REGEDIT4\r\n\r\n[HKEY_CURRENT_USER\\Software\\Vendor]\r\n\"Enabled\"=dword:00000001\r\n";
+/// let operations = RegParser::new()
+///     .current_user(r"\Registry\User\S-1-5-21-1000")
+///     .parse(file.as_bytes())
+///     .unwrap();
+/// assert_eq!(operations.len(), 2);
+/// assert_eq!(
+///     operations[0],
+///     RegOperation::CreateKey {
+///         path: r"\Registry\User\S-1-5-21-1000\Software\Vendor".into()
+///     }
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RegParser {
+    current_user: Option<RegPathBuf>,
+}
+
+impl RegParser {
+    /// A parser that rejects keys below `HKEY_CURRENT_USER`
+    pub fn new() -> RegParser {
+        RegParser::default()
+    }
+
+    /// The kernel path `HKEY_CURRENT_USER` refers to, usually `\Registry\User\<SID>`
+    pub fn current_user<P: Into<RegPathBuf>>(&mut self, path: P) -> &mut RegParser {
+        self.current_user = Some(path.into());
+        self
+    }
+
+    /// Parses a complete `.reg` file
+    pub fn parse(&self, bytes: &[u8]) -> Result<Vec<RegOperation>> {
+        let text = decode(bytes);
+        let lines = text
+            .split(|c| *c == LF)
+            .map(|line| line.strip_suffix(&[CR]).unwrap_or(line))
+            .collect::<Vec<_>>();
+
+        let mut parser = Parser {
+            current_user: self.current_user.as_deref(),
+            ansi: false,
+            lines: &lines,
+            next: 0,
+            key: None,
+            operations: Vec::new(),
+        };
+        parser.header()?;
+        while let Some((number, line, extension)) = parser.next_line() {
+            parser.line(number, &line, extension)?;
+        }
+        Ok(parser.operations)
+    }
+}
+
+/// Parses a `.reg` file with the default [`RegParser`] settings
+pub fn parse_reg(bytes: &[u8]) -> Result<Vec<RegOperation>> {
+    RegParser::new().parse(bytes)
+}
+
+const CR: u16 = 0x0d;
+const LF: u16 = 0x0a;
+
+/// Decodes the file into UTF-16. Whether it is an ANSI (`REGEDIT4`) file is decided by its header, not by
+/// how it is encoded, since version 5 files are often saved as UTF-8 too.
+fn decode(bytes: &[u8]) -> Vec<u16> {
+    if let Some(rest) = bytes.strip_prefix(&[0xff, 0xfe]) {
+        return rest
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+            .collect();
+    }
+
+    let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.encode_utf16().collect(),
+        Err(_) => bytes.iter().map(|b| *b as u16).collect(),
+    }
+}
+
+struct Parser<'a> {
+    current_user: Option<&'a RegPath>,
+    ansi: bool,
+    lines: &'a [&'a [u16]],
+    next: usize,
+    /// The key values are currently added to, `None` inside a `[-...]` section
+    key: Option<RegPathBuf>,
+    operations: Vec<RegOperation>,
+}
+
+impl<'a> Parser<'a> {
+    fn header(&mut self) -> Result<()> {
+        let (number, line, _) = self
+            .next_line()
+            .ok_or_else(|| self.error(1, "the file is empty"))?;
+        let line = String::from_utf16_lossy(&line);
+        match line.trim() {
+            REG_FILE_HEADER => Ok(()),
+            REGEDIT4_HEADER => {
+                self.ansi = true;
+                Ok(())
+            }
+            other => Err(self.error(
+                number,
+                format!(
+                    "expected `{}` or `{}`, found `{}`",
+                    REG_FILE_HEADER, REGEDIT4_HEADER, other
+                ),
+            )),
+        }
+    }
+
+    /// The next line that is not blank or a comment, with its line number and whether it is an extension line
+    fn next_line(&mut self) -> Option<(usize, Vec<u16>, bool)> {
+        while self.next < self.lines.len() {
+            let number = self.next + 1;
+            let line = self.lines[self.next];
+            self.next += 1;
+
+            let (line, extension) = match line.strip_prefix(&wide(EXTENSION_PREFIX)[..]) {
+                Some(rest) => (rest, true),
+                None => (line, false),
+            };
+            let line = trim(line);
+            if line.is_empty() || (!extension && line[0] == ';' as u16) {
+                continue;
+            }
+            return Some((number, line.to_vec(), extension));
+        }
+        None
+    }
+
+    fn line(&mut self, number: usize, line: &[u16], extension: bool) -> Result<()> {
+        if line[0] == '[' as u16 {
+            return self.section(number, line, extension);
+        }
+
+        let path = match &self.key {
+            Some(path) => path.clone(),
+            None => {
+                return Err(self.error(number, "value outside of a key section"));
+            }
+        };
+        let (name, rest) = self.value_name(number, line, extension)?;
+        let rest = trim(rest);
+        let data = match rest.strip_prefix(&['=' as u16]) {
+            Some(data) => trim(data),
+            None => return Err(self.error(number, "expected `=` after the value name")),
+        };
+
+        if data == ['-' as u16] {
+            self.operations
+                .push(RegOperation::DeleteValue { path, name });
+            return Ok(());
+        }
+
+        let raw = self.data(number, data, extension)?;
+        self.operations
+            .push(RegOperation::SetValue { path, name, raw });
+        Ok(())
+    }
+
+    fn section(&mut self, number: usize, line: &[u16], extension: bool) -> Result<()> {
+        let inner = match line.strip_suffix(&[']' as u16]) {
+            Some(inner) => &inner[1..],
+            None => return Err(self.error(number, "key section is missing its closing `]`")),
+        };
+        let (delete, inner) = match inner.strip_prefix(&['-' as u16]) {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let inner = if extension {
+            self.percent_decode(number, inner)?
+        } else {
+            inner.to_vec()
+        };
+
+        let path = self.key_path(number, RegPath::from_wide(&inner))?;
+        if delete {
+            self.key = None;
+            self.operations.push(RegOperation::DeleteKey { path });
+        } else {
+            self.key = Some(path.clone());
+            self.operations.push(RegOperation::CreateKey { path });
+        }
+        Ok(())
+    }
+
+    /// Converts the path of a section to a kernel path
+    fn key_path(&self, number: usize, path: &RegPath) -> Result<RegPathBuf> {
+        if let Some(path) = path.to_nt_path() {
+            return Ok(path);
+        }
+
+        let mut components = path.components();
+        let root = components
+            .next()
+            .ok_or_else(|| self.error(number, "key section has an empty path"))?;
+        let mut nt = if *root == *RegPathBuf::from("Registry") {
+            RegPathBuf::from(r"\Registry")
+        } else if *root == *RegPathBuf::from("HKEY_CURRENT_USER")
+            || *root == *RegPathBuf::from("HKCU")
+        {
+            match self.current_user {
+                Some(current_user) => current_user.to_reg_path_buf(),
+                None => {
+                    return Err(self.error(
+                        number,
+                        "HKEY_CURRENT_USER is used but the parser has no current user",
+                    ))
+                }
+            }
+        } else {
+            return Err(self.error(number, format!("unknown root key `{}`", root)));
+        };
+        components.for_each(|component| nt.push(component));
+        Ok(nt)
+    }
+
+    /// Splits `@` or a quoted name off the start of a value line
+    fn value_name<'l>(
+        &self,
+        number: usize,
+        line: &'l [u16],
+        extension: bool,
+    ) -> Result<(ValueName, &'l [u16])> {
+        if line[0] == '@' as u16 {
+            return Ok((ValueName::default(), &line[1..]));
+        }
+        if line[0] != '"' as u16 {
+            return Err(self.error(number, "expected `@` or a quoted value name"));
+        }
+
+        let (name, rest) = self.quoted(number, line)?;
+        let name = if extension {
+            self.percent_decode(number, &name)?
+        } else {
+            name
+        };
+        Ok((ValueName::from(name), rest))
+    }
+
+    /// Reads a quoted, escaped string from the start of `line`
+    fn quoted<'l>(&self, number: usize, line: &'l [u16]) -> Result<(Vec<u16>, &'l [u16])> {
+        let mut unescaped = Vec::new();
+        let mut chars = line.iter().enumerate().skip(1);
+        while let Some((_, c)) = chars.next() {
+            match *c {
+                c if c == '\\' as u16 => match chars.next() {
+                    Some((_, escaped)) => unescaped.push(*escaped),
+                    None => break,
+                },
+                c if c == '"' as u16 => {
+                    let end = chars.next().map_or(line.len(), |(i, _)| i);
+                    return Ok((unescaped, &line[end..]));
+                }
+                c => unescaped.push(c),
+            }
+        }
+        Err(self.error(number, "string is missing its closing quote"))
+    }
+
+    fn data(&mut self, number: usize, data: &[u16], extension: bool) -> Result<RawValue> {
+        if data.is_empty() {
+            return Err(self.error(number, "missing value data"));
+        }
+        if data[0] == '"' as u16 {
+            let (mut string, rest) = self.quoted(number, data)?;
+            if !trim(rest).is_empty() {
+                return Err(self.error(number, "unexpected text after the string"));
+            }
+            string.push(0);
+            return Ok(RawValue::new(
+                ValueType::REG_SZ as u32,
+                string
+                    .iter()
+                    .flat_map(|c| c.to_le_bytes())
+                    .collect::<Vec<u8>>(),
+            ));
+        }
+
+        let data = String::from_utf16_lossy(data);
+        if let Some(dword) = strip_prefix_ignore_case(&data, "dword:") {
+            let dword = dword.trim();
+            return match u32::from_str_radix(dword, 16) {
+                Ok(value) if dword.len() <= 8 => Ok(RawValue::new(
+                    ValueType::REG_DWORD as u32,
+                    value.to_le_bytes().to_vec(),
+                )),
+                _ => Err(self.error(number, format!("invalid dword `{}`", dword))),
+            };
+        }
+
+        let (value_type, hex) = if let Some(hex) = strip_prefix_ignore_case(&data, "hex:") {
+            (ValueType::REG_BINARY as u32, hex.to_string())
+        } else if let Some(rest) = strip_prefix_ignore_case(&data, "hex(") {
+            let (value_type, hex) = match rest.find("):") {
+                Some(end) => (&rest[..end], &rest[end + 2..]),
+                None => return Err(self.error(number, "expected `):` after the hex type")),
+            };
+            match u32::from_str_radix(value_type, 16) {
+                Ok(value_type) => (value_type, hex.to_string()),
+                Err(_) => {
+                    return Err(self.error(number, format!("invalid value type `{}`", value_type)))
+                }
+            }
+        } else {
+            return Err(self.error(number, format!("unrecognized value data `{}`", data)));
+        };
+
+        let mut bytes = Vec::new();
+        let mut hex = hex;
+        let mut number = number;
+        loop {
+            let trimmed = hex.trim_end();
+            let (chunk, continued) = match trimmed.strip_suffix('\\') {
+                Some(chunk) => (chunk, true),
+                None => (trimmed, false),
+            };
+            for byte in chunk.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+                match u8::from_str_radix(byte, 16) {
+                    Ok(value) if byte.len() <= 2 => bytes.push(value),
+                    _ => return Err(self.error(number, format!("invalid hex byte `{}`", byte))),
+                }
+            }
+            if !continued {
+                break;
+            }
+
+            match self.next_line() {
+                Some((next, line, next_extension)) if next_extension == extension => {
+                    number = next;
+                    hex = String::from_utf16_lossy(&line);
+                }
+                _ => return Err(self.error(number, "hex data continues past the end of the value")),
+            }
+        }
+
+        if self.ansi {
+            bytes = widen_ansi(value_type, bytes);
+        }
+        Ok(RawValue::new(value_type, bytes))
+    }
+
+    fn percent_decode(&self, number: usize, name: &[u16]) -> Result<Vec<u16>> {
+        let mut decoded = Vec::with_capacity(name.len());
+        let mut chars = name.iter();
+        while let Some(c) = chars.next() {
+            if *c != '%' as u16 {
+                decoded.push(*c);
+                continue;
+            }
+
+            let digits = chars.by_ref().take(2).copied().collect::<Vec<u16>>();
+            match u16::from_str_radix(&String::from_utf16_lossy(&digits), 16) {
+                Ok(value) if digits.len() == 2 => decoded.push(value),
+                _ => return Err(self.error(number, "invalid percent-encoded name")),
+            }
+        }
+        Ok(decoded)
+    }
+
+    fn error<M: Into<String>>(&self, line: usize, message: M) -> Error {
+        let path = self.key.clone().unwrap_or_default();
+        Error::new(
+            Operation::ImportKey,
+            path,
+            ErrorKind::Parse {
+                line,
+                message: message.into(),
+            },
+        )
+    }
+}
+
+/// `REGEDIT4` files store string types as ANSI, the registry stores them as UTF-16. Like the file itself,
+/// the data is read as UTF-8 if it is valid UTF-8 and as Latin-1 otherwise.
+fn widen_ansi(value_type: u32, bytes: Vec<u8>) -> Vec<u8> {
+    match ValueType::try_from(value_type) {
+        Ok(ValueType::REG_SZ) | Ok(ValueType::REG_EXPAND_SZ) | Ok(ValueType::REG_MULTI_SZ) => {
+            match std::str::from_utf8(&bytes) {
+                Ok(text) => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+                Err(_) => bytes
+                    .iter()
+                    .flat_map(|b| (*b as u16).to_le_bytes())
+                    .collect(),
+            }
+        }
+        _ => bytes,
+    }
+}
+
+fn strip_prefix_ignore_case<'s>(s: &'s str, prefix: &str) -> Option<&'s str> {
+    match s.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().collect()
+}
+
+fn trim(line: &[u16]) -> &[u16] {
+    let space = |c: &u16| *c == ' ' as u16 || *c == '\t' as u16;
+    let start = line.iter().position(|c| !space(c)).unwrap_or(line.len());
+    let end = line
+        .iter()
+        .rposition(|c| !space(c))
+        .map_or(start, |end| end + 1);
+    &line[start..end]
+}
+
+/// What an operation did, or would do in a dry run
+#[derive(Debug)]
+pub enum Change {
+    /// The key or value already exists exactly as described
+    Unchanged,
+    /// The key or value did not exist and is created
+    Created,
+    /// The value existed with different content
+    Modified {
+        /// The value before the change
+        previous: RawValue,
+    },
+    /// The key or value existed and is deleted
+    Deleted,
+    /// The key or value to delete does not exist, so nothing is done
+    NotFound,
+    /// The operation is not below the root it is applied to and is skipped
+    OutsideRoot,
+    /// The operation (or, in a dry run, reading the current state) failed
+    Failed(Error),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Unchanged => write!(fmt, "unchanged"),
+            Change::Created => write!(fmt, "created"),
+            Change::Modified { previous } => write!(fmt, "modified (was {})", previous.decode()),
+            Change::Deleted => write!(fmt, "deleted"),
+            Change::NotFound => write!(fmt, "not found"),
+            Change::OutsideRoot => write!(fmt, "skipped, outside of the root key"),
+            Change::Failed(error) => write!(fmt, "failed: {}", error),
+        }
+    }
+}
+
+/// An operation and its outcome
+#[derive(Debug)]
+pub struct ImportEntry {
+    /// The operation from the file
+    pub operation: RegOperation,
+    /// What it did, or would do
+    pub change: Change,
+}
+
+/// The outcome of every operation in an import, in file order.
+///
+/// Displaying the report gives one line per operation, which makes a dry run readable as a review of what
+/// a file is going to do.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// One entry per operation
+    pub entries: Vec<ImportEntry>,
+}
+
+impl ImportReport {
+    /// `true` if no operation failed or was skipped
+    pub fn is_clean(&self) -> bool {
+        !self
+            .entries
+            .iter()
+            .any(|entry| matches!(entry.change, Change::Failed(_) | Change::OutsideRoot))
+    }
+
+    /// The entries that failed
+    pub fn failures(&self) -> impl Iterator<Item = &ImportEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.change, Change::Failed(_)))
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(fmt, "{}: {}", entry.operation, entry.change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Works out what `operations` would do to the tree below `root` without changing anything.
+///
+/// Each operation sees the effect of the ones before it, so a value set in a key created earlier in the
+/// file is reported as created. Operations on keys outside of `root` are reported as
+/// [`Change::OutsideRoot`].
+pub fn dry_run<S: KeySource>(operations: &[RegOperation], root: &S) -> ImportReport {
+    let mut planner = Planner::new(root);
+    ImportReport {
+        entries: operations
+            .iter()
+            .map(|operation| {
+                let change = planner.plan(operation);
+                planner.record(operation, &change);
+                ImportEntry {
+                    operation: operation.clone(),
+                    change,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Applies `operations` to the tree below `root`, or only reports what they would do if `dry_run` is set.
+///
+/// `root` should be opened with write access unless `dry_run` is set. Operations outside of `root` are
+/// skipped. A failing operation does not stop the import; the report records the error and later
+/// operations still run.
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::{apply, parse_reg, RegKey};
+/// let operations = parse_reg(&std::fs::read("vendor.reg").unwrap()).unwrap();
+/// let root = RegKey::open_write(r"\Registry\Machine\Software\Vendor").unwrap();
+/// let report = apply(&operations, &root, true);
+/// print!("{}", report);
+/// if report.is_clean() {
+///     apply(&operations, &root, false);
+/// }
+/// ```
+#[cfg(windows)]
+pub fn apply(operations: &[RegOperation], root: &RegKey, dry_run: bool) -> ImportReport {
+    let mut planner = Planner::new(root);
+    ImportReport {
+        entries: operations
+            .iter()
+            .map(|operation| {
+                let mut change = planner.plan(operation);
+                if !dry_run {
+                    if let Err(error) = execute(operation, &change, root) {
+                        change = Change::Failed(error);
+                    }
+                }
+                planner.record(operation, &change);
+                ImportEntry {
+                    operation: operation.clone(),
+                    change,
+                }
+            })
+            .collect(),
+    }
+}
+
+#[cfg(windows)]
+fn execute(operation: &RegOperation, change: &Change, root: &RegKey) -> Result<()> {
+    let relative = match (change, operation.path().strip_prefix(root.path())) {
+        (Change::Created, Some(relative))
+        | (Change::Modified { .. }, Some(relative))
+        | (Change::Deleted, Some(relative)) => relative,
+        _ => return Ok(()),
+    };
+    let write = OpenOptions::new().write(true).clone();
+
+    match operation {
+        RegOperation::CreateKey { .. } => root.create_subkey(relative, &write).map(drop),
+        RegOperation::DeleteKey { .. } => root.open_subkey(relative, &write)?.delete_tree(),
+        RegOperation::SetValue { name, raw, .. } => root
+            .create_subkey(relative, &write)?
+            .write_raw_value(name, raw),
+        RegOperation::DeleteValue { name, .. } => {
+            root.open_subkey(relative, &write)?.delete_value(name)
+        }
+    }
+}
+
+/// The state of a key as changed by earlier operations of the same import
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum KeyState {
+    /// Created by the import, so none of the values or subkeys in the source apply to it
+    Created,
+    Deleted,
+}
+
+/// Tracks the effect of operations on top of a source, so each one can be planned against the state the
+/// earlier ones leave behind
+struct Planner<'a, S: KeySource> {
+    root: &'a S,
+    keys: HashMap<RegPathBuf, KeyState>,
+    values: HashMap<(RegPathBuf, ValueName), Option<RawValue>>,
+}
+
+impl<'a, S: KeySource> Planner<'a, S> {
+    fn new(root: &'a S) -> Self {
+        Planner {
+            root,
+            keys: HashMap::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    fn plan(&self, operation: &RegOperation) -> Change {
+        let path = operation.path();
+        if !path.starts_with(self.root.path()) {
+            return Change::OutsideRoot;
+        }
+
+        let result = match operation {
+            RegOperation::CreateKey { .. } => self.key_exists(path).map(|exists| {
+                if exists {
+                    Change::Unchanged
+                } else {
+                    Change::Created
+                }
+            }),
+            RegOperation::DeleteKey { .. } => self.key_exists(path).map(|exists| {
+                if exists {
+                    Change::Deleted
+                } else {
+                    Change::NotFound
+                }
+            }),
+            RegOperation::SetValue { name, raw, .. } => {
+                self.value(path, name).map(|previous| match previous {
+                    Some(previous) if previous == *raw => Change::Unchanged,
+                    Some(previous) => Change::Modified { previous },
+                    None => Change::Created,
+                })
+            }
+            RegOperation::DeleteValue { name, .. } => {
+                self.value(path, name).map(|previous| match previous {
+                    Some(_) => Change::Deleted,
+                    None => Change::NotFound,
+                })
+            }
+        };
+        result.unwrap_or_else(Change::Failed)
+    }
+
+    /// Updates the tracked state after `operation` made `change`
+    fn record(&mut self, operation: &RegOperation, change: &Change) {
+        match (operation, change) {
+            (RegOperation::CreateKey { path }, Change::Created) => self.create(path),
+            (RegOperation::DeleteKey { path }, Change::Deleted) => {
+                self.keys.retain(|key, _| !key.starts_with(path));
+                self.values.retain(|(key, _), _| !key.starts_with(path));
+                self.keys.insert(path.clone(), KeyState::Deleted);
+            }
+            (RegOperation::SetValue { path, name, raw }, Change::Created)
+            | (RegOperation::SetValue { path, name, raw }, Change::Modified { .. }) => {
+                self.create(path);
+                self.values
+                    .insert((path.clone(), name.clone()), Some(raw.clone()));
+            }
+            (RegOperation::DeleteValue { path, name }, Change::Deleted) => {
+                self.values.insert((path.clone(), name.clone()), None);
+            }
+            _ => {}
+        }
+    }
+
+    /// Marks `path` and any of its missing parents below the root as created
+    fn create(&mut self, path: &RegPath) {
+        let mut missing = Vec::new();
+        let mut current = Some(path);
+        while let Some(key) = current.filter(|key| key.starts_with(self.root.path())) {
+            match self.key_exists(key) {
+                Ok(true) => break,
+                _ => missing.push(key.to_reg_path_buf()),
+            }
+            current = key.parent();
+        }
+        for key in missing {
+            self.keys.insert(key, KeyState::Created);
+        }
+    }
+
+    /// The tracked state of `path`, and whether the source still describes it
+    fn tracked(&self, path: &RegPath) -> (Option<KeyState>, bool) {
+        let mut current = Some(path);
+        while let Some(key) = current {
+            match self.keys.get(key) {
+                Some(KeyState::Deleted) => return (Some(KeyState::Deleted), false),
+                Some(KeyState::Created) if key == path => return (Some(KeyState::Created), false),
+                Some(KeyState::Created) => return (None, false),
+                None => {}
+            }
+            current = key.parent();
+        }
+        (None, true)
+    }
+
+    fn key_exists(&self, path: &RegPath) -> Result<bool> {
+        match self.tracked(path) {
+            (Some(state), _) => Ok(state == KeyState::Created),
+            (None, false) => Ok(false),
+            (None, true) => Ok(self.with_key(path, |_| Ok(()))?.is_some()),
+        }
+    }
+
+    fn value(&self, path: &RegPath, name: &ValueName) -> Result<Option<RawValue>> {
+        if let Some(value) = self.values.get(&(path.to_reg_path_buf(), name.clone())) {
+            return Ok(value.clone());
+        }
+        if let (_, false) = self.tracked(path) {
+            return Ok(None);
+        }
+
+        let value = self.with_key(path, |key| {
+            Ok(key
+                .values()?
+                .into_iter()
+                .find(|value| value.value_name() == name)
+                .map(|value| value.raw().clone()))
+        })?;
+        Ok(value.flatten())
+    }
+
+    /// Runs `f` on the key at `path` in the source, returning `None` if the key does not exist
+    fn with_key<T, F>(&self, path: &RegPath, f: F) -> Result<Option<T>>
+    where
+        F: FnOnce(&S) -> Result<T>,
+    {
+        let relative = match path.strip_prefix(self.root.path()) {
+            Some(relative) => relative,
+            None => return Ok(None),
+        };
+
+        let mut components = relative.components();
+        let mut key = match components.next() {
+            Some(name) => self.root.open_child(name),
+            None => return f(self.root).map(Some),
+        };
+        for name in components {
+            key = key.and_then(|key| key.open_child(name));
+        }
+        match key {
+            Ok(key) => f(&key).map(Some),
+            Err(error) if error.is_not_found() => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reg_export::{export_reg, tests::sample};
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let tree = sample();
+        let exported = export_reg(Vec::new(), &&tree).unwrap();
+        let operations = parse_reg(&exported).unwrap();
+
+        let mut expected = Vec::new();
+        let mut pending = vec![&tree];
+        while let Some(key) = pending.pop() {
+            expected.push(RegOperation::CreateKey {
+                path: key.path.clone(),
+            });
+            for (name, raw) in &key.values {
+                expected.push(RegOperation::SetValue {
+                    path: key.path.clone(),
+                    name: name.clone(),
+                    raw: raw.clone(),
+                });
+            }
+            pending.extend(key.children.iter().rev());
+        }
+        assert_eq!(operations, expected);
+    }
+
+    #[test]
+    fn regedit4() {
+        let file = b"REGEDIT4\r\n\
+            \r\n\
+            ; a comment\r\n\
+            [-HKEY_LOCAL_MACHINE\\SOFTWARE\\Old]\r\n\
+            \r\n\
+            [HKCR\\.vendor]\r\n\
+            @=\"Vendor.File\"\r\n\
+            \"Path\"=hex(2):25,50,41,54,48,25,00\r\n\
+            \"Data\"=hex:01,02,\\\r\n  03\r\n\
+            \"Flags\"=dword:ffffffff\r\n\
+            \"Gone\"=-\r\n\
+            \"Caf\xe9\"=\"\xe9t\xe9\"\r\n";
+        let operations = parse_reg(file).unwrap();
+        let key = RegPathBuf::from(r"\Registry\Machine\Software\Classes\.vendor");
+        assert_eq!(
+            operations,
+            vec![
+                RegOperation::DeleteKey {
+                    path: r"\Registry\Machine\SOFTWARE\Old".into()
+                },
+                RegOperation::CreateKey { path: key.clone() },
+                RegOperation::SetValue {
+                    path: key.clone(),
+                    name: ValueName::default(),
+                    raw: RawValue::new(1, utf16("Vendor.File\0")),
+                },
+                RegOperation::SetValue {
+                    path: key.clone(),
+                    name: "Path".into(),
+                    raw: RawValue::new(2, utf16("%PATH%\0")),
+                },
+                RegOperation::SetValue {
+                    path: key.clone(),
+                    name: "Data".into(),
+                    raw: RawValue::new(3, vec![1, 2, 3]),
+                },
+                RegOperation::SetValue {
+                    path: key.clone(),
+                    name: "Flags".into(),
+                    raw: RawValue::new(4, vec![0xff; 4]),
+                },
+                RegOperation::DeleteValue {
+                    path: key.clone(),
+                    name: "Gone".into(),
+                },
+                RegOperation::SetValue {
+                    path: key,
+                    name: "Caf\u{e9}".into(),
+                    raw: RawValue::new(1, utf16("\u{e9}t\u{e9}\0")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn utf8_version_5() {
+        let file = "\u{feff}Windows Registry Editor Version 5.00\r\n\
+            [HKLM\\Software]\r\n\
+            \"P\"=hex(2):25,00,41,00,00,00\r\n\
+            \"Caf\u{e9}\"=\"\u{e9}t\u{e9}\"\r\n";
+        let operations = parse_reg(file.as_bytes()).unwrap();
+        let key = RegPathBuf::from(r"\Registry\Machine\Software");
+        assert_eq!(
+            &operations[1..],
+            &[
+                RegOperation::SetValue {
+                    path: key.clone(),
+                    name: "P".into(),
+                    raw: RawValue::new(2, utf16("%A\0")),
+                },
+                RegOperation::SetValue {
+                    path: key,
+                    name: "Caf\u{e9}".into(),
+                    raw: RawValue::new(1, utf16("\u{e9}t\u{e9}\0")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn regedit4_hex_strings() {
+        let file = b"REGEDIT4\r\n\
+            [HKLM\\Software]\r\n\
+            \"Utf8\"=hex(2):25,63,61,66,c3,a9,25,00\r\n\
+            \"Latin1\"=hex(1):63,61,66,e9,00\r\n";
+        let operations = parse_reg(file).unwrap();
+        let key = RegPathBuf::from(r"\Registry\Machine\Software");
+        assert_eq!(
+            &operations[1..],
+            &[
+                RegOperation::SetValue {
+                    path: key.clone(),
+                    name: "Utf8".into(),
+                    raw: RawValue::new(2, utf16("%caf\u{e9}%\0")),
+                },
+                RegOperation::SetValue {
+                    path: key,
+                    name: "Latin1".into(),
+                    raw: RawValue::new(1, utf16("caf\u{e9}\0")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error =
+            parse_reg(b"REGEDIT4\r\n[HKLM\\Software]\r\n\"Bad\"=dword:xyz\r\n").unwrap_err();
+        assert_eq!(error.operation(), Operation::ImportKey);
+        assert_eq!(error.path().to_string(), r"\Registry\Machine\Software");
+        assert!(matches!(error.kind(), ErrorKind::Parse { line: 3, .. }));
+
+        let error = parse_reg(b"REGEDIT4\r\n[HKEY_CURRENT_USER\\Software]\r\n").unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Parse { line: 2, .. }));
+
+        assert!(parse_reg(b"Not a registry file\r\n").is_err());
+        assert!(parse_reg(b"REGEDIT4\r\n\"Orphan\"=\"value\"\r\n").is_err());
+
+        for line in &["\"a\"=", "@=", "\"a\" =  "] {
+            let file = format!("REGEDIT4\r\n[HKLM\\Software]\r\n{}\r\n", line);
+            let error = parse_reg(file.as_bytes()).unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                ErrorKind::Parse { line: 3, message } if message == "missing value data"
+            ));
+        }
+    }
+
+    #[test]
+    fn planning() {
+        let tree = sample();
+        let root = tree.path.clone();
+        let child = root.join(RegPathBuf::from("Child"));
+        let new = root.join(RegPathBuf::from(r"New\Nested"));
+
+        let operations = vec![
+            RegOperation::CreateKey { path: root.clone() },
+            RegOperation::SetValue {
+                path: root.clone(),
+                name: "Dword".into(),
+                raw: RawValue::new(4, vec![0x2a, 0, 0, 0]),
+            },
+            RegOperation::SetValue {
+                path: root.clone(),
+                name: "Dword".into(),
+                raw: RawValue::new(4, vec![1, 0, 0, 0]),
+            },
+            RegOperation::DeleteValue {
+                path: root.clone(),
+                name: "Missing".into(),
+            },
+            RegOperation::SetValue {
+                path: new.clone(),
+                name: "Value".into(),
+                raw: RawValue::new(3, vec![]),
+            },
+            RegOperation::CreateKey {
+                path: new.parent().unwrap().to_reg_path_buf(),
+            },
+            RegOperation::DeleteKey {
+                path: child.clone(),
+            },
+            RegOperation::CreateKey {
+                path: child.clone(),
+            },
+            RegOperation::DeleteKey { path: child },
+            RegOperation::DeleteKey {
+                path: root.join(RegPathBuf::from("Missing")),
+            },
+            RegOperation::CreateKey {
+                path: r"\Registry\Machine\SOFTWARE\Elsewhere".into(),
+            },
+        ];
+
+        let report = dry_run(&operations, &&tree);
+        let changes = report
+            .entries
+            .iter()
+            .map(|entry| entry.change.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                "unchanged",
+                "unchanged",
+                "modified (was 42)",
+                "not found",
+                "created",
+                "unchanged",
+                "deleted",
+                "created",
+                "deleted",
+                "not found",
+                "skipped, outside of the root key",
+            ]
+        );
+        assert!(!report.is_clean());
+        assert_eq!(report.failures().count(), 0);
+    }
+}
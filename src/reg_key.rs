@@ -0,0 +1,616 @@
+use crate::{
+    api::{self, *},
+    error::{Error, ErrorKind, Operation},
+    open_options::OpenOptions,
+    path::{RegPath, RegPathBuf},
+    privilege::require,
+    reg_key_iterator::*,
+    reg_value_iterator::*,
+    source::KeySource,
+    status::NtStatus,
+    transaction::{Transaction, TransactionHandle},
+    unicode_string::*,
+    value::{RawValue, RegValueItem},
+    value_name::ValueName,
+    Result,
+};
+use std::{
+    convert::TryFrom,
+    ffi::OsString,
+    mem::{size_of, zeroed},
+    os::windows::ffi::OsStrExt,
+    ptr::null_mut,
+    sync::Arc,
+};
+use winapi::{
+    shared::ntdef::{InitializeObjectAttributes, HANDLE, OBJECT_ATTRIBUTES, OBJ_CASE_INSENSITIVE},
+    um::winnt::{
+        KEY_CREATE_SUB_KEY, REG_BINARY, REG_DWORD, REG_NONE, REG_OPTION_BACKUP_RESTORE,
+        REG_OPTION_NON_VOLATILE, REG_QWORD, REG_SZ,
+    },
+};
+
+/// Entry point for all registry access
+pub struct RegKey {
+    pub(crate) handle: HANDLE,
+    pub(crate) path: RegPathBuf,
+    /// The transaction the key was opened in, which its subkeys are opened in too
+    pub(crate) transaction: Option<Arc<TransactionHandle>>,
+    /// Whether subkeys opened through `KeySource` are opened with backup intent
+    backup_intent: bool,
+    /// Whether subkeys opened through `KeySource` retry with backup intent when access is denied
+    backup_fallback: bool,
+}
+
+impl Drop for RegKey {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe {
+                NtClose(self.handle);
+            }
+        }
+    }
+}
+
+impl RegKey {
+    /// opens a registry key as read only
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winregnt::RegKey;
+    /// assert!(RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").is_ok());
+    /// ```
+    ///
+    pub fn open<P: Into<RegPathBuf>>(name: P) -> Result<RegKey> {
+        Self::open_with(name, &OpenOptions::new())
+    }
+
+    /// opens a registry key with write permissions
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winregnt::RegKey;
+    /// assert!(RegKey::open_write(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").is_ok());
+    /// ```
+    ///
+    pub fn open_write<P: Into<RegPathBuf>>(name: P) -> Result<RegKey> {
+        Self::open_with(name, OpenOptions::new().write(true))
+    }
+
+    /// opens a registry key using the access requested in `options`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::{export_reg, OpenOptions, RegKey};
+    /// // keys below SAM deny administrators, so read them with SeBackupPrivilege instead
+    /// let sam = RegKey::open_with(r"\Registry\Machine\SAM", OpenOptions::new().backup_fallback(true)).unwrap();
+    /// let exported = export_reg(Vec::new(), &sam).unwrap();
+    /// ```
+    ///
+    pub fn open_with<P: Into<RegPathBuf>>(name: P, options: &OpenOptions) -> Result<RegKey> {
+        let path = name.into();
+        Self::open_key(null_mut(), path.as_wide(), path.clone(), options, None)
+    }
+
+    /// opens a registry key as part of `transaction`.
+    ///
+    /// Changes made through the key, or through subkeys opened or created from it, only become visible to
+    /// others once the transaction is committed.
+    pub fn open_transacted<P: Into<RegPathBuf>>(
+        name: P,
+        options: &OpenOptions,
+        transaction: &Transaction,
+    ) -> Result<RegKey> {
+        let path = name.into();
+        Self::open_key(
+            null_mut(),
+            path.as_wide(),
+            path.clone(),
+            options,
+            Some(transaction.handle()),
+        )
+    }
+
+    /// creates a registry key, along with any missing keys leading up to it, as part of `transaction`.
+    ///
+    /// If the key already exists it is opened in the transaction instead.
+    pub fn create_transacted<P: Into<RegPathBuf>>(
+        name: P,
+        options: &OpenOptions,
+        transaction: &Transaction,
+    ) -> Result<RegKey> {
+        let path = name.into();
+        Self::create_key(
+            null_mut(),
+            &path,
+            path.clone(),
+            options,
+            Some(transaction.handle()),
+        )
+    }
+
+    /// opens `name` relative to this key.
+    ///
+    /// The handle of this key is used as the root directory of the open, so the kernel only walks `name`
+    /// rather than the full path. This keeps working if this key has since been renamed and avoids racing
+    /// against changes further up the tree. An absolute `name` is opened from the root instead. If this key
+    /// was opened in a transaction, so is the subkey.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use winregnt::{OpenOptions, RegKey};
+    /// let key = RegKey::open(r"\Registry\Machine\Software\Microsoft").unwrap();
+    /// assert!(key.open_subkey(r"Windows\CurrentVersion", &OpenOptions::new()).is_ok());
+    /// ```
+    ///
+    pub fn open_subkey<P: Into<RegPathBuf>>(
+        &self,
+        name: P,
+        options: &OpenOptions,
+    ) -> Result<RegKey> {
+        let name = name.into();
+        let transaction = self.transaction.as_ref();
+        if name.is_absolute() {
+            Self::open_key(
+                null_mut(),
+                name.as_wide(),
+                name.clone(),
+                options,
+                transaction,
+            )
+        } else {
+            Self::open_key(
+                self.handle,
+                name.as_wide(),
+                self.path.join(&name),
+                options,
+                transaction,
+            )
+        }
+    }
+
+    /// the full path this key was opened with
+    pub fn path(&self) -> &RegPath {
+        &self.path
+    }
+
+    /// when the key or any of its values last changed, as a `FILETIME`
+    pub fn last_write_time(&self) -> Result<u64> {
+        let data = api::query_key(self.handle)
+            .map_err(|status| self.error(Operation::QueryKey, ErrorKind::Status(status)))?;
+        if data.len() < size_of::<KeyBasicInformation>() {
+            return Err(self.error(Operation::QueryKey, ErrorKind::SmallDataBlob));
+        }
+        let mut time = [0; 8];
+        time.copy_from_slice(&data[..8]);
+        Ok(u64::from_ne_bytes(time))
+    }
+
+    /// get an sub key enumerator
+    ///
+    /// Each item is a `Result`: a failed `NtEnumerateKey` call is yielded as an error and ends the
+    /// enumeration, while a malformed entry is yielded as an error and enumeration continues.
+    pub fn enum_keys(&self) -> RegKeyIterator<'_> {
+        RegKeyIterator::new(self)
+    }
+
+    /// get a key value iterator
+    ///
+    /// Each item is a `Result`, the same way as for [`RegKey::enum_keys`].
+    pub fn enum_values(&self) -> RegValueIterator<'_> {
+        RegValueIterator::new(self)
+    }
+
+    /// delete the current key
+    pub fn delete(&self) -> Result<()> {
+        self.check(Operation::DeleteKey, None, unsafe {
+            api::NtDeleteKey(self.handle)
+        })
+    }
+
+    /// delete a value
+    pub fn delete_value<N: Into<ValueName>>(&self, value_name: N) -> Result<()> {
+        let value_name = value_name.into();
+        let unicode_string = self.unicode_name(Operation::DeleteValue, &value_name)?;
+        self.check(Operation::DeleteValue, Some(&value_name), unsafe {
+            NtDeleteValueKey(self.handle, &unicode_string.0 as *const _ as *mut _)
+        })
+    }
+
+    /// converts the result of an `Nt*` call on this key into a `Result` using `NT_SUCCESS` semantics
+    pub(crate) fn check<S: Into<NtStatus>>(
+        &self,
+        operation: Operation,
+        value_name: Option<&ValueName>,
+        status: S,
+    ) -> Result<()> {
+        let status = status.into();
+        if status.is_success() {
+            Ok(())
+        } else {
+            let error = self.error(operation, ErrorKind::Status(status));
+            Err(match value_name {
+                Some(value_name) => error.with_value_name(value_name),
+                None => error,
+            })
+        }
+    }
+
+    /// `name` as a counted string for the kernel, or an error for `operation` if it cannot be one
+    fn unicode_name(&self, operation: Operation, name: &ValueName) -> Result<UnicodeString> {
+        UnicodeString::try_from(name.as_wide())
+            .map_err(|kind| self.error(operation, kind).with_value_name(name))
+    }
+
+    /// an error for `operation` on this key
+    pub(crate) fn error(&self, operation: Operation, kind: ErrorKind) -> Error {
+        Error::new(operation, &self.path, kind)
+    }
+
+    /// creates `name` relative to this key, along with any missing keys leading up to it, and opens it
+    /// using the access requested in `options`. If the key already exists it is opened instead.
+    ///
+    /// An absolute `name` is created from the root instead. If this key was opened in a transaction, the
+    /// subkey is created in it too.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use winregnt::{OpenOptions, RegKey};
+    /// let software = RegKey::open(r"\Registry\Machine\Software").unwrap();
+    /// let key = software
+    ///     .create_subkey(r"winregnt\Example", OpenOptions::new().write(true))
+    ///     .unwrap();
+    /// ```
+    ///
+    pub fn create_subkey<P: Into<RegPathBuf>>(
+        &self,
+        name: P,
+        options: &OpenOptions,
+    ) -> Result<RegKey> {
+        let name = name.into();
+        let transaction = self.transaction.as_ref();
+        if name.is_absolute() {
+            Self::create_key(null_mut(), &name, name.clone(), options, transaction)
+        } else {
+            Self::create_key(
+                self.handle,
+                &name,
+                self.path.join(&name),
+                options,
+                transaction,
+            )
+        }
+    }
+
+    /// delete the current key and all of its subkeys.
+    ///
+    /// The key must have been opened with read and write access.
+    pub fn delete_tree(&self) -> Result<()> {
+        for name in self.subkey_names()? {
+            self.open_subkey(&name, OpenOptions::new().write(true))?
+                .delete_tree()?;
+        }
+        self.delete()
+    }
+
+    fn open_key(
+        root: HANDLE,
+        name: &[u16],
+        path: RegPathBuf,
+        options: &OpenOptions,
+        transaction: Option<&Arc<TransactionHandle>>,
+    ) -> Result<RegKey> {
+        let mut unicode_name = UnicodeString::try_from(name)
+            .map_err(|kind| Error::new(Operation::OpenKey, &path, kind))?;
+        let mut key = RegKey {
+            handle: unsafe { zeroed() },
+            path,
+            transaction: transaction.cloned(),
+            backup_intent: options.is_backup_intent(),
+            backup_fallback: options.is_backup_fallback(),
+        };
+        let object_attr = object_attributes(root, &mut unicode_name);
+
+        match key.open_handle(&object_attr, options, options.is_backup_intent()) {
+            Err(error)
+                if error.is_access_denied()
+                    && options.is_backup_fallback()
+                    && !options.is_backup_intent() =>
+            {
+                key.open_handle(&object_attr, options, true)?
+            }
+            result => result?,
+        }
+        Ok(key)
+    }
+
+    /// opens the handle of this key, with backup intent and the privileges it needs if `backup` is set
+    fn open_handle(
+        &mut self,
+        object_attr: &OBJECT_ATTRIBUTES,
+        options: &OpenOptions,
+        backup: bool,
+    ) -> Result<()> {
+        let access = options.desired_access();
+        let transaction = self.transaction.as_ref().map(|transaction| transaction.0);
+        let status = if backup {
+            let _privileges = require(Operation::OpenKey, &self.path, options.backup_privileges())?;
+            unsafe {
+                match transaction {
+                    Some(transaction) => NtOpenKeyTransactedEx(
+                        &mut self.handle,
+                        access,
+                        object_attr,
+                        REG_OPTION_BACKUP_RESTORE,
+                        transaction,
+                    ),
+                    None => NtOpenKeyEx(
+                        &mut self.handle,
+                        access,
+                        object_attr,
+                        REG_OPTION_BACKUP_RESTORE,
+                    ),
+                }
+            }
+        } else {
+            unsafe {
+                match transaction {
+                    Some(transaction) => {
+                        NtOpenKeyTransacted(&mut self.handle, access, object_attr, transaction)
+                    }
+                    None => NtOpenKey(&mut self.handle, access, object_attr),
+                }
+            }
+        };
+        self.check(Operation::OpenKey, None, status)
+    }
+
+    /// creates `name` below `root`, creating its missing parents first if the kernel reports them missing
+    fn create_key(
+        root: HANDLE,
+        name: &RegPath,
+        path: RegPathBuf,
+        options: &OpenOptions,
+        transaction: Option<&Arc<TransactionHandle>>,
+    ) -> Result<RegKey> {
+        let mut unicode_name = UnicodeString::try_from(name.as_wide())
+            .map_err(|kind| Error::new(Operation::CreateKey, &path, kind))?;
+        let mut key = RegKey {
+            handle: unsafe { zeroed() },
+            path,
+            transaction: transaction.cloned(),
+            backup_intent: options.is_backup_intent(),
+            backup_fallback: options.is_backup_fallback(),
+        };
+        let object_attr = object_attributes(root, &mut unicode_name);
+
+        let access = options.desired_access();
+        let mut create_options = REG_OPTION_NON_VOLATILE;
+        let _privileges = if options.is_backup_intent() {
+            create_options |= REG_OPTION_BACKUP_RESTORE;
+            require(Operation::CreateKey, &key.path, options.backup_privileges())?
+        } else {
+            Vec::new()
+        };
+        let mut disposition = 0;
+        let status = unsafe {
+            match transaction {
+                Some(transaction) => NtCreateKeyTransacted(
+                    &mut key.handle,
+                    access,
+                    &object_attr,
+                    0,
+                    null_mut(),
+                    create_options,
+                    transaction.0,
+                    &mut disposition,
+                ),
+                None => NtCreateKey(
+                    &mut key.handle,
+                    access,
+                    &object_attr,
+                    0,
+                    null_mut(),
+                    create_options,
+                    &mut disposition,
+                ),
+            }
+        };
+        match key.check(Operation::CreateKey, None, status) {
+            Err(error) if error.is_not_found() => {
+                let parent = match name.parent() {
+                    Some(parent) if parent.components().next().is_some() => parent,
+                    _ => return Err(error),
+                };
+                let parent_path = key.path.parent().unwrap_or(&key.path).to_reg_path_buf();
+                Self::create_key(
+                    root,
+                    parent,
+                    parent_path,
+                    OpenOptions::new()
+                        .read(false)
+                        .access(KEY_CREATE_SUB_KEY)
+                        .backup_intent(options.is_backup_intent()),
+                    transaction,
+                )?;
+                Self::create_key(root, name, key.path.clone(), options, transaction)
+            }
+            result => result.map(|_| key),
+        }
+    }
+
+    /// Create or update the value `name` with the type and data of `value`, exactly as given
+    pub fn write_raw_value<N: Into<ValueName>>(&mut self, name: N, value: &RawValue) -> Result<()> {
+        let name = name.into();
+        let unicode_name = self.unicode_name(Operation::SetValue, &name)?;
+        self.check(Operation::SetValue, Some(&name), unsafe {
+            NtSetValueKey(
+                self.handle,
+                &unicode_name.0 as *const _ as *mut _,
+                0,
+                value.value_type,
+                value.data.as_ptr() as *const _ as *mut _,
+                value.data.len() as _,
+            )
+        })
+    }
+
+    /// Create or update a binary value `name` with `value`
+    pub fn write_binary_value<N: Into<ValueName>, V: AsRef<[u8]>>(
+        &mut self,
+        name: N,
+        value: V,
+    ) -> Result<()> {
+        let name = name.into();
+        let unicode_name = self.unicode_name(Operation::SetValue, &name)?;
+        self.check(Operation::SetValue, Some(&name), unsafe {
+            NtSetValueKey(
+                self.handle,
+                &unicode_name.0 as *const _ as *mut _,
+                0,
+                REG_BINARY,
+                value.as_ref() as *const _ as *mut _,
+                value.as_ref().len() as _,
+            )
+        })
+    }
+
+    /// Create or update a binary value `name` with `value`
+    pub fn write_string_value<N: Into<ValueName>, V: AsRef<str>>(
+        &mut self,
+        name: N,
+        value: V,
+    ) -> Result<()> {
+        let name = name.into();
+        let unicode_name = self.unicode_name(Operation::SetValue, &name)?;
+
+        let mut o = OsString::from(value.as_ref())
+            .encode_wide()
+            .collect::<Vec<u16>>();
+        o.push(0x00);
+
+        self.check(Operation::SetValue, Some(&name), unsafe {
+            NtSetValueKey(
+                self.handle,
+                &unicode_name.0 as *const _ as *mut _,
+                0,
+                REG_SZ,
+                o.as_mut_ptr() as _,
+                (o.len() * 2) as _,
+            )
+        })
+    }
+
+    /// Create or update a binary value `name` with `value`
+    pub fn write_dword_value<N: Into<ValueName>>(&mut self, name: N, value: u32) -> Result<()> {
+        let name = name.into();
+        let unicode_name = self.unicode_name(Operation::SetValue, &name)?;
+        self.check(Operation::SetValue, Some(&name), unsafe {
+            NtSetValueKey(
+                self.handle,
+                &unicode_name.0 as *const _ as *mut _,
+                0,
+                REG_DWORD,
+                &mut value.clone() as *const _ as *mut _,
+                std::mem::size_of::<u32>() as _,
+            )
+        })
+    }
+
+    /// Create or update a `NONE` value `name` with `value`
+    pub fn write_qword_value<N: Into<ValueName>>(&mut self, name: N, value: u64) -> Result<()> {
+        let name = name.into();
+        let unicode_name = self.unicode_name(Operation::SetValue, &name)?;
+        self.check(Operation::SetValue, Some(&name), unsafe {
+            NtSetValueKey(
+                self.handle,
+                &unicode_name.0 as *const _ as *mut _,
+                0,
+                REG_QWORD,
+                &mut value.clone() as *const _ as *mut _,
+                std::mem::size_of::<u64>() as _,
+            )
+        })
+    }
+
+    /// Create or update a `NONE` value `name` with `value`
+    pub fn write_none_value<N: Into<ValueName>, V: AsRef<[u8]>>(
+        &mut self,
+        name: N,
+        value: V,
+    ) -> Result<()> {
+        let name = name.into();
+        let unicode_name = self.unicode_name(Operation::SetValue, &name)?;
+        self.check(Operation::SetValue, Some(&name), unsafe {
+            NtSetValueKey(
+                self.handle,
+                &unicode_name.0 as *const _ as *mut _,
+                0,
+                REG_NONE,
+                value.as_ref() as *const _ as *mut _,
+                value.as_ref().len() as _,
+            )
+        })
+    }
+}
+
+/// object attributes for opening or creating `name` relative to `root`, case-insensitively
+pub(crate) fn object_attributes(root: HANDLE, name: &mut UnicodeString) -> OBJECT_ATTRIBUTES {
+    let mut object_attr: OBJECT_ATTRIBUTES = unsafe { zeroed() };
+    unsafe {
+        InitializeObjectAttributes(
+            &mut object_attr,
+            &mut name.0,
+            OBJ_CASE_INSENSITIVE,
+            root,
+            null_mut(),
+        );
+    }
+    object_attr
+}
+
+impl KeySource for RegKey {
+    fn path(&self) -> &RegPath {
+        &self.path
+    }
+
+    fn values(&self) -> Result<Vec<RegValueItem>> {
+        self.enum_values().collect()
+    }
+
+    fn subkey_names(&self) -> Result<Vec<RegPathBuf>> {
+        self.enum_keys()
+            .map(|subkey| subkey.map(|subkey| subkey.name().to_reg_path_buf()))
+            .collect()
+    }
+
+    fn last_write_time(&self) -> Result<u64> {
+        RegKey::last_write_time(self)
+    }
+
+    fn open_child(&self, name: &RegPath) -> Result<RegKey> {
+        self.open_subkey(
+            name,
+            OpenOptions::new()
+                .backup_intent(self.backup_intent)
+                .backup_fallback(self.backup_fallback),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn open() {
+        use crate::RegKey;
+        assert!(
+            RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run",)
+                .is_ok()
+        );
+    }
+}
@@ -1,120 +1,109 @@
 use crate::{
     api::*,
-    error::{self, Error},
+    error::{ErrorKind, Operation},
+    open_options::OpenOptions,
+    path::{RegPath, RegPathBuf},
     RegKey, Result,
 };
-use std::ffi::OsString;
 use std::mem::size_of;
-use std::os::windows::ffi::OsStringExt;
 use winapi::shared::minwindef::ULONG;
-use winapi::shared::ntdef::HANDLE;
 
 /// iterator over registry keys
+///
+/// Enumeration stops after the first failing `NtEnumerateKey` call, which is yielded as an error.
 pub struct RegKeyIterator<'a> {
-    handle: &'a HANDLE,
+    key: &'a RegKey,
     index: ULONG,
-    name: &'a [u16],
+    done: bool,
 }
 
 impl<'a> RegKeyIterator<'a> {
     /// get an iterator for a `RegKey`
     pub fn new(key: &'a RegKey) -> RegKeyIterator<'a> {
         RegKeyIterator {
-            handle: &key.handle,
+            key,
             index: 0,
-            name: &key.name,
+            done: false,
         }
     }
 }
 
 impl<'a> Iterator for RegKeyIterator<'a> {
-    type Item = RegSubkey<'a>;
+    type Item = Result<RegSubkey<'a>>;
 
-    fn next(&mut self) -> Option<RegSubkey<'a>> {
-        match enumerate_key(*self.handle, self.index) {
-            Some(data) => {
-                if data.len() >= size_of::<KeyBasicInformation>() {
-                    match KeyBasicInformation::new(&data) {
-                        Ok(value) => {
-                            let name: Vec<u16> = {
-                                let length = (value.name_length / 2) as usize;
+    fn next(&mut self) -> Option<Result<RegSubkey<'a>>> {
+        if self.done {
+            return None;
+        }
+
+        let data = match enumerate_key(self.key.handle, self.index) {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(status) => {
+                self.done = true;
+                return Some(Err(self
+                    .key
+                    .error(Operation::EnumerateKeys, ErrorKind::Status(status))));
+            }
+        };
+        self.index += 1;
 
-                                let data = data
-                                    .iter()
-                                    .copied()
-                                    .skip(size_of::<KeyBasicInformation>())
-                                    .take(value.name_length as _)
-                                    .collect::<Vec<u8>>();
+        if data.len() < size_of::<KeyBasicInformation>() {
+            return Some(Err(self
+                .key
+                .error(Operation::EnumerateKeys, ErrorKind::SmallDataBlob)));
+        }
 
-                                if data.len() >= length {
-                                    data.chunks_exact(2)
-                                        .map(|chunk| u16::from_ne_bytes([chunk[0], chunk[1]]))
-                                        .take(length)
-                                        .collect::<Vec<u16>>()
-                                } else {
-                                    Vec::new()
-                                }
-                            };
+        let info = KeyBasicInformation::new(&data)
+            .map_err(|kind| self.key.error(Operation::EnumerateKeys, kind));
+        Some(info.map(|value| {
+            let name: Vec<u16> = data
+                .iter()
+                .copied()
+                .skip(size_of::<KeyBasicInformation>())
+                .take(value.name_length as _)
+                .collect::<Vec<u8>>()
+                .chunks_exact(2)
+                .map(|chunk| u16::from_ne_bytes([chunk[0], chunk[1]]))
+                .collect();
 
-                            match OsString::from_wide(&name).into_string() {
-                                Ok(s) => {
-                                    self.index += 1;
-                                    Some(RegSubkey {
-                                        name: s,
-                                        parent: self.name,
-                                    })
-                                }
-                                Err(_) => None,
-                            }
-                        }
-                        _ => None,
-                    }
-                } else {
-                    None
-                }
+            RegSubkey {
+                name: RegPathBuf::from(name),
+                parent: self.key,
             }
-            _ => None,
-        }
+        }))
     }
 }
 
 /// child key
 pub struct RegSubkey<'a> {
-    name: String,
-    parent: &'a [u16],
+    name: RegPathBuf,
+    parent: &'a RegKey,
 }
 
 impl<'a> RegSubkey<'a> {
-    /// returns a `RegKey`
-    pub fn open(&'a self) -> Result<RegKey> {
-        let parent = {
-            let mut p = self.parent.to_vec();
-            p.pop();
-            p
-        };
+    /// the name of this subkey, which may contain embedded `null`s
+    pub fn name(&self) -> &RegPath {
+        &self.name
+    }
 
-        let mut s = OsString::from_wide(&parent)
-            .into_string()
-            .map_err(|_| Into::<Error>::into(error::SubKeyError::ConvertName))?;
-        s.push_str("\\");
-        s.push_str(&self.name);
-        RegKey::open(s)
+    /// the full path of this subkey
+    pub fn path(&self) -> RegPathBuf {
+        self.parent.path().join(&self.name)
     }
 
-    /// returns a `RegKey`
-    pub fn open_write(&'a self) -> Result<RegKey> {
-        let parent = {
-            let mut p = self.parent.to_vec();
-            p.pop();
-            p
-        };
+    /// returns a `RegKey`, opened relative to the parent key
+    pub fn open(&self) -> Result<RegKey> {
+        self.parent.open_subkey(&*self.name, &OpenOptions::new())
+    }
 
-        let mut s = OsString::from_wide(&parent)
-            .into_string()
-            .map_err(|_| Into::<Error>::into(error::SubKeyError::ConvertName))?;
-        s.push_str("\\");
-        s.push_str(&self.name);
-        RegKey::open_write(s)
+    /// returns a `RegKey` with write permissions, opened relative to the parent key
+    pub fn open_write(&self) -> Result<RegKey> {
+        self.parent
+            .open_subkey(&*self.name, OpenOptions::new().write(true))
     }
 }
 
@@ -133,6 +122,6 @@ mod tests {
             RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion".to_owned())
                 .unwrap();
         let mut iter = key.enum_keys();
-        assert!(iter.next().is_some());
+        assert!(matches!(iter.next(), Some(Ok(_))));
     }
 }
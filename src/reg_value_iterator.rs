@@ -1,98 +1,93 @@
-use crate::{api::*, error, Result};
-use std::convert::TryFrom;
-use std::ffi::OsString;
+use crate::{
+    api::*,
+    error::{ErrorKind, Operation},
+    value::{RawValue, RegValueItem},
+    RegKey, Result,
+};
 use std::mem::size_of;
-use std::os::windows::ffi::OsStringExt;
 use winapi::shared::minwindef::ULONG;
-use winapi::shared::ntdef::HANDLE;
 
 /// get an iterator of key values
+///
+/// Enumeration stops after the first failing `NtEnumerateValueKey` call, which is yielded as an error.
 pub struct RegValueIterator<'a> {
-    handle: &'a HANDLE,
+    key: &'a RegKey,
     index: ULONG,
+    done: bool,
 }
 
 impl<'a> RegValueIterator<'a> {
-    pub fn new(handle: &'a HANDLE) -> RegValueIterator<'a> {
-        RegValueIterator { handle, index: 0 }
+    /// get an iterator for the values of a `RegKey`
+    pub fn new(key: &'a RegKey) -> RegValueIterator<'a> {
+        RegValueIterator {
+            key,
+            index: 0,
+            done: false,
+        }
     }
 }
 
 impl<'a> Iterator for RegValueIterator<'a> {
-    type Item = RegValueItem;
+    type Item = Result<RegValueItem>;
+
+    fn next(&mut self) -> Option<Result<RegValueItem>> {
+        if self.done {
+            return None;
+        }
 
-    fn next(&mut self) -> Option<RegValueItem> {
-        match enumerate_value_key(*self.handle, self.index) {
-            Some(data) => {
+        match enumerate_value_key(self.key.handle, self.index) {
+            Ok(Some(data)) => {
                 self.index += 1;
-                RegValueItem::try_from(data).ok()
+                Some(parse(self.key, &data))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(status) => {
+                self.done = true;
+                Some(Err(self.key.error(
+                    Operation::EnumerateValues,
+                    ErrorKind::Status(status),
+                )))
             }
-            _ => None,
         }
     }
 }
 
-/// defines a registry value (name and data)
-pub struct RegValueItem {
-    name: Vec<u16>,
-    value: RegValue,
-}
-
-impl RegValueItem {
-    /// returns the name of the value
-    pub fn name(&self) -> Result<String> {
-        OsString::from_wide(&self.name)
-            .into_string()
-            .map_err(|_| error::RegValueError::ConvertName.into())
+/// parses a `KEY_VALUE_FULL_INFORMATION` buffer returned for `key`
+fn parse(key: &RegKey, data: &[u8]) -> Result<RegValueItem> {
+    let start = size_of::<KeyValueFullInformation>();
+    if data.len() < start {
+        return Err(key.error(Operation::EnumerateValues, ErrorKind::SmallDataBlob));
     }
 
-    /// returns the `RegValue`
-    pub fn value(&self) -> RegValue {
-        self.value.clone()
-    }
-}
+    let info = KeyValueFullInformation::new(data)
+        .map_err(|kind| key.error(Operation::EnumerateValues, kind))?;
 
-impl std::fmt::Display for RegValueItem {
-    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        write!(fmt, "{}", self.name().unwrap_or_default())
+    let name = data[start..]
+        .chunks_exact(2)
+        .map(|chunk| u16::from_ne_bytes([chunk[0], chunk[1]]))
+        .take((info.name_length / 2) as usize)
+        .collect::<Vec<u16>>();
+    if name.len() * 2 < info.name_length as usize {
+        return Err(key.error(Operation::EnumerateValues, ErrorKind::SmallNameBlob));
     }
-}
-
-impl TryFrom<Vec<u8>> for RegValueItem {
-    type Error = error::Error;
-
-    fn try_from(data: Vec<u8>) -> std::result::Result<Self, Self::Error> {
-        let start = size_of::<KeyValueFullInformation>();
-        if data.len() >= start {
-            let value = KeyValueFullInformation::new(&data).map_err(Into::<Self::Error>::into)?;
 
-            let length = (value.name_length / 2) as usize;
+    let value_data = info
+        .data_offset
+        .checked_add(info.data_length)
+        .and_then(|end| data.get(info.data_offset as usize..end as usize))
+        .ok_or_else(|| {
+            key.error(Operation::EnumerateValues, ErrorKind::SmallDataBlob)
+                .with_value_name(&name[..])
+        })?;
 
-            let name_data = data.iter().copied().skip(start).collect::<Vec<u8>>();
-            if name_data.len() >= length {
-                let name = name_data
-                    .chunks_exact(2)
-                    .map(|chunk| u16::from_ne_bytes([chunk[0], chunk[1]]))
-                    .take(length)
-                    .collect::<Vec<u16>>();
-
-                Ok(RegValueItem {
-                    name,
-                    value: RegValue::new(&value, &data).map_err(|e| {
-                        Into::<error::Error>::into(error::RegValueError::ValueData(e.to_string()))
-                    })?,
-                })
-            } else {
-                Err(Into::<error::Error>::into(
-                    error::RegValueError::SmallNameBlob,
-                ))
-            }
-        } else {
-            Err(Into::<error::Error>::into(
-                error::RegValueError::SmallDataBlob,
-            ))
-        }
-    }
+    Ok(RegValueItem::new(
+        key.path(),
+        name,
+        RawValue::new(info.value_type, value_data),
+    ))
 }
 
 #[cfg(test)]
@@ -103,6 +98,6 @@ mod tests {
         let key =
             RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion").unwrap();
         let mut iter = key.enum_values();
-        assert!(iter.next().is_some());
+        assert!(matches!(iter.next(), Some(Ok(_))));
     }
 }
@@ -0,0 +1,884 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::RegPathBuf,
+    reg_import::RegOperation,
+    serde_impl::{from_hex, type_by_name},
+    snapshot::KeySnapshot,
+    source::KeySource,
+    value::{RawValue, RegValue, ValueType},
+    value_name::ValueName,
+    Result,
+};
+use serde::ser::{self, Impossible, Serialize};
+use std::{convert::TryFrom, fmt::Display};
+
+#[cfg(windows)]
+use crate::{
+    reg_import::{apply, Change},
+    reg_key::RegKey,
+};
+
+/// Options for writing a Rust value into an existing key with [`to_operations`] or `to_key`
+#[derive(Clone, Debug, Default)]
+pub struct SerializeOptions {
+    remove_stale_values: bool,
+    remove_stale_keys: bool,
+}
+
+impl SerializeOptions {
+    /// options that only create and replace, never delete
+    pub fn new() -> SerializeOptions {
+        SerializeOptions::default()
+    }
+
+    /// delete values of the keys written to that the value does not have a field or map entry for,
+    /// including fields that are `None`
+    pub fn remove_stale_values(&mut self, remove: bool) -> &mut SerializeOptions {
+        self.remove_stale_values = remove;
+        self
+    }
+
+    /// delete subkeys of the keys written to, and everything below them, that the value does not have a
+    /// field or map entry for
+    pub fn remove_stale_keys(&mut self, remove: bool) -> &mut SerializeOptions {
+        self.remove_stale_keys = remove;
+        self
+    }
+}
+
+/// Serializes a struct or map into an in-memory key at `path`.
+///
+/// This is the reverse of [`from_source`](crate::from_source):
+///
+/// * `bool` and integers up to 32 bits become `REG_DWORD` (negative numbers as two's complement), 64 bit
+///   integers `REG_QWORD`
+/// * strings, `char`s, floats and unit enum variants become `REG_SZ`
+/// * sequences of strings become `REG_MULTI_SZ`, and sequences of bytes, byte buffers and empty sequences
+///   `REG_BINARY`; a sequence holding an empty string is an error, as `REG_MULTI_SZ` cannot store one
+/// * `()` becomes `REG_NONE`, and `RawValue` and `RegValue` keep their own type
+/// * `None` writes nothing
+/// * nested structs and maps become subkeys, as do sequences of structs or maps (named by their index)
+///
+/// Map keys must be strings or integers. Other enum variants, and sequences mixing kinds of elements, cannot
+/// be written.
+pub fn to_snapshot<T: Serialize + ?Sized, P: Into<RegPathBuf>>(
+    value: &T,
+    path: P,
+) -> Result<KeySnapshot> {
+    let path = path.into();
+    match value.serialize(EntrySerializer::new(path.clone()))? {
+        Entry::Key(key) => Ok(key),
+        _ => Err(Error::new(
+            Operation::Serialize,
+            path,
+            ErrorKind::Unsupported("only structs and maps can be written to a key"),
+        )),
+    }
+}
+
+/// Works out the operations that write `value` into the key `current`, as [`to_snapshot`] lays it out.
+///
+/// Every key of the value is created and every value set; importing the result with
+/// [`dry_run`](crate::dry_run) or `apply` reports the ones that are already as wanted as unchanged. Stale
+/// values and subkeys are deleted if `options` ask for it.
+///
+/// # Examples
+///
+/// ```
+/// use serde::Serialize;
+/// use winregnt::{dry_run, to_operations, KeySnapshot, RawValue, SerializeOptions};
+///
+/// #[derive(Serialize)]
+/// #[serde(rename_all = "PascalCase")]
+/// struct Config {
+///     port: u16,
+/// }
+///
+/// let mut current = KeySnapshot::new(r"\Registry\Machine\Software\Vendor");
+/// current.values.push(("Legacy".into(), RawValue::new(1, vec![0, 0])));
+/// let operations = to_operations(
+///     &Config { port: 8080 },
+///     &&current,
+///     SerializeOptions::new().remove_stale_values(true),
+/// )
+/// .unwrap();
+/// print!("{}", dry_run(&operations, &&current));
+/// ```
+pub fn to_operations<T: Serialize + ?Sized, S: KeySource>(
+    value: &T,
+    current: &S,
+    options: &SerializeOptions,
+) -> Result<Vec<RegOperation>> {
+    let wanted = to_snapshot(value, current.path())?;
+    let mut operations = Vec::new();
+    plan(&wanted, Some(current), options, &mut operations)?;
+    Ok(operations)
+}
+
+fn plan<S: KeySource>(
+    wanted: &KeySnapshot,
+    current: Option<&S>,
+    options: &SerializeOptions,
+    operations: &mut Vec<RegOperation>,
+) -> Result<()> {
+    operations.push(RegOperation::CreateKey {
+        path: wanted.path.clone(),
+    });
+    operations.extend(
+        wanted
+            .values
+            .iter()
+            .map(|(name, raw)| RegOperation::SetValue {
+                path: wanted.path.clone(),
+                name: name.clone(),
+                raw: raw.clone(),
+            }),
+    );
+    if let Some(current) = current {
+        if options.remove_stale_values {
+            for item in current.values()? {
+                if wanted.value(item.value_name()).is_none() {
+                    operations.push(RegOperation::DeleteValue {
+                        path: wanted.path.clone(),
+                        name: item.value_name().clone(),
+                    });
+                }
+            }
+        }
+        if options.remove_stale_keys {
+            for name in current.subkey_names()? {
+                if wanted.child(&name).is_none() {
+                    operations.push(RegOperation::DeleteKey {
+                        path: wanted.path.join(&name),
+                    });
+                }
+            }
+        }
+    }
+
+    for child in &wanted.children {
+        let existing = match current.map(|current| current.open_child(child.name())) {
+            Some(Ok(existing)) => Some(existing),
+            Some(Err(error)) if !error.is_not_found() => return Err(error),
+            _ => None,
+        };
+        plan(child, existing.as_ref(), options, operations)?;
+    }
+    Ok(())
+}
+
+/// Writes `value` into `key`, which must be opened with read and write access.
+///
+/// See [`to_snapshot`] for how Rust types map to keys and values and [`to_operations`] for what is written.
+/// Every operation is attempted; the first one that fails is returned as the error.
+///
+/// # Examples
+///
+/// ```no_run
+/// use serde::Serialize;
+/// use winregnt::{to_key, OpenOptions, RegKey, SerializeOptions};
+///
+/// #[derive(Serialize)]
+/// #[serde(rename_all = "PascalCase")]
+/// struct Config {
+///     install_dir: String,
+///     port: u16,
+///     servers: Vec<String>,
+/// }
+///
+/// let key = RegKey::open_with(
+///     r"\Registry\Machine\Software\Vendor",
+///     OpenOptions::new().read(true).write(true),
+/// )
+/// .unwrap();
+/// let config = Config {
+///     install_dir: r"C:\Program Files\Vendor".into(),
+///     port: 8080,
+///     servers: vec!["a.example.com".into(), "b.example.com".into()],
+/// };
+/// to_key(&config, &key, SerializeOptions::new().remove_stale_values(true)).unwrap();
+/// ```
+#[cfg(windows)]
+pub fn to_key<T: Serialize + ?Sized>(
+    value: &T,
+    key: &RegKey,
+    options: &SerializeOptions,
+) -> Result<()> {
+    let operations = to_operations(value, key, options)?;
+    match apply(&operations, key, false)
+        .entries
+        .into_iter()
+        .find_map(|entry| match entry.change {
+            Change::Failed(error) => Some(error),
+            _ => None,
+        }) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::new(
+            Operation::Serialize,
+            RegPathBuf::new(),
+            ErrorKind::Serde(msg.to_string()),
+        )
+    }
+}
+
+fn unsupported(what: &'static str) -> Error {
+    Error::new(
+        Operation::Serialize,
+        RegPathBuf::new(),
+        ErrorKind::Unsupported(what),
+    )
+}
+
+/// What a Rust value serializes to
+enum Entry {
+    /// Nothing, for `None`
+    Skip,
+    Value(RawValue),
+    Key(KeySnapshot),
+    /// A `u8` inside a sequence, which becomes part of a `REG_BINARY` value
+    Byte(u8),
+}
+
+impl Entry {
+    fn value(value: RegValue) -> Entry {
+        Entry::Value(
+            value
+                .to_raw()
+                .expect("only unknown values have no raw form"),
+        )
+    }
+}
+
+/// Serializes a value that will be stored at `path`, as a value of that name or a key at that path
+struct EntrySerializer {
+    path: RegPathBuf,
+    in_seq: bool,
+}
+
+impl EntrySerializer {
+    fn new(path: RegPathBuf) -> EntrySerializer {
+        EntrySerializer {
+            path,
+            in_seq: false,
+        }
+    }
+}
+
+impl ser::Serializer for EntrySerializer {
+    type Ok = Entry;
+    type Error = Error;
+    type SerializeSeq = SeqBuilder;
+    type SerializeTuple = SeqBuilder;
+    type SerializeTupleStruct = SeqBuilder;
+    type SerializeTupleVariant = Impossible<Entry, Error>;
+    type SerializeMap = KeyBuilder;
+    type SerializeStruct = StructBuilder;
+    type SerializeStructVariant = Impossible<Entry, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Entry> {
+        Ok(Entry::value(RegValue::Dword(v.into())))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Entry> {
+        self.serialize_i32(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Entry> {
+        self.serialize_i32(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Entry> {
+        Ok(Entry::value(RegValue::Dword(v as u32)))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Entry> {
+        Ok(Entry::value(RegValue::Qword(v as u64)))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Entry> {
+        let v = i64::try_from(v).map_err(|_| unsupported("integer too large for REG_QWORD"))?;
+        self.serialize_i64(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Entry> {
+        if self.in_seq {
+            Ok(Entry::Byte(v))
+        } else {
+            self.serialize_u32(v.into())
+        }
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Entry> {
+        self.serialize_u32(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Entry> {
+        Ok(Entry::value(RegValue::Dword(v)))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Entry> {
+        Ok(Entry::value(RegValue::Qword(v)))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Entry> {
+        let v = u64::try_from(v).map_err(|_| unsupported("integer too large for REG_QWORD"))?;
+        self.serialize_u64(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Entry> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Entry> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<Entry> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Entry> {
+        Ok(Entry::value(RegValue::String(v.to_owned())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Entry> {
+        Ok(Entry::value(RegValue::Binary(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Entry> {
+        Ok(Entry::Skip)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Entry> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Entry> {
+        Ok(Entry::value(RegValue::None))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Entry> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Entry> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Entry> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Entry> {
+        Err(unsupported("enum variants with data cannot be written"))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqBuilder> {
+        if self.in_seq {
+            return Err(unsupported("nested sequences cannot be written"));
+        }
+        Ok(SeqBuilder {
+            path: self.path,
+            width: len.map_or(1, |len| len.saturating_sub(1).to_string().len()),
+            entries: Vec::new(),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqBuilder> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqBuilder> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Impossible<Entry, Error>> {
+        Err(unsupported("enum variants with data cannot be written"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<KeyBuilder> {
+        Ok(KeyBuilder {
+            key: KeySnapshot::new(self.path),
+            name: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<StructBuilder> {
+        Ok(match name {
+            "RawValue" | "RegValue" => StructBuilder::Value {
+                tagged: name == "RegValue",
+                value_type: None,
+                data: None,
+            },
+            _ => StructBuilder::Key(self.serialize_map(None)?),
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Impossible<Entry, Error>> {
+        Err(unsupported("enum variants with data cannot be written"))
+    }
+}
+
+/// Collects the elements of a sequence, which become a `REG_MULTI_SZ` or `REG_BINARY` value or a key
+struct SeqBuilder {
+    path: RegPathBuf,
+    /// Subkeys are named by their index, padded to this many digits so they sort in order
+    width: usize,
+    entries: Vec<Entry>,
+}
+
+impl ser::SerializeSeq for SeqBuilder {
+    type Ok = Entry;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let name = format!("{:0width$}", self.entries.len(), width = self.width);
+        let entry = value.serialize(EntrySerializer {
+            path: self.path.join(RegPathBuf::from(name.as_str())),
+            in_seq: true,
+        })?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Entry> {
+        let mut strings = Vec::new();
+        let mut bytes = Vec::new();
+        let mut key = KeySnapshot::new(self.path);
+        for entry in self.entries {
+            match entry {
+                Entry::Value(raw) if raw.value_type() == Some(ValueType::REG_SZ) => {
+                    strings.push(raw)
+                }
+                Entry::Byte(byte) => bytes.push(byte),
+                Entry::Key(child) => key.children.push(child),
+                _ => {
+                    return Err(unsupported(
+                        "sequences can only hold strings, bytes, structs or maps",
+                    ))
+                }
+            }
+        }
+        match (
+            strings.is_empty(),
+            bytes.is_empty(),
+            key.children.is_empty(),
+        ) {
+            // an empty sequence reads back as either, but `Vec<u8>` is the one that can tell them apart
+            (true, _, true) => Ok(Entry::value(RegValue::Binary(bytes))),
+            (false, true, true) => {
+                let strings = strings
+                    .iter()
+                    .map(|raw| match raw.decode() {
+                        RegValue::String(s) => s,
+                        _ => String::new(),
+                    })
+                    .collect::<Vec<_>>();
+                // an empty string would end the list early when read back
+                if strings.iter().any(String::is_empty) {
+                    return Err(unsupported("REG_MULTI_SZ cannot hold empty strings"));
+                }
+                Ok(Entry::value(RegValue::MultiString(strings)))
+            }
+            (true, true, false) => Ok(Entry::Key(key)),
+            _ => Err(unsupported(
+                "sequences cannot mix strings, bytes and structs or maps",
+            )),
+        }
+    }
+}
+
+impl ser::SerializeTuple for SeqBuilder {
+    type Ok = Entry;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Entry> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqBuilder {
+    type Ok = Entry;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Entry> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Collects the entries of a struct or map into a key
+struct KeyBuilder {
+    key: KeySnapshot,
+    /// The name of the map entry whose value comes next
+    name: Option<String>,
+}
+
+impl KeyBuilder {
+    fn add<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<()> {
+        let context = |error: Error| error.in_context(&self.key.path, Some(&ValueName::from(name)));
+        let path = self.key.path.join(RegPathBuf::from(name));
+        match value
+            .serialize(EntrySerializer::new(path))
+            .map_err(context)?
+        {
+            Entry::Skip => {}
+            Entry::Value(raw) => self.key.values.push((name.into(), raw)),
+            Entry::Key(_) if name.is_empty() || name.contains('\\') => {
+                return Err(context(unsupported(
+                    "key names must not be empty or hold `\\`",
+                )))
+            }
+            Entry::Key(child) => self.key.children.push(child),
+            Entry::Byte(_) => unreachable!("bytes are only produced inside sequences"),
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for KeyBuilder {
+    type Ok = Entry;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        let path = self.key.path.clone();
+        let name = match key.serialize(EntrySerializer::new(path))? {
+            Entry::Value(raw) => match raw.decode() {
+                RegValue::String(s) => s,
+                RegValue::Dword(n) if raw.data == n.to_le_bytes() => n.to_string(),
+                RegValue::Qword(n) => n.to_string(),
+                _ => return Err(unsupported("map keys must be strings or integers")),
+            },
+            _ => return Err(unsupported("map keys must be strings or integers")),
+        };
+        self.name = Some(name);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let name = self
+            .name
+            .take()
+            .ok_or_else(|| <Error as ser::Error>::custom("map value without a key"))?;
+        self.add(&name, value)
+    }
+
+    fn end(self) -> Result<Entry> {
+        Ok(Entry::Key(self.key))
+    }
+}
+
+/// Collects the fields of a struct: into a key, or into a single value for `RawValue` and `RegValue`
+enum StructBuilder {
+    Key(KeyBuilder),
+    Value {
+        /// `RegValue`, whose type is a tag naming the variant its data belongs to
+        tagged: bool,
+        value_type: Option<RawValue>,
+        data: Option<RawValue>,
+    },
+}
+
+impl ser::SerializeStruct for StructBuilder {
+    type Ok = Entry;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        match self {
+            StructBuilder::Key(builder) => builder.add(key, value),
+            StructBuilder::Value {
+                value_type, data, ..
+            } => {
+                let field = match key {
+                    "type" => value_type,
+                    "data" => data,
+                    _ => return Ok(()),
+                };
+                if let Entry::Value(raw) =
+                    value.serialize(EntrySerializer::new(RegPathBuf::new()))?
+                {
+                    *field = Some(raw);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn end(self) -> Result<Entry> {
+        match self {
+            StructBuilder::Key(builder) => ser::SerializeMap::end(builder),
+            StructBuilder::Value {
+                tagged,
+                value_type,
+                data,
+            } => {
+                let value_type = match value_type.map(|raw| raw.decode()) {
+                    Some(RegValue::String(name)) => type_by_name(&name).map(|t| t as u32),
+                    Some(RegValue::Dword(n)) => Some(n),
+                    _ => None,
+                }
+                .ok_or_else(|| unsupported("values of unknown type cannot be written"))?;
+                let data = data.map(|raw| raw.decode());
+                let raw = if tagged {
+                    retag(value_type, data)
+                } else {
+                    match data {
+                        Some(RegValue::String(hex)) => {
+                            from_hex(&hex).map(|data| RawValue::new(value_type, data))
+                        }
+                        _ => None,
+                    }
+                };
+                raw.map(Entry::Value)
+                    .ok_or_else(|| <Error as ser::Error>::custom("malformed value"))
+            }
+        }
+    }
+}
+
+/// Rebuilds a `RegValue` from its type tag and its data as it was serialized
+fn retag(value_type: u32, data: Option<RegValue>) -> Option<RawValue> {
+    let value = match (ValueType::try_from(value_type).ok()?, data) {
+        (ValueType::REG_NONE, None) => RegValue::None,
+        (ValueType::REG_SZ, Some(RegValue::String(s))) => RegValue::String(s),
+        (ValueType::REG_EXPAND_SZ, Some(RegValue::String(s))) => RegValue::ExpandString(s),
+        (ValueType::REG_LINK, Some(RegValue::String(s))) => RegValue::Link(s),
+        (ValueType::REG_MULTI_SZ, Some(RegValue::MultiString(strings))) => {
+            RegValue::MultiString(strings)
+        }
+        (ValueType::REG_DWORD, Some(RegValue::Dword(n))) => RegValue::Dword(n),
+        (ValueType::REG_DWORD_BIG_ENDIAN, Some(RegValue::Dword(n))) => RegValue::DwordBigEndian(n),
+        (ValueType::REG_QWORD, Some(RegValue::Qword(n))) => RegValue::Qword(n),
+        (ValueType::REG_BINARY, Some(RegValue::String(hex))) => RegValue::Binary(from_hex(&hex)?),
+        _ => return None,
+    };
+    value.to_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{de::from_source, reg_import::dry_run};
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Config {
+        name: String,
+        port: u16,
+        enabled: bool,
+        offset: i32,
+        size: u64,
+        servers: Vec<String>,
+        key: Vec<u8>,
+        ratio: f64,
+        mode: Mode,
+        proxy: Option<String>,
+        raw: RawValue,
+        typed: RegValue,
+        window: Window,
+        plugins: BTreeMap<String, Window>,
+        history: Vec<Window>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Mode {
+        Fast,
+        Safe,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Window {
+        width: u32,
+    }
+
+    fn config() -> Config {
+        Config {
+            name: "app".into(),
+            port: 8080,
+            enabled: true,
+            offset: -1,
+            size: 1 << 40,
+            servers: vec!["a".into(), "b".into()],
+            key: vec![1, 2, 3],
+            ratio: 0.25,
+            mode: Mode::Fast,
+            proxy: None,
+            raw: RawValue::new(0x1234, vec![9]),
+            typed: RegValue::DwordBigEndian(5),
+            window: Window { width: 640 },
+            plugins: vec![("Spell".to_owned(), Window { width: 1 })]
+                .into_iter()
+                .collect(),
+            history: (0..11).map(|width| Window { width }).collect(),
+        }
+    }
+
+    #[test]
+    fn snapshots() {
+        let key = to_snapshot(&config(), r"\Registry\Machine\Software\Vendor").unwrap();
+        let value_type = |name: &str| key.value(name).map(|raw| raw.value_type);
+        assert_eq!(value_type("Name"), Some(ValueType::REG_SZ as u32));
+        assert_eq!(value_type("Port"), Some(ValueType::REG_DWORD as u32));
+        assert_eq!(value_type("Size"), Some(ValueType::REG_QWORD as u32));
+        assert_eq!(value_type("Servers"), Some(ValueType::REG_MULTI_SZ as u32));
+        assert_eq!(value_type("Key"), Some(ValueType::REG_BINARY as u32));
+        assert_eq!(value_type("Raw"), Some(0x1234));
+        assert_eq!(
+            value_type("Typed"),
+            Some(ValueType::REG_DWORD_BIG_ENDIAN as u32)
+        );
+        assert_eq!(value_type("Proxy"), None);
+        assert_eq!(key.value("Offset").unwrap().data, vec![0xff; 4]);
+        assert_eq!(
+            key.child("History").unwrap().children[10].path.to_string(),
+            r"\Registry\Machine\Software\Vendor\History\10"
+        );
+        assert_eq!(
+            key.child("History").unwrap().children[2].name().to_string(),
+            "02"
+        );
+
+        let back: Config = from_source(&&key).unwrap();
+        assert_eq!(back, config());
+    }
+
+    #[test]
+    fn operations() {
+        let mut current = KeySnapshot::new(r"\Registry\Machine\Software\Vendor");
+        current
+            .values
+            .push(("Port".into(), RegValue::Dword(8080).to_raw().unwrap()));
+        current
+            .values
+            .push(("Legacy".into(), RawValue::new(3, vec![])));
+        current.child_mut("Plugins\\Old");
+
+        let operations = to_operations(&config(), &&current, &SerializeOptions::new()).unwrap();
+        assert!(!operations.iter().any(|operation| matches!(
+            operation,
+            RegOperation::DeleteValue { .. } | RegOperation::DeleteKey { .. }
+        )));
+
+        let operations = to_operations(
+            &config(),
+            &&current,
+            SerializeOptions::new()
+                .remove_stale_values(true)
+                .remove_stale_keys(true),
+        )
+        .unwrap();
+        let report = dry_run(&operations, &&current).to_string();
+        assert!(report.contains(
+            "set value \"Port\" in \\Registry\\Machine\\Software\\Vendor to 8080: unchanged"
+        ));
+        assert!(report
+            .contains("delete value \"Legacy\" in \\Registry\\Machine\\Software\\Vendor: deleted"));
+        assert!(report
+            .contains("delete key \\Registry\\Machine\\Software\\Vendor\\Plugins\\Old: deleted"));
+        assert!(
+            !report.contains("delete key \\Registry\\Machine\\Software\\Vendor\\Plugins\\Spell")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        #[derive(Serialize)]
+        enum Shape {
+            Circle(u32),
+        }
+        #[derive(Serialize)]
+        struct Outer {
+            inner: BTreeMap<&'static str, Shape>,
+        }
+        let error = to_snapshot(
+            &Outer {
+                inner: vec![("Wheel", Shape::Circle(1))].into_iter().collect(),
+            },
+            r"\Registry\Machine\Software\Vendor",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not serialize into \\Registry\\Machine\\Software\\Vendor\\inner (value \"Wheel\"): \
+             Not supported: enum variants with data cannot be written"
+        );
+        assert!(to_snapshot(&5u32, r"\Registry").is_err());
+        assert!(to_snapshot(&vec![vec!["a"]], r"\Registry").is_err());
+    }
+
+    #[test]
+    fn empty_sequences() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Lists {
+            bytes: Vec<u8>,
+            strings: Vec<String>,
+        }
+        let empty = Lists {
+            bytes: vec![],
+            strings: vec![],
+        };
+        let key = to_snapshot(&empty, r"\Registry\Machine\Software\Vendor").unwrap();
+        assert_eq!(
+            key.value("bytes").unwrap().value_type,
+            ValueType::REG_BINARY as u32
+        );
+        let back: Lists = from_source(&&key).unwrap();
+        assert_eq!(back, empty);
+
+        let blank = Lists {
+            bytes: vec![],
+            strings: vec!["a".into(), "".into(), "b".into()],
+        };
+        let error = to_snapshot(&blank, r"\Registry\Machine\Software\Vendor").unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("Not supported: REG_MULTI_SZ cannot hold empty strings"));
+    }
+}
@@ -0,0 +1,528 @@
+//! `serde` support for values, names, paths and snapshots.
+//!
+//! In human readable formats such as JSON:
+//!
+//! * names and paths are strings, or arrays of UTF-16 code units when they are not valid UTF-16 (such as a
+//!   name containing an unpaired surrogate), so they always round trip exactly
+//! * value types are their names (`"REG_SZ"`), or plain numbers for types without a name
+//! * raw value data and security descriptors are lowercase hex strings
+//!
+//! Other formats always use the code units, type numbers and bytes.
+
+use crate::{
+    path::{RegPath, RegPathBuf},
+    value::{RawValue, RegValueItem, ValueType},
+    value_name::ValueName,
+};
+use serde::{
+    de::{self, Deserializer, SeqAccess, Visitor},
+    ser::{SerializeSeq, Serializer},
+    Deserialize, Serialize,
+};
+use std::{borrow::Cow, convert::TryFrom, fmt};
+
+/// Writes `wide` as a string if it is valid UTF-16, otherwise as its code units
+fn serialize_wide<S: Serializer>(wide: &[u16], serializer: S) -> Result<S::Ok, S::Error> {
+    match String::from_utf16(wide) {
+        Ok(string) if serializer.is_human_readable() => serializer.serialize_str(&string),
+        _ => {
+            let mut seq = serializer.serialize_seq(Some(wide.len()))?;
+            for unit in wide {
+                seq.serialize_element(unit)?;
+            }
+            seq.end()
+        }
+    }
+}
+
+fn deserialize_wide<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u16>, D::Error> {
+    struct WideVisitor;
+
+    impl<'de> Visitor<'de> for WideVisitor {
+        type Value = Vec<u16>;
+
+        fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str("a string or an array of UTF-16 code units")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u16>, E> {
+            Ok(v.encode_utf16().collect())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u16>, A::Error> {
+            let mut wide = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+            while let Some(unit) = seq.next_element()? {
+                wide.push(unit);
+            }
+            Ok(wide)
+        }
+    }
+
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(WideVisitor)
+    } else {
+        deserializer.deserialize_seq(WideVisitor)
+    }
+}
+
+/// `with` module for bytes, written as hex in human readable formats
+pub(crate) mod hex {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            let hex = data
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+            serializer.serialize_str(&hex)
+        } else {
+            serializer.serialize_bytes(data)
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: From<Vec<u8>>,
+    {
+        struct HexVisitor;
+
+        impl<'de> Visitor<'de> for HexVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt.write_str("a hex string or bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+                from_hex(v).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+                let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                while let Some(byte) = seq.next_element()? {
+                    data.push(byte);
+                }
+                Ok(data)
+            }
+        }
+
+        let data = if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HexVisitor)?
+        } else {
+            deserializer.deserialize_byte_buf(HexVisitor)?
+        };
+        Ok(T::from(data))
+    }
+}
+
+/// Decodes a hex string as written by [`hex::serialize`]
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
+
+/// `with` module for optional bytes
+pub(crate) mod option_hex {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Bytes<'a>(#[serde(with = "hex")] Cow<'a, [u8]>);
+
+    pub(crate) fn serialize<S: Serializer>(
+        data: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        data.as_deref()
+            .map(|data| Bytes(Cow::Borrowed(data)))
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<Bytes>::deserialize(deserializer)?.map(|bytes| bytes.0.into_owned()))
+    }
+}
+
+/// `with` module for optional UTF-16 strings
+pub(crate) mod option_wide {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Wide<'a>(
+        #[serde(
+            serialize_with = "serialize_wide",
+            deserialize_with = "deserialize_cow_wide"
+        )]
+        Cow<'a, [u16]>,
+    );
+
+    fn deserialize_cow_wide<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'static, [u16]>, D::Error> {
+        deserialize_wide(deserializer).map(Cow::Owned)
+    }
+
+    pub(crate) fn serialize<S: Serializer>(
+        wide: &Option<Vec<u16>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        wide.as_deref()
+            .map(|wide| Wide(Cow::Borrowed(wide)))
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u16>>, D::Error> {
+        Ok(Option::<Wide>::deserialize(deserializer)?.map(|wide| wide.0.into_owned()))
+    }
+}
+
+/// `with` module for a value type number, written as the name of the type when it has one
+mod type_tag {
+    use super::*;
+
+    pub(super) fn serialize<S: Serializer>(
+        value_type: &u32,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match ValueType::try_from(*value_type) {
+            Ok(known) => known.serialize(serializer),
+            Err(other) => serializer.serialize_u32(other),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        struct TagVisitor;
+
+        impl<'de> Visitor<'de> for TagVisitor {
+            type Value = u32;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt.write_str("a value type name or number")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<u32, E> {
+                type_by_name(v)
+                    .map(|value_type| value_type as u32)
+                    .ok_or_else(|| E::unknown_variant(v, TYPE_NAMES))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<u32, E> {
+                u32::try_from(v).map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(TagVisitor)
+        } else {
+            deserializer.deserialize_u32(TagVisitor)
+        }
+    }
+}
+
+const TYPE_NAMES: &[&str] = &[
+    "REG_NONE",
+    "REG_SZ",
+    "REG_EXPAND_SZ",
+    "REG_BINARY",
+    "REG_DWORD",
+    "REG_DWORD_BIG_ENDIAN",
+    "REG_LINK",
+    "REG_MULTI_SZ",
+    "REG_RESOURCE_LIST",
+    "REG_FULL_RESOURCE_DESCRIPTOR",
+    "REG_RESOURCE_REQUIREMENTS_LIST",
+    "REG_QWORD",
+];
+
+/// The name of `value_type`, such as `REG_SZ`
+pub(crate) fn type_name(value_type: ValueType) -> &'static str {
+    TYPE_NAMES[value_type as usize]
+}
+
+/// The well known type called `name`, such as `REG_SZ`
+pub(crate) fn type_by_name(name: &str) -> Option<ValueType> {
+    TYPE_NAMES
+        .iter()
+        .position(|known| *known == name)
+        .and_then(|index| ValueType::try_from(index as u32).ok())
+}
+
+impl Serialize for ValueType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(type_name(*self))
+        } else {
+            serializer.serialize_u32(*self as u32)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value_type = type_tag::deserialize(deserializer)?;
+        ValueType::try_from(value_type).map_err(|other| {
+            de::Error::invalid_value(
+                de::Unexpected::Unsigned(other.into()),
+                &"a known value type",
+            )
+        })
+    }
+}
+
+impl Serialize for ValueName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_wide(self.as_wide(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_wide(deserializer).map(ValueName::from_wide)
+    }
+}
+
+impl Serialize for RegPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_wide(self.as_wide(), serializer)
+    }
+}
+
+impl Serialize for RegPathBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_wide(self.as_wide(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RegPathBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_wide(deserializer).map(RegPathBuf::from_wide)
+    }
+}
+
+/// A raw value as written by `serde`, optionally with its name and the path of its key
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "RawValue")]
+struct Value<'a> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<Cow<'a, RegPath>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<Cow<'a, ValueName>>,
+    #[serde(rename = "type", with = "type_tag")]
+    value_type: u32,
+    #[serde(with = "hex")]
+    data: Cow<'a, [u8]>,
+}
+
+impl<'a> Value<'a> {
+    fn new(raw: &'a RawValue) -> Value<'a> {
+        Value {
+            path: None,
+            name: None,
+            value_type: raw.value_type,
+            data: Cow::Borrowed(&raw.data),
+        }
+    }
+
+    fn into_raw(self) -> RawValue {
+        RawValue::new(self.value_type, self.data.into_owned())
+    }
+}
+
+impl Serialize for RawValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Value::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Value::deserialize(deserializer).map(Value::into_raw)
+    }
+}
+
+impl Serialize for RegValueItem {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Value {
+            path: Some(Cow::Borrowed(self.key_path())),
+            name: Some(Cow::Borrowed(self.value_name())),
+            ..Value::new(self.raw())
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RegValueItem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        let path = value
+            .path
+            .take()
+            .ok_or_else(|| de::Error::missing_field("path"))?;
+        let name = value
+            .name
+            .take()
+            .ok_or_else(|| de::Error::missing_field("name"))?;
+        Ok(RegValueItem::new(
+            path.into_owned(),
+            name.into_owned(),
+            value.into_raw(),
+        ))
+    }
+}
+
+/// `with` module for the values of a `KeySnapshot`, written as a list of `{name, type, data}` objects
+pub(crate) mod named_values {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        values: &[(ValueName, RawValue)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for (name, raw) in values {
+            seq.serialize_element(&Value {
+                name: Some(Cow::Borrowed(name)),
+                ..Value::new(raw)
+            })?;
+        }
+        seq.end()
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(ValueName, RawValue)>, D::Error> {
+        Vec::<Value>::deserialize(deserializer)?
+            .into_iter()
+            .map(|mut value| {
+                let name = value
+                    .name
+                    .take()
+                    .ok_or_else(|| de::Error::missing_field("name"))?;
+                Ok((name.into_owned(), value.into_raw()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{snapshot::KeySnapshot, value::RegValue};
+
+    fn round_trip<T>(value: &T) -> String
+    where
+        T: Serialize + for<'de> Deserialize<'de> + PartialEq + fmt::Debug,
+    {
+        let json = serde_json::to_string(value).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value);
+        json
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            round_trip(&RegValue::Dword(7)),
+            r#"{"type":"REG_DWORD","data":7}"#
+        );
+        assert_eq!(round_trip(&RegValue::None), r#"{"type":"REG_NONE"}"#);
+        assert_eq!(
+            round_trip(&RegValue::MultiString(vec!["a".into(), "b".into()])),
+            r#"{"type":"REG_MULTI_SZ","data":["a","b"]}"#
+        );
+        assert_eq!(
+            round_trip(&RegValue::Binary(vec![0xde, 0xad])),
+            r#"{"type":"REG_BINARY","data":"dead"}"#
+        );
+        assert_eq!(
+            round_trip(&RawValue::new(4, vec![1, 0, 0, 0])),
+            r#"{"type":"REG_DWORD","data":"01000000"}"#
+        );
+        assert_eq!(
+            round_trip(&RawValue::new(0x1234, vec![])),
+            r#"{"type":4660,"data":""}"#
+        );
+        assert!(serde_json::from_str::<RawValue>(r#"{"type":"REG_BOGUS","data":""}"#).is_err());
+        assert!(serde_json::from_str::<RawValue>(r#"{"type":1,"data":"abc"}"#).is_err());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(round_trip(&ValueName::from("Run")), r#""Run""#);
+        let lone_surrogate = ValueName::from_wide(vec![b'a' as u16, 0xd800, 0]);
+        assert_eq!(round_trip(&lone_surrogate), "[97,55296,0]");
+        assert_eq!(
+            serde_json::from_str::<ValueName>("[97,55296,0]")
+                .unwrap()
+                .as_wide(),
+            lone_surrogate.as_wide()
+        );
+        assert_eq!(
+            round_trip(&RegPathBuf::from(r"\Registry\Machine")),
+            r#""\\Registry\\Machine""#
+        );
+
+        let item = RegValueItem::new(
+            r"\Registry\Machine\Software",
+            "Version",
+            RawValue::new(1, vec![0x31, 0, 0, 0]),
+        );
+        let json = serde_json::to_string(&item).unwrap();
+        assert_eq!(
+            json,
+            r#"{"path":"\\Registry\\Machine\\Software","name":"Version","type":"REG_SZ","data":"31000000"}"#
+        );
+        let back = serde_json::from_str::<RegValueItem>(&json).unwrap();
+        assert_eq!(back.key_path(), item.key_path());
+        assert_eq!(back.value_name(), item.value_name());
+        assert_eq!(back.raw(), item.raw());
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut root = KeySnapshot::new(r"\Registry\Machine\SOFTWARE");
+        root.last_write_time = 132_000_000_000_000_000;
+        root.class = Some(vec![b'c' as u16, 0xdc00]);
+        root.security = Some(vec![1, 0, 4, 0x80]);
+        let child = root.child_mut("Vendor\\App");
+        child
+            .values
+            .push(("Enabled".into(), RawValue::new(4, vec![1, 0, 0, 0])));
+        child.values.push((
+            ValueName::from_wide(vec![0xd800]),
+            RawValue::new(99, vec![]),
+        ));
+        let json = round_trip(&root);
+        assert!(json.contains(r#""class":[99,56320]"#));
+
+        let back: KeySnapshot = serde_json::from_str(&json).unwrap();
+        let value_names = |snapshot: &KeySnapshot| {
+            let app = snapshot.child("Vendor").unwrap().child("App").unwrap();
+            app.values
+                .iter()
+                .map(|(name, _)| name.as_wide().to_vec())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(value_names(&back), value_names(&root));
+    }
+}
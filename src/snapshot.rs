@@ -0,0 +1,196 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::{RegPath, RegPathBuf},
+    source::{check_depth, KeySource},
+    value::{RawValue, RegValueItem},
+    value_name::ValueName,
+    Result,
+};
+
+/// An owned, in-memory copy of a key and everything below it.
+///
+/// Snapshots hold everything an offline hive stores about a key, so they can be built by hand and written
+/// out with [`HiveWriter`](crate::HiveWriter), and they can be walked like any other [`KeySource`].
+///
+/// # Examples
+///
+/// ```
+/// use winregnt::{KeySnapshot, RawValue};
+/// let mut root = KeySnapshot::new(r"\Registry\Machine\SOFTWARE");
+/// root.child_mut("Vendor")
+///     .values
+///     .push(("Enabled".into(), RawValue::new(4, vec![1, 0, 0, 0])));
+/// assert_eq!(root.children[0].path.to_string(), r"\Registry\Machine\SOFTWARE\Vendor");
+/// ```
+///
+/// With the `serde` feature, snapshots serialize losslessly: values are written as a list of
+/// `{"name", "type", "data"}` objects in order, and names that are not valid UTF-16 as arrays of code units.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeySnapshot {
+    /// Full path of the key; the last component is its name
+    pub path: RegPathBuf,
+    /// Last write time as a `FILETIME` (100ns intervals since 1601-01-01 UTC)
+    pub last_write_time: u64,
+    /// The class name of the key, if it has one
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::option_wide"))]
+    pub class: Option<Vec<u16>>,
+    /// The self-relative security descriptor of the key, `None` to inherit the one of the parent
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::option_hex"))]
+    pub security: Option<Vec<u8>>,
+    /// The values of the key, in order
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impl::named_values"))]
+    pub values: Vec<(ValueName, RawValue)>,
+    /// The subkeys of the key, in order
+    pub children: Vec<KeySnapshot>,
+}
+
+impl KeySnapshot {
+    /// An empty key at `path`
+    pub fn new<P: Into<RegPathBuf>>(path: P) -> KeySnapshot {
+        KeySnapshot {
+            path: path.into(),
+            ..KeySnapshot::default()
+        }
+    }
+
+    /// Copies `source` and everything below it into a snapshot.
+    ///
+    /// Class names and security descriptors are only available from offline hives, through
+    /// [`HiveKey::to_snapshot`](crate::HiveKey::to_snapshot), and are left `None` here. Subkeys that are deleted
+    /// while the capture runs are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[cfg(windows)]
+    /// # fn main() {
+    /// use winregnt::{diff, DiffOptions, KeySnapshot, RegKey};
+    /// let run = RegKey::open(r"\Registry\Machine\Software\Microsoft\Windows\CurrentVersion\Run").unwrap();
+    /// let before = KeySnapshot::capture(&run).unwrap();
+    /// // ... install something ...
+    /// let after = KeySnapshot::capture(&run).unwrap();
+    /// print!("{}", diff(&before, &after, &DiffOptions::new()));
+    /// # }
+    /// # #[cfg(not(windows))]
+    /// # fn main() {}
+    /// ```
+    pub fn capture<S: KeySource>(source: &S) -> Result<KeySnapshot> {
+        KeySnapshot::capture_below(source, 0)
+    }
+
+    fn capture_below<S: KeySource>(source: &S, depth: usize) -> Result<KeySnapshot> {
+        check_depth(Operation::EnumerateKeys, source, depth)?;
+        let mut children = Vec::new();
+        for name in source.subkey_names()? {
+            match source.open_child(&name) {
+                Ok(child) => children.push(KeySnapshot::capture_below(&child, depth + 1)?),
+                Err(error) if error.is_not_found() => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(KeySnapshot {
+            path: source.path().to_reg_path_buf(),
+            last_write_time: source.last_write_time()?,
+            class: None,
+            security: None,
+            values: source
+                .values()?
+                .into_iter()
+                .map(|value| (value.value_name().clone(), value.raw().clone()))
+                .collect(),
+            children,
+        })
+    }
+
+    /// The name of the key, the last component of its path
+    pub fn name(&self) -> &RegPath {
+        self.path.name().unwrap_or_else(|| RegPath::from_wide(&[]))
+    }
+
+    /// The direct subkey called `name`, compared case-insensitively
+    pub fn child<P: Into<RegPathBuf>>(&self, name: P) -> Option<&KeySnapshot> {
+        let name = name.into();
+        self.children.iter().find(|child| child.name() == &*name)
+    }
+
+    /// The subkey at the relative path `name`, adding empty keys for any part of it that does not exist yet
+    pub fn child_mut<P: Into<RegPathBuf>>(&mut self, name: P) -> &mut KeySnapshot {
+        let name = name.into();
+        name.components().fold(self, |key, component| {
+            let index = match key
+                .children
+                .iter()
+                .position(|child| child.name() == component)
+            {
+                Some(index) => index,
+                None => {
+                    key.children
+                        .push(KeySnapshot::new(key.path.join(component)));
+                    key.children.len() - 1
+                }
+            };
+            &mut key.children[index]
+        })
+    }
+
+    /// The value called `name`, compared case-insensitively
+    pub fn value<N: Into<ValueName>>(&self, name: N) -> Option<&RawValue> {
+        let name = name.into();
+        self.values
+            .iter()
+            .find(|(value_name, _)| *value_name == name)
+            .map(|(_, raw)| raw)
+    }
+}
+
+impl<'a> KeySource for &'a KeySnapshot {
+    fn path(&self) -> &RegPath {
+        &self.path
+    }
+
+    fn values(&self) -> Result<Vec<RegValueItem>> {
+        Ok(self
+            .values
+            .iter()
+            .map(|(name, raw)| RegValueItem::new(&self.path, name, raw.clone()))
+            .collect())
+    }
+
+    fn subkey_names(&self) -> Result<Vec<RegPathBuf>> {
+        Ok(self
+            .children
+            .iter()
+            .map(|child| child.name().to_reg_path_buf())
+            .collect())
+    }
+
+    fn last_write_time(&self) -> Result<u64> {
+        Ok(self.last_write_time)
+    }
+
+    fn open_child(&self, name: &RegPath) -> Result<&'a KeySnapshot> {
+        let snapshot: &'a KeySnapshot = self;
+        snapshot.child(name).ok_or_else(|| {
+            Error::new(
+                Operation::OpenKey,
+                self.path.join(name),
+                ErrorKind::NotFound,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reg_export::tests::Endless;
+
+    #[test]
+    fn cycle() {
+        let error = KeySnapshot::capture(&Endless(RegPathBuf::from(r"\Registry\Machine\Loop")))
+            .unwrap_err();
+        assert_eq!(error.operation(), Operation::EnumerateKeys);
+        assert!(matches!(error.kind(), ErrorKind::Unsupported(_)));
+    }
+}
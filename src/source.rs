@@ -0,0 +1,52 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::{RegPath, RegPathBuf},
+    value::RegValueItem,
+    Result,
+};
+
+/// A key in a registry tree that can be walked without knowing where the tree lives.
+///
+/// This is implemented for live keys (`RegKey`) as well as in-memory and offline trees, so the exporters
+/// and walkers in this crate work the same on all of them. Children are opened one at a time, so walking a
+/// source never needs more than one level of the tree in memory.
+pub trait KeySource: Sized {
+    /// The full path of this key
+    fn path(&self) -> &RegPath;
+
+    /// The values of this key, in the order the source stores them
+    fn values(&self) -> Result<Vec<RegValueItem>>;
+
+    /// The names of the direct subkeys of this key, in the order the source stores them
+    fn subkey_names(&self) -> Result<Vec<RegPathBuf>>;
+
+    /// When the key was last written, as a `FILETIME`, or `0` if the source does not record it
+    fn last_write_time(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Opens the direct subkey `name`
+    fn open_child(&self, name: &RegPath) -> Result<Self>;
+}
+
+/// How many levels below the starting key a walk may descend. The registry itself stops at 512, so anything
+/// deeper can only come from a source whose subkeys lead back to one of their ancestors.
+pub(crate) const MAX_DEPTH: usize = 512;
+
+/// Fails `operation` on `source` once a walk has gone `depth` levels down, so a cyclic source ends in an
+/// error instead of a stack overflow
+pub(crate) fn check_depth<S: KeySource>(
+    operation: Operation,
+    source: &S,
+    depth: usize,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        Err(Error::new(
+            operation,
+            source.path(),
+            ErrorKind::Unsupported("key tree is nested deeper than the registry allows"),
+        ))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+use std::fmt;
+
+/// The severity encoded in the top two bits of an `NTSTATUS`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// `STATUS_SEVERITY_SUCCESS`
+    Success,
+    /// `STATUS_SEVERITY_INFORMATIONAL`
+    Informational,
+    /// `STATUS_SEVERITY_WARNING`
+    Warning,
+    /// `STATUS_SEVERITY_ERROR`
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Success => write!(fmt, "success"),
+            Severity::Informational => write!(fmt, "informational"),
+            Severity::Warning => write!(fmt, "warning"),
+            Severity::Error => write!(fmt, "error"),
+        }
+    }
+}
+
+/// An `NTSTATUS` value returned by the `Nt*` functions.
+///
+/// The layout of the value is documented
+/// [here](https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-erref/87fba13e-bf06-450e-83b1-9241dc81e781).
+/// Statuses that are commonly returned by the registry functions can be decoded to their symbolic name and a
+/// short message, anything else is displayed by its severity, facility and code.
+///
+/// # Examples
+///
+/// ```
+/// use winregnt::NtStatus;
+/// let status = NtStatus::from(0xC000_0034u32);
+/// assert_eq!(status, NtStatus::OBJECT_NAME_NOT_FOUND);
+/// assert_eq!(status.name(), Some("STATUS_OBJECT_NAME_NOT_FOUND"));
+/// assert!(status.is_error());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NtStatus(pub u32);
+
+macro_rules! statuses {
+    ($($konst:ident = $value:expr, $name:expr, $message:expr;)*) => {
+        impl NtStatus {
+            $(
+                #[doc = "`"]
+                #[doc = $name]
+                #[doc = "`: "]
+                #[doc = $message]
+                pub const $konst: NtStatus = NtStatus($value);
+            )*
+        }
+
+        const STATUS_TABLE: &[(u32, &str, &str)] = &[$(($value, $name, $message)),*];
+    };
+}
+
+statuses! {
+    SUCCESS = 0x0000_0000, "STATUS_SUCCESS", "The operation completed successfully.";
+    PENDING = 0x0000_0103, "STATUS_PENDING", "The operation that was requested is pending completion.";
+    REPARSE = 0x0000_0104, "STATUS_REPARSE", "A reparse should be performed by the Object Manager.";
+    MORE_ENTRIES = 0x0000_0105, "STATUS_MORE_ENTRIES", "More entries are available than fit in the buffer.";
+    NOT_ALL_ASSIGNED = 0x0000_0106, "STATUS_NOT_ALL_ASSIGNED", "Not all of the requested privileges are held by the caller.";
+    NOTIFY_CLEANUP = 0x0000_010B, "STATUS_NOTIFY_CLEANUP", "The handle used for change notification was closed.";
+    NOTIFY_ENUM_DIR = 0x0000_010C, "STATUS_NOTIFY_ENUM_DIR", "Too many changes occurred to be reported individually.";
+    OBJECT_NAME_EXISTS = 0x4000_0000, "STATUS_OBJECT_NAME_EXISTS", "An object with the requested name already exists and was opened.";
+    REGISTRY_RECOVERED = 0x4000_0009, "STATUS_REGISTRY_RECOVERED", "The registry hive was corrupt and has been recovered.";
+    BUFFER_OVERFLOW = 0x8000_0005, "STATUS_BUFFER_OVERFLOW", "The data was too large to fit into the specified buffer.";
+    NO_MORE_ENTRIES = 0x8000_001A, "STATUS_NO_MORE_ENTRIES", "No more entries are available from an enumeration operation.";
+    UNSUCCESSFUL = 0xC000_0001, "STATUS_UNSUCCESSFUL", "The requested operation was unsuccessful.";
+    NOT_IMPLEMENTED = 0xC000_0002, "STATUS_NOT_IMPLEMENTED", "The requested operation is not implemented.";
+    INVALID_INFO_CLASS = 0xC000_0003, "STATUS_INVALID_INFO_CLASS", "The specified information class is not valid for this operation.";
+    INFO_LENGTH_MISMATCH = 0xC000_0004, "STATUS_INFO_LENGTH_MISMATCH", "The buffer length does not match the information class.";
+    ACCESS_VIOLATION = 0xC000_0005, "STATUS_ACCESS_VIOLATION", "An invalid memory address was referenced.";
+    INVALID_HANDLE = 0xC000_0008, "STATUS_INVALID_HANDLE", "The handle is not valid.";
+    INVALID_PARAMETER = 0xC000_000D, "STATUS_INVALID_PARAMETER", "An invalid parameter was passed to a service or function.";
+    NO_SUCH_FILE = 0xC000_000F, "STATUS_NO_SUCH_FILE", "The file does not exist.";
+    NO_MEMORY = 0xC000_0017, "STATUS_NO_MEMORY", "Not enough virtual memory or paging file quota is available.";
+    ACCESS_DENIED = 0xC000_0022, "STATUS_ACCESS_DENIED", "Access was denied by the object's security descriptor.";
+    BUFFER_TOO_SMALL = 0xC000_0023, "STATUS_BUFFER_TOO_SMALL", "The buffer is too small to contain the entry.";
+    OBJECT_TYPE_MISMATCH = 0xC000_0024, "STATUS_OBJECT_TYPE_MISMATCH", "The object is not of the type required by the operation.";
+    OBJECT_NAME_INVALID = 0xC000_0033, "STATUS_OBJECT_NAME_INVALID", "The object name is invalid.";
+    OBJECT_NAME_NOT_FOUND = 0xC000_0034, "STATUS_OBJECT_NAME_NOT_FOUND", "The object name was not found.";
+    OBJECT_NAME_COLLISION = 0xC000_0035, "STATUS_OBJECT_NAME_COLLISION", "The object name already exists.";
+    OBJECT_PATH_INVALID = 0xC000_0039, "STATUS_OBJECT_PATH_INVALID", "The object path component was not a directory object.";
+    OBJECT_PATH_NOT_FOUND = 0xC000_003A, "STATUS_OBJECT_PATH_NOT_FOUND", "The object path was not found.";
+    OBJECT_PATH_SYNTAX_BAD = 0xC000_003B, "STATUS_OBJECT_PATH_SYNTAX_BAD", "The object path has invalid syntax.";
+    SHARING_VIOLATION = 0xC000_0043, "STATUS_SHARING_VIOLATION", "The object is in use and cannot be opened with the requested sharing.";
+    PRIVILEGE_NOT_HELD = 0xC000_0061, "STATUS_PRIVILEGE_NOT_HELD", "A required privilege is not held by the client.";
+    NO_TOKEN = 0xC000_007C, "STATUS_NO_TOKEN", "An attempt was made to reference a token that does not exist.";
+    DISK_FULL = 0xC000_007F, "STATUS_DISK_FULL", "The disk is full.";
+    FILE_INVALID = 0xC000_0098, "STATUS_FILE_INVALID", "The volume for the file was externally altered.";
+    INSUFFICIENT_RESOURCES = 0xC000_009A, "STATUS_INSUFFICIENT_RESOURCES", "Insufficient system resources exist to complete the operation.";
+    NOT_SUPPORTED = 0xC000_00BB, "STATUS_NOT_SUPPORTED", "The request is not supported.";
+    INTERNAL_ERROR = 0xC000_00E5, "STATUS_INTERNAL_ERROR", "An internal error occurred.";
+    CANNOT_DELETE = 0xC000_0121, "STATUS_CANNOT_DELETE", "The object cannot be deleted, or deletion is already pending.";
+    REGISTRY_CORRUPT = 0xC000_014C, "STATUS_REGISTRY_CORRUPT", "The structure of a registry file is corrupt.";
+    REGISTRY_IO_FAILED = 0xC000_014D, "STATUS_REGISTRY_IO_FAILED", "An I/O operation on a registry file failed.";
+    NOT_REGISTRY_FILE = 0xC000_015C, "STATUS_NOT_REGISTRY_FILE", "The file is not a valid registry file.";
+    KEY_DELETED = 0xC000_017C, "STATUS_KEY_DELETED", "The key has been marked for deletion.";
+    NO_LOG_SPACE = 0xC000_017D, "STATUS_NO_LOG_SPACE", "There is no space to write the registry log.";
+    KEY_HAS_CHILDREN = 0xC000_0180, "STATUS_KEY_HAS_CHILDREN", "A symbolic link cannot be created in a key that has subkeys or values.";
+    CHILD_MUST_BE_VOLATILE = 0xC000_0181, "STATUS_CHILD_MUST_BE_VOLATILE", "A stable subkey cannot be created under a volatile parent key.";
+    REGISTRY_QUOTA_LIMIT = 0xC000_0256, "STATUS_REGISTRY_QUOTA_LIMIT", "The registry has reached its storage quota.";
+    TRANSACTIONAL_CONFLICT = 0xC019_0001, "STATUS_TRANSACTIONAL_CONFLICT", "The operation conflicts with another transaction.";
+    INVALID_TRANSACTION = 0xC019_0002, "STATUS_INVALID_TRANSACTION", "The transaction handle is not valid.";
+    TRANSACTION_NOT_ACTIVE = 0xC019_0003, "STATUS_TRANSACTION_NOT_ACTIVE", "The transaction is no longer active.";
+}
+
+impl NtStatus {
+    /// the raw 32 bit value
+    pub fn value(self) -> u32 {
+        self.0
+    }
+
+    /// the severity stored in bits 30 and 31
+    pub fn severity(self) -> Severity {
+        match self.0 >> 30 {
+            0 => Severity::Success,
+            1 => Severity::Informational,
+            2 => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// `true` if the customer bit (29) is set, meaning the value is not defined by Microsoft
+    pub fn is_customer(self) -> bool {
+        self.0 & 0x2000_0000 != 0
+    }
+
+    /// the facility stored in bits 16 through 27
+    pub fn facility(self) -> u16 {
+        ((self.0 >> 16) & 0x0fff) as u16
+    }
+
+    /// the facility specific code stored in the low 16 bits
+    pub fn code(self) -> u16 {
+        (self.0 & 0xffff) as u16
+    }
+
+    /// `NT_SUCCESS`: `true` for success and informational statuses
+    pub fn is_success(self) -> bool {
+        (self.0 as i32) >= 0
+    }
+
+    /// `NT_INFORMATION`: `true` for informational statuses
+    pub fn is_information(self) -> bool {
+        self.severity() == Severity::Informational
+    }
+
+    /// `NT_WARNING`: `true` for warning statuses
+    pub fn is_warning(self) -> bool {
+        self.severity() == Severity::Warning
+    }
+
+    /// `NT_ERROR`: `true` for error statuses
+    pub fn is_error(self) -> bool {
+        self.severity() == Severity::Error
+    }
+
+    /// the symbolic name of this status, e.g. `STATUS_KEY_DELETED`, if it is a known value
+    pub fn name(self) -> Option<&'static str> {
+        self.lookup().map(|(_, name, _)| *name)
+    }
+
+    /// a short human readable description of this status, if it is a known value
+    pub fn message(self) -> Option<&'static str> {
+        self.lookup().map(|(_, _, message)| *message)
+    }
+
+    /// `Ok(self)` if this is a success status, otherwise `Err(self)`
+    pub fn ok(self) -> std::result::Result<NtStatus, NtStatus> {
+        if self.is_success() {
+            Ok(self)
+        } else {
+            Err(self)
+        }
+    }
+
+    fn lookup(self) -> Option<&'static (u32, &'static str, &'static str)> {
+        STATUS_TABLE.iter().find(|(value, _, _)| *value == self.0)
+    }
+}
+
+impl From<u32> for NtStatus {
+    fn from(value: u32) -> Self {
+        NtStatus(value)
+    }
+}
+
+impl From<i32> for NtStatus {
+    fn from(value: i32) -> Self {
+        NtStatus(value as u32)
+    }
+}
+
+impl From<NtStatus> for u32 {
+    fn from(status: NtStatus) -> Self {
+        status.0
+    }
+}
+
+impl From<NtStatus> for i32 {
+    fn from(status: NtStatus) -> Self {
+        status.0 as i32
+    }
+}
+
+impl fmt::Debug for NtStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(fmt, "{}(0x{:08X})", name, self.0),
+            None => write!(fmt, "NtStatus(0x{:08X})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for NtStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.lookup() {
+            Some((value, name, message)) => write!(fmt, "{} (0x{:08X}): {}", name, value, message),
+            None => write!(
+                fmt,
+                "0x{:08X} ({}, facility 0x{:03X}, code 0x{:04X})",
+                self.0,
+                self.severity(),
+                self.facility(),
+                self.code()
+            ),
+        }
+    }
+}
+
+impl fmt::LowerHex for NtStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, fmt)
+    }
+}
+
+impl fmt::UpperHex for NtStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let status = NtStatus::from(0xC000_017Cu32);
+        assert_eq!(status, NtStatus::KEY_DELETED);
+        assert_eq!(status.severity(), Severity::Error);
+        assert_eq!(status.facility(), 0);
+        assert_eq!(status.code(), 0x017C);
+        assert!(!status.is_success());
+        assert!(status.is_error());
+
+        let status = NtStatus::from(0xC019_0001u32);
+        assert_eq!(status.facility(), 0x19);
+        assert_eq!(status.code(), 1);
+
+        assert!(NtStatus::NO_MORE_ENTRIES.is_warning());
+        assert!(!NtStatus::NO_MORE_ENTRIES.is_success());
+        assert!(NtStatus::REGISTRY_RECOVERED.is_information());
+        assert!(NtStatus::REGISTRY_RECOVERED.is_success());
+        assert!(NtStatus::SUCCESS.is_success());
+        assert!(NtStatus::from(-1073741790i32) == NtStatus::ACCESS_DENIED);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            NtStatus::CANNOT_DELETE.to_string(),
+            "STATUS_CANNOT_DELETE (0xC0000121): The object cannot be deleted, or deletion is already pending."
+        );
+        assert_eq!(
+            NtStatus(0xE123_4567).to_string(),
+            "0xE1234567 (error, facility 0x123, code 0x4567)"
+        );
+        assert!(NtStatus(0xE123_4567).is_customer());
+        assert_eq!(
+            format!("{:?}", NtStatus::OBJECT_NAME_NOT_FOUND),
+            "STATUS_OBJECT_NAME_NOT_FOUND(0xC0000034)"
+        );
+    }
+
+    #[test]
+    fn table_is_consistent() {
+        for (value, name, _) in STATUS_TABLE {
+            assert!(name.starts_with("STATUS_"));
+            assert_eq!(
+                STATUS_TABLE.iter().filter(|(v, _, _)| v == value).count(),
+                1,
+                "{} is duplicated",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Fixtures shared by the tests of the offline formats
+
+use crate::{hive_writer::HiveWriter, snapshot::KeySnapshot};
+
+/// A hive file holding the tree `build` fills in below an empty root key, written as `minor_version`
+pub(crate) fn hive_with(minor_version: u32, build: impl FnOnce(&mut KeySnapshot)) -> Vec<u8> {
+    let mut root = KeySnapshot::new(r"\");
+    build(&mut root);
+    HiveWriter::new()
+        .minor_version(minor_version)
+        .to_bytes(&root)
+        .unwrap()
+}
+
+/// A hive file holding the tree `build` fills in, written as the minor version `HiveWriter` defaults to
+pub(crate) fn hive(build: impl FnOnce(&mut KeySnapshot)) -> Vec<u8> {
+    hive_with(5, build)
+}
+
+/// Calls `check` with `rounds` copies of `bytes`, each with `count` of the bytes at or after `start`
+/// overwritten. The damage comes from a fixed xorshift sequence, so a failure is reproducible.
+pub(crate) fn damaged(
+    bytes: &[u8],
+    start: usize,
+    rounds: usize,
+    count: usize,
+    mut check: impl FnMut(Vec<u8>),
+) {
+    let mut state = 0x2545_f491u32;
+    for _ in 0..rounds {
+        let mut damaged = bytes.to_vec();
+        for _ in 0..count {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let offset = start + state as usize % (bytes.len() - start);
+            damaged[offset] = (state >> 24) as u8;
+        }
+        check(damaged);
+    }
+}
+
+/// Calls `check` with prefixes of `bytes`, from `start` bytes long up, growing by `step`
+pub(crate) fn truncated(bytes: &[u8], start: usize, step: usize, mut check: impl FnMut(&[u8])) {
+    for length in (start..bytes.len()).step_by(step) {
+        check(&bytes[..length]);
+    }
+}
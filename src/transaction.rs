@@ -0,0 +1,149 @@
+use crate::{
+    api::{NtClose, NtCommitTransaction, NtCreateTransaction, NtRollbackTransaction},
+    error::{Error, ErrorKind, Operation},
+    path::RegPathBuf,
+    status::NtStatus,
+    Result,
+};
+use std::{
+    mem::zeroed,
+    ptr::{null, null_mut},
+    sync::Arc,
+};
+use winapi::{shared::ntdef::HANDLE, um::winnt::TRANSACTION_ALL_ACCESS};
+
+/// A handle to a kernel transaction, shared by the `Transaction` and every key opened in it
+pub(crate) struct TransactionHandle(pub(crate) HANDLE);
+
+// Kernel handles can be used from any thread
+unsafe impl Send for TransactionHandle {}
+unsafe impl Sync for TransactionHandle {}
+
+impl Drop for TransactionHandle {
+    fn drop(&mut self) {
+        unsafe {
+            NtClose(self.0);
+        }
+    }
+}
+
+/// A Kernel Transaction Manager transaction grouping registry changes so they happen atomically.
+///
+/// Keys opened with [`RegKey::open_transacted`] or [`RegKey::create_transacted`], and any subkeys opened or
+/// created through them, make their changes inside the transaction: other handles do not see them until
+/// [`Transaction::commit`] succeeds, and they are all discarded by [`Transaction::rollback`]. A transaction
+/// dropped without being committed is rolled back.
+///
+/// [`RegKey::open_transacted`]: crate::RegKey::open_transacted
+/// [`RegKey::create_transacted`]: crate::RegKey::create_transacted
+///
+/// # Examples
+///
+/// ```no_run
+/// use winregnt::{OpenOptions, RegKey, Transaction};
+/// let transaction = Transaction::new().unwrap();
+/// let mut key = RegKey::create_transacted(
+///     r"\Registry\Machine\Software\winregnt",
+///     OpenOptions::new().write(true),
+///     &transaction,
+/// )
+/// .unwrap();
+/// key.write_dword_value("Version", 2).unwrap();
+/// key.write_string_value("Path", r"C:\Program Files\winregnt").unwrap();
+/// transaction.commit().unwrap();
+/// ```
+pub struct Transaction {
+    handle: Arc<TransactionHandle>,
+    finished: bool,
+}
+
+impl Transaction {
+    /// Starts a new transaction
+    pub fn new() -> Result<Transaction> {
+        let mut handle: HANDLE = unsafe { zeroed() };
+        let status = NtStatus::from(unsafe {
+            NtCreateTransaction(
+                &mut handle,
+                TRANSACTION_ALL_ACCESS,
+                null(),
+                null(),
+                null_mut(),
+                0,
+                0,
+                0,
+                null(),
+                null(),
+            )
+        });
+        check(Operation::CreateTransaction, status)?;
+        Ok(Transaction {
+            handle: Arc::new(TransactionHandle(handle)),
+            finished: false,
+        })
+    }
+
+    /// Makes every change made in the transaction visible at once. If the commit fails, the transaction is
+    /// rolled back when it is dropped.
+    pub fn commit(mut self) -> Result<()> {
+        check(Operation::CommitTransaction, unsafe {
+            NtCommitTransaction(self.handle.0, 1)
+        })?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Discards every change made in the transaction
+    pub fn rollback(mut self) -> Result<()> {
+        self.finished = true;
+        check(Operation::RollbackTransaction, unsafe {
+            NtRollbackTransaction(self.handle.0, 1)
+        })
+    }
+
+    pub(crate) fn handle(&self) -> &Arc<TransactionHandle> {
+        &self.handle
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.finished {
+            unsafe {
+                NtRollbackTransaction(self.handle.0, 1);
+            }
+        }
+    }
+}
+
+fn check<S: Into<NtStatus>>(operation: Operation, status: S) -> Result<()> {
+    let status = status.into();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            operation,
+            RegPathBuf::new(),
+            ErrorKind::Status(status),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{OpenOptions, RegKey, Transaction};
+
+    #[test]
+    fn transacted_open() {
+        let transaction = Transaction::new().unwrap();
+        let key = RegKey::open_transacted(
+            r"\Registry\Machine\Software\Microsoft",
+            &OpenOptions::new(),
+            &transaction,
+        )
+        .unwrap();
+        assert!(key
+            .open_subkey(r"Windows\CurrentVersion", &OpenOptions::new())
+            .is_ok());
+        transaction.commit().unwrap();
+    }
+}
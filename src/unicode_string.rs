@@ -1,10 +1,19 @@
-use crate::api::RtlInitUnicodeString;
-use std::ffi::OsString;
-use std::mem::zeroed;
-use std::os::windows::ffi::OsStrExt;
+use crate::error::ErrorKind;
+use std::{convert::TryFrom, mem::zeroed};
 use winapi::shared::ntdef::UNICODE_STRING;
 
-pub(crate) struct UnicodeString(pub UNICODE_STRING, Vec<u16>);
+/// A counted `UNICODE_STRING` that owns its buffer.
+///
+/// The length is taken from the buffer rather than by scanning for a terminator, so names with embedded
+/// `null`s are passed to the kernel intact.
+pub(crate) struct UnicodeString(
+    pub UNICODE_STRING,
+    // never read, only keeps alive the buffer `UNICODE_STRING.Buffer` points into
+    #[allow(dead_code)] Vec<u16>,
+);
+
+/// The most UTF-16 code units a `UNICODE_STRING` can count, as its length is a `u16` in bytes
+const MAX_LENGTH: usize = u16::MAX as usize / 2;
 
 impl Default for UnicodeString {
     fn default() -> Self {
@@ -12,27 +21,42 @@ impl Default for UnicodeString {
     }
 }
 
-impl From<&str> for UnicodeString {
-    fn from(input: &str) -> Self {
-        let mut u: UNICODE_STRING = unsafe { zeroed() };
-        let mut o = OsString::from(input).encode_wide().collect::<Vec<u16>>();
-        o.push(0x00);
-        o.push(0x00);
+impl TryFrom<&str> for UnicodeString {
+    type Error = ErrorKind;
 
-        unsafe {
-            RtlInitUnicodeString(&mut u, o.as_ptr());
-        }
-        UnicodeString(u, o)
+    fn try_from(input: &str) -> Result<Self, ErrorKind> {
+        UnicodeString::try_from(input.encode_utf16().collect::<Vec<u16>>())
     }
 }
 
-impl From<&Vec<u16>> for UnicodeString {
-    fn from(input: &Vec<u16>) -> Self {
-        let mut u: UNICODE_STRING = unsafe { zeroed() };
-        unsafe {
-            RtlInitUnicodeString(&mut u, input.as_ptr());
+impl TryFrom<&[u16]> for UnicodeString {
+    type Error = ErrorKind;
+
+    fn try_from(input: &[u16]) -> Result<Self, ErrorKind> {
+        UnicodeString::try_from(input.to_vec())
+    }
+}
+
+impl TryFrom<Vec<u16>> for UnicodeString {
+    type Error = ErrorKind;
+
+    /// Fails with [`ErrorKind::InvalidName`] for names longer than 32,767 code units, which the kernel would
+    /// otherwise be handed cut short
+    fn try_from(mut input: Vec<u16>) -> Result<Self, ErrorKind> {
+        if input.len() > MAX_LENGTH {
+            return Err(ErrorKind::InvalidName(
+                "longer than 32767 UTF-16 code units",
+            ));
         }
-        UnicodeString(u, input.to_vec())
+        let length = input.len() * 2;
+        // keep a terminator after the counted part for any consumer that expects one
+        input.push(0x00);
+
+        let mut u: UNICODE_STRING = unsafe { zeroed() };
+        u.Length = length as _;
+        u.MaximumLength = length as _;
+        u.Buffer = input.as_mut_ptr();
+        Ok(UnicodeString(u, input))
     }
 }
 
@@ -40,8 +64,18 @@ impl From<&Vec<u16>> for UnicodeString {
 mod tests {
     #[test]
     fn unicode() {
-        use crate::UnicodeString;
-        let s = UnicodeString::from("testing");
+        use crate::{error::ErrorKind, unicode_string::UnicodeString};
+        use std::convert::TryFrom;
+        let s = UnicodeString::try_from("testing").unwrap();
         assert_eq!(s.0.Length, 14);
+
+        assert_eq!(
+            UnicodeString::try_from(vec![0x41; 32767]).unwrap().0.Length,
+            65534
+        );
+        assert!(matches!(
+            UnicodeString::try_from(vec![0x41; 32768]),
+            Err(ErrorKind::InvalidName(_))
+        ));
     }
 }
@@ -0,0 +1,367 @@
+use crate::{
+    error::{Error, ErrorKind, Operation},
+    path::{RegPath, RegPathBuf},
+    value_name::ValueName,
+    Result,
+};
+use std::convert::TryFrom;
+
+/// Values read from registry keys
+///
+/// With the `serde` feature, values are written as `{"type": "REG_SZ", "data": "text"}`, with binary data as
+/// a hex string.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "data")
+)]
+pub enum RegValue {
+    /// No value
+    #[cfg_attr(feature = "serde", serde(rename = "REG_NONE"))]
+    None,
+    /// Value that can be represented as a string
+    #[cfg_attr(feature = "serde", serde(rename = "REG_SZ"))]
+    String(String),
+    /// String containing unexpanded environment variables
+    #[cfg_attr(feature = "serde", serde(rename = "REG_EXPAND_SZ"))]
+    ExpandString(String),
+    /// Symbolic link target
+    #[cfg_attr(feature = "serde", serde(rename = "REG_LINK"))]
+    Link(String),
+    /// A sequence of strings
+    #[cfg_attr(feature = "serde", serde(rename = "REG_MULTI_SZ"))]
+    MultiString(Vec<String>),
+    /// DWORD
+    #[cfg_attr(feature = "serde", serde(rename = "REG_DWORD"))]
+    Dword(u32),
+    /// Big endian DWORD
+    #[cfg_attr(feature = "serde", serde(rename = "REG_DWORD_BIG_ENDIAN"))]
+    DwordBigEndian(u32),
+    /// QWORD
+    #[cfg_attr(feature = "serde", serde(rename = "REG_QWORD"))]
+    Qword(u64),
+    /// Binary data
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "REG_BINARY", with = "crate::serde_impl::hex")
+    )]
+    Binary(Vec<u8>),
+    /// Unknown or unsupported registry value type
+    #[cfg_attr(feature = "serde", serde(rename = "unknown"))]
+    Unknown,
+}
+
+impl ::std::fmt::Display for RegValue {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            RegValue::String(ref v) | RegValue::ExpandString(ref v) | RegValue::Link(ref v) => {
+                write!(fmt, "{}", v)
+            }
+            RegValue::MultiString(ref v) => write!(fmt, "{:?}", v),
+            RegValue::Dword(ref v) | RegValue::DwordBigEndian(ref v) => write!(fmt, "{}", v),
+            RegValue::Qword(ref v) => write!(fmt, "{}", v),
+            RegValue::Binary(ref v) => write!(fmt, "{:?}", v),
+            v => write!(fmt, "? {:?}", v),
+        }
+    }
+}
+
+impl RegValue {
+    /// Decodes raw value data according to its type.
+    ///
+    /// Decoding never fails: strings with invalid UTF-16 are converted lossily (embedded `null`s are kept,
+    /// trailing terminators are dropped) and numbers whose data has the wrong size are returned as `Binary`.
+    /// The exact bytes are always available from the `RawValue`.
+    pub fn from_raw(raw: &RawValue) -> RegValue {
+        let data = &raw.data[..];
+        match raw.value_type() {
+            Some(ValueType::REG_NONE) => RegValue::None,
+            Some(ValueType::REG_SZ) => RegValue::String(decode_string(data)),
+            Some(ValueType::REG_EXPAND_SZ) => RegValue::ExpandString(decode_string(data)),
+            Some(ValueType::REG_LINK) => RegValue::Link(decode_string(data)),
+            Some(ValueType::REG_MULTI_SZ) => RegValue::MultiString(decode_multi_string(data)),
+            Some(ValueType::REG_BINARY) => RegValue::Binary(data.to_vec()),
+            Some(ValueType::REG_DWORD) => match <[u8; 4]>::try_from(data) {
+                Ok(bytes) => RegValue::Dword(u32::from_le_bytes(bytes)),
+                Err(_) => RegValue::Binary(data.to_vec()),
+            },
+            Some(ValueType::REG_DWORD_BIG_ENDIAN) => match <[u8; 4]>::try_from(data) {
+                Ok(bytes) => RegValue::DwordBigEndian(u32::from_be_bytes(bytes)),
+                Err(_) => RegValue::Binary(data.to_vec()),
+            },
+            Some(ValueType::REG_QWORD) => match <[u8; 8]>::try_from(data) {
+                Ok(bytes) => RegValue::Qword(u64::from_le_bytes(bytes)),
+                Err(_) => RegValue::Binary(data.to_vec()),
+            },
+            _ => RegValue::Unknown,
+        }
+    }
+
+    /// Encodes this value as it would be stored in the registry.
+    ///
+    /// Strings are written as null terminated UTF-16LE. Returns `None` for `Unknown`.
+    pub fn to_raw(&self) -> Option<RawValue> {
+        let (value_type, data) = match self {
+            RegValue::None => (ValueType::REG_NONE, Vec::new()),
+            RegValue::String(s) => (ValueType::REG_SZ, encode_string(s)),
+            RegValue::ExpandString(s) => (ValueType::REG_EXPAND_SZ, encode_string(s)),
+            RegValue::Link(s) => (
+                ValueType::REG_LINK,
+                s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            ),
+            RegValue::MultiString(strings) => {
+                let mut data = strings
+                    .iter()
+                    .flat_map(|s| encode_string(s))
+                    .collect::<Vec<u8>>();
+                data.extend_from_slice(&[0, 0]);
+                (ValueType::REG_MULTI_SZ, data)
+            }
+            RegValue::Dword(v) => (ValueType::REG_DWORD, v.to_le_bytes().to_vec()),
+            RegValue::DwordBigEndian(v) => {
+                (ValueType::REG_DWORD_BIG_ENDIAN, v.to_be_bytes().to_vec())
+            }
+            RegValue::Qword(v) => (ValueType::REG_QWORD, v.to_le_bytes().to_vec()),
+            RegValue::Binary(data) => (ValueType::REG_BINARY, data.clone()),
+            RegValue::Unknown => return None,
+        };
+        Some(RawValue::new(value_type as u32, data))
+    }
+
+    /// The registry type this value is stored as, `None` for `Unknown`
+    pub fn value_type(&self) -> Option<ValueType> {
+        match self {
+            RegValue::None => Some(ValueType::REG_NONE),
+            RegValue::String(_) => Some(ValueType::REG_SZ),
+            RegValue::ExpandString(_) => Some(ValueType::REG_EXPAND_SZ),
+            RegValue::Link(_) => Some(ValueType::REG_LINK),
+            RegValue::MultiString(_) => Some(ValueType::REG_MULTI_SZ),
+            RegValue::Dword(_) => Some(ValueType::REG_DWORD),
+            RegValue::DwordBigEndian(_) => Some(ValueType::REG_DWORD_BIG_ENDIAN),
+            RegValue::Qword(_) => Some(ValueType::REG_QWORD),
+            RegValue::Binary(_) => Some(ValueType::REG_BINARY),
+            RegValue::Unknown => None,
+        }
+    }
+}
+
+fn wide_units(data: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    data.chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+}
+
+fn decode_string(data: &[u8]) -> String {
+    let mut wide = wide_units(data).collect::<Vec<u16>>();
+    while wide.last() == Some(&0) {
+        wide.pop();
+    }
+    String::from_utf16_lossy(&wide)
+}
+
+fn decode_multi_string(data: &[u8]) -> Vec<String> {
+    let wide = wide_units(data).collect::<Vec<u16>>();
+    let mut strings = wide
+        .split(|c| *c == 0)
+        .map(String::from_utf16_lossy)
+        .collect::<Vec<String>>();
+    // the list is terminated by an empty string
+    while strings.last().map(String::is_empty).unwrap_or(false) {
+        strings.pop();
+    }
+    strings
+}
+
+fn encode_string(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+/// The exact type and bytes of a registry value
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RawValue {
+    /// The registry type, normally one of `ValueType` but any number is allowed
+    pub value_type: u32,
+    /// The value data as stored in the registry
+    pub data: Vec<u8>,
+}
+
+impl RawValue {
+    /// Creates a raw value from a type number and its data
+    pub fn new<D: Into<Vec<u8>>>(value_type: u32, data: D) -> RawValue {
+        RawValue {
+            value_type,
+            data: data.into(),
+        }
+    }
+
+    /// The registry type, if it is one of the well known types
+    pub fn value_type(&self) -> Option<ValueType> {
+        ValueType::try_from(self.value_type).ok()
+    }
+
+    /// Decodes the data, see `RegValue::from_raw`
+    pub fn decode(&self) -> RegValue {
+        RegValue::from_raw(self)
+    }
+}
+
+/// The registry value types
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ValueType {
+    /// No defined value type
+    REG_NONE = 0,
+    /// A null terminated string
+    REG_SZ = 1,
+    /// A null terminated string with unexpanded environment variables
+    REG_EXPAND_SZ = 2,
+    /// Binary data in any form
+    REG_BINARY = 3,
+    /// A 32 bit little endian number
+    REG_DWORD = 4,
+    /// A 32 bit big endian number
+    REG_DWORD_BIG_ENDIAN = 5,
+    /// The target path of a symbolic link
+    REG_LINK = 6,
+    /// A sequence of null terminated strings, terminated by an empty string
+    REG_MULTI_SZ = 7,
+    /// A device driver resource list
+    REG_RESOURCE_LIST = 8,
+    /// A hardware resource description
+    REG_FULL_RESOURCE_DESCRIPTOR = 9,
+    /// A device driver resource requirements list
+    REG_RESOURCE_REQUIREMENTS_LIST = 10,
+    /// A 64 bit little endian number
+    REG_QWORD = 11,
+}
+
+impl TryFrom<u32> for ValueType {
+    type Error = u32;
+
+    fn try_from(value: u32) -> std::result::Result<ValueType, u32> {
+        match value {
+            0 => Ok(ValueType::REG_NONE),
+            1 => Ok(ValueType::REG_SZ),
+            2 => Ok(ValueType::REG_EXPAND_SZ),
+            3 => Ok(ValueType::REG_BINARY),
+            4 => Ok(ValueType::REG_DWORD),
+            5 => Ok(ValueType::REG_DWORD_BIG_ENDIAN),
+            6 => Ok(ValueType::REG_LINK),
+            7 => Ok(ValueType::REG_MULTI_SZ),
+            8 => Ok(ValueType::REG_RESOURCE_LIST),
+            9 => Ok(ValueType::REG_FULL_RESOURCE_DESCRIPTOR),
+            10 => Ok(ValueType::REG_RESOURCE_REQUIREMENTS_LIST),
+            11 => Ok(ValueType::REG_QWORD),
+            other => Err(other),
+        }
+    }
+}
+
+/// defines a registry value (name and data)
+#[derive(Clone, Debug)]
+pub struct RegValueItem {
+    path: RegPathBuf,
+    name: ValueName,
+    raw: RawValue,
+}
+
+impl RegValueItem {
+    /// creates a value item for the value `name` of the key at `path`
+    pub fn new<P: Into<RegPathBuf>, N: Into<ValueName>>(path: P, name: N, raw: RawValue) -> Self {
+        RegValueItem {
+            path: path.into(),
+            name: name.into(),
+            raw,
+        }
+    }
+
+    /// returns the name of the value
+    pub fn name(&self) -> Result<String> {
+        self.name.to_string_checked().ok_or_else(|| {
+            Error::new(
+                Operation::EnumerateValues,
+                &self.path,
+                ErrorKind::ConvertName,
+            )
+            .with_value_name(&self.name)
+        })
+    }
+
+    /// returns the name of the value without any lossy conversion
+    pub fn value_name(&self) -> &ValueName {
+        &self.name
+    }
+
+    /// returns the path of the key this value belongs to
+    pub fn key_path(&self) -> &RegPath {
+        &self.path
+    }
+
+    /// returns the `RegValue`
+    pub fn value(&self) -> RegValue {
+        self.raw.decode()
+    }
+
+    /// returns the exact type and data of the value
+    pub fn raw(&self) -> &RawValue {
+        &self.raw
+    }
+}
+
+impl std::fmt::Display for RegValueItem {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(fmt, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let raw = RawValue::new(1, b"a\0\0\0b\0\0\0".to_vec());
+        assert_eq!(raw.decode(), RegValue::String("a\0b".to_owned()));
+
+        let raw = RawValue::new(7, b"a\0\0\0b\0c\0\0\0\0\0".to_vec());
+        assert_eq!(
+            raw.decode(),
+            RegValue::MultiString(vec!["a".to_owned(), "bc".to_owned()])
+        );
+
+        assert_eq!(
+            RawValue::new(4, vec![1, 0, 0, 0]).decode(),
+            RegValue::Dword(1)
+        );
+        assert_eq!(
+            RawValue::new(5, vec![0, 0, 0, 1]).decode(),
+            RegValue::DwordBigEndian(1)
+        );
+        assert_eq!(
+            RawValue::new(4, vec![1, 0]).decode(),
+            RegValue::Binary(vec![1, 0])
+        );
+        assert_eq!(RawValue::new(0x1234, vec![1]).decode(), RegValue::Unknown);
+    }
+
+    #[test]
+    fn round_trip() {
+        let values = vec![
+            RegValue::None,
+            RegValue::String("hello".to_owned()),
+            RegValue::ExpandString("%SystemRoot%".to_owned()),
+            RegValue::MultiString(vec!["a".to_owned(), "b".to_owned()]),
+            RegValue::Dword(0xdead_beef),
+            RegValue::DwordBigEndian(7),
+            RegValue::Qword(u64::MAX),
+            RegValue::Binary(vec![1, 2, 3]),
+        ];
+        for value in values {
+            assert_eq!(value.to_raw().unwrap().decode(), value);
+        }
+    }
+}
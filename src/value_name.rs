@@ -0,0 +1,157 @@
+use crate::path::upcase;
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+/// The name of a registry value.
+///
+/// Like key names, value names are counted UTF-16 strings and may contain embedded `null`s or unpaired
+/// surrogates, so the raw code units are kept instead of a `String`. Unlike key names they may also contain
+/// backslashes. The empty name refers to the default value of a key.
+///
+/// Comparison, ordering and hashing are case-insensitive.
+#[derive(Clone, Default)]
+pub struct ValueName {
+    inner: Vec<u16>,
+}
+
+impl ValueName {
+    /// Creates a name from raw UTF-16 code units
+    pub fn from_wide<W: Into<Vec<u16>>>(wide: W) -> ValueName {
+        ValueName { inner: wide.into() }
+    }
+
+    /// Returns the raw UTF-16 code units of this name
+    pub fn as_wide(&self) -> &[u16] {
+        &self.inner
+    }
+
+    /// Consumes the name, returning its UTF-16 code units
+    pub fn into_wide(self) -> Vec<u16> {
+        self.inner
+    }
+
+    /// Returns `true` for the default (unnamed) value
+    pub fn is_default(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Converts the name to a `String`, failing if it is not valid UTF-16
+    pub fn to_string_checked(&self) -> Option<String> {
+        String::from_utf16(&self.inner).ok()
+    }
+
+    /// Converts the name to a `String`, replacing invalid UTF-16 with `U+FFFD`
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf16_lossy(&self.inner)
+    }
+
+    fn upcased(&self) -> impl Iterator<Item = u16> + '_ {
+        self.inner.iter().copied().map(upcase)
+    }
+}
+
+impl PartialEq for ValueName {
+    fn eq(&self, other: &ValueName) -> bool {
+        self.inner.len() == other.inner.len() && self.upcased().eq(other.upcased())
+    }
+}
+
+impl Eq for ValueName {}
+
+impl PartialOrd for ValueName {
+    fn partial_cmp(&self, other: &ValueName) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ValueName {
+    fn cmp(&self, other: &ValueName) -> Ordering {
+        self.upcased().cmp(other.upcased())
+    }
+}
+
+impl Hash for ValueName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.inner.len());
+        self.upcased().for_each(|c| state.write_u16(c));
+    }
+}
+
+impl fmt::Debug for ValueName {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:?}", self.to_string_lossy())
+    }
+}
+
+impl fmt::Display for ValueName {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.to_string_lossy())
+    }
+}
+
+impl AsRef<[u16]> for ValueName {
+    fn as_ref(&self) -> &[u16] {
+        &self.inner
+    }
+}
+
+impl From<&str> for ValueName {
+    fn from(input: &str) -> Self {
+        ValueName {
+            inner: input.encode_utf16().collect(),
+        }
+    }
+}
+
+impl From<String> for ValueName {
+    fn from(input: String) -> Self {
+        ValueName::from(input.as_str())
+    }
+}
+
+impl From<&String> for ValueName {
+    fn from(input: &String) -> Self {
+        ValueName::from(input.as_str())
+    }
+}
+
+impl From<&ValueName> for ValueName {
+    fn from(input: &ValueName) -> Self {
+        input.clone()
+    }
+}
+
+impl From<Vec<u16>> for ValueName {
+    fn from(input: Vec<u16>) -> Self {
+        ValueName { inner: input }
+    }
+}
+
+impl From<&[u16]> for ValueName {
+    fn from(input: &[u16]) -> Self {
+        ValueName {
+            inner: input.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lossless() {
+        let wide = vec![b'R' as u16, 0x0000, b'u' as u16, 0xd800, b'n' as u16];
+        let name = ValueName::from(wide.clone());
+        assert_eq!(name.as_wide(), &wide[..]);
+        assert!(name.to_string_checked().is_none());
+        assert_eq!(name.to_string_lossy(), "R\u{0}u\u{fffd}n");
+
+        assert_eq!(ValueName::from("Path"), ValueName::from("PATH"));
+        assert_ne!(ValueName::from("Path"), ValueName::from("Path\u{0}"));
+        assert!(ValueName::default().is_default());
+    }
+}